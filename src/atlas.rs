@@ -0,0 +1,175 @@
+//! Packing every face's lightmaps into a few fixed-size textures, for renderers that draw with a
+//! GPU
+//!
+//! Each style of each face gets its own rectangle, surrounded by `padding` texels copied from its
+//! edges so that bilinear filtering doesn't bleed in light from the neighbouring rectangle.
+
+use bsp::{Bsp, Error, MapVersion};
+use render::Image;
+use sys::bsp::Quake1Lump;
+
+/// Where one lightmap landed, in texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// One of a face's lightmaps in the atlas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceLightmap {
+    pub style: u8,
+    pub rect: AtlasRect,
+    /// Texture coordinates in the page of each of `Face::vertices`, in the same order
+    pub uvs: Vec<(f32, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightmapAtlas {
+    /// Square RGBA pages. Quake's greyscale lighting is copied into all three channels.
+    pub pages: Vec<Image>,
+    /// For each face, its lightmaps in style order. Empty for unlit faces.
+    pub faces: Vec<Vec<FaceLightmap>>,
+}
+
+struct Item<'a> {
+    face: usize,
+    style: u8,
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+}
+
+impl LightmapAtlas {
+    /// Pack the lightmaps of every face of `bsp` into `page_size` square pages, using shelves
+    /// of lightmaps sorted by height. Fails if a single lightmap doesn't fit on a page.
+    pub fn new<V>(bsp: &Bsp<V>, page_size: u32, padding: u32) -> Result<Self, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        let channels = bsp.lightmap_channels();
+        let mut items = Vec::new();
+
+        for (face_id, face) in bsp.faces() {
+            let size = face.extents().size;
+
+            for (style, data) in face.lightmaps() {
+                if size[0] + padding * 2 > page_size || size[1] + padding * 2 > page_size {
+                    return Err(Error::Unsupported("lightmap larger than an atlas page"));
+                }
+
+                items.push(Item {
+                    face: face_id,
+                    style,
+                    data,
+                    width: size[0],
+                    height: size[1],
+                });
+            }
+        }
+
+        // Tallest first keeps the shelves full. The sort is stable, so each face's styles stay
+        // in order.
+        let mut order = (0..items.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| ::std::cmp::Reverse(items[i].height));
+
+        let mut pages: Vec<Image> = Vec::new();
+        let mut rects = vec![None; items.len()];
+        let (mut x, mut y, mut shelf) = (page_size, page_size, 0);
+
+        for i in order {
+            let item = &items[i];
+            let width = item.width + padding * 2;
+            let height = item.height + padding * 2;
+
+            if x + width > page_size {
+                x = 0;
+                y += shelf;
+                shelf = 0;
+            }
+
+            if y + height > page_size {
+                pages.push(Image::new(page_size, page_size));
+                x = 0;
+                y = 0;
+                shelf = 0;
+            }
+
+            let rect = AtlasRect {
+                page: pages.len() - 1,
+                x: x + padding,
+                y: y + padding,
+                width: item.width,
+                height: item.height,
+            };
+
+            blit(&mut pages[rect.page], item, &rect, padding, channels);
+            rects[i] = Some(rect);
+
+            x += width;
+            shelf = shelf.max(height);
+        }
+
+        let mut faces = vec![Vec::new(); bsp.faces().len()];
+
+        for (item, rect) in items.iter().zip(rects) {
+            let rect = rect.expect("Every item is placed");
+            let face = bsp.faces().get(item.face).expect("Item faces exist");
+            let extents = face.extents();
+            let texinfo = face.texinfo();
+            let (s_axis, s_offset) = texinfo.s();
+            let (t_axis, t_offset) = texinfo.t();
+
+            // Sample centres sit 16 texels apart starting at `texture_min`, hence the half
+            // sample offset
+            let uvs = face.vertices()
+                .into_iter()
+                .map(|v| {
+                    let s = v.dot(&s_axis) + s_offset - extents.texture_min[0] as f32;
+                    let t = v.dot(&t_axis) + t_offset - extents.texture_min[1] as f32;
+
+                    (
+                        ((s + 8.) / 16. + rect.x as f32) / page_size as f32,
+                        ((t + 8.) / 16. + rect.y as f32) / page_size as f32,
+                    )
+                })
+                .collect();
+
+            faces[item.face].push(FaceLightmap {
+                style: item.style,
+                rect,
+                uvs,
+            });
+        }
+
+        Ok(LightmapAtlas { pages, faces })
+    }
+}
+
+/// Copy a lightmap into its rectangle, extending its edges out into the padding.
+fn blit(page: &mut Image, item: &Item, rect: &AtlasRect, padding: u32, channels: usize) {
+    let padding = padding as i64;
+
+    for dy in -padding..item.height as i64 + padding {
+        for dx in -padding..item.width as i64 + padding {
+            let sx = dx.clamp(0, item.width as i64 - 1) as usize;
+            let sy = dy.clamp(0, item.height as i64 - 1) as usize;
+            let at = (sy * item.width as usize + sx) * channels;
+            let sample = &item.data[at..at + channels];
+
+            page.set_pixel(
+                (rect.x as i64 + dx) as u32,
+                (rect.y as i64 + dy) as u32,
+                [
+                    sample[0],
+                    sample[1.min(channels - 1)],
+                    sample[2.min(channels - 1)],
+                    255,
+                ],
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Render a map from an entity's position and write the result as a PNG.
+//!
+//! Usage: bsp-screenshot <map.bsp> <out.png> [classname] [width] [height]
+
+extern crate goldsrs;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::path::Path;
+use std::process;
+
+use goldsrs::bsp::mapversions::{Goldsrc, Quake1};
+use goldsrs::bsp::{Bsp, Error, MapVersion, Vec3};
+use goldsrs::render::{Camera, Renderer};
+use goldsrs::sys::bsp::Quake1Lump;
+use goldsrs::wad::Resolver;
+
+/// Eye height above an entity's origin.
+const VIEW_HEIGHT: f32 = 22.;
+
+fn screenshot<V>(map: &Path, out: &Path, class: &str, width: u32, height: u32) -> Result<(), Error>
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let data = fs::read(map)?;
+    let bsp: Bsp<V> = Bsp::new(&data[..])?;
+
+    let entities = bsp.entities();
+    let entity = entities
+        .iter()
+        .find(|e| e.class_name() == Some(class))
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, format!("no {} in map", class))
+        })?;
+
+    let origin = entity.origin().unwrap_or(Vec3 { x: 0., y: 0., z: 0. });
+    let angles = entity.angles().unwrap_or(Vec3 { x: 0., y: 0., z: 0. });
+    let camera = Camera::new(
+        origin + Vec3 { x: 0., y: 0., z: VIEW_HEIGHT },
+        angles,
+        width,
+        height,
+    );
+
+    // Look for external WADs next to the map and in the game directory above it
+    let maps_dir = map.parent().unwrap_or_else(|| Path::new("."));
+    let mut search_path = vec![maps_dir.to_owned()];
+    search_path.extend(maps_dir.parent().map(Path::to_owned));
+
+    let mut resolver = Resolver::new(search_path);
+    resolver.load_for(&bsp)?;
+
+    let textures = resolver.resolve(&bsp);
+    let unresolved = bsp.textures()
+        .iter()
+        .zip(&textures)
+        .filter(|&((_, ref texture), resolved)| texture.is_some() && resolved.is_none())
+        .count();
+
+    if unresolved > 0 {
+        eprintln!("warning: {} textures not found", unresolved);
+
+        for missing in resolver.missing() {
+            eprintln!("warning: couldn't find {}", missing);
+        }
+    }
+
+    let image = Renderer::new(&bsp).with_textures(textures).render(&camera);
+
+    image.write_png(BufWriter::new(File::create(out)?))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("Usage: {} <map.bsp> <out.png> [classname] [width] [height]", args[0]);
+        process::exit(2);
+    }
+
+    let class = args.get(3).map(|s| s.as_str()).unwrap_or("info_player_start");
+    let width = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(640);
+    let height = args.get(5).and_then(|s| s.parse().ok()).unwrap_or(480);
+    let map = Path::new(&args[1]);
+    let out = Path::new(&args[2]);
+
+    let mut version = [0; 4];
+    let result = File::open(map)
+        .and_then(|mut file| file.read_exact(&mut version))
+        .map_err(Error::from)
+        .and_then(|_| if u32::from_le_bytes(version) == Goldsrc::VERSION {
+            screenshot::<Goldsrc>(map, out, class, width, height)
+        } else {
+            screenshot::<Quake1>(map, out, class, width, height)
+        });
+
+    if let Err(e) = result {
+        eprintln!("{}: {}", map.display(), e);
+        process::exit(1);
+    }
+}
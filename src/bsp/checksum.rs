@@ -0,0 +1,168 @@
+//! The checksums engines compare to tell whether a client has the same map as the server
+//!
+//! The original NetQuake doesn't check maps at all. QuakeWorld's `checksum` XORs together an
+//! MD4-based checksum of every lump but the entities, which servers are free to change, and its
+//! `checksum2` also leaves out the lumps `vis` writes, so that re-vised maps are still accepted.
+//! GoldSrc takes a CRC-32 of the same lumps as `checksum` instead.
+
+use ioendian::IntoNativeEndian;
+
+use super::mapversions::Goldsrc;
+use super::{Bsp, MapVersion, Quake1Lump};
+
+// The order each MD4 round reads the block's words in
+const ROUND1: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+const ROUND2: [usize; 16] = [0, 4, 8, 12, 1, 5, 9, 13, 2, 6, 10, 14, 3, 7, 11, 15];
+const ROUND3: [usize; 16] = [0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15];
+
+/// One round of MD4. Each step updates one word from the other three, working backwards from the
+/// first.
+fn round(
+    h: &mut [u32; 4],
+    x: &[u32; 16],
+    f: fn(u32, u32, u32) -> u32,
+    order: &[usize; 16],
+    shifts: [u32; 4],
+    k: u32,
+) {
+    for i in 0..16 {
+        let t = (4 - i % 4) % 4;
+        let (b, c, d) = (h[(t + 1) % 4], h[(t + 2) % 4], h[(t + 3) % 4]);
+
+        h[t] = h[t]
+            .wrapping_add(f(b, c, d))
+            .wrapping_add(x[order[i]])
+            .wrapping_add(k)
+            .rotate_left(shifts[i % 4]);
+    }
+}
+
+/// MD4 as specified in RFC 1320.
+pub(crate) fn md4(data: &[u8]) -> [u32; 4] {
+    let mut state = [0x6745_2301u32, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    let mut message = data.to_vec();
+
+    message.push(0x80);
+
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for block in message.chunks(64) {
+        let mut x = [0u32; 16];
+
+        for (word, bytes) in x.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let mut h = state;
+
+        round(&mut h, &x, |x, y, z| (x & y) | (!x & z), &ROUND1, [3, 7, 11, 19], 0);
+        round(
+            &mut h,
+            &x,
+            |x, y, z| (x & y) | (x & z) | (y & z),
+            &ROUND2,
+            [3, 5, 9, 13],
+            0x5a82_7999,
+        );
+        round(&mut h, &x, |x, y, z| x ^ y ^ z, &ROUND3, [3, 9, 11, 15], 0x6ed9_eba1);
+
+        for (word, add) in state.iter_mut().zip(&h) {
+            *word = word.wrapping_add(*add);
+        }
+    }
+
+    state
+}
+
+/// Quake's `Com_BlockChecksum`: the four words of the MD4 digest XORed together.
+pub(crate) fn block_checksum(data: &[u8]) -> u32 {
+    let digest = md4(data);
+
+    digest[0] ^ digest[1] ^ digest[2] ^ digest[3]
+}
+
+/// The CRC-32 used by zlib and GoldSrc's `CRC32_ProcessBuffer`, continued from `crc`. Start from
+/// `!0` and invert the result to finish.
+pub(crate) fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+// Indices into the lump directory
+const ENTITIES: usize = 0;
+const VISIBILITY: usize = 4;
+const NODES: usize = 5;
+const LEAVES: usize = 10;
+
+impl<'a, V: MapVersion<Lump = Quake1Lump>> Bsp<'a, V> {
+    /// Every lump's contents, in the order of the header.
+    pub(crate) fn lump_data(&self) -> Vec<&[u8]> {
+        let l = &self.header().lumps;
+
+        [
+            &l.entities,
+            &l.planes.clone().transmute(),
+            &l.miptex,
+            &l.vertices.clone().transmute(),
+            &l.vislist.clone().transmute(),
+            &l.nodes.clone().transmute(),
+            &l.texinfo.clone().transmute(),
+            &l.faces.clone().transmute(),
+            &l.lightmaps,
+            &l.clipnodes.clone().transmute(),
+            &l.leaves.clone().transmute(),
+            &l.lfaces.clone().transmute(),
+            &l.edges.clone().transmute(),
+            &l.ledges.clone().transmute(),
+            &l.models.clone().transmute(),
+        ]
+            .iter()
+            .map(|entry| {
+                let offset = entry.offset.native() as usize;
+
+                &self.0[offset..offset + entry.len.native() as usize]
+            })
+            .collect()
+    }
+
+    /// The checksum the engine checks clients' maps against: a CRC-32 for GoldSrc maps, and
+    /// QuakeWorld's `checksum` otherwise. Neither covers the entities.
+    pub fn checksum(&self) -> u32 {
+        let lumps = self.lump_data().into_iter().enumerate();
+
+        if self.header().version.native() == Goldsrc::VERSION {
+            !lumps
+                .filter(|&(i, _)| i != ENTITIES)
+                .fold(!0, |crc, (_, data)| crc32(crc, data))
+        } else {
+            lumps
+                .filter(|&(i, _)| i != ENTITIES)
+                .fold(0, |sum, (_, data)| sum ^ block_checksum(data))
+        }
+    }
+
+    /// QuakeWorld's `checksum2`, which also leaves out the visibility, node and leaf lumps. This
+    /// is always the Quake algorithm, as GoldSrc has no equivalent.
+    pub fn checksum2(&self) -> u32 {
+        self.lump_data()
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| ![ENTITIES, VISIBILITY, NODES, LEAVES].contains(&i))
+            .fold(0, |sum, (_, data)| sum ^ block_checksum(data))
+    }
+}
@@ -0,0 +1,161 @@
+//! Parsing of the entity lump, the plain-text list of `{ "key" "value" ... }` blocks that places
+//! everything that isn't world geometry.
+
+use std::borrow::Cow;
+
+use bsp::Vec3;
+
+/// A single entity. Keys can legally appear more than once, `get` returns the first match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entity<'a> {
+    pub properties: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl<'a> Entity<'a> {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    /// A key holding three space-separated numbers, like `origin` or `angles`.
+    pub fn vector(&self, key: &str) -> Option<Vec3<f32>> {
+        let mut parts = self.get(key)?.split_whitespace().map(|p| p.parse::<f32>());
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(x)), Some(Ok(y)), Some(Ok(z))) => Some(Vec3 { x, y, z }),
+            _ => None,
+        }
+    }
+
+    pub fn origin(&self) -> Option<Vec3<f32>> {
+        self.vector("origin")
+    }
+
+    /// The entity's `angles`, falling back to the single `angle` (yaw) key that most point
+    /// entities use. `-1` and `-2` are the special "up" and "down" values.
+    pub fn angles(&self) -> Option<Vec3<f32>> {
+        self.vector("angles").or_else(|| {
+            let angle = self.get("angle")?.trim().parse::<f32>().ok()?;
+
+            Some(if angle == -1. {
+                Vec3 { x: -90., y: 0., z: 0. }
+            } else if angle == -2. {
+                Vec3 { x: 90., y: 0., z: 0. }
+            } else {
+                Vec3 { x: 0., y: angle, z: 0. }
+            })
+        })
+    }
+
+    /// The index of the brush submodel this entity uses, from a `model` key of the form `*N`.
+    pub fn model_index(&self) -> Option<usize> {
+        self.get("model")?.strip_prefix('*')?.parse().ok()
+    }
+}
+
+/// Parse the contents of an entity lump. This is lenient in the same way the engine is: unquoted
+/// tokens and `//` comments are accepted, and parsing stops quietly at the first thing that
+/// doesn't make sense rather than failing the whole map.
+pub fn parse(data: &[u8]) -> Vec<Entity<'_>> {
+    let mut tokens = Tokenizer { data, pos: 0 };
+    let mut out = Vec::new();
+
+    while let Some(Token::Open) = tokens.next() {
+        let mut entity = Entity::default();
+
+        while let Some(key) = tokens.next().and_then(Token::text) {
+            let value = match tokens.next().and_then(Token::text) {
+                Some(value) => value,
+                None => break,
+            };
+
+            entity
+                .properties
+                .push((String::from_utf8_lossy(key), String::from_utf8_lossy(value)));
+        }
+
+        out.push(entity);
+    }
+
+    out
+}
+
+/// Braces only count as structure outside quotes, so a value of `"}"` stays a value.
+enum Token<'a> {
+    Open,
+    Close,
+    Quoted(&'a [u8]),
+    Bare(&'a [u8]),
+}
+
+impl<'a> Token<'a> {
+    fn text(self) -> Option<&'a [u8]> {
+        match self {
+            Token::Quoted(text) | Token::Bare(text) => Some(text),
+            Token::Open | Token::Close => None,
+        }
+    }
+}
+
+struct Tokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let data = self.data;
+
+        loop {
+            while self.pos < data.len() && data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+
+            if data[self.pos..].starts_with(b"//") {
+                while self.pos < data.len() && data[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+
+        match data.get(self.pos) {
+            None | Some(&0) => None,
+            Some(&b'"') => {
+                let start = self.pos + 1;
+                let len = data[start..].iter().position(|&c| c == b'"')?;
+
+                self.pos = start + len + 1;
+                Some(Token::Quoted(&data[start..start + len]))
+            }
+            Some(&b'{') => {
+                self.pos += 1;
+                Some(Token::Open)
+            }
+            Some(&b'}') => {
+                self.pos += 1;
+                Some(Token::Close)
+            }
+            Some(_) => {
+                let start = self.pos;
+
+                while self.pos < data.len() && !data[self.pos].is_ascii_whitespace() &&
+                    data[self.pos] != 0
+                {
+                    self.pos += 1;
+                }
+
+                Some(Token::Bare(&data[start..self.pos]))
+            }
+        }
+    }
+}
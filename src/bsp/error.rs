@@ -0,0 +1,147 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while loading a map (or any of the other formats that share the
+/// same lump-and-record layout).
+///
+/// Lumps, records and fields are named by `&'static str` so that errors stay cheap to construct
+/// and can be matched on without allocating.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the file failed before we even got to parse it.
+    Io(io::Error),
+    /// The header's version number isn't one the requested `MapVersion` understands.
+    VersionMismatch(u32),
+    /// The file doesn't start with the identifier the format requires. Formats that accept more
+    /// than one spell them all out in `expected`, like `b"WAD2 or WAD3"`.
+    InvalidMagic {
+        expected: &'static [u8],
+        found: [u8; 4],
+    },
+    /// A lump (or the header itself, named `"header"`) is shorter than it needs to be. `expected`
+    /// and `actual` are in bytes.
+    Truncated {
+        lump: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A lump's directory entry points outside of the file.
+    LumpOutOfBounds {
+        lump: &'static str,
+        offset: i32,
+        len: i32,
+        file_len: usize,
+    },
+    /// A record refers to something that doesn't exist. `index` is the offending value and `len`
+    /// the number of records it should have been less than.
+    BadIndex {
+        lump: &'static str,
+        record: usize,
+        field: &'static str,
+        index: i64,
+        len: usize,
+    },
+    /// A record holds fewer of something than the format allows, like a negative number of
+    /// vertices or a frame group with no frames in it. `min` is the smallest valid count.
+    BadCount {
+        lump: &'static str,
+        record: usize,
+        field: &'static str,
+        count: i64,
+        min: i64,
+    },
+    /// The file is well-formed but uses something this crate can't handle yet.
+    Unsupported(&'static str),
+    /// A text format (like a `.map` source file) has something other than what's `expected` on
+    /// the given line, counting from 1.
+    Syntax { line: usize, expected: &'static str },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::VersionMismatch(version) => write!(f, "unsupported version {}", version),
+            Error::InvalidMagic { expected, found } => write!(
+                f,
+                "invalid magic: expected {:?}, found {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&found)
+            ),
+            Error::Truncated {
+                lump,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} truncated: expected {} bytes, found {}",
+                lump,
+                expected,
+                actual
+            ),
+            Error::LumpOutOfBounds {
+                lump,
+                offset,
+                len,
+                file_len,
+            } => write!(
+                f,
+                "{} lump ({} bytes at offset {}) lies outside of the {} byte file",
+                lump,
+                len,
+                offset,
+                file_len
+            ),
+            Error::BadIndex {
+                lump,
+                record,
+                field,
+                index,
+                len,
+            } => write!(
+                f,
+                "{}[{}].{} is {}, which is out of range (must be less than {})",
+                lump,
+                record,
+                field,
+                index,
+                len
+            ),
+            Error::BadCount {
+                lump,
+                record,
+                field,
+                count,
+                min,
+            } => write!(
+                f,
+                "{}[{}].{} is {}, which is too few (must be at least {})",
+                lump,
+                record,
+                field,
+                count,
+                min
+            ),
+            Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Error::Syntax { line, expected } => {
+                write!(f, "syntax error on line {}: expected {}", line, expected)
+            }
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(other: io::Error) -> Self {
+        Error::Io(other)
+    }
+}
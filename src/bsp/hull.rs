@@ -0,0 +1,410 @@
+//! Collision hulls and traces against them.
+//!
+//! Every model has four hulls. Hull 0 is the node tree used for rendering and point queries, the
+//! rest are clipnode trees that the compiler has pre-expanded by a bounding box (see
+//! `MapVersion::HULLS`) so that moving that box reduces to moving a single point.
+
+use std::f32::consts::PI;
+
+use ioendian::IntoNativeEndian;
+
+use bsp::{Bsp, BoundingBox, FromBsp, Vec3};
+use bsp::mapversions::MapVersion;
+use bsp::quake1::{LeafType, Plane, Side};
+
+use sys::bsp as sys;
+
+/// The distance traces stop short of the plane they hit, so that the end position is never
+/// considered to be inside it.
+pub const DIST_EPSILON: f32 = 0.031_25;
+
+/// A node of one of the clipping hulls (1-3).
+pub struct ClipNode<'a, V: 'a>(&'a sys::ClipNode, &'a Bsp<'a, V>);
+
+/// Where a clipnode's child points: either another clipnode or straight to a contents value.
+pub enum ClipChild<'a, V: 'a> {
+    Node(ClipNode<'a, V>),
+    Contents(LeafType),
+}
+
+impl<'a, V: 'a> FromBsp<'a, sys::ClipNode, V> for ClipNode<'a, V> {
+    fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a sys::ClipNode) -> Self {
+        ClipNode(from, bsp)
+    }
+}
+
+impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> ClipNode<'a, V> {
+    pub fn plane(&self) -> Plane {
+        self.1.plane(self.0.plane_id.native() as _)
+    }
+
+    pub fn front(&self) -> ClipChild<'a, V> {
+        self.child(self.0.front_id.native())
+    }
+
+    pub fn back(&self) -> ClipChild<'a, V> {
+        self.child(self.0.back_id.native())
+    }
+
+    fn child(&self, id: i16) -> ClipChild<'a, V> {
+        if id < 0 {
+            ClipChild::Contents((id as i32).into())
+        } else {
+            ClipChild::Node(ClipNode::from_bsp(self.1, &self.1.raw_clipnodes()[id as usize]))
+        }
+    }
+}
+
+/// A position and orientation that a brush model has been moved to, i.e. its entity's `origin`
+/// and `angles` (pitch, yaw and roll in degrees).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub origin: Vec3<f32>,
+    pub angles: Vec3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        let zero = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+
+        Transform {
+            origin: zero,
+            angles: zero,
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_origin(origin: Vec3<f32>) -> Self {
+        Transform {
+            origin,
+            ..Default::default()
+        }
+    }
+
+    fn is_rotated(&self) -> bool {
+        self.angles.x != 0. || self.angles.y != 0. || self.angles.z != 0.
+    }
+
+    /// The forward, right and up vectors for the given angles, as the engine's `AngleVectors`.
+    pub fn angle_vectors(angles: &Vec3<f32>) -> (Vec3<f32>, Vec3<f32>, Vec3<f32>) {
+        let to_rad = PI * 2. / 360.;
+        let (sp, cp) = (angles.x * to_rad).sin_cos();
+        let (sy, cy) = (angles.y * to_rad).sin_cos();
+        let (sr, cr) = (angles.z * to_rad).sin_cos();
+
+        (
+            Vec3 {
+                x: cp * cy,
+                y: cp * sy,
+                z: -sp,
+            },
+            Vec3 {
+                x: -sr * sp * cy + cr * sy,
+                y: -sr * sp * sy - cr * cy,
+                z: -sr * cp,
+            },
+            Vec3 {
+                x: cr * sp * cy + sr * sy,
+                y: cr * sp * sy - sr * cy,
+                z: cr * cp,
+            },
+        )
+    }
+
+    /// Convert a world-space point into the model's own space.
+    pub fn to_local(&self, point: &Vec3<f32>) -> Vec3<f32> {
+        let offset = *point - self.origin;
+
+        if self.is_rotated() {
+            let (forward, right, up) = Self::angle_vectors(&self.angles);
+
+            Vec3 {
+                x: offset.dot(&forward),
+                y: -offset.dot(&right),
+                z: offset.dot(&up),
+            }
+        } else {
+            offset
+        }
+    }
+
+    /// Convert a direction in the model's space back into world space.
+    pub fn direction_to_world(&self, direction: &Vec3<f32>) -> Vec3<f32> {
+        if self.is_rotated() {
+            // The basis is orthonormal, so its transpose is its inverse
+            let (forward, right, up) = Self::angle_vectors(&self.angles);
+
+            forward * direction.x - right * direction.y + up * direction.z
+        } else {
+            *direction
+        }
+    }
+
+    /// Convert a point in the model's space back into world space.
+    pub fn to_world(&self, point: &Vec3<f32>) -> Vec3<f32> {
+        self.direction_to_world(point) + self.origin
+    }
+}
+
+/// The result of moving a point through a hull, as the engine's `trace_t`.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// The whole move was inside solid space
+    pub all_solid: bool,
+    /// The move started inside solid space
+    pub start_solid: bool,
+    /// Some part of the move went through empty space
+    pub in_open: bool,
+    /// Some part of the move went through a liquid (or any other non-solid, non-empty contents)
+    pub in_water: bool,
+    /// How much of the move was completed, from 0 to 1
+    pub fraction: f32,
+    pub end_pos: Vec3<f32>,
+    /// The surface that was hit, facing back towards the start of the move
+    pub plane: Option<Plane>,
+}
+
+impl Trace {
+    fn new(end: Vec3<f32>) -> Self {
+        Trace {
+            all_solid: true,
+            start_solid: false,
+            in_open: false,
+            in_water: false,
+            fraction: 1.,
+            end_pos: end,
+            plane: None,
+        }
+    }
+
+    /// Whether the move hit anything at all
+    pub fn hit(&self) -> bool {
+        self.fraction < 1. || self.start_solid
+    }
+}
+
+/// Identifies a node in either kind of tree. Hull 0 refers to leaves by `-(index + 1)`, while the
+/// clip hulls store the contents directly in the negative child ids.
+#[derive(Debug, Clone, Copy)]
+enum HullNode {
+    Node(usize),
+    Contents(LeafType),
+}
+
+/// One of a model's four collision hulls.
+pub struct Hull<'a, V: 'a> {
+    bsp: &'a Bsp<'a, V>,
+    index: usize,
+    root: i32,
+}
+
+impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Hull<'a, V> {
+    pub(crate) fn new(bsp: &'a Bsp<'a, V>, index: usize, root: i32) -> Self {
+        Hull { bsp, index, root }
+    }
+
+    /// Which of the four hulls this is.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The box that this hull has been expanded by. A trace through the hull is a trace of a box
+    /// this size centred on the traced point.
+    pub fn bounds(&self) -> BoundingBox<Vec3<f32>> {
+        V::HULLS[self.index].clone()
+    }
+
+    fn resolve(&self, id: i32) -> HullNode {
+        if self.index == 0 {
+            if id < 0 {
+                let leaf = &self.bsp.raw_leaves()[(-id - 1) as usize];
+                HullNode::Contents(leaf.leaf_type.native().into())
+            } else {
+                HullNode::Node(id as usize)
+            }
+        } else if id < 0 {
+            HullNode::Contents(id.into())
+        } else {
+            HullNode::Node(id as usize)
+        }
+    }
+
+    fn split(&self, node: usize) -> (Plane, i32, i32) {
+        if self.index == 0 {
+            let node = &self.bsp.raw_branches()[node];
+            (
+                self.bsp.plane(node.plane_id.native() as _),
+                node.front_id.native() as _,
+                node.back_id.native() as _,
+            )
+        } else {
+            let node = &self.bsp.raw_clipnodes()[node];
+            (
+                self.bsp.plane(node.plane_id.native() as _),
+                node.front_id.native() as _,
+                node.back_id.native() as _,
+            )
+        }
+    }
+
+    fn contents_from(&self, mut id: i32, point: &Vec3<f32>) -> LeafType {
+        loop {
+            match self.resolve(id) {
+                HullNode::Contents(contents) => break contents,
+                HullNode::Node(node) => {
+                    let (plane, front, back) = self.split(node);
+
+                    id = match plane.side(point) {
+                        Side::Front => front,
+                        Side::Back => back,
+                    };
+                }
+            }
+        }
+    }
+
+    /// The contents at `point`, in the model's own space.
+    pub fn point_contents(&self, point: &Vec3<f32>) -> LeafType {
+        self.contents_from(self.root, point)
+    }
+
+    /// The contents at the world-space `point`, with the model moved by `transform`.
+    pub fn point_contents_transformed(&self, point: &Vec3<f32>, transform: &Transform) -> LeafType {
+        self.point_contents(&transform.to_local(point))
+    }
+
+    /// Move a point from `start` to `end` (in the model's own space), stopping at the first solid
+    /// surface.
+    pub fn trace(&self, start: &Vec3<f32>, end: &Vec3<f32>) -> Trace {
+        let mut trace = Trace::new(*end);
+
+        self.recursive_check(self.root, 0., 1., *start, *end, &mut trace);
+
+        trace
+    }
+
+    /// Like `trace`, but with world-space endpoints and the model moved by `transform`. The
+    /// result's end position and plane are in world space too.
+    pub fn trace_transformed(
+        &self,
+        start: &Vec3<f32>,
+        end: &Vec3<f32>,
+        transform: &Transform,
+    ) -> Trace {
+        let mut trace = self.trace(&transform.to_local(start), &transform.to_local(end));
+
+        trace.end_pos = *start + (*end - *start) * trace.fraction;
+        trace.plane = trace.plane.map(|plane| {
+            let normal = transform.direction_to_world(&plane.normal);
+
+            Plane {
+                distance: plane.distance + normal.dot(&transform.origin),
+                normal,
+                ..plane
+            }
+        });
+
+        trace
+    }
+
+    // Returns `false` once the trace has been stopped, to unwind the recursion
+    fn recursive_check(
+        &self,
+        id: i32,
+        start_frac: f32,
+        end_frac: f32,
+        start: Vec3<f32>,
+        end: Vec3<f32>,
+        trace: &mut Trace,
+    ) -> bool {
+        let node = match self.resolve(id) {
+            HullNode::Contents(contents) => {
+                match contents {
+                    LeafType::Solid => trace.start_solid = true,
+                    LeafType::Ordinary => {
+                        trace.all_solid = false;
+                        trace.in_open = true;
+                    }
+                    _ => {
+                        trace.all_solid = false;
+                        trace.in_water = true;
+                    }
+                }
+
+                return true;
+            }
+            HullNode::Node(node) => node,
+        };
+
+        let (plane, front, back) = self.split(node);
+        let t1 = plane.distance_to(&start);
+        let t2 = plane.distance_to(&end);
+
+        if t1 >= 0. && t2 >= 0. {
+            return self.recursive_check(front, start_frac, end_frac, start, end, trace);
+        }
+        if t1 < 0. && t2 < 0. {
+            return self.recursive_check(back, start_frac, end_frac, start, end, trace);
+        }
+
+        // Put the crosspoint DIST_EPSILON units on the near side
+        let mut frac = if t1 < 0. {
+            (t1 + DIST_EPSILON) / (t1 - t2)
+        } else {
+            (t1 - DIST_EPSILON) / (t1 - t2)
+        };
+        frac = frac.clamp(0., 1.);
+
+        let mut mid_frac = start_frac + (end_frac - start_frac) * frac;
+        let mut mid = start + (end - start) * frac;
+
+        let (near, far) = if t1 < 0. { (back, front) } else { (front, back) };
+
+        if !self.recursive_check(near, start_frac, mid_frac, start, mid, trace) {
+            return false;
+        }
+
+        if self.contents_from(far, &mid) != LeafType::Solid {
+            return self.recursive_check(far, mid_frac, end_frac, mid, end, trace);
+        }
+
+        // Never got out of the solid area
+        if trace.all_solid {
+            return false;
+        }
+
+        trace.plane = Some(if t1 < 0. {
+            Plane {
+                normal: -plane.normal,
+                distance: -plane.distance,
+                ..plane
+            }
+        } else {
+            plane
+        });
+
+        // Back up until we're out of the solid, the epsilon above isn't always enough
+        while self.point_contents(&mid) == LeafType::Solid {
+            frac -= 0.1;
+
+            if frac < 0. {
+                trace.fraction = mid_frac;
+                trace.end_pos = mid;
+                return false;
+            }
+
+            mid_frac = start_frac + (end_frac - start_frac) * frac;
+            mid = start + (end - start) * frac;
+        }
+
+        trace.fraction = mid_frac;
+        trace.end_pos = mid;
+
+        false
+    }
+}
@@ -1,4 +1,4 @@
-use sys::bsp::{Quake1Lump, Quake2Lump};
+use sys::bsp::{BoundingBox, Quake1Lump, Quake2Lump, Vec3};
 
 pub struct Quake1;
 pub struct Quake2;
@@ -8,13 +8,52 @@ pub trait MapVersion {
     type Magic;
     type Lump;
 
+    /// The identifier at the very start of the file, empty for formats that start straight
+    /// with the version number.
+    const MAGIC: &'static [u8];
+
+    /// The version number that compilers write for this format.
+    const VERSION: u32;
+
+    /// The boxes that the compiler expanded each of the four collision hulls by. Unused hulls are
+    /// zero-sized.
+    const HULLS: [BoundingBox<Vec3<f32>>; 4];
+
     fn accepts_version(version: u32) -> bool;
 }
 
+const fn hull(aa: [f32; 3], bb: [f32; 3]) -> BoundingBox<Vec3<f32>> {
+    BoundingBox {
+        aa: Vec3 {
+            x: aa[0],
+            y: aa[1],
+            z: aa[2],
+        },
+        bb: Vec3 {
+            x: bb[0],
+            y: bb[1],
+            z: bb[2],
+        },
+    }
+}
+
+const POINT_HULL: BoundingBox<Vec3<f32>> = hull([0., 0., 0.], [0., 0., 0.]);
+
+const QUAKE_HULLS: [BoundingBox<Vec3<f32>>; 4] = [
+    POINT_HULL,
+    hull([-16., -16., -24.], [16., 16., 32.]),
+    hull([-32., -32., -24.], [32., 32., 64.]),
+    POINT_HULL,
+];
+
 impl MapVersion for Quake1 {
     type Magic = ();
     type Lump = Quake1Lump;
 
+    const MAGIC: &'static [u8] = b"";
+    const VERSION: u32 = 29;
+    const HULLS: [BoundingBox<Vec3<f32>>; 4] = QUAKE_HULLS;
+
     fn accepts_version(version: u32) -> bool {
         version <= 0x1d
     }
@@ -24,6 +63,16 @@ impl MapVersion for Goldsrc {
     type Magic = ();
     type Lump = Quake1Lump;
 
+    const MAGIC: &'static [u8] = b"";
+    const VERSION: u32 = 30;
+    // Standing player, large monsters and crouching player
+    const HULLS: [BoundingBox<Vec3<f32>>; 4] = [
+        POINT_HULL,
+        hull([-16., -16., -36.], [16., 16., 36.]),
+        hull([-32., -32., -32.], [32., 32., 32.]),
+        hull([-16., -16., -18.], [16., 16., 18.]),
+    ];
+
     fn accepts_version(version: u32) -> bool {
         version == 0x1e
     }
@@ -33,6 +82,11 @@ impl MapVersion for Quake2 {
     type Magic = [u8; 4];
     type Lump = Quake2Lump;
 
+    const MAGIC: &'static [u8] = b"IBSP";
+    const VERSION: u32 = 38;
+    // Quake 2 traces arbitrary boxes against brushes instead of using pre-expanded hulls
+    const HULLS: [BoundingBox<Vec3<f32>>; 4] = [POINT_HULL; 4];
+
     fn accepts_version(version: u32) -> bool {
         version <= 0x26 && version > 0x1d
     }
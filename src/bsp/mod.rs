@@ -1,20 +1,43 @@
+use std::iter;
 use std::mem;
 use std::slice;
 use std::marker::PhantomData;
 use std::borrow::Cow;
+use std::io::Read;
 
-use ioendian::IntoNativeEndian;
+use ioendian::{IntoNativeEndian, Little};
 
 use sys::bsp as sys;
 
 pub use sys::bsp::{BoundingBox, Vec3, Quake1Lump, UnifiesWith};
 
+pub mod checksum;
+pub mod entities;
+pub mod error;
+pub mod hull;
+pub mod portals;
 pub mod mapversions;
 pub mod quake1;
+pub mod raycast;
+pub mod writer;
 
+use self::hull::ClipNode;
+use self::mapversions::Goldsrc;
 use self::quake1::*;
 
+pub use self::entities::Entity;
+pub use self::error::Error;
 pub use self::mapversions::MapVersion;
+pub use self::writer::BspWriter;
+
+/// Identifiers of formats that share the `.bsp` extension but not the Quake 1 layout.
+const FOREIGN_MAGIC: &[(&[u8], &str)] = &[
+    (b"IBSP", "Quake 2 and Quake 3 maps (IBSP)"),
+    (b"VBSP", "Source engine maps (VBSP)"),
+    (b"RBSP", "Raven maps (RBSP)"),
+    (b"BSP2", "BSP2 maps"),
+    (b"2PSB", "2PSB maps"),
+];
 
 pub trait FromBsp<'a, Src, V> {
     fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a Src) -> Self;
@@ -40,9 +63,9 @@ pub struct ValueIter<'a, V: 'a, Src, Dst> {
 impl<'a, V, Src, Dst> ValueIter<'a, V, Src, Dst> {
     unsafe fn new(bsp: &'a Bsp<'a, V>, slice: &[Src]) -> Self {
         ValueIter {
-            bsp: bsp,
+            bsp,
             start: slice.as_ptr(),
-            end: slice.as_ptr().offset(slice.len() as _),
+            end: slice.as_ptr().add(slice.len()),
             output: PhantomData,
         }
     }
@@ -63,18 +86,98 @@ impl<'a, V, Src: 'a, Dst: FromBsp<'a, Src, V>> Iterator for ValueIter<'a, V, Src
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Error {
-    VersionMismatch(u32),
-    HeaderCorrupted,
-    EntryCorrupted(&'static str),
+/// Every record of one lump, converted to `Dst` on demand.
+pub struct Lump<'a, V: 'a, Src: 'a, Dst> {
+    bsp: &'a Bsp<'a, V>,
+    records: &'a [Src],
+    output: PhantomData<Dst>,
+}
+
+impl<'a, V, Src, Dst> Clone for Lump<'a, V, Src, Dst> {
+    fn clone(&self) -> Self {
+        Lump {
+            bsp: self.bsp,
+            records: self.records,
+            output: PhantomData,
+        }
+    }
 }
 
+impl<'a, V, Src: 'a, Dst: FromBsp<'a, Src, V>> Lump<'a, V, Src, Dst> {
+    fn new(bsp: &'a Bsp<'a, V>, records: &'a [Src]) -> Self {
+        Lump {
+            bsp,
+            records,
+            output: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Dst> {
+        self.records
+            .get(index)
+            .map(|record| Dst::from_bsp(self.bsp, record))
+    }
+
+    /// Iterate over `(index, record)` pairs, in file order.
+    pub fn iter(&self) -> LumpIter<'a, V, Src, Dst> {
+        LumpIter {
+            bsp: self.bsp,
+            inner: self.records.iter().enumerate(),
+            output: PhantomData,
+        }
+    }
+}
+
+impl<'a, V, Src: 'a, Dst: FromBsp<'a, Src, V>> IntoIterator for Lump<'a, V, Src, Dst> {
+    type Item = (usize, Dst);
+    type IntoIter = LumpIter<'a, V, Src, Dst>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct LumpIter<'a, V: 'a, Src: 'a, Dst> {
+    bsp: &'a Bsp<'a, V>,
+    inner: iter::Enumerate<slice::Iter<'a, Src>>,
+    output: PhantomData<Dst>,
+}
+
+impl<'a, V, Src: 'a, Dst: FromBsp<'a, Src, V>> Iterator for LumpIter<'a, V, Src, Dst> {
+    type Item = (usize, Dst);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bsp = self.bsp;
+
+        self.inner
+            .next()
+            .map(|(i, record)| (i, Dst::from_bsp(bsp, record)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, V, Src: 'a, Dst: FromBsp<'a, Src, V>> ExactSizeIterator for LumpIter<'a, V, Src, Dst> {}
+
 impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
     pub fn into_static(self) -> Bsp<'static, V> {
         Bsp(Cow::Owned(self.0.into_owned()), PhantomData)
     }
 
+    /// # Safety
+    ///
+    /// The buffer must contain a well-formed map of version `V`, every accessor blindly trusts the
+    /// header and the indices stored in the lumps.
     pub unsafe fn new_unchecked<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Self {
         Bsp(buffer.into(), PhantomData)
     }
@@ -83,10 +186,46 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
         self.0.len()
     }
 
+    /// The whole file, as it was loaded.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
         let unchecked = unsafe { Self::new_unchecked(buffer) };
-        if unchecked.len() < mem::size_of::<sys::Header<V::Magic, V::Lump>>() {
-            return Err(Error::HeaderCorrupted);
+        let header_len = mem::size_of::<sys::Header<V::Magic, V::Lump>>();
+
+        if !unchecked.0.starts_with(V::MAGIC) {
+            let mut found = [0; 4];
+            for (dst, src) in found.iter_mut().zip(unchecked.0.iter()) {
+                *dst = *src;
+            }
+
+            return Err(Error::InvalidMagic {
+                expected: V::MAGIC,
+                found,
+            });
+        }
+
+        if V::MAGIC.is_empty() {
+            // Formats without a magic number would otherwise report these as a nonsensical version
+            for &(magic, what) in FOREIGN_MAGIC {
+                if unchecked.0.starts_with(magic) {
+                    return Err(Error::Unsupported(what));
+                }
+            }
+        }
+
+        if unchecked.len() < header_len {
+            return Err(Error::Truncated {
+                lump: "header",
+                expected: header_len,
+                actual: unchecked.len(),
+            });
         }
 
         {
@@ -96,44 +235,351 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
                 return Err(Error::VersionMismatch(h.version.native()));
             }
 
-            for &(ref entry, ref name) in
+            for &(entry, name, record_len) in
                 &[
-                    (&h.lumps.entities.clone().transmute::<sys::Entry>(), "entities"),
-                    (&h.lumps.planes.clone().transmute(), "planes"),
-                    (&h.lumps.miptex.clone().transmute(), "miptex"),
-                    (&h.lumps.vertices.clone().transmute(), "vertices"),
-                    (&h.lumps.vislist.clone().transmute(), "vislist"),
-                    (&h.lumps.nodes.clone().transmute(), "nodes"),
-                    (&h.lumps.texinfo.clone().transmute(), "texinfo"),
-                    (&h.lumps.faces.clone().transmute(), "faces"),
-                    (&h.lumps.lightmaps.clone().transmute(), "lightmaps"),
-                    (&h.lumps.clipnodes.clone().transmute(), "clipnodes"),
-                    (&h.lumps.leaves.clone().transmute(), "leaves"),
-                    (&h.lumps.lfaces.clone().transmute(), "lfaces"),
-                    (&h.lumps.edges.clone().transmute(), "edges"),
-                    (&h.lumps.ledges.clone().transmute(), "ledges"),
-                    (&h.lumps.models.clone().transmute(), "models"),
+                    (&h.lumps.entities.clone().transmute::<sys::Entry>(), "entities", 1),
+                    (&h.lumps.planes.clone().transmute(), "planes", mem::size_of::<sys::Plane>()),
+                    (&h.lumps.miptex.clone().transmute(), "miptex", 1),
+                    (
+                        &h.lumps.vertices.clone().transmute(),
+                        "vertices",
+                        mem::size_of::<sys::Scalar3>(),
+                    ),
+                    (&h.lumps.vislist.clone().transmute(), "vislist", 1),
+                    (&h.lumps.nodes.clone().transmute(), "nodes", mem::size_of::<sys::Node>()),
+                    (
+                        &h.lumps.texinfo.clone().transmute(),
+                        "texinfo",
+                        mem::size_of::<sys::Surface>(),
+                    ),
+                    (&h.lumps.faces.clone().transmute(), "faces", mem::size_of::<sys::Face>()),
+                    (&h.lumps.lightmaps.clone().transmute(), "lightmaps", 1),
+                    (
+                        &h.lumps.clipnodes.clone().transmute(),
+                        "clipnodes",
+                        mem::size_of::<sys::ClipNode>(),
+                    ),
+                    (&h.lumps.leaves.clone().transmute(), "leaves", mem::size_of::<sys::Leaf>()),
+                    (&h.lumps.lfaces.clone().transmute(), "lfaces", mem::size_of::<FaceRef>()),
+                    (&h.lumps.edges.clone().transmute(), "edges", mem::size_of::<sys::Edge>()),
+                    (&h.lumps.ledges.clone().transmute(), "ledges", mem::size_of::<EdgeRef>()),
+                    (&h.lumps.models.clone().transmute(), "models", mem::size_of::<sys::Model>()),
                 ]
             {
-                if !entry
-                    .offset
-                    .native()
-                    .checked_add(entry.len.native())
-                    .map(|end| (end as usize) <= unchecked.len())
-                    .unwrap_or(false)
+                let offset = entry.offset.native();
+                let len = entry.len.native();
+
+                if offset < 0 || len < 0 ||
+                    !offset
+                        .checked_add(len)
+                        .map(|end| (end as usize) <= unchecked.len())
+                        .unwrap_or(false)
                 {
-                    return Err(Error::EntryCorrupted(name));
+                    return Err(Error::LumpOutOfBounds {
+                        lump: name,
+                        offset,
+                        len,
+                        file_len: unchecked.len(),
+                    });
+                }
+
+                if (len as usize) % record_len != 0 {
+                    return Err(Error::Truncated {
+                        lump: name,
+                        expected: (len as usize / record_len + 1) * record_len,
+                        actual: len as usize,
+                    });
                 }
             }
         }
 
+        unchecked.validate()?;
+
         Ok(unchecked)
     }
 
-    unsafe fn slice_from_header<'b, T, U: UnifiesWith<T>>(
-        &'b self,
-        header: &'b sys::Entry<U>,
-    ) -> &'b [T] {
+    /// Read a whole map into memory, for when it isn't already in a buffer.
+    pub fn read<R: Read>(mut reader: R) -> Result<Bsp<'static, V>, Error>
+    where
+        V: 'static,
+    {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+
+        Bsp::new(buffer)
+    }
+
+    /// Check that every index stored in the map points at a record that exists, so that none of
+    /// the accessors can go out of bounds. This is called by `new`, but maps constructed with
+    /// `new_unchecked` can be checked after the fact.
+    pub fn validate(&self) -> Result<(), Error> {
+        fn check(
+            lump: &'static str,
+            record: usize,
+            field: &'static str,
+            index: i64,
+            len: usize,
+        ) -> Result<(), Error> {
+            if index < 0 || index as usize >= len {
+                Err(Error::BadIndex {
+                    lump,
+                    record,
+                    field,
+                    index,
+                    len,
+                })
+            } else {
+                Ok(())
+            }
+        }
+
+        fn check_range(
+            lump: &'static str,
+            record: usize,
+            field: &'static str,
+            start: i64,
+            count: i64,
+            len: usize,
+        ) -> Result<(), Error> {
+            if count == 0 {
+                return Ok(());
+            }
+
+            check(lump, record, field, start, len)?;
+            check(lump, record, field, start + count - 1, len)
+        }
+
+        // Children with the top bit set index into the leaves (hull 0) or are contents (hulls 1-3)
+        fn check_child(
+            lump: &'static str,
+            record: usize,
+            field: &'static str,
+            child: i64,
+            nodes: usize,
+            leaves: Option<usize>,
+        ) -> Result<(), Error> {
+            if child >= 0 {
+                check(lump, record, field, child, nodes)
+            } else if let Some(leaves) = leaves {
+                check(lump, record, field, -child - 1, leaves)
+            } else {
+                Ok(())
+            }
+        }
+
+        {
+            let entry = &self.header().lumps.miptex;
+            let lump_len = entry.len.native() as usize;
+
+            if lump_len >= 4 {
+                let count: &Little<i32> = unsafe { self.value_ref(entry.offset.native() as usize) };
+                let count = count.native();
+                let expected = (count.max(0) as usize + 1) * 4;
+
+                if count < 0 || expected > lump_len {
+                    return Err(Error::Truncated {
+                        lump: "miptex",
+                        expected,
+                        actual: lump_len,
+                    });
+                }
+            }
+
+            for (i, &offset) in self.raw_textures().iter().enumerate() {
+                let offset = offset.0.native();
+
+                if offset < 0 {
+                    continue;
+                }
+
+                check(
+                    "miptex",
+                    i,
+                    "offset",
+                    offset as i64 + mem::size_of::<sys::MipTexture>() as i64 - 1,
+                    lump_len,
+                )?;
+
+                let texture = self.textures().get(i).and_then(|t| t);
+                let texture = texture.as_ref().unwrap();
+
+                if !texture.is_external() {
+                    // Embedded pixels can't be wider or taller than the lump holding them, which
+                    // also keeps the sizes below from overflowing
+                    check("miptex", i, "width", texture.width() as i64, lump_len + 1)?;
+                    check("miptex", i, "height", texture.height() as i64, lump_len + 1)?;
+                }
+
+                for level in 0..4 {
+                    let mip = texture.header.offsets[level].native() as i64;
+                    let len = (texture.width() as i64 >> level) * (texture.height() as i64 >> level);
+
+                    if mip != 0 {
+                        check_range("miptex", i, "offsets", offset as i64 + mip, len, lump_len)?;
+                    }
+                }
+            }
+        }
+
+        let textures = self.raw_textures().len();
+        let planes = self.raw_planes().len();
+        let nodes = self.raw_branches().len();
+        let leaves = self.raw_leaves().len();
+        let faces = self.raw_faces().len();
+        let lfaces = self.face_indices().len();
+        let ledges = self.edge_indices().len();
+        let edges = self.raw_edges().len();
+        let vertices = self.raw_vertices().len();
+        let texinfo = self.raw_texinfo().len();
+        let clipnodes = self.raw_clipnodes().len();
+
+        for (i, node) in self.raw_branches().iter().enumerate() {
+            check("nodes", i, "plane_id", node.plane_id.native() as _, planes)?;
+            check_child("nodes", i, "front_id", node.front_id.native() as _, nodes, Some(leaves))?;
+            check_child("nodes", i, "back_id", node.back_id.native() as _, nodes, Some(leaves))?;
+            check_range(
+                "nodes",
+                i,
+                "face_id",
+                node.face_id.native() as _,
+                node.face_len.native() as _,
+                faces,
+            )?;
+        }
+
+        for (i, node) in self.raw_clipnodes().iter().enumerate() {
+            check("clipnodes", i, "plane_id", node.plane_id.native() as _, planes)?;
+            check_child("clipnodes", i, "front_id", node.front_id.native() as _, clipnodes, None)?;
+            check_child("clipnodes", i, "back_id", node.back_id.native() as _, clipnodes, None)?;
+        }
+
+        for (i, texinfo) in self.raw_texinfo().iter().enumerate() {
+            check("texinfo", i, "texture", texinfo.texture.native() as _, textures)?;
+        }
+
+        for (i, leaf) in self.raw_leaves().iter().enumerate() {
+            check_range(
+                "leaves",
+                i,
+                "face_index_id",
+                leaf.face_index_id.native() as _,
+                leaf.face_index_len.native() as _,
+                lfaces,
+            )?;
+
+            let vis_index = leaf.vis_index.native();
+            if vis_index >= 0 {
+                check("leaves", i, "vis_index", vis_index as _, self.raw_vislist().len())?;
+            }
+        }
+
+        for (i, face) in self.face_indices().iter().enumerate() {
+            check("lfaces", i, "face", face.0.native() as _, faces)?;
+        }
+
+        for (i, face) in self.raw_faces().iter().enumerate() {
+            check("faces", i, "plane_id", face.plane_id.native() as _, planes)?;
+            check("faces", i, "texinfo_id", face.texinfo_id.native() as _, texinfo)?;
+
+            // Anything less isn't a polygon, and has no extents to light or draw
+            if face.ledge_len.native() < 3 {
+                return Err(Error::BadCount {
+                    lump: "faces",
+                    record: i,
+                    field: "ledge_len",
+                    count: face.ledge_len.native() as _,
+                    min: 3,
+                });
+            }
+
+            check_range(
+                "faces",
+                i,
+                "ledge_id",
+                face.ledge_id.native() as _,
+                face.ledge_len.native() as _,
+                ledges,
+            )?;
+        }
+
+        for (i, edge) in self.edge_indices().iter().enumerate() {
+            check("ledges", i, "edge", (edge.0.native() as i64).abs(), edges)?;
+        }
+
+        for (i, edge) in self.raw_edges().iter().enumerate() {
+            check("edges", i, "start", edge.start.native() as _, vertices)?;
+            check("edges", i, "end", edge.end.native() as _, vertices)?;
+        }
+
+        for (i, model) in self.raw_models().iter().enumerate() {
+            check_child(
+                "models",
+                i,
+                "hulls[0]",
+                model.hulls[0].native() as _,
+                nodes,
+                Some(leaves),
+            )?;
+            if clipnodes > 0 {
+                for (hull, field) in ["hulls[1]", "hulls[2]", "hulls[3]"].iter().enumerate() {
+                    let root = model.hulls[hull + 1].native();
+
+                    check_child("models", i, field, root as _, clipnodes, None)?;
+                }
+            }
+            check_range(
+                "models",
+                i,
+                "face_id",
+                model.face_id.native() as _,
+                model.face_len.native() as _,
+                faces,
+            )?;
+        }
+
+        if self.raw_models().is_empty() {
+            return Err(Error::Truncated {
+                lump: "models",
+                expected: mem::size_of::<sys::Model>(),
+                actual: 0,
+            });
+        }
+
+        // Vis rows and portals are sized by the world's leaf count, which excludes leaf 0, so
+        // it must leave room for that and cover every leaf the world's tree can reach
+        let world = &self.raw_models()[0];
+        let num_leaves = world.numleafs.native() as i64;
+        check("models", 0, "numleafs", num_leaves, leaves)?;
+
+        let mut seen = vec![false; nodes];
+        let mut stack = vec![world.hulls[0].native() as i64];
+
+        while let Some(child) = stack.pop() {
+            if child < 0 {
+                let leaf = -child - 1;
+
+                if leaf > num_leaves {
+                    return Err(Error::BadCount {
+                        lump: "models",
+                        record: 0,
+                        field: "numleafs",
+                        count: num_leaves,
+                        min: leaf,
+                    });
+                }
+            } else if !seen[child as usize] {
+                seen[child as usize] = true;
+
+                let node = &self.raw_branches()[child as usize];
+                stack.push(node.front_id.native() as _);
+                stack.push(node.back_id.native() as _);
+            }
+        }
+
+        Ok(())
+    }
+
+    unsafe fn slice_from_header<T, U: UnifiesWith<T>>(&self, header: &sys::Entry<U>) -> &[T] {
         self.slice_ref(
             header.offset.native() as _,
             (header.len.native() as usize) / mem::size_of::<T>(),
@@ -141,7 +587,7 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
     }
 
     #[inline(always)]
-    unsafe fn slice_ref<'b, T>(&'b self, offset: usize, count: usize) -> &'b [T] {
+    unsafe fn slice_ref<T>(&self, offset: usize, count: usize) -> &[T] {
         debug_assert!(
             offset
                 .checked_add(count)
@@ -149,50 +595,84 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
                 .unwrap_or(false)
         );
 
-        slice::from_raw_parts(self.0.as_ptr().offset(offset as _) as _, count)
+        slice::from_raw_parts(self.0.as_ptr().add(offset) as _, count)
     }
 
     #[inline(always)]
-    unsafe fn value_ref<'b, T>(&'b self, offset: usize) -> &'b T {
-        &*(self.0.as_ptr().offset(offset as _) as *const T)
+    unsafe fn value_ref<T>(&self, offset: usize) -> &T {
+        &*(self.0.as_ptr().add(offset) as *const T)
     }
 
     fn header(&self) -> &sys::Header<V::Magic, V::Lump> {
         unsafe { self.value_ref(0) }
     }
 
-    fn faces(&self) -> &[sys::Face] {
+    fn raw_faces(&self) -> &[sys::Face] {
         unsafe { self.slice_from_header(&self.header().lumps.faces) }
     }
 
-    fn edges(&self) -> &[sys::Edge] {
+    fn raw_edges(&self) -> &[sys::Edge] {
         unsafe { self.slice_from_header(&self.header().lumps.edges) }
     }
 
-    fn vertices(&self) -> &[sys::Scalar3] {
+    fn raw_vertices(&self) -> &[sys::Scalar3] {
         unsafe { self.slice_from_header(&self.header().lumps.vertices) }
     }
 
-    fn planes(&self) -> &[sys::Plane] {
+    fn raw_planes(&self) -> &[sys::Plane] {
         unsafe { self.slice_from_header(&self.header().lumps.planes) }
     }
 
-    fn models(&self) -> &[sys::Model] {
+    fn raw_models(&self) -> &[sys::Model] {
         unsafe { self.slice_from_header(&self.header().lumps.models) }
     }
 
-    fn branches(&self) -> &[sys::Node] {
+    fn raw_branches(&self) -> &[sys::Node] {
         unsafe { self.slice_from_header(&self.header().lumps.nodes) }
     }
 
-    fn leaves(&self) -> &[sys::Leaf] {
+    fn raw_leaves(&self) -> &[sys::Leaf] {
         unsafe { self.slice_from_header(&self.header().lumps.leaves) }
     }
 
-    fn vislist(&self) -> &[u8] {
+    fn raw_vislist(&self) -> &[u8] {
         unsafe { self.slice_from_header(&self.header().lumps.vislist) }
     }
 
+    fn raw_lightmaps(&self) -> &[u8] {
+        unsafe { self.slice_from_header(&self.header().lumps.lightmaps.clone().transmute::<u8>()) }
+    }
+
+    // GoldSrc is the only version with a palette after each embedded texture
+    fn has_texture_palettes(&self) -> bool {
+        self.header().version.native() == Goldsrc::VERSION
+    }
+
+    fn miptex_offset(&self) -> usize {
+        self.header().lumps.miptex.offset.native() as usize
+    }
+
+    fn raw_textures(&self) -> &[MipRef] {
+        let entry = &self.header().lumps.miptex;
+
+        if entry.len.native() < 4 {
+            return &[];
+        }
+
+        unsafe {
+            let count: &Little<i32> = self.value_ref(entry.offset.native() as usize);
+            self.slice_ref(entry.offset.native() as usize + 4, count.native() as usize)
+        }
+    }
+
+    fn raw_texinfo(&self) -> &[sys::Surface] {
+        unsafe { self.slice_from_header(&self.header().lumps.texinfo) }
+    }
+
+    fn raw_clipnodes(&self) -> &[sys::ClipNode] {
+        unsafe { self.slice_from_header(&self.header().lumps.clipnodes) }
+    }
+
     fn face_indices(&self) -> &[FaceRef] {
         unsafe { self.slice_from_header(&self.header().lumps.lfaces) }
     }
@@ -201,20 +681,83 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
         unsafe { self.slice_from_header(&self.header().lumps.ledges) }
     }
 
-    pub fn leaf(&self, index: usize) -> Option<Leaf<V>> {
-        let leaf: Leaf<V> = FromBsp::from_bsp(self, &self.leaves()[index]);
+    pub fn models(&self) -> Lump<'_, V, sys::Model, Model<'_, V>> {
+        Lump::new(self, self.raw_models())
+    }
+
+    pub fn faces(&self) -> Lump<'_, V, sys::Face, Face<'_, V>> {
+        Lump::new(self, self.raw_faces())
+    }
+
+    /// All leaves, including the shared solid leaf 0 that `leaf` hides.
+    pub fn leaves(&self) -> Lump<'_, V, sys::Leaf, Leaf<'_, V>> {
+        Lump::new(self, self.raw_leaves())
+    }
+
+    pub fn branches(&self) -> Lump<'_, V, sys::Node, Branch<'_, V>> {
+        Lump::new(self, self.raw_branches())
+    }
+
+    pub fn planes(&self) -> Lump<'_, V, sys::Plane, Plane> {
+        Lump::new(self, self.raw_planes())
+    }
+
+    pub fn edges(&self) -> Lump<'_, V, sys::Edge, Edge<'_, V>> {
+        Lump::new(self, self.raw_edges())
+    }
+
+    pub fn vertices(&self) -> Lump<'_, V, sys::Scalar3, Vec3<f32>> {
+        Lump::new(self, self.raw_vertices())
+    }
+
+    pub fn texinfo(&self) -> Lump<'_, V, sys::Surface, TexInfo<'_, V>> {
+        Lump::new(self, self.raw_texinfo())
+    }
+
+    /// Parse the entity lump. Entity 0 is always `worldspawn`.
+    pub fn entities(&self) -> Vec<Entity<'_>> {
+        entities::parse(self.entity_data())
+    }
+
+    /// The raw text of the entity lump.
+    pub fn entity_data(&self) -> &[u8] {
+        unsafe { self.slice_from_header(&self.header().lumps.entities.clone().transmute::<u8>()) }
+    }
+
+    /// The miptex lump, with `None` for texture slots that are left empty.
+    pub fn textures(&self) -> Lump<'_, V, MipRef, Option<Texture<'_, V>>> {
+        Lump::new(self, self.raw_textures())
+    }
+
+    /// The raw lighting lump, which `Face::lightmaps` slices up.
+    pub fn lightmaps(&self) -> &[u8] {
+        self.raw_lightmaps()
+    }
+
+    /// Bytes per lightmap sample: 1 for Quake's greyscale lighting, 3 for GoldSrc's RGB.
+    pub fn lightmap_channels(&self) -> usize {
+        if self.header().version.native() == Goldsrc::VERSION { 3 } else { 1 }
+    }
+
+    /// The compressed potentially-visible-set data that `Leaf::visible_leaves` decodes.
+    pub fn vislist(&self) -> &[u8] {
+        self.raw_vislist()
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<Leaf<'_, V>> {
+        let leaf: Leaf<V> = FromBsp::from_bsp(self, self.raw_leaves().get(index)?);
         if leaf.is_invalid() { None } else { Some(leaf) }
     }
 
-    pub fn branch(&self, index: usize) -> Branch<V> {
-        FromBsp::from_bsp(self, &self.branches()[index])
+    pub fn branch(&self, index: usize) -> Branch<'_, V> {
+        FromBsp::from_bsp(self, &self.raw_branches()[index])
     }
 
     pub fn plane(&self, index: usize) -> Plane {
-        FromBsp::from_bsp(self, &self.planes()[index])
+        FromBsp::from_bsp(self, &self.raw_planes()[index])
     }
 
-    fn node(&self, id_with_flag: i32) -> Option<Node<V>> {
+    fn node(&self, id_with_flag: i32) -> Option<Node<'_, V>> {
         let is_leaf = id_with_flag < 0;
         let id: u16 = if is_leaf {
             (-id_with_flag - 1) as _
@@ -229,11 +772,67 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Bsp<'a, V> {
         }
     }
 
-    pub fn map_model(&self) -> Model<V> {
-        Model::from_bsp(self, &self.models()[0])
+    pub fn map_model(&self) -> Model<'_, V> {
+        Model::from_bsp(self, &self.raw_models()[0])
+    }
+
+    /// Model 0 is the world, the rest are brush entities, referred to as `"*N"` in their
+    /// entity's `model` key.
+    pub fn model(&self, index: usize) -> Option<Model<'_, V>> {
+        self.models().get(index)
     }
 
-    pub fn root(&self) -> Option<Node<V>> {
+    pub fn clipnodes(&self) -> Lump<'_, V, sys::ClipNode, ClipNode<'_, V>> {
+        Lump::new(self, self.raw_clipnodes())
+    }
+
+    pub fn root(&self) -> Option<Node<'_, V>> {
         self.map_model().root()
     }
+
+    /// The world leaf containing `position`, or `None` if it's in solid space.
+    pub fn leaf_at(&self, position: Vec3<f32>) -> Option<Leaf<'_, V>> {
+        self.map_model().leaf_at(&position)
+    }
+
+    /// The contents of the world model at `position`. Solid leaves (which `traverse` reports as
+    /// `None`) come back as `LeafType::Solid`.
+    pub fn point_contents(&self, position: Vec3<f32>) -> LeafType {
+        self.map_model().point_contents(&position)
+    }
+
+    /// Like `point_contents`, but also checks brush entities. Each item of `models` is the index of
+    /// a submodel (the `N` in an entity's `"model" "*N"`) along with the entity's current origin.
+    ///
+    /// Solid world geometry always wins, otherwise the first submodel that isn't empty decides the
+    /// contents, which is how a `func_water` makes the point it covers count as water.
+    pub fn point_contents_with_models<I>(&self, position: Vec3<f32>, models: I) -> LeafType
+    where
+        I: IntoIterator<Item = (usize, Vec3<f32>)>,
+    {
+        let world = self.point_contents(position);
+
+        if world == LeafType::Solid {
+            return world;
+        }
+
+        for (index, origin) in models {
+            let model = match self.model(index) {
+                Some(model) => model,
+                None => continue,
+            };
+            let local = position - origin;
+
+            if !model.contains(&local) {
+                continue;
+            }
+
+            match model.point_contents(&local) {
+                LeafType::Ordinary => {}
+                contents => return contents,
+            }
+        }
+
+        world
+    }
 }
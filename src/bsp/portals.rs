@@ -0,0 +1,526 @@
+//! The portals between neighbouring leaves, the input to `vis`
+//!
+//! These are rebuilt from the node tree the same way `qbsp` makes them: the world is boxed in by
+//! six portals to the outside, then each node gets a portal covering its plane, clipped to the
+//! portals around it, and the portals around it are split between its children. Once every node
+//! is done each portal separates two leaves.
+
+use std::io::Write;
+use std::mem;
+
+use ioendian::IntoNativeEndian;
+
+use bsp::quake1::{LeafType, Plane, PlaneType};
+use bsp::{Bsp, Error, MapVersion, Quake1Lump, Vec3};
+
+/// How far a point can be from a plane and still count as lying on it
+pub(crate) const ON_EPSILON: f64 = 0.05;
+/// How far the outside portals are from the world, as in `qbsp`
+const SIDESPACE: f64 = 24.;
+/// Half the size of the square a node's portal starts as, before it's clipped
+const BOGUS_RANGE: f64 = 65536.;
+
+pub(crate) type Point = Vec3<f64>;
+
+#[derive(Debug, Clone)]
+pub struct Portal {
+    /// The leaves in front of and behind `plane`, as indices into `Bsp::leaves`
+    pub leaves: (usize, usize),
+    pub plane: Plane,
+    pub winding: Vec<Vec3<f32>>,
+}
+
+/// The portals between the open leaves of the world.
+#[derive(Debug, Clone)]
+pub struct Portals {
+    /// The number of leaves in the world, not counting the shared solid leaf 0
+    pub num_leaves: usize,
+    /// Every portal where neither side is solid, including those between different liquids
+    pub portals: Vec<Portal>,
+    contents: Vec<LeafType>,
+}
+
+/// A plane in double precision, which the portals are built with
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Split {
+    pub(crate) normal: Point,
+    pub(crate) distance: f64,
+}
+
+impl Split {
+    pub(crate) fn distance_to(&self, point: &Point) -> f64 {
+        dot(&self.normal, point) - self.distance
+    }
+
+    pub(crate) fn flip(&self) -> Self {
+        Split {
+            normal: -self.normal,
+            distance: -self.distance,
+        }
+    }
+}
+
+pub(crate) enum Kind {
+    Branch { split: Split, children: [usize; 2] },
+    /// Every solid leaf of the tree is leaf 0, so several nodes can share a leaf
+    Leaf(usize),
+    Outside,
+}
+
+pub(crate) struct TreeNode {
+    pub(crate) kind: Kind,
+    pub(crate) portals: Vec<usize>,
+}
+
+pub(crate) struct Building {
+    pub(crate) split: Split,
+    pub(crate) winding: Vec<Point>,
+    /// The nodes in front of and behind `split`
+    pub(crate) nodes: [usize; 2],
+    /// The node whose plane the portal lies on, `None` for the portals to the outside
+    pub(crate) on_node: Option<usize>,
+}
+
+pub(crate) fn dot(a: &Point, b: &Point) -> f64 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub(crate) fn cross(a: &Point, b: &Point) -> Point {
+    Vec3 {
+        x: a.y * b.z - a.z * b.y,
+        y: a.z * b.x - a.x * b.z,
+        z: a.x * b.y - a.y * b.x,
+    }
+}
+
+pub(crate) fn normalize(v: Point) -> Point {
+    let length = dot(&v, &v).sqrt();
+
+    if length > 0. {
+        v * (1. / length)
+    } else {
+        v
+    }
+}
+
+/// A huge square on the plane, wound the same way as `qbsp`'s.
+pub(crate) fn base_winding(split: &Split) -> Vec<Point> {
+    let n = split.normal;
+    let up = if n.x.abs() >= n.z.abs() || n.y.abs() >= n.z.abs() {
+        Vec3 { x: 0., y: 0., z: 1. }
+    } else {
+        Vec3 { x: 1., y: 0., z: 0. }
+    };
+    let up = normalize(up - n * dot(&up, &n)) * BOGUS_RANGE;
+    let right = cross(&up, &n);
+    let origin = n * split.distance;
+
+    vec![
+        origin - right + up,
+        origin + right + up,
+        origin + right - up,
+        origin - right - up,
+    ]
+}
+
+/// Cut a winding in two along a plane. A winding lying on the plane goes behind it, and either
+/// half is empty when the winding is entirely on the other side.
+pub(crate) fn divide(winding: &[Point], split: &Split) -> (Vec<Point>, Vec<Point>) {
+    divide_within(winding, split, ON_EPSILON)
+}
+
+/// Like `divide`, but counting points within `epsilon` of the plane as lying on it.
+pub(crate) fn divide_within(
+    winding: &[Point],
+    split: &Split,
+    epsilon: f64,
+) -> (Vec<Point>, Vec<Point>) {
+    let distances = winding
+        .iter()
+        .map(|p| split.distance_to(p))
+        .collect::<Vec<_>>();
+
+    if !distances.iter().any(|&d| d > epsilon) {
+        return (Vec::new(), winding.to_vec());
+    }
+    if !distances.iter().any(|&d| d < -epsilon) {
+        return (winding.to_vec(), Vec::new());
+    }
+
+    let (mut front, mut back) = (Vec::new(), Vec::new());
+
+    for (i, &p) in winding.iter().enumerate() {
+        let d = distances[i];
+        let next_d = distances[(i + 1) % winding.len()];
+
+        if d.abs() <= epsilon {
+            front.push(p);
+            back.push(p);
+            continue;
+        }
+
+        if d > 0. {
+            front.push(p);
+        } else {
+            back.push(p);
+        }
+
+        if next_d.abs() <= epsilon || (next_d > 0.) == (d > 0.) {
+            continue;
+        }
+
+        let next = winding[(i + 1) % winding.len()];
+        let mut mid = p + (next - p) * (d / (d - next_d));
+
+        // Axial planes put the new point exactly on them, to avoid drift
+        let n = split.normal;
+        for (m, n) in [(&mut mid.x, n.x), (&mut mid.y, n.y), (&mut mid.z, n.z)] {
+            if n == 1. {
+                *m = split.distance;
+            } else if n == -1. {
+                *m = -split.distance;
+            }
+        }
+
+        front.push(mid);
+        back.push(mid);
+    }
+
+    let valid = |w: Vec<Point>| if w.len() < 3 { Vec::new() } else { w };
+
+    (valid(front), valid(back))
+}
+
+pub(crate) struct Builder {
+    pub(crate) nodes: Vec<TreeNode>,
+    pub(crate) portals: Vec<Building>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Builder {
+            nodes: Vec::new(),
+            portals: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_node(&mut self, kind: Kind) -> usize {
+        self.nodes.push(TreeNode {
+            kind,
+            portals: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_portal(
+        &mut self,
+        split: Split,
+        winding: Vec<Point>,
+        nodes: [usize; 2],
+        on_node: Option<usize>,
+    ) {
+        let id = self.portals.len();
+
+        self.portals.push(Building {
+            split,
+            winding,
+            nodes,
+            on_node,
+        });
+        self.link(id, nodes);
+    }
+
+    fn link(&mut self, portal: usize, nodes: [usize; 2]) {
+        self.portals[portal].nodes = nodes;
+        self.nodes[nodes[0]].portals.push(portal);
+        self.nodes[nodes[1]].portals.push(portal);
+    }
+
+    /// Copy the world's node tree, giving every solid leaf a node of its own.
+    fn copy_tree<V>(&mut self, bsp: &Bsp<V>, id: i32) -> usize
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        if id < 0 {
+            return self.add_node(Kind::Leaf((-id - 1) as usize));
+        }
+
+        let raw = &bsp.raw_branches()[id as usize];
+        let plane = bsp.plane(raw.plane_id.native() as _);
+        let front = self.copy_tree(bsp, raw.front_id.native() as i32);
+        let back = self.copy_tree(bsp, raw.back_id.native() as i32);
+
+        self.add_node(Kind::Branch {
+            split: Split {
+                normal: Vec3 {
+                    x: plane.normal.x as f64,
+                    y: plane.normal.y as f64,
+                    z: plane.normal.z as f64,
+                },
+                distance: plane.distance as f64,
+            },
+            children: [front, back],
+        })
+    }
+
+    /// Box the whole tree in with six portals leading to the outside node.
+    pub(crate) fn head_portals(&mut self, head: usize, outside: usize, mins: Point, maxs: Point) {
+        let axes = [
+            (Vec3 { x: 1., y: 0., z: 0. }, mins.x, maxs.x),
+            (Vec3 { x: 0., y: 1., z: 0. }, mins.y, maxs.y),
+            (Vec3 { x: 0., y: 0., z: 1. }, mins.z, maxs.z),
+        ];
+        let mut splits = Vec::new();
+
+        // Each faces in, so the tree is in front of them
+        for &(normal, min, max) in &axes {
+            splits.push(Split {
+                normal,
+                distance: min - SIDESPACE,
+            });
+            splits.push(Split {
+                normal: -normal,
+                distance: -(max + SIDESPACE),
+            });
+        }
+
+        for (i, split) in splits.iter().enumerate() {
+            let mut winding = base_winding(split);
+
+            for (j, other) in splits.iter().enumerate() {
+                if i != j {
+                    winding = divide(&winding, other).0;
+                }
+            }
+
+            self.add_portal(*split, winding, [head, outside], None);
+        }
+    }
+
+    pub(crate) fn make_tree_portals(&mut self, node: usize) {
+        let (split, children) = match self.nodes[node].kind {
+            Kind::Branch { split, children } => (split, children),
+            _ => return,
+        };
+
+        self.make_node_portal(node, split, children);
+        self.split_node_portals(node, split, children);
+        self.make_tree_portals(children[0]);
+        self.make_tree_portals(children[1]);
+    }
+
+    /// Create the portal on the node's plane, cut down to the space the node covers.
+    fn make_node_portal(&mut self, node: usize, split: Split, children: [usize; 2]) {
+        let mut winding = base_winding(&split);
+
+        for &p in &self.nodes[node].portals {
+            let portal = &self.portals[p];
+            let inside = if portal.nodes[0] == node {
+                portal.split
+            } else {
+                portal.split.flip()
+            };
+
+            winding = divide(&winding, &inside).0;
+
+            if winding.is_empty() {
+                return;
+            }
+        }
+
+        self.add_portal(split, winding, children, Some(node));
+    }
+
+    /// Hand the portals around the node down to whichever of its children they touch.
+    fn split_node_portals(&mut self, node: usize, split: Split, children: [usize; 2]) {
+        for p in mem::take(&mut self.nodes[node].portals) {
+            let side = if self.portals[p].nodes[0] == node { 0 } else { 1 };
+            let other = self.portals[p].nodes[1 - side];
+
+            self.nodes[other].portals.retain(|&o| o != p);
+
+            // Keeps the node on the same side of the portal's plane as before
+            let place = |child: usize| {
+                if side == 0 {
+                    [child, other]
+                } else {
+                    [other, child]
+                }
+            };
+
+            let (front, back) = divide(&self.portals[p].winding, &split);
+
+            if front.is_empty() {
+                self.link(p, place(children[1]));
+            } else if back.is_empty() {
+                self.link(p, place(children[0]));
+            } else {
+                let (portal_split, on_node) = (self.portals[p].split, self.portals[p].on_node);
+
+                self.portals[p].winding = front;
+                self.link(p, place(children[0]));
+                self.add_portal(portal_split, back, place(children[1]), on_node);
+            }
+        }
+    }
+}
+
+fn to_f32(p: &Point) -> Vec3<f32> {
+    Vec3 {
+        x: p.x as f32,
+        y: p.y as f32,
+        z: p.z as f32,
+    }
+}
+
+/// `qbsp` writes whole numbers without a fraction so that they read back exactly.
+fn format_coordinate(v: f32) -> String {
+    if (v - v.round()).abs() < 0.001 {
+        format!("{}", v.round() as i64)
+    } else {
+        format!("{:.6}", v)
+    }
+}
+
+impl Portals {
+    /// The portals `vis` looks through, those between leaves with the same contents. Liquid
+    /// surfaces block the view.
+    pub fn visible(&self) -> impl Iterator<Item = &Portal> {
+        self.portals
+            .iter()
+            .filter(move |p| self.contents[p.leaves.0] == self.contents[p.leaves.1])
+    }
+
+    /// Write the portals that `vis` sees through in the `PRT1` format used by `vis` and level
+    /// editors' portal viewers: only portals between leaves with the same contents, with leaf
+    /// numbers not counting leaf 0.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let visible = self.visible().collect::<Vec<_>>();
+
+        write!(out, "PRT1\n{}\n{}\n", self.num_leaves, visible.len())?;
+
+        for portal in visible {
+            let w = &portal.winding;
+            let corner = |i: usize| {
+                let (a, b, c) = (w[i], w[(i + 1) % w.len()], w[(i + 2) % w.len()]);
+                cross(
+                    &Vec3 {
+                        x: (a.x - b.x) as f64,
+                        y: (a.y - b.y) as f64,
+                        z: (a.z - b.z) as f64,
+                    },
+                    &Vec3 {
+                        x: (c.x - b.x) as f64,
+                        y: (c.y - b.y) as f64,
+                        z: (c.z - b.z) as f64,
+                    },
+                )
+            };
+
+            // Tools take the plane from the first three points, so start at the corner that
+            // gives the most reliable one
+            let start = (0..w.len())
+                .max_by(|&a, &b| {
+                    let (a, b) = (corner(a), corner(b));
+
+                    dot(&a, &a)
+                        .partial_cmp(&dot(&b, &b))
+                        .unwrap_or(::std::cmp::Ordering::Equal)
+                })
+                .unwrap_or(0);
+            let wound = normalize(corner(start));
+            let normal = Vec3 {
+                x: portal.plane.normal.x as f64,
+                y: portal.plane.normal.y as f64,
+                z: portal.plane.normal.z as f64,
+            };
+
+            // The first leaf is the one the winding's plane faces, which can be the back one
+            let (a, b) = if dot(&normal, &wound) < 0.99 {
+                (portal.leaves.1, portal.leaves.0)
+            } else {
+                portal.leaves
+            };
+
+            write!(out, "{} {} {} ", w.len(), a - 1, b - 1)?;
+
+            for i in 0..w.len() {
+                let p = w[(start + i) % w.len()];
+
+                write!(
+                    out,
+                    "({} {} {} ) ",
+                    format_coordinate(p.x),
+                    format_coordinate(p.y),
+                    format_coordinate(p.z)
+                )?;
+            }
+
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump>> Bsp<'a, V> {
+    /// Build the portals between the world's leaves from its node tree.
+    pub fn portals(&self) -> Portals {
+        let world = self.map_model();
+        let contents = self
+            .leaves()
+            .iter()
+            .map(|(_, leaf)| leaf.leaf_type())
+            .collect::<Vec<_>>();
+        let num_leaves = self.raw_models()[0].numleafs.native() as usize;
+
+        let mut builder = Builder::new();
+        let head = builder.copy_tree(self, self.raw_models()[0].hulls[0].native());
+        let outside = builder.add_node(Kind::Outside);
+        let bounds = world.bounds();
+        let widen = |v: Vec3<f32>| Vec3 {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+        };
+
+        builder.head_portals(head, outside, widen(bounds.aa), widen(bounds.bb));
+        builder.make_tree_portals(head);
+
+        let open = |node: usize| match builder.nodes[node].kind {
+            Kind::Leaf(leaf) if leaf != 0 && contents[leaf] != LeafType::Solid => Some(leaf),
+            _ => None,
+        };
+
+        let portals = builder
+            .portals
+            .iter()
+            .filter_map(|portal| {
+                let leaves = (open(portal.nodes[0])?, open(portal.nodes[1])?);
+                let normal = to_f32(&portal.split.normal);
+
+                Some(Portal {
+                    leaves,
+                    plane: Plane {
+                        plane_type: PlaneType::from_normal(&normal),
+                        normal,
+                        distance: portal.split.distance as f32,
+                    },
+                    winding: portal.winding.iter().map(to_f32).collect(),
+                })
+            })
+            .collect();
+
+        Portals {
+            num_leaves,
+            portals,
+            contents,
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::marker::PhantomData;
 
 use bsp::{Bsp, ValueIter, FromBsp, BoundingBox, Vec3};
+use bsp::hull::{Hull, Trace, Transform};
 use bsp::mapversions::MapVersion;
 
 use ioendian::{Little, IntoNativeEndian};
@@ -9,6 +11,8 @@ use ioendian::{Little, IntoNativeEndian};
 use sys::bsp as sys;
 use sys::bsp::Scalar3;
 
+use wad::MipTex;
+
 #[cfg(feature = "nightly")]
 pub struct VisibilityIterator<'a, V: 'a> {
     bsp: &'a Bsp<'a, V>,
@@ -67,27 +71,34 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Iterator for VisibilityIterator<
             } else if self.other_index > self.num_leaves {
                 break None;
             } else if let Some(bit) = self.bit {
-                if get(bit) >= 8 {
+                // `bit` counts from 1, so that it can be non-zero
+                if get(bit) > 8 {
                     self.bit = None;
                     self.index += 1;
                 } else {
                     let other_index = self.other_index;
                     self.other_index += 1;
 
-                    let mask = 2 << get(bit);
+                    let mask = 1 << (get(bit) - 1);
                     self.bit = nonzero(get(bit) + 1);
 
+                    // A corrupt vislist can claim that a solid leaf is visible, we just skip it
                     if self.vis_list[self.index as usize] & mask != 0 {
-                        break Some(self.bsp.leaf(other_index as _).expect(
-                            "Leaf can see invalid leaf",
-                        ));
+                        if let Some(leaf) = self.bsp.leaf(other_index as _) {
+                            break Some(leaf);
+                        }
                     }
                 }
-            } else if self.vis_list[self.index as usize] == 0 {
-                self.other_index += 8 * self.vis_list[self.index as usize + 1] as usize;
-                self.index += 2;
             } else {
-                self.bit = nonzero(1u8);
+                match self.vis_list.get(self.index as usize) {
+                    None => break None,
+                    Some(&0) => {
+                        let skip = self.vis_list.get(self.index as usize + 1).cloned();
+                        self.other_index += 8 * skip.unwrap_or(0) as usize;
+                        self.index += 2;
+                    }
+                    Some(_) => self.bit = nonzero(1u8),
+                }
             }
         }
     }
@@ -96,14 +107,85 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Iterator for VisibilityIterator<
 pub struct Branch<'a, V: 'a>(&'a sys::Node, &'a Bsp<'a, V>);
 pub struct Leaf<'a, V: 'a>(&'a sys::Leaf, &'a Bsp<'a, V>);
 
-#[repr(i8)]
-#[derive(Debug)]
+/// The contents of a leaf. Quake 1 only uses `Ordinary` through `Sky`, the rest are GoldSrc
+/// additions (most of which only ever appear in clipnodes or brush entities). Anything else, such
+/// as Quake 2's content bitflags or a mod's custom values, is kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LeafType {
-    Ordinary = -1,
-    Water = -3,
-    Slime = -4,
-    Lava = -5,
-    Sky = -6,
+    Ordinary,
+    Solid,
+    Water,
+    Slime,
+    Lava,
+    Sky,
+    Origin,
+    Clip,
+    Current0,
+    Current90,
+    Current180,
+    Current270,
+    CurrentUp,
+    CurrentDown,
+    Translucent,
+    Ladder,
+    FlyField,
+    GravityFlyField,
+    Fog,
+    Other(i32),
+}
+
+impl From<i32> for LeafType {
+    fn from(other: i32) -> Self {
+        match other {
+            -1 => LeafType::Ordinary,
+            -2 => LeafType::Solid,
+            -3 => LeafType::Water,
+            -4 => LeafType::Slime,
+            -5 => LeafType::Lava,
+            -6 => LeafType::Sky,
+            -7 => LeafType::Origin,
+            -8 => LeafType::Clip,
+            -9 => LeafType::Current0,
+            -10 => LeafType::Current90,
+            -11 => LeafType::Current180,
+            -12 => LeafType::Current270,
+            -13 => LeafType::CurrentUp,
+            -14 => LeafType::CurrentDown,
+            -15 => LeafType::Translucent,
+            -16 => LeafType::Ladder,
+            -17 => LeafType::FlyField,
+            -18 => LeafType::GravityFlyField,
+            -19 => LeafType::Fog,
+            other => LeafType::Other(other),
+        }
+    }
+}
+
+impl From<LeafType> for i32 {
+    fn from(other: LeafType) -> Self {
+        match other {
+            LeafType::Ordinary => -1,
+            LeafType::Solid => -2,
+            LeafType::Water => -3,
+            LeafType::Slime => -4,
+            LeafType::Lava => -5,
+            LeafType::Sky => -6,
+            LeafType::Origin => -7,
+            LeafType::Clip => -8,
+            LeafType::Current0 => -9,
+            LeafType::Current90 => -10,
+            LeafType::Current180 => -11,
+            LeafType::Current270 => -12,
+            LeafType::CurrentUp => -13,
+            LeafType::CurrentDown => -14,
+            LeafType::Translucent => -15,
+            LeafType::Ladder => -16,
+            LeafType::FlyField => -17,
+            LeafType::GravityFlyField => -18,
+            LeafType::Fog => -19,
+            LeafType::Other(other) => other,
+        }
+    }
 }
 
 impl<'a, V: 'a> Clone for Branch<'a, V> {
@@ -125,6 +207,21 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Branch<'a, V> {
         self.1.node(self.0.back_id.native() as _)
     }
 
+    pub fn face_id(&self) -> usize {
+        self.0.face_id.native() as _
+    }
+
+    pub fn face_len(&self) -> usize {
+        self.0.face_len.native() as _
+    }
+
+    /// The faces lying on this node's plane, `face_len` of them starting at `face_id`.
+    pub fn faces(&self) -> ValueIter<'a, V, sys::Face, Face<'a, V>> {
+        let start = self.face_id();
+        let end = start + self.face_len();
+        unsafe { ValueIter::new(self.1, &self.1.raw_faces()[start..end]) }
+    }
+
     pub fn bounds(&self) -> Bounds {
         let bounds = &self.0.bounds;
         let aa = &bounds.aa;
@@ -145,24 +242,22 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Branch<'a, V> {
     }
 
     pub fn traverse(&self, position: &Vec3<i16>) -> Option<Leaf<'a, V>> {
-        fn dot(a: &Vec3<f32>, b: &Vec3<f32>) -> f32 {
-            a.x * b.x + a.y * b.y + a.z * b.z
-        }
-
-        let mut node = Cow::Borrowed(self);
-        let fpos = Vec3 {
+        self.traverse_float(&Vec3 {
             x: position.x as _,
             y: position.y as _,
             z: position.z as _,
-        };
+        })
+    }
 
-        loop {
-            let plane = node.plane();
+    /// Find the leaf containing `position` without truncating it to whole units first. A point
+    /// lying exactly on a splitting plane always goes down the front side, matching the engine.
+    pub fn traverse_float(&self, position: &Vec3<f32>) -> Option<Leaf<'a, V>> {
+        let mut node = Cow::Borrowed(self);
 
-            let o_out = if dot(&plane.normal, &fpos) - plane.distance >= 0. {
-                node.front()
-            } else {
-                node.back()
+        loop {
+            let o_out = match node.plane().side(position) {
+                Side::Front => node.front(),
+                Side::Back => node.back(),
             };
 
             match o_out {
@@ -180,22 +275,21 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Branch<'a, V> {
 
 impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Leaf<'a, V> {
     pub fn leaf_type(&self) -> LeafType {
-        use std::mem;
-
-        let lty = self.0.leaf_type.native() as i8;
-        assert!(lty >= LeafType::Sky as i8 && lty <= LeafType::Ordinary as i8);
-        unsafe { mem::transmute(lty) }
+        self.0.leaf_type.native().into()
     }
 
     pub fn is_invalid(&self) -> bool {
-        const INVALID: i32 = -2;
-
-        self.0.leaf_type.native() == INVALID
+        self.leaf_type() == LeafType::Solid
     }
 
-    pub fn visible_leaves(&self) -> VisibilityIterator<V> {
-        let num_leaves = self.1.leaves().len();
-        let vis_list = self.1.vislist();
+    pub fn visible_leaves(&self) -> VisibilityIterator<'_, V> {
+        // Rows only cover the world's leaves, brush entities' leaves come after them
+        let num_leaves = self.1
+            .raw_models()
+            .first()
+            .map(|model| model.numleafs.native() as usize)
+            .unwrap_or_else(|| self.1.raw_leaves().len());
+        let vis_list = self.1.raw_vislist();
 
         let my_index = self.0.vis_index.native();
         let other_index = 1;
@@ -203,11 +297,11 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Leaf<'a, V> {
         VisibilityIterator {
             bsp: self.1,
             bit: None,
-            num_leaves: num_leaves,
-            vis_list: vis_list,
+            num_leaves,
+            vis_list,
             all: my_index < 0,
             index: my_index as _,
-            other_index: other_index,
+            other_index,
         }
     }
 
@@ -230,7 +324,7 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> Leaf<'a, V> {
         }
     }
 
-    pub fn faces(&self) -> ValueIter<V, FaceRef, Face<V>> {
+    pub fn faces(&self) -> ValueIter<'_, V, FaceRef, Face<'_, V>> {
         let start = self.0.face_index_id.native() as usize;
         let end = start + self.0.face_index_len.native() as usize;
         unsafe { ValueIter::new(self.1, &self.1.face_indices()[start..end]) }
@@ -259,20 +353,21 @@ impl<'a, V: 'a> Node<'a, V> {
 }
 
 #[derive(Copy, Clone, Debug)]
-pub struct EdgeRef(Little<i16>);
+pub struct EdgeRef(pub(crate) Little<i32>);
 #[derive(Copy, Clone, Debug)]
-pub struct FaceRef(Little<u16>);
+pub struct FaceRef(pub(crate) Little<u16>);
 
 impl sys::UnifiesWith<FaceRef> for Little<u16> {}
-impl sys::UnifiesWith<EdgeRef> for Little<i16> {}
+impl sys::UnifiesWith<EdgeRef> for Little<i32> {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
     Back,
     Front,
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlaneType {
     AxialX = 0,
     AxialY = 1,
@@ -282,37 +377,103 @@ pub enum PlaneType {
     NonAxialZ = 5,
 }
 
+impl TryFrom<i32> for PlaneType {
+    /// The unrecognised value
+    type Error = i32;
+
+    fn try_from(other: i32) -> Result<Self, i32> {
+        match other {
+            0 => Ok(PlaneType::AxialX),
+            1 => Ok(PlaneType::AxialY),
+            2 => Ok(PlaneType::AxialZ),
+            3 => Ok(PlaneType::NonAxialX),
+            4 => Ok(PlaneType::NonAxialY),
+            5 => Ok(PlaneType::NonAxialZ),
+            other => Err(other),
+        }
+    }
+}
+
+impl PlaneType {
+    /// Classify a plane the same way the compiler does, by the largest component of its normal.
+    pub fn from_normal(normal: &Vec3<f32>) -> Self {
+        if normal.x == 1. || normal.x == -1. {
+            return PlaneType::AxialX;
+        }
+        if normal.y == 1. || normal.y == -1. {
+            return PlaneType::AxialY;
+        }
+        if normal.z == 1. || normal.z == -1. {
+            return PlaneType::AxialZ;
+        }
+
+        let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+
+        if ax >= ay && ax >= az {
+            PlaneType::NonAxialX
+        } else if ay >= ax && ay >= az {
+            PlaneType::NonAxialY
+        } else {
+            PlaneType::NonAxialZ
+        }
+    }
+}
+
 pub type Bounds = BoundingBox<Vec3<i16>>;
 
 // TODO: Load this lazily from the BSP
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Plane {
     pub normal: Vec3<f32>,
     pub distance: f32,
     pub plane_type: PlaneType,
 }
 
+impl Plane {
+    /// Signed distance from the plane to `point`, positive on the front side. Axial planes only
+    /// look at the one component their normal has, like the engine does.
+    pub fn distance_to(&self, point: &Vec3<f32>) -> f32 {
+        // The normal is multiplied in rather than assumed to be 1 so that planes flipped by
+        // `Face::plane` still give the right answer.
+        match self.plane_type {
+            PlaneType::AxialX => self.normal.x * point.x - self.distance,
+            PlaneType::AxialY => self.normal.y * point.y - self.distance,
+            PlaneType::AxialZ => self.normal.z * point.z - self.distance,
+            _ => self.normal.dot(point) - self.distance,
+        }
+    }
+
+    /// Which side of the plane `point` is on. Points exactly on the plane count as in front.
+    pub fn side(&self, point: &Vec3<f32>) -> Side {
+        if self.distance_to(point) >= 0. {
+            Side::Front
+        } else {
+            Side::Back
+        }
+    }
+}
+
 impl<'a, V, Src: Clone + Into<Dst>, Dst> FromBsp<'a, Src, V> for Dst {
     fn from_bsp(_: &'a Bsp<'a, V>, from: &'a Src) -> Self {
         from.clone().into()
     }
 }
 
+/// Planes with an unrecognised type (from a corrupt file or another game's compiler) are
+/// reclassified from their normal rather than rejected.
 impl From<sys::Plane> for Plane {
     fn from(other: sys::Plane) -> Self {
-        use std::mem;
-
-        let plane_type = other.plane_type.native() as u8;
-        assert!(plane_type <= PlaneType::NonAxialZ as u8 && plane_type >= PlaneType::AxialX as u8);
+        let normal = Vec3 {
+            x: other.normal.x.native(),
+            y: other.normal.y.native(),
+            z: other.normal.z.native(),
+        };
 
         Plane {
-            normal: Vec3 {
-                x: other.normal.x.native(),
-                y: other.normal.y.native(),
-                z: other.normal.z.native(),
-            },
+            plane_type: PlaneType::try_from(other.plane_type.native())
+                .unwrap_or_else(|_| PlaneType::from_normal(&normal)),
+            normal,
             distance: other.dist.native(),
-            plane_type: unsafe { mem::transmute(plane_type) },
         }
     }
 }
@@ -348,7 +509,7 @@ impl<'a, V> FromBsp<'a, sys::Node, V> for Branch<'a, V> {
 
 impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> FromBsp<'a, sys::Edge, V> for Edge<'a, V> {
     fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a sys::Edge) -> Self {
-        let verts = bsp.vertices();
+        let verts = bsp.raw_vertices();
 
         Edge {
             start: &verts[from.start.native() as usize],
@@ -360,13 +521,25 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> FromBsp<'a, sys::Edge, V> for Ed
 
 impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> FromBsp<'a, FaceRef, V> for Face<'a, V> {
     fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a FaceRef) -> Self {
-        Self::from_bsp(bsp, &bsp.faces()[from.0.native() as usize])
+        Self::from_bsp(bsp, &bsp.raw_faces()[from.0.native() as usize])
     }
 }
 
+/// Negative edge references mean the edge is used backwards by this face.
 impl<'a, V: MapVersion<Lump = sys::Quake1Lump>> FromBsp<'a, EdgeRef, V> for Edge<'a, V> {
     fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a EdgeRef) -> Self {
-        Self::from_bsp(bsp, &bsp.edges()[from.0.native() as usize])
+        let index = from.0.native();
+        let edge = Self::from_bsp(bsp, &bsp.raw_edges()[index.unsigned_abs() as usize]);
+
+        if index < 0 {
+            Edge {
+                start: edge.end,
+                end: edge.start,
+                _phantom: PhantomData,
+            }
+        } else {
+            edge
+        }
     }
 }
 
@@ -396,12 +569,103 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Face<'a, V> {
         }
     }
 
-    pub fn edges(&self) -> ValueIter<V, EdgeRef, Edge<V>> {
+    pub fn texinfo(&self) -> TexInfo<'a, V> {
+        TexInfo(&self.1.raw_texinfo()[self.0.texinfo_id.native() as usize], self.1)
+    }
+
+    pub fn edges(&self) -> ValueIter<'_, V, EdgeRef, Edge<'_, V>> {
         let start = self.0.ledge_id.native() as usize;
         let end = start + self.0.ledge_len.native() as usize;
         unsafe { ValueIter::new(self.1, &self.1.edge_indices()[start..end]) }
     }
 
+    /// The corners of the face, in order.
+    pub fn vertices(&self) -> Vec<Vec3<f32>> {
+        self.edges().map(|edge| edge.start()).collect()
+    }
+
+    /// The lightstyle of each of the face's lightmaps, 255 marking unused slots. Style 0 is
+    /// normal, unchanging light.
+    pub fn styles(&self) -> [u8; 4] {
+        [
+            self.0.typelight.native(),
+            self.0.baselight.native(),
+            self.0.light[0].native(),
+            self.0.light[1].native(),
+        ]
+    }
+
+    /// Byte offset of the face's first lightmap in the lighting lump, or `None` if it's unlit.
+    pub fn lightmap_offset(&self) -> Option<usize> {
+        let offset = self.0.lightmap.native();
+
+        if offset < 0 { None } else { Some(offset as usize) }
+    }
+
+    /// The face's extents in texture space, rounded out to the 16-texel lightmap grid, as the
+    /// engine's `CalcSurfaceExtents`.
+    pub fn extents(&self) -> LightmapExtents {
+        let texinfo = self.texinfo();
+        let axes = [texinfo.s(), texinfo.t()];
+        let mut min = [f64::MAX; 2];
+        let mut max = [f64::MIN; 2];
+
+        for vertex in self.vertices() {
+            for (i, &(axis, offset)) in axes.iter().enumerate() {
+                // Summed in double precision, like the later engines, so faces lying right on a
+                // grid line don't gain an extra column
+                let value = vertex.x as f64 * axis.x as f64 + vertex.y as f64 * axis.y as f64 +
+                    vertex.z as f64 * axis.z as f64 + offset as f64;
+
+                min[i] = min[i].min(value);
+                max[i] = max[i].max(value);
+            }
+        }
+
+        let mut out = LightmapExtents::default();
+
+        // Worked out in floating point, whose casts saturate, so that a wild texture projection
+        // gives huge extents rather than overflowing
+        for i in 0..2 {
+            let lo = (min[i] / 16.).floor();
+            let hi = (max[i] / 16.).ceil();
+
+            out.texture_min[i] = (lo * 16.) as i32;
+            out.size[i] = ((hi - lo).max(0.) + 1.) as u32;
+        }
+
+        out
+    }
+
+    /// Each of the face's lightmaps with its style, `extents().size` samples of
+    /// `Bsp::lightmap_channels` bytes each. Empty for unlit faces, and for faces too big for the
+    /// engine to light.
+    pub fn lightmaps(&self) -> Vec<(u8, &'a [u8])> {
+        let offset = match self.lightmap_offset() {
+            Some(offset) => offset,
+            None => return vec![],
+        };
+        let extents = self.extents();
+
+        if !extents.is_valid() {
+            return vec![];
+        }
+
+        let len = extents.samples() * self.1.lightmap_channels();
+        let data = self.1.lightmaps();
+
+        self.styles()
+            .iter()
+            .take_while(|&&style| style != 255)
+            .enumerate()
+            .filter_map(|(i, &style)| {
+                let start = offset + i * len;
+
+                data.get(start..start + len).map(|map| (style, map))
+            })
+            .collect()
+    }
+
     #[cfg(feature = "nightly")]
     pub fn points(&self) -> impl Iterator<Item = Vec3<f32>> {
         // TODO: Some of these points are probably redundant
@@ -409,6 +673,152 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Face<'a, V> {
     }
 }
 
+/// Where a face's lightmap sits in texture space: its first sample lies at `texture_min`, and
+/// samples are 16 texels apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LightmapExtents {
+    pub texture_min: [i32; 2],
+    /// Width and height in samples
+    pub size: [u32; 2],
+}
+
+impl LightmapExtents {
+    /// The most texels the engine lets a lit face span on either axis before refusing the map
+    /// with "Bad surface extents".
+    pub const MAX_TEXELS: u32 = 256;
+
+    /// Whether the engine would accept a lit face this big.
+    pub fn is_valid(&self) -> bool {
+        self.size.iter().all(|&size| size <= Self::MAX_TEXELS / 16 + 1)
+    }
+
+    /// The number of samples in the lightmap.
+    pub fn samples(&self) -> usize {
+        self.size[0] as usize * self.size[1] as usize
+    }
+}
+
+/// How a texture is projected onto a face, `s` and `t` being the texture's horizontal and
+/// vertical axes (in texels per unit) and their offsets.
+pub struct TexInfo<'a, V: 'a>(&'a sys::Surface, &'a Bsp<'a, V>);
+
+impl<'a, V: 'a> FromBsp<'a, sys::Surface, V> for TexInfo<'a, V> {
+    fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a sys::Surface) -> Self {
+        TexInfo(from, bsp)
+    }
+}
+
+impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> TexInfo<'a, V> {
+    pub fn s(&self) -> (Vec3<f32>, f32) {
+        (self.0.s.vector.native(), self.0.s.distance.native())
+    }
+
+    pub fn t(&self) -> (Vec3<f32>, f32) {
+        (self.0.t.vector.native(), self.0.t.distance.native())
+    }
+
+    /// Index into the miptex lump
+    pub fn texture_id(&self) -> u32 {
+        self.0.texture.native()
+    }
+
+    /// The texture this projects, or `None` if its slot in the miptex lump is empty.
+    pub fn texture(&self) -> Option<Texture<'a, V>> {
+        self.1
+            .textures()
+            .get(self.texture_id() as usize)
+            .and_then(|texture| texture)
+    }
+
+    /// Whether this is a special (sky, liquid) surface that doesn't get a lightmap
+    pub fn is_special(&self) -> bool {
+        self.0.animated.native() & 1 != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MipRef(pub(crate) Little<i32>);
+
+impl sys::UnifiesWith<MipRef> for Little<i32> {}
+
+/// An entry of the miptex lump. GoldSrc maps usually only store the name and size here and leave
+/// the pixels to an external WAD, in which case `mip` returns `None`.
+pub struct Texture<'a, V: 'a> {
+    pub(crate) header: &'a sys::MipTexture,
+    offset: usize,
+    bsp: &'a Bsp<'a, V>,
+}
+
+impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> FromBsp<'a, MipRef, V>
+    for Option<Texture<'a, V>> {
+    fn from_bsp(bsp: &'a Bsp<'a, V>, from: &'a MipRef) -> Self {
+        let offset = from.0.native();
+
+        if offset < 0 {
+            None
+        } else {
+            let offset = bsp.miptex_offset() + offset as usize;
+
+            Some(Texture {
+                header: unsafe { bsp.value_ref(offset) },
+                offset,
+                bsp,
+            })
+        }
+    }
+}
+
+impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Texture<'a, V> {
+    pub fn name(&self) -> Cow<'a, str> {
+        let name: &'a [u8; 16] = unsafe { &*(&self.header.name as *const _ as *const [u8; 16]) };
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        String::from_utf8_lossy(&name[..len])
+    }
+
+    pub fn width(&self) -> u32 {
+        self.header.width.native()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.header.height.native()
+    }
+
+    /// Whether the pixels live in an external WAD rather than in the map itself.
+    pub fn is_external(&self) -> bool {
+        self.header.offsets.iter().all(|o| o.native() == 0)
+    }
+
+    /// The embedded texture as a `MipTex`, including the palette that GoldSrc stores after each
+    /// texture. Returns `None` for external textures or if the embedded data is truncated.
+    pub fn miptex(&self) -> Option<MipTex<'a>> {
+        if self.is_external() {
+            return None;
+        }
+
+        let lump = &self.bsp.header().lumps.miptex;
+        let lump_end = (lump.offset.native() + lump.len.native()) as usize;
+        let data = unsafe { self.bsp.slice_ref(self.offset, lump_end - self.offset) };
+
+        MipTex::parse(data, self.bsp.has_texture_palettes()).ok()
+    }
+
+    /// The 8-bit indexed pixels of mip level `level` (0 to 3), each level half the size of the
+    /// previous one.
+    pub fn mip(&self, level: usize) -> Option<&'a [u8]> {
+        let offset = self.header.offsets.get(level)?.native() as usize;
+
+        if offset == 0 {
+            None
+        } else {
+            let (width, height) = (self.width() as usize >> level, self.height() as usize >> level);
+            let len = width.checked_mul(height)?;
+
+            Some(unsafe { self.bsp.slice_ref(self.offset + offset, len) })
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Model<'a, V: 'a>(&'a sys::Model, &'a Bsp<'a, V>);
 
@@ -422,4 +832,93 @@ impl<'a, V: MapVersion<Lump = sys::Quake1Lump> + 'a> Model<'a, V> {
     pub fn root(&self) -> Option<Node<'a, V>> {
         self.1.node(self.0.hulls[0].native())
     }
+
+    /// The bounding box of the model, in its own space.
+    pub fn bounds(&self) -> BoundingBox<Vec3<f32>> {
+        self.0.bound.clone().native()
+    }
+
+    /// The origin the model was compiled around, this is where an `origin` brush was placed.
+    pub fn origin(&self) -> Vec3<f32> {
+        self.0.origin.native()
+    }
+
+    pub fn face_id(&self) -> usize {
+        self.0.face_id.native() as _
+    }
+
+    pub fn face_len(&self) -> usize {
+        self.0.face_len.native() as _
+    }
+
+    /// The faces belonging to this model, `face_len` of them starting at `face_id`.
+    pub fn faces(&self) -> ValueIter<'a, V, sys::Face, Face<'a, V>> {
+        let start = self.face_id();
+        let end = start + self.face_len();
+        unsafe { ValueIter::new(self.1, &self.1.raw_faces()[start..end]) }
+    }
+
+    /// One of the four collision hulls. Hull 0 is the same tree as `root`.
+    pub fn hull(&self, index: usize) -> Option<Hull<'a, V>> {
+        if index > 0 && self.1.raw_clipnodes().is_empty() {
+            return None;
+        }
+
+        self.0
+            .hulls
+            .get(index)
+            .map(|root| Hull::new(self.1, index, root.native()))
+    }
+
+    /// The contents of hull `hull` at the world-space `position`, with the model moved by
+    /// `transform`.
+    pub fn point_contents_transformed(
+        &self,
+        hull: usize,
+        position: &Vec3<f32>,
+        transform: &Transform,
+    ) -> Option<LeafType> {
+        self.hull(hull)
+            .map(|hull| hull.point_contents_transformed(position, transform))
+    }
+
+    /// Trace a point (or, for hulls 1-3, a box of the hull's size) through the model moved by
+    /// `transform`, as the engine does for moving brush entities.
+    pub fn trace_transformed(
+        &self,
+        hull: usize,
+        start: &Vec3<f32>,
+        end: &Vec3<f32>,
+        transform: &Transform,
+    ) -> Option<Trace> {
+        self.hull(hull)
+            .map(|hull| hull.trace_transformed(start, end, transform))
+    }
+
+    /// The contents of hull 0 at `position`, which is relative to the model's own origin (i.e. the
+    /// entity's origin has already been subtracted).
+    pub fn point_contents(&self, position: &Vec3<f32>) -> LeafType {
+        self.leaf_at(position)
+            .map(|leaf| leaf.leaf_type())
+            .unwrap_or(LeafType::Solid)
+    }
+
+    /// The hull 0 leaf containing `position`, relative to the model's origin, or `None` if the
+    /// point is in solid space.
+    pub fn leaf_at(&self, position: &Vec3<f32>) -> Option<Leaf<'a, V>> {
+        match self.root() {
+            Some(Node::Branch(branch)) => branch.traverse_float(position),
+            Some(Node::Leaf(leaf)) => Some(leaf),
+            None => None,
+        }
+    }
+
+    /// Whether `position` (again relative to the model's origin) is inside the model's bounding
+    /// box. Used to skip the tree walk for brush entities that can't possibly contain the point.
+    pub fn contains(&self, position: &Vec3<f32>) -> bool {
+        let bound = self.bounds();
+
+        bound.aa.x <= position.x && bound.aa.y <= position.y && bound.aa.z <= position.z &&
+            bound.bb.x >= position.x && bound.bb.y >= position.y && bound.bb.z >= position.z
+    }
 }
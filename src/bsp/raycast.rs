@@ -0,0 +1,225 @@
+//! Ray casts against the geometry the player sees (hull 0), for line of sight checks and hitscan
+//! weapons
+//!
+//! The ray walks down the node tree front to back and stops at the first node where it passes
+//! from open space into a solid or sky leaf. The face it hit is the one on that node's plane that
+//! contains the crossing point.
+
+use std::borrow::Cow;
+
+use bsp::quake1::{Branch, LeafType, Model, Node};
+use bsp::{Bsp, MapVersion, Quake1Lump, Vec3};
+
+/// Where a ray first hit something.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RayHit<'a> {
+    /// The index of the face that was hit. This is `None` if the ray started inside a wall, or
+    /// if no face on the plane it hit faces the ray. When rounding puts the point just outside
+    /// all of the faces that do, it's the one closest to the point.
+    pub face: Option<usize>,
+    pub point: Vec3<f32>,
+    /// How far `point` is from the start of the ray
+    pub distance: f32,
+    /// The normal of the surface that was hit, facing back towards the start of the ray. A ray
+    /// that starts inside a wall hits no surface, so this is just the unit vector back along the
+    /// ray, or zero if the ray has no length.
+    pub normal: Vec3<f32>,
+    pub texture: Option<Cow<'a, str>>,
+}
+
+/// Where a ray went from open space into solid space.
+struct Crossing<'a, V: 'a> {
+    /// The node whose plane the ray crossed, or `None` if it started in solid space
+    branch: Option<Branch<'a, V>>,
+    point: Vec3<f32>,
+}
+
+fn blocks(contents: LeafType) -> bool {
+    matches!(contents, LeafType::Solid | LeafType::Sky)
+}
+
+fn contents<V>(node: &Option<Node<V>>, point: &Vec3<f32>) -> LeafType
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    match *node {
+        None => LeafType::Solid,
+        Some(Node::Leaf(ref leaf)) => leaf.leaf_type(),
+        Some(Node::Branch(ref branch)) => {
+            branch
+                .traverse_float(point)
+                .map(|leaf| leaf.leaf_type())
+                .unwrap_or(LeafType::Solid)
+        }
+    }
+}
+
+fn first_crossing<'a, V>(
+    node: Option<Node<'a, V>>,
+    start: Vec3<f32>,
+    end: Vec3<f32>,
+) -> Option<Crossing<'a, V>>
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let branch = match node {
+        Some(Node::Branch(branch)) => branch,
+        Some(Node::Leaf(ref leaf)) if !blocks(leaf.leaf_type()) => return None,
+        // Only reachable if the ray started here, a solid leaf further along the ray is caught
+        // by the node above it before going down that side
+        _ => {
+            return Some(Crossing {
+                branch: None,
+                point: start,
+            })
+        }
+    };
+
+    let plane = branch.plane();
+    let (d1, d2) = (plane.distance_to(&start), plane.distance_to(&end));
+
+    if d1 >= 0. && d2 >= 0. {
+        return first_crossing(branch.front(), start, end);
+    }
+    if d1 < 0. && d2 < 0. {
+        return first_crossing(branch.back(), start, end);
+    }
+
+    let mid = start + (end - start) * (d1 / (d1 - d2));
+    let (near, far) = if d1 >= 0. {
+        (branch.front(), branch.back())
+    } else {
+        (branch.back(), branch.front())
+    };
+
+    if let Some(crossing) = first_crossing(near, start, mid) {
+        return Some(crossing);
+    }
+
+    if blocks(contents(&far, &mid)) {
+        return Some(Crossing {
+            branch: Some(branch),
+            point: mid,
+        });
+    }
+
+    first_crossing(far, mid, end)
+}
+
+pub(crate) fn cross(a: Vec3<f32>, b: Vec3<f32>) -> Vec3<f32> {
+    Vec3 {
+        x: a.y * b.z - a.z * b.y,
+        y: a.z * b.x - a.x * b.z,
+        z: a.x * b.y - a.y * b.x,
+    }
+}
+
+/// Whether `point`, which lies on the polygon's plane, is inside it.
+fn polygon_contains(vertices: &[Vec3<f32>], normal: Vec3<f32>, point: Vec3<f32>) -> bool {
+    let (mut front, mut back) = (false, false);
+
+    for (i, &a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        let side = cross(b - a, point - a).dot(&normal);
+
+        front |= side > 0.01;
+        back |= side < -0.01;
+    }
+
+    !(front && back)
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump> + 'a> Model<'a, V> {
+    /// Cast a ray from `start` to `end`, in the model's own space, returning where it first goes
+    /// into a wall or the sky. Liquids don't stop it.
+    pub fn raycast(&self, start: &Vec3<f32>, end: &Vec3<f32>) -> Option<RayHit<'a>> {
+        let Crossing { branch, point } = first_crossing(self.root(), *start, *end)?;
+        let direction = *end - *start;
+        let distance = {
+            let offset = point - *start;
+
+            offset.dot(&offset).sqrt()
+        };
+
+        let branch = match branch {
+            Some(branch) => branch,
+            None => {
+                let length = direction.dot(&direction).sqrt();
+
+                return Some(RayHit {
+                    face: None,
+                    point,
+                    distance,
+                    normal: if length > 0. {
+                        -direction * (1. / length)
+                    } else {
+                        direction
+                    },
+                    texture: None,
+                });
+            }
+        };
+
+        let plane = branch.plane();
+        let normal = if plane.normal.dot(&direction) > 0. {
+            -plane.normal
+        } else {
+            plane.normal
+        };
+
+        // Faces on both sides of the plane share the node, only the ones facing the ray count
+        let candidates = branch
+            .faces()
+            .enumerate()
+            .filter(|(_, face)| face.plane().normal.dot(&direction) < 0.)
+            .map(|(i, face)| (branch.face_id() + i, face.vertices(), face))
+            .collect::<Vec<_>>();
+
+        let hit = candidates
+            .iter()
+            .find(|(_, vertices, _)| polygon_contains(vertices, normal, point))
+            .or_else(|| {
+                // Rounding can put the point just outside every face, fall back to the closest
+                candidates.iter().min_by(|a, b| {
+                    let distance = |vertices: &[Vec3<f32>]| {
+                        let sum = vertices
+                            .iter()
+                            .fold(Vec3 { x: 0., y: 0., z: 0. }, |sum, &v| sum + v);
+                        let offset = sum * (1. / vertices.len() as f32) - point;
+
+                        offset.dot(&offset)
+                    };
+
+                    distance(&a.1)
+                        .partial_cmp(&distance(&b.1))
+                        .unwrap_or(::std::cmp::Ordering::Equal)
+                })
+            });
+
+        Some(RayHit {
+            face: hit.map(|&(id, _, _)| id),
+            point,
+            distance,
+            normal,
+            texture: hit.and_then(|(_, _, face)| face.texinfo().texture().map(|t| t.name())),
+        })
+    }
+
+    /// Whether nothing solid lies between `a` and `b`, in the model's own space. This is cheaper
+    /// than `raycast`, since it doesn't have to find the face that was hit.
+    pub fn can_see(&self, a: &Vec3<f32>, b: &Vec3<f32>) -> bool {
+        first_crossing(self.root(), *a, *b).is_none()
+    }
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump>> Bsp<'a, V> {
+    /// Cast a ray through the world, see `Model::raycast`.
+    pub fn raycast(&self, start: Vec3<f32>, end: Vec3<f32>) -> Option<RayHit<'_>> {
+        self.map_model().raycast(&start, &end)
+    }
+
+    /// Whether the world leaves a clear view from `a` to `b`.
+    pub fn can_see(&self, a: Vec3<f32>, b: Vec3<f32>) -> bool {
+        self.map_model().can_see(&a, &b)
+    }
+}
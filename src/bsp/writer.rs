@@ -0,0 +1,124 @@
+//! Putting a map back together from its lumps, for tools that compile maps or rewrite parts of
+//! existing ones
+
+use std::io::Write;
+use std::mem;
+
+use ioendian::IntoNativeEndian;
+
+use super::mapversions::{Goldsrc, Quake1};
+use super::{sys, Bsp, Error, MapVersion, Quake1Lump};
+
+/// The version number of Quake maps
+pub const QUAKE_VERSION: u32 = Quake1::VERSION;
+/// The version number of GoldSrc maps
+pub const GOLDSRC_VERSION: u32 = Goldsrc::VERSION;
+
+/// The lumps of a Quake or GoldSrc map as the bytes that go in the file, in the order of the
+/// header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BspWriter {
+    pub version: u32,
+    pub entities: Vec<u8>,
+    pub planes: Vec<u8>,
+    pub miptex: Vec<u8>,
+    pub vertices: Vec<u8>,
+    pub vislist: Vec<u8>,
+    pub nodes: Vec<u8>,
+    pub texinfo: Vec<u8>,
+    pub faces: Vec<u8>,
+    pub lightmaps: Vec<u8>,
+    pub clipnodes: Vec<u8>,
+    pub leaves: Vec<u8>,
+    pub lfaces: Vec<u8>,
+    pub edges: Vec<u8>,
+    pub ledges: Vec<u8>,
+    pub models: Vec<u8>,
+}
+
+impl BspWriter {
+    /// A map with every lump empty.
+    pub fn new(version: u32) -> Self {
+        BspWriter {
+            version,
+            ..Default::default()
+        }
+    }
+
+    /// Copy every lump of an existing map, to be modified before writing it out again.
+    pub fn from_bsp<V: MapVersion<Lump = Quake1Lump>>(bsp: &Bsp<V>) -> Self {
+        let lumps = bsp.lump_data();
+
+        BspWriter {
+            version: bsp.header().version.native(),
+            entities: lumps[0].to_vec(),
+            planes: lumps[1].to_vec(),
+            miptex: lumps[2].to_vec(),
+            vertices: lumps[3].to_vec(),
+            vislist: lumps[4].to_vec(),
+            nodes: lumps[5].to_vec(),
+            texinfo: lumps[6].to_vec(),
+            faces: lumps[7].to_vec(),
+            lightmaps: lumps[8].to_vec(),
+            clipnodes: lumps[9].to_vec(),
+            leaves: lumps[10].to_vec(),
+            lfaces: lumps[11].to_vec(),
+            edges: lumps[12].to_vec(),
+            ledges: lumps[13].to_vec(),
+            models: lumps[14].to_vec(),
+        }
+    }
+
+    fn lumps(&self) -> [&[u8]; 15] {
+        [
+            &self.entities,
+            &self.planes,
+            &self.miptex,
+            &self.vertices,
+            &self.vislist,
+            &self.nodes,
+            &self.texinfo,
+            &self.faces,
+            &self.lightmaps,
+            &self.clipnodes,
+            &self.leaves,
+            &self.lfaces,
+            &self.edges,
+            &self.ledges,
+            &self.models,
+        ]
+    }
+
+    /// Write the map, with each lump starting on a 4-byte boundary like `qbsp` lays them out.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let header_len = mem::size_of::<sys::Header<(), Quake1Lump>>();
+        let lumps = self.lumps();
+        let mut offset = header_len;
+
+        out.write_all(&self.version.to_le_bytes())?;
+
+        for lump in &lumps {
+            out.write_all(&(offset as u32).to_le_bytes())?;
+            out.write_all(&(lump.len() as u32).to_le_bytes())?;
+            offset += (lump.len() + 3) & !3;
+        }
+
+        for lump in &lumps {
+            out.write_all(lump)?;
+            out.write_all(&[0; 3][..(4 - lump.len() % 4) % 4])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+
+    /// Write the map out and load it back, checking it the same way as `Bsp::new`.
+    pub fn build<V: MapVersion<Lump = Quake1Lump> + 'static>(&self) -> Result<Bsp<'static, V>, Error> {
+        Bsp::new(self.to_bytes())
+    }
+}
@@ -0,0 +1,454 @@
+//! Planes, texture projections and the convex brushes built from a map's faces
+
+use std::collections::HashMap;
+
+use bsp::portals::{base_winding, cross, divide, dot, normalize, Point, Split, ON_EPSILON};
+use bsp::quake1::{LeafType, PlaneType};
+use bsp::Vec3;
+use map::{MapFace, Projection};
+
+/// Anything further out than this means a brush is missing a side
+const MAX_COORDINATE: f64 = 32768.;
+/// How close two planes' normals have to be to count as the same plane
+const NORMAL_EPSILON: f64 = 0.00001;
+/// How close two planes' distances have to be to count as the same plane
+const DIST_EPSILON: f64 = 0.01;
+
+/// The `animated` flag of a texinfo that isn't lightmapped or subdivided
+pub(crate) const TEX_SPECIAL: u32 = 1;
+
+/// How a texture is laid on a face, as stored in the texinfo lump.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TexInfo {
+    /// The `s` and `t` axes, with their offsets as the fourth element
+    pub(crate) vecs: [[f64; 4]; 2],
+    /// Index into the miptex lump
+    pub(crate) texture: usize,
+    pub(crate) flags: u32,
+}
+
+/// Every plane used by the map. Each is stored once, facing whichever way `qbsp` would pick,
+/// and users keep a flag for whether they face the other way.
+#[derive(Debug, Default)]
+pub(crate) struct Planes {
+    pub(crate) list: Vec<Split>,
+    /// Planes by their distance rounded to a whole number
+    lookup: HashMap<i64, Vec<usize>>,
+}
+
+pub(crate) fn plane_type(normal: &Point) -> PlaneType {
+    PlaneType::from_normal(&Vec3 {
+        x: normal.x as f32,
+        y: normal.y as f32,
+        z: normal.z as f32,
+    })
+}
+
+pub(crate) fn is_axial(normal: &Point) -> bool {
+    matches!(
+        plane_type(normal),
+        PlaneType::AxialX | PlaneType::AxialY | PlaneType::AxialZ
+    )
+}
+
+impl Planes {
+    /// The index of the plane, adding it if it's new, and whether `split` faces the opposite
+    /// way to the stored plane.
+    pub(crate) fn find(&mut self, split: Split) -> (usize, bool) {
+        let mut normal = split.normal;
+        let mut distance = split.distance;
+
+        for n in [&mut normal.x, &mut normal.y, &mut normal.z] {
+            if (*n - 1.).abs() < NORMAL_EPSILON {
+                *n = 1.;
+            } else if (*n + 1.).abs() < NORMAL_EPSILON {
+                *n = -1.;
+            } else if n.abs() < NORMAL_EPSILON {
+                *n = 0.;
+            }
+        }
+
+        if (distance - distance.round()).abs() < DIST_EPSILON {
+            distance = distance.round();
+        }
+
+        // Planes face along the positive end of the axis they're closest to
+        let dominant = if normal.x.abs() >= normal.y.abs() && normal.x.abs() >= normal.z.abs() {
+            normal.x
+        } else if normal.y.abs() >= normal.z.abs() {
+            normal.y
+        } else {
+            normal.z
+        };
+        let flipped = dominant < 0.;
+
+        if flipped {
+            normal = -normal;
+            distance = -distance;
+        }
+
+        let key = distance.round() as i64;
+
+        for k in key - 1..=key + 1 {
+            for &i in self.lookup.get(&k).into_iter().flatten() {
+                let other = &self.list[i];
+                let d = other.normal - normal;
+
+                if d.x.abs() < NORMAL_EPSILON &&
+                    d.y.abs() < NORMAL_EPSILON &&
+                    d.z.abs() < NORMAL_EPSILON &&
+                    (other.distance - distance).abs() < DIST_EPSILON
+                {
+                    return (i, flipped);
+                }
+            }
+        }
+
+        self.list.push(Split { normal, distance });
+        self.lookup.entry(key).or_default().push(self.list.len() - 1);
+
+        (self.list.len() - 1, flipped)
+    }
+
+    pub(crate) fn oriented(&self, plane: usize, flipped: bool) -> Split {
+        if flipped {
+            self.list[plane].flip()
+        } else {
+            self.list[plane]
+        }
+    }
+}
+
+/// The plane through a face's three points, facing out of the brush.
+pub(crate) fn face_plane(face: &MapFace) -> Option<Split> {
+    let [a, b, c] = face.points;
+    let normal = cross(&(a - b), &(c - b));
+
+    if dot(&normal, &normal) < 1e-12 {
+        return None;
+    }
+
+    let normal = normalize(normal);
+
+    Some(Split {
+        normal,
+        distance: dot(&b, &normal),
+    })
+}
+
+/// What a brush is made of, going by its first face's texture the way `qbsp` does.
+pub(crate) fn texture_contents(texture: &str) -> LeafType {
+    let texture = texture.to_ascii_lowercase();
+
+    if texture.starts_with("*lava") || texture.starts_with("!lava") {
+        LeafType::Lava
+    } else if texture.starts_with("*slime") || texture.starts_with("!slime") {
+        LeafType::Slime
+    } else if texture.starts_with('*') || texture.starts_with('!') {
+        LeafType::Water
+    } else if texture.starts_with("sky") {
+        LeafType::Sky
+    } else if texture == "clip" {
+        LeafType::Clip
+    } else if texture == "origin" {
+        LeafType::Origin
+    } else {
+        LeafType::Solid
+    }
+}
+
+/// Sky and liquids are drawn without lightmaps and aren't cut up to fit them.
+pub(crate) fn is_special_texture(texture: &str) -> bool {
+    texture.starts_with('*') ||
+        texture.starts_with('!') ||
+        texture.to_ascii_lowercase().starts_with("sky")
+}
+
+/// The face normal and the two world axes a Quake-style texture is projected along.
+const BASE_AXES: [[[f64; 3]; 3]; 6] = [
+    // Floor
+    [[0., 0., 1.], [1., 0., 0.], [0., -1., 0.]],
+    // Ceiling
+    [[0., 0., -1.], [1., 0., 0.], [0., -1., 0.]],
+    // West wall
+    [[1., 0., 0.], [0., 1., 0.], [0., 0., -1.]],
+    // East wall
+    [[-1., 0., 0.], [0., 1., 0.], [0., 0., -1.]],
+    // South wall
+    [[0., 1., 0.], [1., 0., 0.], [0., 0., -1.]],
+    // North wall
+    [[0., -1., 0.], [1., 0., 0.], [0., 0., -1.]],
+];
+
+/// The world axes a Quake-style texture is projected along on a face whose outward normal is
+/// `normal`, before it's rotated.
+pub(crate) fn base_axes(normal: &Point) -> [[f64; 3]; 2] {
+    let mut best = 0;
+    let mut best_dot = 0.;
+
+    for (i, axes) in BASE_AXES.iter().enumerate() {
+        let d = normal.x * axes[0][0] + normal.y * axes[0][1] + normal.z * axes[0][2];
+
+        if d > best_dot {
+            best_dot = d;
+            best = i;
+        }
+    }
+
+    [BASE_AXES[best][1], BASE_AXES[best][2]]
+}
+
+/// The texinfo `s` and `t` vectors (with their offsets as the fourth element) for a face whose
+/// outward normal is `normal`.
+pub(crate) fn texture_vectors(face: &MapFace, normal: &Point) -> [[f64; 4]; 2] {
+    let scale = |s: f64| if s == 0. { 1. } else { s };
+
+    match face.projection {
+        Projection::Valve { axes, scale: s, .. } => {
+            let mut vecs = [[0.; 4]; 2];
+
+            for (i, &(axis, offset)) in axes.iter().enumerate() {
+                let axis = axis * (1. / scale(s[i]));
+
+                vecs[i] = [axis.x, axis.y, axis.z, offset];
+            }
+
+            vecs
+        }
+        Projection::Quake {
+            offset,
+            rotation,
+            scale: s,
+        } => {
+            let mut vecs = base_axes(normal);
+
+            // The exact values for right angles, so that they don't pick up rounding errors
+            let (sin, cos) = match rotation {
+                0. => (0., 1.),
+                90. => (1., 0.),
+                180. => (0., -1.),
+                270. => (-1., 0.),
+                r => r.to_radians().sin_cos(),
+            };
+            let sv = vecs[0].iter().position(|&v| v != 0.).unwrap_or(2);
+            let tv = vecs[1].iter().position(|&v| v != 0.).unwrap_or(2);
+
+            for v in &mut vecs {
+                let (ns, nt) = (cos * v[sv] - sin * v[tv], sin * v[sv] + cos * v[tv]);
+
+                v[sv] = ns;
+                v[tv] = nt;
+            }
+
+            let mut out = [[0.; 4]; 2];
+
+            for i in 0..2 {
+                for j in 0..3 {
+                    out[i][j] = vecs[i][j] / scale(s[i]);
+                }
+                out[i][3] = offset[i];
+            }
+
+            out
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Side {
+    pub(crate) plane: usize,
+    /// Whether the side faces the opposite way to `plane`, so that outwards is always the front
+    pub(crate) flipped: bool,
+    /// `None` for sides made by cutting a brush in two, which are never seen
+    pub(crate) texinfo: Option<usize>,
+    pub(crate) winding: Vec<Point>,
+}
+
+/// A convex volume, either a whole brush from the map or a piece of one cut up by the tree.
+#[derive(Debug, Clone)]
+pub(crate) struct Brush {
+    pub(crate) contents: LeafType,
+    pub(crate) sides: Vec<Side>,
+    pub(crate) mins: Point,
+    pub(crate) maxs: Point,
+}
+
+pub(crate) fn bounds<'a, I>(points: I) -> (Point, Point)
+where
+    I: IntoIterator<Item = &'a Point>,
+{
+    let mut mins = Vec3 {
+        x: f64::MAX,
+        y: f64::MAX,
+        z: f64::MAX,
+    };
+    let mut maxs = -mins;
+
+    for p in points {
+        mins = Vec3 {
+            x: mins.x.min(p.x),
+            y: mins.y.min(p.y),
+            z: mins.z.min(p.z),
+        };
+        maxs = Vec3 {
+            x: maxs.x.max(p.x),
+            y: maxs.y.max(p.y),
+            z: maxs.z.max(p.z),
+        };
+    }
+
+    (mins, maxs)
+}
+
+impl Brush {
+    /// Build a brush from its sides' planes, each as `(plane, flipped, texinfo)`. Returns `None`
+    /// if the planes don't enclose a volume.
+    pub(crate) fn new(
+        contents: LeafType,
+        sides: &[(usize, bool, Option<usize>)],
+        planes: &Planes,
+    ) -> Option<Self> {
+        let mut out = Vec::new();
+
+        for (i, &(plane, flipped, texinfo)) in sides.iter().enumerate() {
+            // Editors sometimes leave the same plane in twice
+            if sides[..i].iter().any(|s| s.0 == plane && s.1 == flipped) {
+                continue;
+            }
+
+            let mut winding = base_winding(&planes.oriented(plane, flipped));
+
+            for (j, &(other, other_flipped, _)) in sides.iter().enumerate() {
+                if i == j || (other == plane && other_flipped == flipped) {
+                    continue;
+                }
+
+                winding = divide(&winding, &planes.oriented(other, other_flipped)).1;
+
+                if winding.is_empty() {
+                    break;
+                }
+            }
+
+            if !winding.is_empty() {
+                out.push(Side {
+                    plane,
+                    flipped,
+                    texinfo,
+                    winding,
+                });
+            }
+        }
+
+        Brush::finish(contents, out)
+    }
+
+    fn finish(contents: LeafType, sides: Vec<Side>) -> Option<Self> {
+        if sides.len() < 4 {
+            return None;
+        }
+
+        let (mins, maxs) = bounds(sides.iter().flat_map(|s| &s.winding));
+        let size = maxs - mins;
+
+        if size.x < ON_EPSILON || size.y < ON_EPSILON || size.z < ON_EPSILON {
+            return None;
+        }
+
+        if mins.x < -MAX_COORDINATE ||
+            mins.y < -MAX_COORDINATE ||
+            mins.z < -MAX_COORDINATE ||
+            maxs.x > MAX_COORDINATE ||
+            maxs.y > MAX_COORDINATE ||
+            maxs.z > MAX_COORDINATE
+        {
+            return None;
+        }
+
+        Some(Brush {
+            contents,
+            sides,
+            mins,
+            maxs,
+        })
+    }
+
+    /// The furthest the brush reaches behind and in front of the plane.
+    pub(crate) fn extent(&self, split: &Split) -> (f64, f64) {
+        let mut range = (f64::MAX, f64::MIN);
+
+        for p in self.sides.iter().flat_map(|s| &s.winding) {
+            let d = split.distance_to(p);
+
+            range = (range.0.min(d), range.1.max(d));
+        }
+
+        range
+    }
+
+    /// Cut the brush in two along one of the planes, returning the pieces in front of and
+    /// behind it.
+    pub(crate) fn split(&self, plane: usize, planes: &Planes) -> (Option<Brush>, Option<Brush>) {
+        let split = planes.list[plane];
+        let (min, max) = self.extent(&split);
+
+        if max < ON_EPSILON {
+            return (None, Some(self.clone()));
+        }
+        if min > -ON_EPSILON {
+            return (Some(self.clone()), None);
+        }
+
+        let mut middle = base_winding(&split);
+
+        for side in &self.sides {
+            middle = divide(&middle, &planes.oriented(side.plane, side.flipped)).1;
+        }
+
+        // The brush only just pokes through the plane
+        if middle.is_empty() {
+            return if max > -min {
+                (Some(self.clone()), None)
+            } else {
+                (None, Some(self.clone()))
+            };
+        }
+
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+
+        for side in &self.sides {
+            let (f, b) = divide(&side.winding, &split);
+
+            if !f.is_empty() {
+                front.push(Side {
+                    winding: f,
+                    ..side.clone()
+                });
+            }
+            if !b.is_empty() {
+                back.push(Side {
+                    winding: b,
+                    ..side.clone()
+                });
+            }
+        }
+
+        front.push(Side {
+            plane,
+            flipped: true,
+            texinfo: None,
+            winding: middle.iter().rev().cloned().collect(),
+        });
+        back.push(Side {
+            plane,
+            flipped: false,
+            texinfo: None,
+            winding: middle,
+        });
+
+        (
+            Brush::finish(self.contents, front),
+            Brush::finish(self.contents, back),
+        )
+    }
+}
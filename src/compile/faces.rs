@@ -0,0 +1,311 @@
+//! Turning the portals between leaves with different contents into the faces that get drawn
+
+use std::collections::HashMap;
+
+use bsp::portals::{cross, divide, dot, normalize, Builder, Kind, Point, Split};
+use bsp::quake1::LeafType;
+
+use super::brush::{Planes, TexInfo, TEX_SPECIAL};
+use super::tree::{blocks, contents, rank, Tree};
+
+/// `qbsp`'s limit on the number of edges a face can have
+const MAX_POINTS: usize = 32;
+/// How close two points have to be to count as the same
+const POINT_EPSILON: f64 = 0.01;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Face {
+    pub(crate) plane: usize,
+    /// Whether the face looks out of the back of its plane
+    pub(crate) back: bool,
+    pub(crate) texinfo: usize,
+    /// Clockwise when looking at the face
+    pub(crate) winding: Vec<Point>,
+    /// The node whose plane the face is on
+    pub(crate) node: usize,
+    /// The leaves the face can be seen from
+    pub(crate) leaves: Vec<usize>,
+}
+
+fn is_liquid(contents: LeafType) -> bool {
+    matches!(contents, LeafType::Water | LeafType::Slime | LeafType::Lava)
+}
+
+/// The texture of the brush side on `plane` that faces out of `leaf` the way `flipped` says.
+fn side_texinfo(tree: &[Tree], leaf: usize, plane: usize, flipped: bool) -> Option<usize> {
+    let (contents, brushes) = match tree[leaf] {
+        Tree::Leaf {
+            contents,
+            ref brushes,
+        } => (contents, brushes),
+        _ => return None,
+    };
+
+    // Brushes of the leaf's own contents first, since those are what's seen
+    let ordered = brushes
+        .iter()
+        .filter(|brush| brush.contents == contents)
+        .chain(brushes.iter().filter(|brush| brush.contents != contents));
+    let sides = || ordered.clone().flat_map(|brush| &brush.sides);
+
+    sides()
+        .find(|side| side.plane == plane && side.flipped == flipped && side.texinfo.is_some())
+        .or_else(|| sides().find(|side| side.texinfo.is_some()))
+        .and_then(|side| side.texinfo)
+}
+
+/// One face for every portal that has something visible on it, looking into the emptier leaf.
+/// Liquid surfaces are seen from both sides.
+pub(crate) fn make_faces(tree: &[Tree], builder: &Builder) -> Vec<Face> {
+    let mut faces = Vec::new();
+
+    for portal in &builder.portals {
+        let node = match portal.on_node {
+            Some(node) => node,
+            None => continue,
+        };
+        let plane = match tree[node] {
+            Tree::Branch { plane, .. } => plane,
+            _ => continue,
+        };
+        let kinds = (
+            &builder.nodes[portal.nodes[0]].kind,
+            &builder.nodes[portal.nodes[1]].kind,
+        );
+        let leaves = match kinds {
+            (&Kind::Leaf(a), &Kind::Leaf(b)) => [a, b],
+            _ => continue,
+        };
+        let (a, b) = match (contents(tree, leaves[0]), contents(tree, leaves[1])) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+
+        if a == b || (blocks(a) && blocks(b)) {
+            continue;
+        }
+
+        let viewer = if rank(a) < rank(b) { 0 } else { 1 };
+        let other = 1 - viewer;
+        let texinfo = match side_texinfo(tree, leaves[other], plane, viewer == 1) {
+            Some(texinfo) => texinfo,
+            None => continue,
+        };
+
+        let mut sides = vec![viewer];
+
+        if is_liquid([a, b][other]) {
+            sides.push(other);
+        }
+
+        for side in sides {
+            let winding = if side == 0 {
+                portal.winding.clone()
+            } else {
+                portal.winding.iter().rev().cloned().collect()
+            };
+
+            faces.push(Face {
+                plane,
+                back: side == 1,
+                texinfo,
+                winding,
+                node,
+                leaves: vec![leaves[side]],
+            });
+        }
+    }
+
+    faces
+}
+
+fn same_point(a: &Point, b: &Point) -> bool {
+    (a.x - b.x).abs() < POINT_EPSILON &&
+        (a.y - b.y).abs() < POINT_EPSILON &&
+        (a.z - b.z).abs() < POINT_EPSILON
+}
+
+/// Drop the points in the middle of straight edges, or `None` if what's left isn't convex.
+fn convex(winding: Vec<Point>, normal: &Point) -> Option<Vec<Point>> {
+    let mut points = winding;
+    let mut sign = 0.;
+    let mut i = 0;
+
+    while i < points.len() && points.len() >= 3 {
+        let len = points.len();
+        let (prev, cur, next) = (points[(i + len - 1) % len], points[i], points[(i + 1) % len]);
+        let turn = dot(&cross(&normalize(cur - prev), &normalize(next - cur)), normal);
+
+        if turn.abs() < 0.001 {
+            points.remove(i);
+            i = 0;
+            sign = 0.;
+            continue;
+        }
+
+        if sign == 0. {
+            sign = turn.signum();
+        } else if turn.signum() != sign {
+            return None;
+        }
+
+        i += 1;
+    }
+
+    if points.len() < 3 {
+        None
+    } else {
+        Some(points)
+    }
+}
+
+/// Join two faces on the same plane that share an edge, if they make a convex face together.
+fn try_merge(a: &[Point], b: &[Point], normal: &Point) -> Option<Vec<Point>> {
+    for i in 0..a.len() {
+        let (p1, p2) = (a[i], a[(i + 1) % a.len()]);
+
+        for j in 0..b.len() {
+            let (p3, p4) = (b[j], b[(j + 1) % b.len()]);
+
+            if !same_point(&p1, &p4) || !same_point(&p2, &p3) {
+                continue;
+            }
+
+            let mut merged = Vec::with_capacity(a.len() + b.len() - 2);
+
+            merged.extend((1..=a.len()).map(|k| a[(i + k) % a.len()]));
+            merged.extend((2..b.len()).map(|k| b[(j + k) % b.len()]));
+
+            return convex(merged, normal).filter(|w| w.len() <= MAX_POINTS);
+        }
+    }
+
+    None
+}
+
+/// Merge the faces of each node that share a texture into as few as possible.
+pub(crate) fn merge(faces: Vec<Face>, planes: &Planes) -> Vec<Face> {
+    let mut groups = HashMap::<(usize, bool, usize), Vec<Face>>::new();
+    let mut order = Vec::new();
+
+    for face in faces {
+        let key = (face.node, face.back, face.texinfo);
+
+        groups
+            .entry(key)
+            .or_insert_with(|| {
+                order.push(key);
+                Vec::new()
+            })
+            .push(face);
+    }
+
+    let mut out = Vec::new();
+
+    for key in order {
+        let mut group = groups.remove(&key).unwrap_or_default();
+        let normal = planes.oriented(group[0].plane, key.1).normal;
+        let mut merged = true;
+
+        while merged {
+            merged = false;
+
+            'outer: for i in 0..group.len() {
+                for j in i + 1..group.len() {
+                    let winding = try_merge(&group[i].winding, &group[j].winding, &normal);
+
+                    if let Some(winding) = winding {
+                        let other = group.remove(j);
+
+                        group[i].winding = winding;
+                        for leaf in other.leaves {
+                            if !group[i].leaves.contains(&leaf) {
+                                group[i].leaves.push(leaf);
+                            }
+                        }
+
+                        merged = true;
+                        break 'outer;
+                    }
+                }
+            }
+        }
+
+        out.extend(group);
+    }
+
+    out
+}
+
+fn subdivide_face(face: Face, texinfo: &TexInfo, size: f64, out: &mut Vec<Face>) {
+    for vecs in &texinfo.vecs {
+        let axis = Point {
+            x: vecs[0],
+            y: vecs[1],
+            z: vecs[2],
+        };
+        let (mut min, mut max) = (f64::MAX, f64::MIN);
+
+        for p in &face.winding {
+            let v = dot(p, &axis);
+
+            min = min.min(v);
+            max = max.max(v);
+        }
+
+        if max - min <= size {
+            continue;
+        }
+
+        let length = dot(&axis, &axis).sqrt();
+        let split = Split {
+            normal: normalize(axis),
+            distance: (min + size - 16.) / length,
+        };
+        let (front, back) = divide(&face.winding, &split);
+
+        if front.is_empty() || back.is_empty() {
+            continue;
+        }
+
+        subdivide_face(
+            Face {
+                winding: back,
+                ..face.clone()
+            },
+            texinfo,
+            size,
+            out,
+        );
+        subdivide_face(
+            Face {
+                winding: front,
+                ..face
+            },
+            texinfo,
+            size,
+            out,
+        );
+        return;
+    }
+
+    out.push(face);
+}
+
+/// Cut faces into pieces no more than `size` texels across, so their lightmaps fit in the
+/// engine's surface cache.
+pub(crate) fn subdivide(faces: Vec<Face>, texinfo: &[TexInfo], size: f64) -> Vec<Face> {
+    let mut out = Vec::new();
+
+    for face in faces {
+        let info = &texinfo[face.texinfo];
+
+        if info.flags & TEX_SPECIAL != 0 || size <= 0. {
+            out.push(face);
+        } else {
+            subdivide_face(face, info, size, &mut out);
+        }
+    }
+
+    out
+}
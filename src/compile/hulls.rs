@@ -0,0 +1,107 @@
+//! Growing brushes by the size of a box, so that collision for that box can be checked by
+//! tracing a single point through the resulting hull
+
+use bsp::portals::{cross, dot, normalize, Point, Split, ON_EPSILON};
+use bsp::quake1::LeafType;
+use bsp::{BoundingBox, Vec3};
+
+use super::brush::{Brush, Planes};
+
+const AXES: [Point; 3] = [
+    Vec3 { x: 1., y: 0., z: 0. },
+    Vec3 { x: 0., y: 1., z: 0. },
+    Vec3 { x: 0., y: 0., z: 1. },
+];
+
+/// How far the box reaches along `normal` from its centre.
+fn support(normal: &Point, mins: &Point, maxs: &Point) -> f64 {
+    let pick = |n: f64, min: f64, max: f64| if n > 0. { n * max } else { n * min };
+
+    pick(normal.x, mins.x, maxs.x) +
+        pick(normal.y, mins.y, maxs.y) +
+        pick(normal.z, mins.z, maxs.z)
+}
+
+/// The brush swept by the box: its own sides pushed out, plus the axial and edge bevels that
+/// stop the corners from sticking out too far, like `qbsp`'s `ExpandBrush`. Liquids are left
+/// out of the clipping hulls, and everything else in them is solid.
+pub(crate) fn expand(
+    brush: &Brush,
+    hull: &BoundingBox<Vec3<f32>>,
+    planes: &mut Planes,
+) -> Option<Brush> {
+    if !matches!(brush.contents, LeafType::Solid | LeafType::Sky | LeafType::Clip) {
+        return None;
+    }
+
+    let widen = |v: &Vec3<f32>| Vec3 {
+        x: v.x as f64,
+        y: v.y as f64,
+        z: v.z as f64,
+    };
+    // The box's origin is blocked wherever the box itself would overlap the brush, so the brush
+    // grows by the box mirrored through its origin, like `qbsp`'s `hull_size`
+    let (mins, maxs) = (-widen(&hull.bb), -widen(&hull.aa));
+    let points = brush
+        .sides
+        .iter()
+        .flat_map(|side| &side.winding)
+        .collect::<Vec<_>>();
+    let furthest = |normal: &Point| {
+        points
+            .iter()
+            .map(|p| dot(p, normal))
+            .fold(f64::MIN, f64::max)
+    };
+
+    let mut normals = brush
+        .sides
+        .iter()
+        .map(|side| planes.oriented(side.plane, side.flipped).normal)
+        .collect::<Vec<_>>();
+
+    for axis in &AXES {
+        normals.push(*axis);
+        normals.push(-*axis);
+    }
+
+    for side in &brush.sides {
+        for (i, &start) in side.winding.iter().enumerate() {
+            let end = side.winding[(i + 1) % side.winding.len()];
+            let edge = end - start;
+
+            if dot(&edge, &edge) < 0.25 {
+                continue;
+            }
+
+            for axis in &AXES {
+                let normal = cross(&normalize(edge), axis);
+
+                if dot(&normal, &normal) < 0.25 {
+                    continue;
+                }
+
+                // Only the planes through the edge that the whole brush is behind
+                for normal in [normalize(normal), -normalize(normal)] {
+                    if furthest(&normal) - dot(&start, &normal) < ON_EPSILON {
+                        normals.push(normal);
+                    }
+                }
+            }
+        }
+    }
+
+    let sides = normals
+        .iter()
+        .map(|normal| {
+            let (plane, flipped) = planes.find(Split {
+                normal: *normal,
+                distance: furthest(normal) + support(normal, &mins, &maxs),
+            });
+
+            (plane, flipped, None)
+        })
+        .collect::<Vec<_>>();
+
+    Brush::new(LeafType::Solid, &sides, planes)
+}
@@ -0,0 +1,843 @@
+//! Compiling `.map` sources into maps the engine can load, the job of `qbsp`
+//!
+//! Space is cut up along the brushes' planes until every leaf is either empty or entirely inside
+//! some brushes. The leaves that can be reached from outside the map are then filled in, and the
+//! faces are whatever lies between leaves with different contents. The clipping hulls are built
+//! the same way from the brushes grown by the size of each hull. Lighting and visibility are left
+//! empty, for `light` and `vis` to fill in afterwards.
+
+mod brush;
+mod faces;
+mod hulls;
+mod tree;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
+
+use bsp::portals::{dot, Builder, Point, Split};
+use bsp::quake1::LeafType;
+use bsp::{BoundingBox, Bsp, BspWriter, Entity, Error, MapVersion, Quake1Lump, Vec3};
+use map::{MapBrush, MapFace, MapFile};
+use wad::{LumpKind, Wad};
+
+use self::brush::{
+    bounds, face_plane, is_special_texture, plane_type, texture_contents, texture_vectors, Brush,
+    Planes, TexInfo, TEX_SPECIAL,
+};
+use self::faces::Face;
+use self::tree::{Flood, Tree};
+
+pub(crate) use self::brush::base_axes;
+
+/// How far apart the points of a leak's pointfile are
+const POINTFILE_STEP: f64 = 8.;
+/// The size given to textures that couldn't be found, which are left for the engine to find
+const MISSING_TEXTURE_SIZE: u32 = 16;
+
+#[derive(Debug)]
+pub struct CompileConfig {
+    /// Where to find the textures to embed in the map. Textures that aren't in any of these are
+    /// left for the engine to find in its own WADs, which only GoldSrc does.
+    pub wads: Vec<Wad<'static>>,
+    /// The most texels across a lightmapped face can be before it's cut in two
+    pub subdivide: f64,
+}
+
+impl Default for CompileConfig {
+    fn default() -> Self {
+        CompileConfig {
+            wads: Vec::new(),
+            subdivide: 240.,
+        }
+    }
+}
+
+/// A way from an entity to the outside of the map, meaning the world isn't sealed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    /// The index in `MapFile::entities` of the entity the leak was found from
+    pub entity: usize,
+    /// From the entity's origin out through the hole in the map
+    pub points: Vec<Vec3<f32>>,
+}
+
+impl Leak {
+    /// Write the path as a `.pts` pointfile, which Quake's `pointfile` command and level editors
+    /// can draw.
+    pub fn write_pointfile<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        for p in &self.points {
+            writeln!(out, "{:.6} {:.6} {:.6}", p.x, p.y, p.z)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_pointfile(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+}
+
+pub struct Compiled<V> {
+    pub bsp: Bsp<'static, V>,
+    /// Set if the world isn't sealed. The map is still compiled, but without filling the
+    /// outside, so it's slow and full of faces nobody can see.
+    pub leak: Option<Leak>,
+    /// The textures that weren't in any of the WADs, which are stored by name only
+    pub missing_textures: Vec<String>,
+}
+
+/// The brushes of the world or of one brush entity.
+struct ModelBrushes {
+    brushes: Vec<Brush>,
+}
+
+/// A model's tree with the faces on it.
+struct BuiltModel {
+    tree: Vec<Tree>,
+    /// The trees of the clipping hulls after the first, `None` for the ones that aren't used
+    clip: Vec<Option<Vec<Tree>>>,
+    builder: Builder,
+    faces: Vec<Face>,
+    mins: Point,
+    maxs: Point,
+}
+
+#[derive(Default)]
+struct Compiler {
+    planes: Planes,
+    textures: Vec<String>,
+    texinfo: Vec<TexInfo>,
+    texinfo_lookup: HashMap<([u64; 8], usize, u32), usize>,
+}
+
+fn to_f32(p: &Point) -> Vec3<f32> {
+    Vec3 {
+        x: p.x as f32,
+        y: p.y as f32,
+        z: p.z as f32,
+    }
+}
+
+fn set_key(entity: &mut Entity<'static>, key: &'static str, value: String) {
+    entity.properties.retain(|(k, _)| k != key);
+    entity
+        .properties
+        .push((Cow::Borrowed(key), Cow::Owned(value)));
+}
+
+impl Compiler {
+    fn texture(&mut self, name: &str) -> usize {
+        match self
+            .textures
+            .iter()
+            .position(|t| t.eq_ignore_ascii_case(name))
+        {
+            Some(i) => i,
+            None => {
+                self.textures.push(name.to_owned());
+                self.textures.len() - 1
+            }
+        }
+    }
+
+    fn texinfo(&mut self, face: &MapFace, normal: &Point, origin: &Point) -> usize {
+        let mut vecs = texture_vectors(face, normal);
+
+        // Keep the texture where it was on the brush before it moved to the entity's origin
+        for v in &mut vecs {
+            v[3] += v[0] * origin.x + v[1] * origin.y + v[2] * origin.z;
+        }
+
+        let texture = self.texture(&face.texture);
+        let flags = if is_special_texture(&face.texture) {
+            TEX_SPECIAL
+        } else {
+            0
+        };
+
+        let mut bits = [0; 8];
+        for (bit, v) in bits.iter_mut().zip(vecs.iter().flatten()) {
+            *bit = v.to_bits();
+        }
+
+        let info = TexInfo {
+            vecs,
+            texture,
+            flags,
+        };
+        let next = self.texinfo.len();
+        let id = *self
+            .texinfo_lookup
+            .entry((bits, texture, flags))
+            .or_insert(next);
+
+        if id == next {
+            self.texinfo.push(info);
+        }
+
+        id
+    }
+
+    /// Build a brush, moved so that `origin` ends up at the model's origin.
+    fn brush(&mut self, brush: &MapBrush, origin: &Point) -> Option<Brush> {
+        let contents = texture_contents(&brush.faces.first()?.texture);
+        let visible = !matches!(contents, LeafType::Clip | LeafType::Origin);
+        let mut sides = Vec::new();
+
+        for face in &brush.faces {
+            let mut split = face_plane(face)?;
+
+            split.distance -= dot(&split.normal, origin);
+
+            let (plane, flipped) = self.planes.find(split);
+            let texinfo = if visible {
+                Some(self.texinfo(face, &split.normal, origin))
+            } else {
+                None
+            };
+
+            sides.push((plane, flipped, texinfo));
+        }
+
+        Brush::new(contents, &sides, &self.planes)
+    }
+
+    /// Fill in the leaves of `tree` that can be reached from outside the map, or find the way
+    /// out if one of the `occupants` can reach them.
+    fn fill(
+        &self,
+        tree: &mut Vec<Tree>,
+        (mins, maxs): (Point, Point),
+        occupants: &[(usize, Point)],
+    ) -> Option<Leak> {
+        let builder = tree::portals(tree, &self.planes, mins, maxs);
+        let flood = Flood::new(tree, &builder);
+        let occupied = occupants
+            .iter()
+            .map(|&(entity, origin)| (entity, origin, tree::leaf_at(tree, &self.planes, &origin)))
+            .filter(|&(_, _, leaf)| !tree::contents(tree, leaf).is_some_and(tree::blocks))
+            .collect::<Vec<_>>();
+
+        match occupied.iter().find(|&&(_, _, leaf)| flood.reached(leaf)) {
+            Some(&(entity, origin, leaf)) => Some(Leak {
+                entity,
+                points: flood
+                    .path(&builder, leaf, origin, POINTFILE_STEP)
+                    .iter()
+                    .map(to_f32)
+                    .collect(),
+            }),
+            // Without anything in the map there's no telling inside from outside
+            None if occupied.is_empty() => None,
+            None => {
+                flood.fill(tree);
+                *tree = tree::prune(mem::take(tree));
+                None
+            }
+        }
+    }
+
+    /// Build the model's tree, and a tree for each of `hulls` after the first with the brushes
+    /// grown by its size. For the world, `occupants` are the point entities with their origins,
+    /// used to fill in everything outside the map, or to find a leak.
+    fn model(
+        &mut self,
+        brushes: &[Brush],
+        hulls: &[BoundingBox<Vec3<f32>>],
+        occupants: Option<&[(usize, Point)]>,
+        config: &CompileConfig,
+    ) -> (BuiltModel, Option<Leak>) {
+        let extent = |brushes: &[Brush]| {
+            if brushes.is_empty() {
+                let zero = Vec3 { x: 0., y: 0., z: 0. };
+
+                (zero, zero)
+            } else {
+                bounds(brushes.iter().flat_map(|brush| [&brush.mins, &brush.maxs]))
+            }
+        };
+
+        let visible = brushes
+            .iter()
+            .filter(|brush| !matches!(brush.contents, LeafType::Clip | LeafType::Origin))
+            .cloned()
+            .collect::<Vec<_>>();
+        let (mins, maxs) = extent(&visible);
+        let mut tree = tree::build(visible, &self.planes);
+        let leak = occupants.and_then(|occupants| self.fill(&mut tree, (mins, maxs), occupants));
+
+        let clip = hulls
+            .iter()
+            .skip(1)
+            .map(|hull| {
+                if hull.aa == hull.bb {
+                    return None;
+                }
+
+                let grown = brushes
+                    .iter()
+                    .filter_map(|brush| hulls::expand(brush, hull, &mut self.planes))
+                    .collect::<Vec<_>>();
+                let extent = extent(&grown);
+                let mut tree = tree::build(grown, &self.planes);
+
+                // Only the leaks in the drawn hull are worth reporting
+                if leak.is_none() {
+                    if let Some(occupants) = occupants {
+                        self.fill(&mut tree, extent, occupants);
+                    }
+                }
+
+                Some(tree)
+            })
+            .collect();
+
+        let builder = tree::portals(&tree, &self.planes, mins, maxs);
+        let faces = faces::make_faces(&tree, &builder);
+        let faces = faces::merge(faces, &self.planes);
+        let faces = faces::subdivide(faces, &self.texinfo, config.subdivide);
+
+        (
+            BuiltModel {
+                tree,
+                clip,
+                builder,
+                faces,
+                mins,
+                maxs,
+            },
+            leak,
+        )
+    }
+}
+
+fn put_i32(out: &mut Vec<u8>, v: i32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_i16(out: &mut Vec<u8>, v: i16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_f32(out: &mut Vec<u8>, v: f32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_bounds(out: &mut Vec<u8>, (mins, maxs): (Point, Point)) {
+    let clamp = |v: f64| v.max(i16::MIN as f64).min(i16::MAX as f64) as i16;
+
+    for v in [mins.x, mins.y, mins.z] {
+        put_i16(out, clamp(v.floor()));
+    }
+    for v in [maxs.x, maxs.y, maxs.z] {
+        put_i16(out, clamp(v.ceil()));
+    }
+}
+
+fn too_many(what: &'static str) -> Error {
+    Error::Unsupported(what)
+}
+
+/// Turns the compiled models into lumps.
+struct Emitter {
+    writer: BspWriter,
+    vertex_lookup: HashMap<(i64, i64, i64), u16>,
+    num_vertices: usize,
+    edge_lookup: HashMap<(u16, u16), usize>,
+    /// How many faces use each edge
+    edge_uses: Vec<u8>,
+    num_faces: usize,
+    num_nodes: usize,
+    num_leaves: usize,
+    num_lfaces: usize,
+    num_clipnodes: usize,
+}
+
+impl Emitter {
+    fn vertex(&mut self, p: &Point) -> Result<u16, Error> {
+        let snap = |v: f64| if (v - v.round()).abs() < 0.01 { v.round() } else { v };
+        let p = Vec3 {
+            x: snap(p.x),
+            y: snap(p.y),
+            z: snap(p.z),
+        };
+        let key = (
+            (p.x * 8.).round() as i64,
+            (p.y * 8.).round() as i64,
+            (p.z * 8.).round() as i64,
+        );
+
+        if let Some(&v) = self.vertex_lookup.get(&key) {
+            return Ok(v);
+        }
+
+        if self.num_vertices > u16::MAX as usize {
+            return Err(too_many("maps with more than 65536 vertices"));
+        }
+
+        let id = self.num_vertices as u16;
+
+        for v in [p.x, p.y, p.z] {
+            put_f32(&mut self.writer.vertices, v as f32);
+        }
+
+        self.num_vertices += 1;
+        self.vertex_lookup.insert(key, id);
+        Ok(id)
+    }
+
+    /// An edge from `a` to `b`, negative to use an existing edge backwards. Each edge is shared
+    /// by at most two faces.
+    fn edge(&mut self, a: u16, b: u16) -> i32 {
+        if let Some(&e) = self.edge_lookup.get(&(b, a)) {
+            if self.edge_uses[e] == 1 {
+                self.edge_uses[e] += 1;
+                return -(e as i32);
+            }
+        }
+
+        let id = self.edge_uses.len();
+
+        put_u16(&mut self.writer.edges, a);
+        put_u16(&mut self.writer.edges, b);
+        self.edge_uses.push(1);
+        self.edge_lookup.insert((a, b), id);
+        id as i32
+    }
+
+    fn face(&mut self, face: &Face) -> Result<Option<usize>, Error> {
+        let mut vertices = Vec::new();
+
+        for p in &face.winding {
+            let v = self.vertex(p)?;
+
+            if vertices.last() != Some(&v) {
+                vertices.push(v);
+            }
+        }
+
+        while vertices.len() > 1 && vertices.first() == vertices.last() {
+            vertices.pop();
+        }
+
+        if vertices.len() < 3 {
+            return Ok(None);
+        }
+
+        let ledge_id = self.writer.ledges.len() / 4;
+
+        for i in 0..vertices.len() {
+            let e = self.edge(vertices[i], vertices[(i + 1) % vertices.len()]);
+
+            put_i32(&mut self.writer.ledges, e);
+        }
+
+        let out = &mut self.writer.faces;
+
+        put_u16(out, face.plane as u16);
+        put_u16(out, face.back as u16);
+        put_i32(out, ledge_id as i32);
+        put_u16(out, vertices.len() as u16);
+        put_u16(out, face.texinfo as u16);
+        // No light styles or lightmap until `light` is run
+        out.extend_from_slice(&[255; 4]);
+        put_i32(out, -1);
+
+        self.num_faces += 1;
+        Ok(Some(self.num_faces - 1))
+    }
+
+    /// Write the clipnodes under `node` of a clipping hull's tree, returning what to point at it
+    /// with. Branches with the same contents on both sides are left out.
+    fn clip_node(&mut self, tree: &[Tree], node: usize) -> i32 {
+        let (plane, children) = match tree[node] {
+            Tree::Branch { plane, children } => (plane, children),
+            Tree::Leaf { contents, .. } => return contents.into(),
+        };
+        let (id, at) = (self.num_clipnodes, self.writer.clipnodes.len());
+
+        self.writer.clipnodes.extend_from_slice(&[0; 8]);
+        self.num_clipnodes += 1;
+
+        let front = self.clip_node(tree, children[0]);
+        let back = self.clip_node(tree, children[1]);
+
+        if front < 0 && front == back {
+            self.writer.clipnodes.truncate(at);
+            self.num_clipnodes = id;
+            return front;
+        }
+
+        let mut out = Vec::with_capacity(8);
+
+        put_i32(&mut out, plane as i32);
+        put_i16(&mut out, front as i16);
+        put_i16(&mut out, back as i16);
+        self.writer.clipnodes[at..at + 8].copy_from_slice(&out);
+
+        id as i32
+    }
+
+    /// Write a model's faces, nodes, leaves and clipnodes, and the model itself.
+    fn model(&mut self, model: &BuiltModel) -> Result<(), Error> {
+        let tree = &model.tree;
+        let face_start = self.num_faces;
+        let mut node_faces = vec![(0, 0); tree.len()];
+        let mut leaf_faces = vec![Vec::new(); tree.len()];
+
+        for (node, range) in node_faces.iter_mut().enumerate() {
+            let start = self.num_faces;
+
+            for face in model.faces.iter().filter(|face| face.node == node) {
+                if let Some(id) = self.face(face)? {
+                    for &leaf in &face.leaves {
+                        leaf_faces[leaf].push(id);
+                    }
+                }
+            }
+
+            *range = (start, self.num_faces - start);
+        }
+
+        let bounds = tree::node_bounds(tree, &model.builder);
+        let mut ids = vec![0i32; tree.len()];
+        let mut num_leaves = 0;
+
+        // A model with no brushes still needs a node for the model to point at
+        let dummy_root = matches!(tree[0], Tree::Leaf { .. });
+        let mut next_node = self.num_nodes + dummy_root as usize;
+
+        for (i, node) in tree.iter().enumerate() {
+            ids[i] = match *node {
+                Tree::Branch { .. } => {
+                    next_node += 1;
+                    next_node as i32 - 1
+                }
+                Tree::Leaf {
+                    contents: LeafType::Solid,
+                    ..
+                } => -1,
+                Tree::Leaf { .. } => {
+                    num_leaves += 1;
+                    -((self.num_leaves + num_leaves) as i32) - 1
+                }
+            };
+        }
+
+        let root = self.num_nodes as i32;
+
+        if dummy_root {
+            put_i32(&mut self.writer.nodes, 0);
+            put_i16(&mut self.writer.nodes, ids[0] as i16);
+            put_i16(&mut self.writer.nodes, ids[0] as i16);
+            put_bounds(&mut self.writer.nodes, bounds[0]);
+            put_u16(&mut self.writer.nodes, face_start as u16);
+            put_u16(&mut self.writer.nodes, 0);
+        }
+
+        for (i, node) in tree.iter().enumerate() {
+            match *node {
+                Tree::Branch { plane, children } => {
+                    let out = &mut self.writer.nodes;
+
+                    put_i32(out, plane as i32);
+                    put_i16(out, ids[children[0]] as i16);
+                    put_i16(out, ids[children[1]] as i16);
+                    put_bounds(out, bounds[i]);
+                    put_u16(out, node_faces[i].0 as u16);
+                    put_u16(out, node_faces[i].1 as u16);
+                }
+                Tree::Leaf { contents, .. } if contents != LeafType::Solid => {
+                    let out = &mut self.writer.leaves;
+
+                    put_i32(out, contents.into());
+                    put_i32(out, -1);
+                    put_bounds(out, bounds[i]);
+                    put_u16(out, self.num_lfaces as u16);
+                    put_u16(out, leaf_faces[i].len() as u16);
+                    out.extend_from_slice(&[0; 4]);
+
+                    for &face in &leaf_faces[i] {
+                        put_u16(&mut self.writer.lfaces, face as u16);
+                    }
+
+                    self.num_lfaces += leaf_faces[i].len();
+                }
+                Tree::Leaf { .. } => {}
+            }
+        }
+
+        self.num_nodes = next_node;
+        self.num_leaves += num_leaves;
+
+        let clip_roots = model
+            .clip
+            .iter()
+            .map(|tree| tree.as_ref().map_or(0, |tree| self.clip_node(tree, 0)))
+            .collect::<Vec<_>>();
+
+        let out = &mut self.writer.models;
+
+        for p in [model.mins, model.maxs] {
+            for v in [p.x, p.y, p.z] {
+                put_f32(out, v as f32);
+            }
+        }
+        for _ in 0..3 {
+            put_f32(out, 0.);
+        }
+        put_i32(out, root);
+        for hull in 0..3 {
+            put_i32(out, clip_roots.get(hull).cloned().unwrap_or(0));
+        }
+        put_i32(out, num_leaves as i32);
+        put_i32(out, face_start as i32);
+        put_i32(out, (self.num_faces - face_start) as i32);
+
+        Ok(())
+    }
+}
+
+/// The miptex lump, with the textures found in `wads` embedded and the rest stored by name only.
+fn miptex_lump(names: &[String], wads: &[Wad], missing: &mut Vec<String>) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    put_i32(&mut out, names.len() as i32);
+    out.resize(4 + names.len() * 4, 0);
+
+    for (i, name) in names.iter().enumerate() {
+        let offset = out.len() as i32;
+
+        out[4 + i * 4..8 + i * 4].copy_from_slice(&offset.to_le_bytes());
+
+        let found = wads.iter().find_map(|wad| {
+            wad.lumps().find(|lump| {
+                lump.kind() == LumpKind::MipTex &&
+                    !lump.is_compressed() &&
+                    lump.name().eq_ignore_ascii_case(name)
+            })
+        });
+
+        match found {
+            Some(lump) => out.extend_from_slice(lump.data()),
+            None => {
+                let mut header = [0; 16];
+                let len = name.len().min(15);
+
+                header[..len].copy_from_slice(&name.as_bytes()[..len]);
+                out.extend_from_slice(&header);
+                out.extend_from_slice(&MISSING_TEXTURE_SIZE.to_le_bytes());
+                out.extend_from_slice(&MISSING_TEXTURE_SIZE.to_le_bytes());
+                out.extend_from_slice(&[0; 16]);
+                missing.push(name.clone());
+            }
+        }
+
+        out.resize(out.len().div_ceil(4) * 4, 0);
+    }
+
+    out
+}
+
+fn entity_lump(entities: &[Entity]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for entity in entities {
+        out.extend_from_slice(b"{\n");
+        for (key, value) in &entity.properties {
+            out.extend_from_slice(format!("\"{}\" \"{}\"\n", key, value).as_bytes());
+        }
+        out.extend_from_slice(b"}\n");
+    }
+
+    out.push(0);
+    out
+}
+
+/// Compile a map. Fails if the result breaks one of the format's limits, but not if the map
+/// leaks, see `Compiled::leak`.
+pub fn compile<V>(map: &MapFile, config: &CompileConfig) -> Result<Compiled<V>, Error>
+where
+    V: MapVersion<Lump = Quake1Lump> + 'static,
+{
+    if map.entities.is_empty() {
+        return Err(Error::Unsupported("maps without a worldspawn"));
+    }
+
+    let zero = Vec3 { x: 0., y: 0., z: 0. };
+    let mut compiler = Compiler::default();
+    let mut models = vec![ModelBrushes {
+        brushes: Vec::new(),
+    }];
+    let mut entities = Vec::new();
+    let mut occupants = Vec::new();
+
+    for (i, map_entity) in map.entities.iter().enumerate() {
+        let mut entity = map_entity.entity.clone();
+        let class_name = entity.class_name().unwrap_or("").to_owned();
+
+        if i == 0 || class_name == "func_group" {
+            models[0]
+                .brushes
+                .extend(map_entity.brushes.iter().filter_map(|b| compiler.brush(b, &zero)));
+
+            if i == 0 {
+                entities.push(entity);
+            }
+            continue;
+        }
+
+        if map_entity.brushes.is_empty() {
+            if let Some(origin) = entity.origin() {
+                let origin = Vec3 {
+                    x: origin.x as f64,
+                    y: origin.y as f64,
+                    z: origin.z as f64,
+                };
+
+                occupants.push((i, origin));
+            }
+
+            entities.push(entity);
+            continue;
+        }
+
+        // An origin brush sets the point the model moves and rotates around
+        let (origin_brushes, brushes): (Vec<_>, Vec<_>) = map_entity
+            .brushes
+            .iter()
+            .partition(|b| {
+                b.faces.first().map(|f| texture_contents(&f.texture)) == Some(LeafType::Origin)
+            });
+        let origin_brushes = origin_brushes
+            .into_iter()
+            .filter_map(|b| compiler.brush(b, &zero))
+            .collect::<Vec<_>>();
+        let origin = if origin_brushes.is_empty() {
+            zero
+        } else {
+            let (mins, maxs) = bounds(origin_brushes.iter().flat_map(|b| [&b.mins, &b.maxs]));
+
+            (mins + maxs) * 0.5
+        };
+
+        if !origin_brushes.is_empty() {
+            set_key(
+                &mut entity,
+                "origin",
+                format!("{} {} {}", origin.x, origin.y, origin.z),
+            );
+        }
+
+        set_key(&mut entity, "model", format!("*{}", models.len()));
+        models.push(ModelBrushes {
+            brushes: brushes
+                .into_iter()
+                .filter_map(|b| compiler.brush(b, &origin))
+                .collect(),
+        });
+        entities.push(entity);
+    }
+
+    let mut leak = None;
+    let mut built = Vec::new();
+
+    for (i, model) in models.iter().enumerate() {
+        let (model, model_leak) = compiler.model(
+            &model.brushes,
+            &V::HULLS,
+            if i == 0 { Some(&occupants) } else { None },
+            config,
+        );
+
+        leak = leak.or(model_leak);
+        built.push(model);
+    }
+
+    if compiler.planes.list.is_empty() {
+        compiler.planes.find(Split {
+            normal: Vec3 { x: 1., y: 0., z: 0. },
+            distance: 0.,
+        });
+    }
+
+    let mut emitter = Emitter {
+        writer: BspWriter::new(V::VERSION),
+        vertex_lookup: HashMap::new(),
+        num_vertices: 0,
+        edge_lookup: HashMap::new(),
+        // Edge 0 can't be used, since it can't be negated
+        edge_uses: vec![2],
+        num_faces: 0,
+        num_nodes: 0,
+        num_leaves: 0,
+        num_lfaces: 0,
+        num_clipnodes: 0,
+    };
+
+    emitter.writer.edges.extend_from_slice(&[0; 4]);
+
+    // Leaf 0 is the solid leaf that every model shares
+    let out = &mut emitter.writer.leaves;
+    put_i32(out, LeafType::Solid.into());
+    put_i32(out, -1);
+    out.extend_from_slice(&[0; 20]);
+
+    for model in &built {
+        emitter.model(model)?;
+    }
+
+    // The reserved edge 0 points at vertex 0, which maps without any faces wouldn't otherwise have
+    if emitter.num_vertices == 0 {
+        emitter.vertex(&Vec3 { x: 0., y: 0., z: 0. })?;
+    }
+
+    if emitter.num_faces > u16::MAX as usize + 1 ||
+        emitter.num_nodes > i16::MAX as usize ||
+        emitter.num_leaves > i16::MAX as usize ||
+        emitter.num_clipnodes > i16::MAX as usize ||
+        emitter.num_lfaces > u16::MAX as usize + 1 ||
+        compiler.planes.list.len() > u16::MAX as usize + 1 ||
+        compiler.texinfo.len() > u16::MAX as usize + 1
+    {
+        return Err(too_many("maps with more records than the format can address"));
+    }
+
+    let mut writer = emitter.writer;
+
+    for split in &compiler.planes.list {
+        for v in [split.normal.x, split.normal.y, split.normal.z, split.distance] {
+            put_f32(&mut writer.planes, v as f32);
+        }
+        put_i32(&mut writer.planes, plane_type(&split.normal) as i32);
+    }
+
+    for info in &compiler.texinfo {
+        for axis in &info.vecs {
+            for &v in axis {
+                put_f32(&mut writer.texinfo, v as f32);
+            }
+        }
+        put_i32(&mut writer.texinfo, info.texture as i32);
+        put_i32(&mut writer.texinfo, info.flags as i32);
+    }
+
+    let mut missing_textures = Vec::new();
+
+    writer.miptex = miptex_lump(&compiler.textures, &config.wads, &mut missing_textures);
+    writer.entities = entity_lump(&entities);
+
+    Ok(Compiled {
+        bsp: writer.build()?,
+        leak,
+        missing_textures,
+    })
+}
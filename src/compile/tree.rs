@@ -0,0 +1,381 @@
+//! Building the node tree from brushes, then finding the leaves that can be reached from outside
+//! the map and filling them in
+
+use std::collections::VecDeque;
+use std::mem;
+
+use bsp::portals::{Builder, Kind, Point, ON_EPSILON};
+use bsp::quake1::LeafType;
+use bsp::Vec3;
+
+use super::brush::{bounds, is_axial, Brush, Planes};
+
+pub(crate) enum Tree {
+    Branch { plane: usize, children: [usize; 2] },
+    /// The brushes that fill the leaf, kept to find the textures of the faces around it
+    Leaf {
+        contents: LeafType,
+        brushes: Vec<Brush>,
+    },
+}
+
+/// How solid some contents are. Where brushes overlap the most solid wins, and faces are seen
+/// from the less solid side.
+pub(crate) fn rank(contents: LeafType) -> u8 {
+    match contents {
+        LeafType::Ordinary => 0,
+        LeafType::Water => 1,
+        LeafType::Slime => 2,
+        LeafType::Lava => 3,
+        LeafType::Sky => 4,
+        _ => 5,
+    }
+}
+
+/// Whether the contents stop the flood from the outside, and so don't need faces between them.
+pub(crate) fn blocks(contents: LeafType) -> bool {
+    matches!(contents, LeafType::Solid | LeafType::Sky)
+}
+
+pub(crate) fn contents(tree: &[Tree], node: usize) -> Option<LeafType> {
+    match tree.get(node) {
+        Some(&Tree::Leaf { contents, .. }) => Some(contents),
+        _ => None,
+    }
+}
+
+/// The plane that best divides the brushes, preferring ones that many sides lie on, that cut
+/// few brushes and that leave a similar number on each side, like `qbsp` does.
+fn choose_split(brushes: &[Brush], used: &[usize], planes: &Planes) -> Option<usize> {
+    let mut candidates = brushes
+        .iter()
+        .flat_map(|brush| brush.sides.iter().map(|side| side.plane))
+        .filter(|plane| !used.contains(plane))
+        .collect::<Vec<_>>();
+
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut best = None;
+
+    for plane in candidates {
+        let split = planes.list[plane];
+        let (mut front, mut back, mut splits, mut facing) = (0i64, 0i64, 0i64, 0i64);
+
+        for brush in brushes {
+            facing += brush
+                .sides
+                .iter()
+                .filter(|side| side.plane == plane && side.texinfo.is_some())
+                .count() as i64;
+
+            let (min, max) = brush.extent(&split);
+
+            if min < -ON_EPSILON && max > ON_EPSILON {
+                splits += 1;
+            } else if max > ON_EPSILON {
+                front += 1;
+            } else {
+                back += 1;
+            }
+        }
+
+        let mut value = 5 * facing - 5 * splits - (front - back).abs();
+
+        if is_axial(&split.normal) {
+            value += 5;
+        }
+
+        if best.map_or(true, |(_, best)| value > best) {
+            best = Some((plane, value));
+        }
+    }
+
+    best.map(|(plane, _)| plane)
+}
+
+fn build_node(
+    nodes: &mut Vec<Tree>,
+    brushes: Vec<Brush>,
+    used: &mut Vec<usize>,
+    planes: &Planes,
+) -> usize {
+    let id = nodes.len();
+
+    let plane = match choose_split(&brushes, used, planes) {
+        Some(plane) => plane,
+        None => {
+            // Every brush left fills the whole leaf
+            let contents = brushes
+                .iter()
+                .map(|brush| brush.contents)
+                .max_by_key(|&contents| rank(contents))
+                .unwrap_or(LeafType::Ordinary);
+
+            nodes.push(Tree::Leaf { contents, brushes });
+            return id;
+        }
+    };
+
+    nodes.push(Tree::Branch {
+        plane,
+        children: [0, 0],
+    });
+
+    let (mut front, mut back) = (Vec::new(), Vec::new());
+
+    for brush in brushes {
+        let (f, b) = brush.split(plane, planes);
+
+        front.extend(f);
+        back.extend(b);
+    }
+
+    used.push(plane);
+    let children = [
+        build_node(nodes, front, used, planes),
+        build_node(nodes, back, used, planes),
+    ];
+    used.pop();
+
+    nodes[id] = Tree::Branch { plane, children };
+    id
+}
+
+/// Divide space along the brushes' sides until every leaf is either empty or entirely inside
+/// some brushes. The nodes come out in the order the map stores them, the root first and each
+/// node followed by everything in front of it.
+pub(crate) fn build(brushes: Vec<Brush>, planes: &Planes) -> Vec<Tree> {
+    let mut nodes = Vec::new();
+
+    build_node(&mut nodes, brushes, &mut Vec::new(), planes);
+    nodes
+}
+
+/// Make the portals between the tree's leaves. Node `i` of the builder is node `i` of the tree,
+/// and the node after the last is the outside.
+pub(crate) fn portals(tree: &[Tree], planes: &Planes, mins: Point, maxs: Point) -> Builder {
+    let mut builder = Builder::new();
+
+    for (i, node) in tree.iter().enumerate() {
+        builder.add_node(match *node {
+            Tree::Branch { plane, children } => Kind::Branch {
+                split: planes.list[plane],
+                children,
+            },
+            Tree::Leaf { .. } => Kind::Leaf(i),
+        });
+    }
+
+    let outside = builder.add_node(Kind::Outside);
+
+    builder.head_portals(0, outside, mins, maxs);
+    builder.make_tree_portals(0);
+    builder
+}
+
+/// The leaf that `point` is in.
+pub(crate) fn leaf_at(tree: &[Tree], planes: &Planes, point: &Point) -> usize {
+    let mut node = 0;
+
+    while let Tree::Branch { plane, children } = tree[node] {
+        node = if planes.list[plane].distance_to(point) >= 0. {
+            children[0]
+        } else {
+            children[1]
+        };
+    }
+
+    node
+}
+
+pub(crate) fn center(winding: &[Point]) -> Point {
+    let sum = winding
+        .iter()
+        .fold(Vec3 { x: 0., y: 0., z: 0. }, |sum, &p| sum + p);
+
+    sum * (1. / winding.len().max(1) as f64)
+}
+
+/// Which nodes can be reached from the outside without going through anything solid.
+pub(crate) struct Flood {
+    /// The portal each node was first reached through, or `None` if it can't be reached. The
+    /// outside itself has `Some(None)`.
+    via: Vec<Option<Option<usize>>>,
+}
+
+impl Flood {
+    pub(crate) fn new(tree: &[Tree], builder: &Builder) -> Self {
+        let outside = tree.len();
+        let mut via = vec![None; builder.nodes.len()];
+        let mut queue = VecDeque::new();
+
+        via[outside] = Some(None);
+        queue.push_back(outside);
+
+        while let Some(node) = queue.pop_front() {
+            for &p in &builder.nodes[node].portals {
+                let nodes = builder.portals[p].nodes;
+                let other = if nodes[0] == node { nodes[1] } else { nodes[0] };
+
+                if via[other].is_some() || contents(tree, other).is_some_and(blocks) {
+                    continue;
+                }
+
+                via[other] = Some(Some(p));
+                queue.push_back(other);
+            }
+        }
+
+        Flood { via }
+    }
+
+    pub(crate) fn reached(&self, node: usize) -> bool {
+        self.via[node].is_some()
+    }
+
+    /// The way out from `start` in `leaf` as a line through the middle of each portal on the
+    /// way, with a point every `step` units for editors to draw.
+    pub(crate) fn path(
+        &self,
+        builder: &Builder,
+        leaf: usize,
+        start: Point,
+        step: f64,
+    ) -> Vec<Point> {
+        let mut corners = vec![start];
+        let mut node = leaf;
+
+        while let Some(Some(p)) = self.via[node] {
+            let portal = &builder.portals[p];
+
+            corners.push(center(&portal.winding));
+            node = if portal.nodes[0] == node {
+                portal.nodes[1]
+            } else {
+                portal.nodes[0]
+            };
+        }
+
+        let mut points = vec![start];
+
+        for pair in corners.windows(2) {
+            let delta = pair[1] - pair[0];
+            let length = delta.x.hypot(delta.y).hypot(delta.z);
+            let steps = (length / step).ceil().max(1.) as usize;
+
+            for i in 1..=steps {
+                points.push(pair[0] + delta * (i as f64 / steps as f64));
+            }
+        }
+
+        points
+    }
+
+    /// Make every leaf that can be reached from outside solid.
+    pub(crate) fn fill(&self, tree: &mut [Tree]) {
+        for (i, node) in tree.iter_mut().enumerate() {
+            if let Tree::Leaf {
+                ref mut contents, ..
+            } = *node
+            {
+                if self.reached(i) {
+                    *contents = LeafType::Solid;
+                }
+            }
+        }
+    }
+}
+
+fn prune_node(tree: &mut [Tree], id: usize, out: &mut Vec<Tree>) -> usize {
+    let at = out.len();
+
+    let (plane, children) = match tree[id] {
+        Tree::Branch { plane, children } => (plane, children),
+        Tree::Leaf { contents, .. } => {
+            out.push(mem::replace(
+                &mut tree[id],
+                Tree::Leaf {
+                    contents,
+                    brushes: Vec::new(),
+                },
+            ));
+            return at;
+        }
+    };
+
+    out.push(Tree::Branch {
+        plane,
+        children: [0, 0],
+    });
+
+    let children = [
+        prune_node(tree, children[0], out),
+        prune_node(tree, children[1], out),
+    ];
+
+    if contents(out, children[0]) == Some(LeafType::Solid) &&
+        contents(out, children[1]) == Some(LeafType::Solid)
+    {
+        let mut brushes = Vec::new();
+
+        for child in out.drain(at + 1..) {
+            if let Tree::Leaf { brushes: b, .. } = child {
+                brushes.extend(b);
+            }
+        }
+
+        out[at] = Tree::Leaf {
+            contents: LeafType::Solid,
+            brushes,
+        };
+    } else {
+        out[at] = Tree::Branch { plane, children };
+    }
+
+    at
+}
+
+/// Merge nodes that are solid on both sides, which filling leaves all around the outside.
+pub(crate) fn prune(mut tree: Vec<Tree>) -> Vec<Tree> {
+    let mut out = Vec::with_capacity(tree.len());
+
+    prune_node(&mut tree, 0, &mut out);
+    out
+}
+
+/// The bounds of every node: the portals around each leaf, and everything under each branch.
+/// Solid leaves aren't given any, since the engine never looks at them.
+pub(crate) fn node_bounds(tree: &[Tree], builder: &Builder) -> Vec<(Point, Point)> {
+    let zero = Vec3 { x: 0., y: 0., z: 0. };
+    let mut out = vec![(zero, zero); tree.len()];
+
+    // Children always come after their parents
+    for i in (0..tree.len()).rev() {
+        out[i] = match tree[i] {
+            Tree::Leaf { contents, .. } => {
+                let portals = &builder.nodes[i].portals;
+
+                if contents == LeafType::Solid || portals.is_empty() {
+                    continue;
+                }
+
+                bounds(portals.iter().flat_map(|&p| &builder.portals[p].winding))
+            }
+            Tree::Branch { children, .. } => {
+                let (a, b) = (out[children[0]], out[children[1]]);
+                let empty = |bounds: (Point, Point)| bounds.0 == zero && bounds.1 == zero;
+
+                if empty(a) {
+                    b
+                } else if empty(b) {
+                    a
+                } else {
+                    bounds(&[a.0, a.1, b.0, b.1])
+                }
+            }
+        };
+    }
+
+    out
+}
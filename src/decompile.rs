@@ -0,0 +1,663 @@
+//! Turning a compiled map back into a `.map` source, for when the original is lost
+//!
+//! Every leaf of a model's hull 0 tree that isn't empty is a convex volume bounded by the planes
+//! of the nodes above it, so each one becomes a brush. The sides of the brush take their texture
+//! and alignment from the faces drawn on them, written in Valve's 220 format since texinfo holds
+//! the texture axes directly. Some of the source can't be recovered: brushes come back cut up
+//! along the tree's planes, clip brushes are lost since they only exist grown by the size of the
+//! clipping hulls, and hidden sides get the texture of another side of their brush.
+//!
+//! Quake 2 keeps the original brushes in lumps of their own for collision, so `decompile_quake2`
+//! reads those instead and gets whole brushes back, clip brushes included. Their content and
+//! surface flags aren't written, so the compiler takes them from the textures again.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::mem;
+
+use ioendian::{IntoNativeEndian, Little};
+
+use bsp::entities;
+use bsp::mapversions::Quake2;
+use bsp::portals::{base_winding, cross, divide, dot, normalize, Point, Split, ON_EPSILON};
+use bsp::quake1::{Face, LeafType, Node};
+use bsp::{Bsp, Entity, Error, MapVersion, Quake1Lump, Vec3};
+use compile::base_axes;
+use map::{MapBrush, MapEntity, MapFace, MapFile, Projection};
+use sys::bsp::{self as raw, Quake2Lump};
+use sys::{slice_at, value_at};
+
+/// How far apart an `origin` brush's sides are
+const ORIGIN_SIZE: f64 = 16.;
+
+fn widen(v: &Vec3<f32>) -> Point {
+    Vec3 {
+        x: v.x as f64,
+        y: v.y as f64,
+        z: v.z as f64,
+    }
+}
+
+/// Round off what's left of the precision lost in the map, so whole numbers stay whole.
+fn snap(v: f64) -> f64 {
+    if (v - v.round()).abs() < 0.001 {
+        v.round()
+    } else {
+        v
+    }
+}
+
+fn area(winding: &[Point]) -> f64 {
+    let zero = Vec3 { x: 0., y: 0., z: 0. };
+    let total = (1..winding.len().saturating_sub(1)).fold(zero, |sum, i| {
+        sum + cross(&(winding[i] - winding[0]), &(winding[i + 1] - winding[0]))
+    });
+
+    dot(&total, &total).sqrt() / 2.
+}
+
+/// How much of `face` lies inside `side`, a winding on the same plane facing along `normal`.
+fn overlap(face: &[Point], side: &[Point], normal: &Point) -> f64 {
+    let zero = Vec3 { x: 0., y: 0., z: 0. };
+    let centre = side.iter().fold(zero, |sum, &p| sum + p) * (1. / side.len() as f64);
+    let mut clipped = face.to_vec();
+
+    for (i, &p) in side.iter().enumerate() {
+        let q = side[(i + 1) % side.len()];
+        let mut edge = Split {
+            normal: normalize(cross(&(q - p), normal)),
+            distance: 0.,
+        };
+
+        edge.distance = dot(&edge.normal, &p);
+        if edge.distance_to(&centre) > 0. {
+            edge = edge.flip();
+        }
+
+        clipped = divide(&clipped, &edge).1;
+        if clipped.is_empty() {
+            return 0.;
+        }
+    }
+
+    area(&clipped)
+}
+
+/// Three points of a winding as far apart as possible: the first, the one furthest from it and
+/// the one furthest from the line between those two.
+fn extremes(winding: &[Point]) -> [Point; 3] {
+    let a = winding[0];
+    let distance = |p: &&Point| dot(&(**p - a), &(**p - a));
+    let b = *winding
+        .iter()
+        .max_by(|p, q| distance(p).total_cmp(&distance(q)))
+        .unwrap_or(&a);
+    let spread = |p: &&Point| {
+        let c = cross(&(b - a), &(**p - a));
+
+        dot(&c, &c)
+    };
+    let c = *winding
+        .iter()
+        .max_by(|p, q| spread(p).total_cmp(&spread(q)))
+        .unwrap_or(&a);
+
+    [a, b, c]
+}
+
+/// Three points on a side, as far apart as possible so the plane comes back accurately, in the
+/// order that makes the plane face out of the brush.
+fn side_points(winding: &[Point], normal: &Point) -> [Point; 3] {
+    let [a, b, c] = extremes(winding);
+    let snapped = |p: Point| Vec3 {
+        x: snap(p.x),
+        y: snap(p.y),
+        z: snap(p.z),
+    };
+
+    // The compiler takes the plane's normal from (a - b) × (c - b)
+    if dot(&cross(&(a - b), &(c - b)), normal) > 0. {
+        [snapped(a), snapped(b), snapped(c)]
+    } else {
+        [snapped(c), snapped(b), snapped(a)]
+    }
+}
+
+/// The texture shown on a side and how it's laid on it, if anything shows one.
+type Shown = Option<(String, Projection)>;
+
+/// The texture of a face and how it's laid on it, with the face moved by `offset`.
+fn face_texture<V>(face: &Face<V>, offset: &Point) -> Shown
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let texinfo = face.texinfo();
+    let name = texinfo.texture()?.name().into_owned();
+
+    let normal = widen(&face.plane().normal);
+
+    Some((name, projection(texinfo.s(), texinfo.t(), offset, &normal)))
+}
+
+/// Texture axes from a texinfo's `s` and `t` vectors and offsets, with the face moved by
+/// `offset`. A damaged texinfo with an axis of no length is laid like a hidden side facing along
+/// `normal` instead.
+fn projection(
+    s: (Vec3<f32>, f32),
+    t: (Vec3<f32>, f32),
+    offset: &Point,
+    normal: &Point,
+) -> Projection {
+    if [s.0, t.0].iter().any(|v| v.dot(v) == 0.) {
+        return hidden_projection(normal);
+    }
+
+    let axis = |(vector, distance): (Vec3<f32>, f32)| {
+        let vector = widen(&vector);
+        let length = dot(&vector, &vector).sqrt();
+        let shift = dot(&vector, offset);
+
+        (vector * (1. / length), snap(distance as f64 - shift), snap(1. / length))
+    };
+    let ((s, s_offset, s_scale), (t, t_offset, t_scale)) = (axis(s), axis(t));
+
+    Projection::Valve {
+        axes: [(s, s_offset), (t, t_offset)],
+        rotation: 0.,
+        scale: [s_scale, t_scale],
+    }
+}
+
+/// How a side facing along `normal` is textured when nothing shows how it was: along the world
+/// axes, as a new brush would be, but in the 220 format the rest of the map is written in.
+fn hidden_projection(normal: &Point) -> Projection {
+    let axis = |v: [f64; 3]| (Vec3 { x: v[0], y: v[1], z: v[2] }, 0.);
+    let [s, t] = base_axes(normal);
+
+    Projection::Valve {
+        axes: [axis(s), axis(t)],
+        rotation: 0.,
+        scale: [1., 1.],
+    }
+}
+
+/// A texture of the right contents for sides that nothing on the map shows.
+fn hidden_texture(contents: LeafType) -> &'static str {
+    match contents {
+        LeafType::Water => "*water",
+        LeafType::Slime => "*slime",
+        LeafType::Lava => "*lava",
+        LeafType::Sky => "sky",
+        _ => "hidden",
+    }
+}
+
+struct Decompiler<'a, V: 'a> {
+    bsp: &'a Bsp<'a, V>,
+    /// The model's bounds, which close off the leaves around the outside of the tree
+    bounds: Vec<Split>,
+    /// How far the model has to move to get back to where it was in the source
+    offset: Point,
+    brushes: Vec<MapBrush>,
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump> + 'a> Decompiler<'a, V> {
+    /// Make brushes of the leaves under `node`. `sides` are the planes above it facing out of
+    /// the space it covers, with the faces of the node each came from.
+    fn node(&mut self, node: Node<V>, sides: &mut Vec<(Split, (usize, usize))>) {
+        let branch = match node {
+            Node::Branch(branch) => branch,
+            Node::Leaf(leaf) => {
+                let contents = leaf.leaf_type();
+
+                if contents != LeafType::Ordinary {
+                    let brush = self.brush(sides, contents);
+
+                    self.brushes.extend(brush);
+                }
+
+                return;
+            }
+        };
+
+        let plane = branch.plane();
+        let split = Split {
+            normal: widen(&plane.normal),
+            distance: plane.distance as f64,
+        };
+        let faces = (branch.face_id(), branch.face_len());
+
+        for (child, outward) in [(branch.front(), split.flip()), (branch.back(), split)] {
+            sides.push((outward, faces));
+
+            match child {
+                Some(child) => self.node(child, sides),
+                // `Bsp::leaf` leaves out the solid leaf
+                None => {
+                    let brush = self.brush(sides, LeafType::Solid);
+
+                    self.brushes.extend(brush);
+                }
+            }
+
+            sides.pop();
+        }
+    }
+
+    fn brush(&self, sides: &[(Split, (usize, usize))], contents: LeafType) -> Option<MapBrush> {
+        let no_faces = (0, 0);
+        let planes = sides
+            .iter()
+            .cloned()
+            .chain(self.bounds.iter().map(|&bound| (bound, no_faces)))
+            .collect::<Vec<_>>();
+        let windings = convex_sides(&planes.iter().map(|&(plane, _)| plane).collect::<Vec<_>>())?;
+
+        let faces = windings
+            .into_iter()
+            .map(|(i, winding)| {
+                let (plane, (first, len)) = planes[i];
+                let shown = (first..first + len)
+                    .filter_map(|i| self.bsp.faces().get(i))
+                    .filter(|face| dot(&widen(&face.plane().normal), &plane.normal) > 0.999)
+                    .map(|face| {
+                        let vertices = face.vertices().iter().map(widen).collect::<Vec<_>>();
+
+                        (overlap(&vertices, &winding, &plane.normal), face)
+                    })
+                    .filter(|&(overlap, _)| overlap > 0.)
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+                    .and_then(|(_, face)| face_texture(&face, &self.offset));
+                let moved = winding.iter().map(|&p| p + self.offset).collect::<Vec<_>>();
+
+                (plane, side_points(&moved, &plane.normal), shown)
+            })
+            .collect();
+
+        Some(textured_brush(faces, hidden_texture(contents)))
+    }
+}
+
+/// The sides of the convex volume behind all of `planes`, each the index of its plane and its
+/// winding. Planes that only touch the volume along an edge or at a corner (like the bevels
+/// Quake 2 adds to brushes) don't make a side, and volumes too thin to be a brush give `None`.
+fn convex_sides(planes: &[Split]) -> Option<Vec<(usize, Vec<Point>)>> {
+    let same = |a: &Split, b: &Split| {
+        dot(&a.normal, &b.normal) > 0.9999 && (a.distance - b.distance).abs() < ON_EPSILON
+    };
+    let mut windings = Vec::new();
+
+    for (i, plane) in planes.iter().enumerate() {
+        if planes[..i].iter().any(|other| same(other, plane)) {
+            continue;
+        }
+
+        let mut winding = base_winding(plane);
+
+        for other in planes {
+            if !winding.is_empty() && !same(other, plane) {
+                winding = divide(&winding, other).1;
+            }
+        }
+
+        if winding.is_empty() {
+            continue;
+        }
+
+        // How far the winding reaches from the line between its furthest points
+        let [a, b, c] = extremes(&winding);
+        let spread = cross(&(b - a), &(c - a));
+
+        if dot(&spread, &spread).sqrt() > ON_EPSILON * dot(&(b - a), &(b - a)).sqrt() {
+            windings.push((i, winding));
+        }
+    }
+
+    // Leaves squeezed flat against the bounds aren't worth a brush
+    let points = windings
+        .iter()
+        .flat_map(|(_, winding)| winding)
+        .collect::<Vec<_>>();
+    let thick = windings.iter().all(|&(i, _)| {
+        points.iter().any(|p| planes[i].distance_to(p) < -ON_EPSILON)
+    });
+
+    if windings.len() < 4 || !thick {
+        None
+    } else {
+        Some(windings)
+    }
+}
+
+/// A brush of sides given by their plane, the points written for them and the texture shown on
+/// them, if any. Sides that nothing shows take the texture of one that something does, or
+/// `hidden` if none do.
+fn textured_brush(
+    mut sides: Vec<(Split, [Point; 3], Shown)>,
+    hidden: &str,
+) -> MapBrush {
+    // The compiler takes the brush's contents from its first side, so that has to be one with a
+    // texture that was really there
+    sides.sort_by_key(|(_, _, shown)| shown.is_none());
+
+    let texture = sides
+        .first()
+        .and_then(|(_, _, shown)| shown.as_ref())
+        .map_or(hidden, |(name, _)| name)
+        .to_owned();
+
+    MapBrush {
+        faces: sides
+            .into_iter()
+            .map(|(plane, points, shown)| {
+                let (texture, projection) = shown.unwrap_or_else(|| {
+                    (texture.clone(), hidden_projection(&plane.normal))
+                });
+
+                MapFace {
+                    points,
+                    texture,
+                    projection,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// An `origin` brush around `origin`, for the compiler to move the model back to its origin.
+fn origin_brush(origin: &Point) -> MapBrush {
+    let half = ORIGIN_SIZE / 2.;
+    let face = |normal: Point| {
+        let centre = *origin + normal * half;
+        let (u, v) = if normal.z != 0. {
+            (Vec3 { x: 1., y: 0., z: 0. }, Vec3 { x: 0., y: 1., z: 0. })
+        } else {
+            (Vec3 { x: -normal.y, y: normal.x, z: 0. }, Vec3 { x: 0., y: 0., z: 1. })
+        };
+        let winding = [centre + u, centre + u + v, centre + v];
+
+        MapFace {
+            points: side_points(&winding, &normal),
+            texture: "origin".to_owned(),
+            projection: hidden_projection(&normal),
+        }
+    };
+    let axes = [
+        Vec3 { x: 1., y: 0., z: 0. },
+        Vec3 { x: 0., y: 1., z: 0. },
+        Vec3 { x: 0., y: 0., z: 1. },
+    ];
+
+    MapBrush {
+        faces: axes
+            .iter()
+            .flat_map(|&axis| [axis, -axis])
+            .map(face)
+            .collect(),
+    }
+}
+
+/// The entity as it was in the source, with the index of its model and where that model has to
+/// move back to. The compiler works out the `model` keys again, and the origins of models that
+/// have one from the `origin` brush they get back.
+fn source_entity(i: usize, entity: Entity) -> (Entity<'static>, Option<usize>, Option<Point>) {
+    let model = if i == 0 { Some(0) } else { entity.model_index() };
+    let origin = entity
+        .origin()
+        .filter(|_| model.is_some_and(|model| model > 0))
+        .map(|origin| widen(&origin));
+    let mut entity = Entity {
+        properties: entity
+            .properties
+            .into_iter()
+            .filter(|(key, _)| {
+                model.is_none() || (key != "model" && !(key == "origin" && origin.is_some()))
+            })
+            .map(|(key, value)| (Cow::Owned(key.into_owned()), Cow::Owned(value.into_owned())))
+            .collect(),
+    };
+
+    if i == 0 && entity.get("mapversion").is_none() {
+        entity
+            .properties
+            .push((Cow::Borrowed("mapversion"), Cow::Borrowed("220")));
+    }
+
+    (entity, model, origin)
+}
+
+/// The map's source, as near as it can be worked out. Models are put back in their entities,
+/// and models with an origin get an `origin` brush to move them back around it.
+pub fn decompile<V>(bsp: &Bsp<V>) -> MapFile
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let mut entities = Vec::new();
+
+    for (i, entity) in bsp.entities().into_iter().enumerate() {
+        let (entity, model, origin) = source_entity(i, entity);
+        let mut brushes = Vec::new();
+
+        if let Some(model) = model.and_then(|model| bsp.model(model)) {
+            let bounds = model.bounds();
+            let (mins, maxs) = (widen(&bounds.aa), widen(&bounds.bb));
+            let offset = origin.unwrap_or(Vec3 { x: 0., y: 0., z: 0. });
+            let mut decompiler = Decompiler {
+                bsp,
+                bounds: vec![
+                    Split { normal: Vec3 { x: 1., y: 0., z: 0. }, distance: maxs.x },
+                    Split { normal: Vec3 { x: 0., y: 1., z: 0. }, distance: maxs.y },
+                    Split { normal: Vec3 { x: 0., y: 0., z: 1. }, distance: maxs.z },
+                    Split { normal: Vec3 { x: -1., y: 0., z: 0. }, distance: -mins.x },
+                    Split { normal: Vec3 { x: 0., y: -1., z: 0. }, distance: -mins.y },
+                    Split { normal: Vec3 { x: 0., y: 0., z: -1. }, distance: -mins.z },
+                ],
+                offset,
+                brushes: Vec::new(),
+            };
+
+            if let Some(root) = model.root() {
+                decompiler.node(root, &mut Vec::new());
+            }
+
+            brushes = decompiler.brushes;
+            brushes.extend(origin.as_ref().map(origin_brush));
+        }
+
+        entities.push(MapEntity { entity, brushes });
+    }
+
+    MapFile { entities }
+}
+
+/// The record at `index`, which came from `field` of the given record of `lump`.
+fn record<'a, T>(
+    records: &'a [T],
+    index: i64,
+    (lump, record, field): (&'static str, usize, &'static str),
+) -> Result<&'a T, Error> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| records.get(i))
+        .ok_or(Error::BadIndex {
+            lump,
+            record,
+            field,
+            index,
+            len: records.len(),
+        })
+}
+
+/// The lumps of a Quake 2 map that its brushes are read from.
+struct Quake2Map<'a> {
+    planes: &'a [raw::Plane],
+    nodes: &'a [raw::Quake2Node],
+    leaves: &'a [raw::Quake2Leaf],
+    leaf_brushes: &'a [Little<u16>],
+    brushes: &'a [raw::Brush],
+    sides: &'a [raw::BrushSide],
+    texinfo: &'a [raw::Quake2Surface],
+}
+
+impl<'a> Quake2Map<'a> {
+    /// The brushes in the leaves under `head`, in the order they were in the source.
+    fn model_brushes(&self, model: usize, head: i32) -> Result<BTreeSet<usize>, Error> {
+        let mut stack = vec![(head, ("models", model, "head_id"))];
+        let mut seen = vec![false; self.nodes.len()];
+        let mut brushes = BTreeSet::new();
+
+        while let Some((id, referrer)) = stack.pop() {
+            if id < 0 {
+                let index = -(id as i64 + 1);
+                let leaf = record(self.leaves, index, referrer)?;
+                let first = leaf.brush_index_id.native() as usize;
+
+                for i in first..first + leaf.brush_index_len.native() as usize {
+                    let field = ("leaves", index as usize, "brush_index_id");
+                    let brush = record(self.leaf_brushes, i as i64, field)?.native() as usize;
+
+                    record(self.brushes, brush as i64, ("leaf_brushes", i, "brush"))?;
+                    brushes.insert(brush);
+                }
+            } else {
+                let node = record(self.nodes, id as i64, referrer)?;
+
+                // The tree can't loop back on itself in a map that loads
+                if !mem::replace(&mut seen[id as usize], true) {
+                    stack.push((node.back_id.native(), ("nodes", id as usize, "back_id")));
+                    stack.push((node.front_id.native(), ("nodes", id as usize, "front_id")));
+                }
+            }
+        }
+
+        Ok(brushes)
+    }
+
+    fn brush(&self, index: usize, offset: &Point) -> Result<Option<MapBrush>, Error> {
+        let brush = &self.brushes[index];
+        let first = brush.side_id.native() as i64;
+        let mut planes = Vec::new();
+        let mut shown = Vec::new();
+
+        for i in first..first + brush.side_len.native() as i64 {
+            let side = record(self.sides, i, ("brushes", index, "side_id"))?;
+            let field = |name| ("brush_sides", i as usize, name);
+            let plane = record(self.planes, side.plane_id.native() as i64, field("plane_id"))?;
+            let texinfo = side.texinfo_id.native();
+
+            planes.push(Split {
+                normal: widen(&plane.normal.native()),
+                distance: plane.dist.native() as f64,
+            });
+            shown.push(if texinfo < 0 {
+                None
+            } else {
+                let texinfo = record(self.texinfo, texinfo as i64, field("texinfo_id"))?;
+                let name = texinfo
+                    .texture
+                    .iter()
+                    .map(|c| c.native())
+                    .take_while(|&c| c != 0)
+                    .collect::<Vec<_>>();
+                let s = (texinfo.s.vector.native(), texinfo.s.distance.native());
+                let t = (texinfo.t.vector.native(), texinfo.t.distance.native());
+                let projection = projection(s, t, offset, &planes[planes.len() - 1].normal);
+
+                Some((String::from_utf8_lossy(&name).into_owned(), projection))
+            });
+        }
+
+        Ok(convex_sides(&planes).map(|windings| {
+            let sides = windings
+                .into_iter()
+                .map(|(i, winding)| {
+                    let moved = winding.iter().map(|&p| p + *offset).collect::<Vec<_>>();
+
+                    (planes[i], side_points(&moved, &planes[i].normal), shown[i].clone())
+                })
+                .collect();
+
+            textured_brush(sides, "hidden")
+        }))
+    }
+}
+
+/// The records of a lump, as long as they're all inside the file.
+fn quake2_lump<'a, T>(
+    data: &'a [u8],
+    entry: &raw::Entry<T>,
+    lump: &'static str,
+) -> Result<&'a [T], Error> {
+    let (offset, len) = (entry.offset.native(), entry.len.native());
+    let size = mem::size_of::<T>();
+
+    if offset >= 0 && len >= 0 && (len as usize) % size == 0 {
+        if let Some(records) = unsafe { slice_at(data, offset as usize, len as usize / size) } {
+            return Ok(records);
+        }
+    }
+
+    Err(Error::LumpOutOfBounds {
+        lump,
+        offset,
+        len,
+        file_len: data.len(),
+    })
+}
+
+/// The source of a Quake 2 map, from the brushes it keeps for collision. Entities come back as
+/// `decompile` puts them back, and brushes whole, as they were in the source.
+pub fn decompile_quake2(data: &[u8]) -> Result<MapFile, Error> {
+    type Header = raw::Header<[u8; 4], Quake2Lump>;
+
+    let header: &Header = unsafe { value_at(data, 0) }.ok_or(Error::Truncated {
+        lump: "header",
+        expected: mem::size_of::<Header>(),
+        actual: data.len(),
+    })?;
+
+    if &header.magic[..] != Quake2::MAGIC {
+        return Err(Error::InvalidMagic {
+            expected: Quake2::MAGIC,
+            found: header.magic,
+        });
+    }
+
+    if !Quake2::accepts_version(header.version.native()) {
+        return Err(Error::VersionMismatch(header.version.native()));
+    }
+
+    let lumps = &header.lumps;
+    let map = Quake2Map {
+        planes: quake2_lump(data, &lumps.planes, "planes")?,
+        nodes: quake2_lump(data, &lumps.nodes, "nodes")?,
+        leaves: quake2_lump(data, &lumps.leaves, "leaves")?,
+        leaf_brushes: quake2_lump(data, &lumps.lbrush, "leaf_brushes")?,
+        brushes: quake2_lump(data, &lumps.brushes, "brushes")?,
+        sides: quake2_lump(data, &lumps.brush_sides, "brush_sides")?,
+        texinfo: quake2_lump(data, &lumps.texinfo, "texinfo")?,
+    };
+    let models = quake2_lump(data, &lumps.models, "models")?;
+    let text = quake2_lump(data, &lumps.entities.clone().transmute::<u8>(), "entities")?;
+    let mut out = Vec::new();
+
+    for (i, entity) in entities::parse(text).into_iter().enumerate() {
+        let (entity, model, origin) = source_entity(i, entity);
+        let mut brushes = Vec::new();
+
+        if let Some((index, model)) = model.and_then(|i| Some((i, models.get(i)?))) {
+            let offset = origin.unwrap_or(Vec3 { x: 0., y: 0., z: 0. });
+
+            for brush in map.model_brushes(index, model.head_id.native())? {
+                brushes.extend(map.brush(brush, &offset)?);
+            }
+
+            brushes.extend(origin.as_ref().map(origin_brush));
+        }
+
+        out.push(MapEntity { entity, brushes });
+    }
+
+    Ok(MapFile { entities: out })
+}
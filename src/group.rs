@@ -0,0 +1,12 @@
+//! Timing for the animated groups that models and sprites share, where each item of a group is
+//! shown for its own stretch of a loop.
+
+/// The item of a group to show `time` seconds into its loop, `times` being when each one ends.
+/// Groups are never empty once loaded.
+pub(crate) fn group_item<'t, T>(times: &[f32], items: &'t [T], time: f32) -> &'t T {
+    let total = times.last().cloned().unwrap_or(0.);
+    let time = if total > 0. { time % total } else { 0. };
+    let index = times.iter().position(|&t| t > time).unwrap_or(0);
+
+    &items[index.min(items.len() - 1)]
+}
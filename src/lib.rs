@@ -4,9 +4,31 @@
 extern crate core;
 
 extern crate ioendian;
+extern crate memmap;
+extern crate png;
+
+mod group;
+mod parallel;
+mod storage;
 
 pub mod sys;
+pub mod atlas;
 pub mod bsp;
+pub mod compile;
+pub mod decompile;
+pub mod light;
+pub mod mdl;
+pub mod map;
+pub mod nav;
+pub mod overview;
+pub mod pak;
+pub mod palette;
+pub mod render;
+pub mod spr;
+pub mod studio;
+pub mod vis;
+pub mod wad;
+pub mod waypoint;
 
 #[cfg(test)]
 mod tests {
@@ -29,7 +51,7 @@ mod tests {
                 .unwrap_or("");
 
             match node {
-                Some(&Node::Branch(ref inner)) => {
+                Some(Node::Branch(inner)) => {
                     let bounds: [[u16; 3]; 2] = unsafe { mem::transmute(inner.bounds()) };
 
                     println!("{}{}{:?}", prefix, init, bounds);
@@ -43,7 +65,7 @@ mod tests {
                     print_node_inner(Some(true), new_prefix.clone(), inner.back().as_ref());
                     print_node_inner(Some(false), new_prefix, inner.front().as_ref());
                 }
-                Some(&Node::Leaf(ref inner)) => {
+                Some(Node::Leaf(inner)) => {
                     let bounds: [[u16; 3]; 2] = unsafe { mem::transmute(inner.bounds()) };
                     println!("{}{}{:?} - {:?}", prefix, init, bounds, inner.leaf_type());
                 }
@@ -53,7 +75,7 @@ mod tests {
             }
         }
 
-        print_node_inner(None, Default::default(), Some(&node));
+        print_node_inner(None, Default::default(), Some(node));
     }
 
     #[test]
@@ -84,4 +106,1957 @@ mod tests {
 
         assert_eq!(bounds_as_array, [[2424, 832, -2544], [2432, 1248, -2352]]);
     }
+
+    #[test]
+    fn quake_dm1_point_contents() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+
+        let pos = Vec3 { x: 2426, y: 879, z: -2517 };
+        let leaf = bsp.map_model().root().unwrap().branch().unwrap().traverse(&pos).unwrap();
+        let fpos = Vec3 { x: 2426.5, y: 879.5, z: -2516.5 };
+
+        assert_eq!(bsp.point_contents(fpos), leaf.leaf_type());
+        assert_eq!(bsp.leaf_at(fpos).unwrap().bounds(), leaf.bounds());
+        assert_eq!(bsp.point_contents_with_models(fpos, vec![]), leaf.leaf_type());
+        assert_eq!(
+            bsp.point_contents(Vec3 { x: 1.0e5, y: 1.0e5, z: 1.0e5 }),
+            LeafType::Solid
+        );
+    }
+
+    #[test]
+    fn plane_sides() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let points = [
+            Vec3 { x: 2426.5, y: 879.5, z: -2516.5 },
+            Vec3 { x: -13., y: 1024., z: 64.25 },
+            Vec3 { x: 0., y: 0., z: 0. },
+        ];
+
+        // The axial fast path has to agree with the general one it stands in for
+        let mut axial = 0;
+        for (_, plane) in bsp.planes() {
+            let general = Plane {
+                plane_type: PlaneType::NonAxialX,
+                ..plane.clone()
+            };
+
+            if (plane.plane_type as u8) < 3 {
+                axial += 1;
+            }
+
+            for point in &points {
+                assert_eq!(plane.distance_to(point), general.distance_to(point));
+                assert_eq!(plane.side(point), general.side(point));
+            }
+        }
+        assert!(axial > 0);
+
+        let flipped = Plane {
+            normal: Vec3 { x: 0., y: 0., z: -1. },
+            distance: -64.,
+            plane_type: PlaneType::AxialZ,
+        };
+        let above = Vec3 { x: 5., y: 5., z: 100. };
+        assert_eq!(flipped.distance_to(&above), -36.);
+        assert_eq!(flipped.side(&above), Side::Back);
+
+        // Points exactly on a plane go to its front, both for the plane and when traversing
+        let root = bsp.map_model().root().unwrap().branch().unwrap();
+        let plane = root.plane();
+        assert!((plane.plane_type as u8) < 3);
+
+        let on = Vec3 {
+            x: plane.normal.x * plane.distance + 16. * plane.normal.y.abs(),
+            y: plane.normal.y * plane.distance + 16. * plane.normal.z.abs(),
+            z: plane.normal.z * plane.distance + 16. * plane.normal.x.abs(),
+        };
+        assert_eq!(plane.distance_to(&on), 0.);
+        assert_eq!(plane.side(&on), Side::Front);
+
+        let front = match root.front() {
+            Some(Node::Branch(branch)) => branch.traverse_float(&on),
+            Some(Node::Leaf(leaf)) => Some(leaf),
+            None => None,
+        };
+        assert_eq!(
+            root.traverse_float(&on).map(|leaf| leaf.bounds()),
+            front.map(|leaf| leaf.bounds())
+        );
+    }
+
+    #[test]
+    fn quake_dm1_face_edges() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+
+        let mut nodes = vec![bsp.root().unwrap()];
+        let mut faces = 0;
+
+        while let Some(node) = nodes.pop() {
+            let leaf = match node {
+                Node::Branch(branch) => {
+                    nodes.extend(branch.front());
+                    nodes.extend(branch.back());
+                    continue;
+                }
+                Node::Leaf(leaf) => leaf,
+            };
+
+            for face in leaf.faces() {
+                let edges = face.edges().collect::<Vec<_>>();
+
+                // Faces are closed loops, which only holds if the face records and the signed
+                // edge references are read at the right width
+                assert!(edges.len() >= 3);
+                for (edge, next) in edges.iter().zip(edges.iter().cycle().skip(1)) {
+                    assert_eq!(edge.end(), next.start());
+                }
+
+                faces += 1;
+            }
+        }
+
+        assert!(faces > 0);
+    }
+
+    #[test]
+    fn quake_dm1_visible_leaves() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let rows = dm1_vis_rows();
+
+        // Rows only cover the world's leaves, not the brush entities' ones after them
+        assert!(rows.len() < bsp.leaves().len() - 1);
+
+        for (i, row) in (1..).zip(&rows) {
+            let expected = (1..=rows.len())
+                .filter(|&j| row[(j - 1) / 8] & (1 << ((j - 1) % 8)) != 0)
+                .filter_map(|j| bsp.leaf(j))
+                .map(|leaf| leaf.bounds())
+                .collect::<Vec<_>>();
+            let visible = bsp
+                .leaf(i)
+                .unwrap()
+                .visible_leaves()
+                .map(|leaf| leaf.bounds())
+                .collect::<Vec<_>>();
+
+            assert_eq!(visible, expected, "leaf {}", i);
+        }
+    }
+
+    #[test]
+    fn leaf_type_roundtrip() {
+        for raw in -25..5 {
+            assert_eq!(i32::from(LeafType::from(raw)), raw);
+        }
+
+        assert_eq!(LeafType::from(-15), LeafType::Translucent);
+        assert_eq!(LeafType::from(1), LeafType::Other(1));
+    }
+
+    #[test]
+    fn load_errors() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+        static DM5: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/simple-dm5.bsp"));
+
+        match Bsp::<Quake1>::new(DM5) {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("Expected Unsupported, got {:?}", other),
+        }
+
+        match Bsp::<Quake1>::new(&DM1[..20]) {
+            Err(Error::Truncated { lump: "header", actual: 20, .. }) => {}
+            other => panic!("Expected truncated header, got {:?}", other),
+        }
+
+        match Bsp::<Quake1>::new(&DM1[..DM1.len() / 2]) {
+            Err(Error::LumpOutOfBounds { .. }) => {}
+            other => panic!("Expected lump out of bounds, got {:?}", other),
+        }
+
+        // The world's root is an i32, one that only fits in 16 bits after truncation is still bad
+        let models = u32::from_le_bytes([DM1[116], DM1[117], DM1[118], DM1[119]]) as usize;
+        let mut edited = DM1.to_vec();
+        edited[models + 36..models + 40].copy_from_slice(&65536i32.to_le_bytes());
+
+        match Bsp::<Quake1>::new(&edited[..]) {
+            Err(Error::BadIndex { lump: "models", field: "hulls[0]", index: 65536, .. }) => {}
+            other => panic!("Expected a bad world root, got {:?}", other),
+        }
+
+        // Portals and vis are sized by the world's leaf count, so it has to cover its tree
+        let mut edited = DM1.to_vec();
+        edited[models + 52..models + 56].copy_from_slice(&(-1i32).to_le_bytes());
+
+        match Bsp::<Quake1>::new(&edited[..]) {
+            Err(Error::BadIndex { lump: "models", field: "numleafs", index: -1, .. }) => {}
+            other => panic!("Expected a bad leaf count, got {:?}", other),
+        }
+
+        edited[models + 52..models + 56].copy_from_slice(&1i32.to_le_bytes());
+
+        match Bsp::<Quake1>::new(&edited[..]) {
+            Err(Error::BadCount { lump: "models", field: "numleafs", count: 1, .. }) => {}
+            other => panic!("Expected too few leaves, got {:?}", other),
+        }
+
+        // Texture sizes near 2^32 would overflow the size of their mip levels
+        let le32 = |at: usize| u32::from_le_bytes([DM1[at], DM1[at + 1], DM1[at + 2], DM1[at + 3]]);
+        let miptex = le32(20) as usize;
+        let texture = miptex + le32(miptex + 4) as usize;
+        let mut edited = DM1.to_vec();
+        edited[texture + 16..texture + 20].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        edited[texture + 20..texture + 24].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+
+        match Bsp::<Quake1>::new(&edited[..]) {
+            Err(Error::BadIndex { lump: "miptex", record: 0, field: "width", .. }) => {}
+            other => panic!("Expected a bad texture size, got {:?}", other),
+        }
+
+        // A face without any edges has no extents
+        let faces = le32(60) as usize;
+        let mut edited = DM1.to_vec();
+        edited[faces + 8..faces + 10].copy_from_slice(&[0, 0]);
+
+        match Bsp::<Quake1>::new(&edited[..]) {
+            Err(Error::BadCount { lump: "faces", record: 0, count: 0, min: 3, .. }) => {}
+            other => panic!("Expected a face without edges, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn quake_dm1_lumps() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+
+        assert!(bsp.models().len() > 1);
+        assert_eq!(bsp.leaves().iter().count(), bsp.leaves().len());
+        assert_eq!(bsp.leaves().get(0).unwrap().leaf_type(), LeafType::Solid);
+
+        for (_, face) in bsp.faces() {
+            let edges = face.edges().collect::<Vec<_>>();
+
+            for (a, b) in edges.iter().zip(edges.iter().cycle().skip(1)) {
+                assert_eq!(a.end(), b.start());
+            }
+        }
+
+        for (i, texture) in bsp.textures() {
+            let texture = texture.unwrap();
+
+            assert!(!texture.name().is_empty(), "Texture {} has no name", i);
+            assert_eq!(
+                texture.mip(3).unwrap().len() as u32,
+                (texture.width() / 8) * (texture.height() / 8)
+            );
+        }
+    }
+
+    #[test]
+    fn quake_dm1_hull_trace() {
+        use bsp::hull::Transform;
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let world = bsp.map_model();
+        let pos = Vec3 { x: 2426.5, y: 879.5, z: -2516.5 };
+        let below = Vec3 { x: 2426.5, y: 879.5, z: -4000. };
+
+        let trace = world.hull(0).unwrap().trace(&pos, &below);
+        assert!(!trace.start_solid);
+        assert!(trace.fraction < 1.);
+        assert!(trace.plane.unwrap().normal.z > 0.);
+
+        let offset = Vec3 { x: 64., y: -32., z: 8. };
+        let moved = Transform::from_origin(offset);
+
+        assert_eq!(
+            world.point_contents_transformed(0, &(pos + offset), &moved),
+            Some(bsp.point_contents(pos))
+        );
+
+        let moved_trace = world
+            .trace_transformed(0, &(pos + offset), &(below + offset), &moved)
+            .unwrap();
+        assert_eq!(moved_trace.fraction, trace.fraction);
+        assert_eq!(moved_trace.end_pos.z, trace.end_pos.z + offset.z);
+
+        // A submodel yawed a quarter turn, like a rotating door. Yawing by 90 degrees takes
+        // (x, y, z) to (-y, x, z), which the hit and its normal have to come back rotated by.
+        let door = bsp.model(1).unwrap();
+        let bounds = door.bounds();
+        let centre = (bounds.aa + bounds.bb) * 0.5;
+        let outside = Vec3 { x: bounds.bb.x + 64., ..centre };
+        let local = door.trace_transformed(0, &outside, &centre, &Transform::default()).unwrap();
+        let local_normal = local.plane.as_ref().unwrap().normal;
+        assert!(local.fraction < 1.);
+        assert!(local_normal.x > 0.);
+
+        let yawed = Transform {
+            origin: Vec3 { x: 100., y: -200., z: 16. },
+            angles: Vec3 { x: 0., y: 90., z: 0. },
+        };
+        let yaw = |v: Vec3<f32>| Vec3 { x: -v.y, y: v.x, z: v.z };
+        let place = |v: Vec3<f32>| yaw(v) + yawed.origin;
+        let close = |a: Vec3<f32>, b: Vec3<f32>| (a - b).dot(&(a - b)) < 1e-3;
+
+        let world_trace = door
+            .trace_transformed(0, &place(outside), &place(centre), &yawed)
+            .unwrap();
+        let plane = world_trace.plane.unwrap();
+        assert!((world_trace.fraction - local.fraction).abs() < 1e-4);
+        assert!(close(world_trace.end_pos, place(local.end_pos)));
+        assert!(close(plane.normal, yaw(local_normal)));
+        assert!(plane.normal.y > 0.);
+        assert!((plane.normal.dot(&world_trace.end_pos) - plane.distance).abs() < 0.05);
+    }
+
+    #[test]
+    fn quake_dm1_entities() {
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let entities = bsp.entities();
+
+        assert_eq!(entities[0].class_name(), Some("worldspawn"));
+
+        let start = entities
+            .iter()
+            .find(|e| e.class_name() == Some("info_player_start"))
+            .unwrap();
+        assert!(start.origin().is_some());
+
+        for entity in entities.iter().filter(|e| e.model_index().is_some()) {
+            assert!(bsp.model(entity.model_index().unwrap()).is_some());
+        }
+
+        // Quoted braces are values, and Latin-1 bytes aren't separators
+        let parsed = entities::parse(b"{ \"message\" \"}\" name a\xa0b }\n{ \"k\" \"{\" }");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].get("message"), Some("}"));
+        assert_eq!(parsed[0].properties[1].1, "a\u{fffd}b");
+        assert_eq!(parsed[1].get("k"), Some("{"));
+    }
+
+    #[test]
+    fn wad3_texture() {
+        use wad::{LumpKind, MipTex, Pic, Version, Wad};
+
+        // A 16x16 texture whose pixels are all index 1, with a palette mapping 1 to pure red
+        let mut lump = Vec::new();
+        let name = *b"{fence\0\0\0\0\0\0\0\0\0\0";
+        lump.extend_from_slice(&name);
+        le32(&mut lump, 16);
+        le32(&mut lump, 16);
+        let mut offset = 40;
+        for level in 0..4 {
+            le32(&mut lump, offset);
+            offset += (16 >> level) * (16 >> level);
+        }
+        lump.resize(offset as usize, 1);
+        lump.extend_from_slice(&[0, 1]);
+        lump.extend((0..256).flat_map(|i| if i == 1 { vec![255, 0, 0] } else { vec![0; 3] }));
+
+        let mut file = b"WAD3".to_vec();
+        le32(&mut file, 1);
+        le32(&mut file, 12 + lump.len() as i32);
+        file.extend_from_slice(&lump);
+        le32(&mut file, 12);
+        le32(&mut file, lump.len() as i32);
+        le32(&mut file, lump.len() as i32);
+        file.extend_from_slice(&[0x43, 0, 0, 0]);
+        file.extend_from_slice(&name);
+
+        let wad = Wad::new(&file[..]).unwrap();
+        assert_eq!(wad.version(), Version::Wad3);
+        assert_eq!(wad.lumps().next().unwrap().kind(), LumpKind::MipTex);
+
+        let texture = wad.texture("{FENCE").unwrap();
+        assert_eq!((texture.width, texture.height), (16, 16));
+        assert!(texture.is_masked());
+        assert_eq!(&texture.rgba(3, None).unwrap()[..4], &[255, 0, 0, 255]);
+
+        match Wad::new(&file[4..]) {
+            Err(Error::InvalidMagic { expected: b"WAD2 or WAD3", .. }) => {}
+            other => panic!("Expected invalid magic, got {:?}", other),
+        }
+
+        // Sizes from the file are untrusted, so huge ones are truncation rather than overflow
+        let mut huge = lump.clone();
+        huge[16..24].copy_from_slice(&[0xff; 8]);
+        match MipTex::parse(&huge, true) {
+            Err(Error::Truncated { lump: "miptex", .. }) => {}
+            other => panic!("Expected truncated miptex, got {:?}", other),
+        }
+
+        match Pic::parse(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f], false) {
+            Err(Error::Truncated { lump: "qpic", .. }) => {}
+            other => panic!("Expected truncated qpic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pak_roundtrip() {
+        use bsp::mapversions::Quake1;
+        use pak::{FileSystem, Pak, PakWriter};
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let mut writer = PakWriter::new();
+        writer.add("maps/dm1.bsp", DM1).unwrap();
+        writer.add("readme.txt", &b"old"[..]).unwrap();
+        writer.add("readme.txt", &b"new"[..]).unwrap();
+        assert!(writer.add("x".repeat(56), vec![]).is_err());
+        for name in &["../autoexec.cfg", "/etc/passwd", "maps/../../x.bsp", "maps/e1m1\0.bsp"] {
+            assert!(matches!(writer.add(*name, vec![]), Err(Error::Unsupported(_))));
+        }
+
+        let bytes = writer.to_bytes();
+        let pak = Pak::new(&bytes[..]).unwrap();
+
+        assert_eq!(pak.len(), 3);
+        assert_eq!(pak.get("readme.txt"), Some(&b"new"[..]));
+
+        let bsp: Bsp<Quake1> = Bsp::new(pak.get("maps/dm1.bsp").unwrap()).unwrap();
+        assert_eq!(bsp.len(), DM1.len());
+
+        let mut fs = FileSystem::new();
+        fs.add_pak(Pak::new(bytes.clone()).unwrap());
+        fs.add_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"));
+
+        assert_eq!(&*fs.read("readme.txt").unwrap(), b"new");
+        assert_eq!(fs.read("death.bsp").unwrap().len(), DM1.len());
+        assert!(fs.read("missing.txt").is_err());
+
+        // Names can't climb out of a directory layer, even to a file that's there
+        for name in &["../assets/death.bsp", "/etc/passwd", "maps/../../assets/death.bsp"] {
+            assert!(fs.read(name).is_err());
+            assert!(!fs.exists(name));
+        }
+        assert!(fs.exists("./death.bsp"));
+
+        // Offsets have to fit in the directory's `i32`s
+        let mut writer = PakWriter::new();
+        assert!(writer.add("big.dat", vec![0; i32::MAX as usize - 64]).is_err());
+        assert_eq!(writer.to_bytes().len(), 12);
+    }
+
+    #[test]
+    fn mdl_frames() {
+        use mdl::{Frame, Mdl, Skin};
+
+        fn frame(out: &mut Vec<u8>, name: &[u8], z: u8) {
+            out.extend_from_slice(&[0, 0, 0, 0, 2, 2, z, 0]);
+            let mut raw_name = [0; 16];
+            raw_name[..name.len()].copy_from_slice(name);
+            out.extend_from_slice(&raw_name);
+            for &v in &[[0, 0, z], [2, 0, z], [0, 2, z]] {
+                out.extend_from_slice(&v);
+                out.push(0);
+            }
+        }
+
+        // One triangle, an 8x4 skin, and a single frame followed by a two-frame group
+        let mut file = b"IDPO".to_vec();
+        le32(&mut file, 6);
+        f32s(&mut file, &[2., 2., 2., -1., -1., 0., 10., 0., 0., 22.]);
+        for &v in &[1, 8, 4, 3, 1, 2, 0, 0] {
+            le32(&mut file, v);
+        }
+        f32s(&mut file, &[0.]);
+
+        le32(&mut file, 0);
+        file.extend((0..32).map(|i| i as u8));
+
+        for &(on_seam, s, t) in &[(1, 0, 0), (0, 2, 0), (0, 0, 2)] {
+            le32(&mut file, on_seam);
+            le32(&mut file, s);
+            le32(&mut file, t);
+        }
+        for &v in &[0, 0, 1, 2] {
+            le32(&mut file, v);
+        }
+
+        le32(&mut file, 0);
+        frame(&mut file, b"stand1", 0);
+        le32(&mut file, 1);
+        le32(&mut file, 2);
+        file.extend_from_slice(&[0; 8]);
+        f32s(&mut file, &[0.1, 0.2]);
+        frame(&mut file, b"flame1", 0);
+        frame(&mut file, b"flame2", 4);
+
+        let mdl = Mdl::new(&file[..]).unwrap();
+
+        match mdl.skins()[0] {
+            Skin::Single(pixels) => assert_eq!(pixels.len(), 32),
+            ref other => panic!("Expected a single skin, got {:?}", other),
+        }
+
+        let triangle = mdl.triangles()[0];
+        assert!(!triangle.faces_front);
+        assert_eq!(mdl.triangle_uv(&triangle, 0), (4.5 / 8., 0.5 / 4.));
+
+        let frames = mdl.frames();
+        match frames[0] {
+            Frame::Single(ref frame) => {
+                assert_eq!(frame.name, "stand1");
+                assert_eq!(frame.position(1), Vec3 { x: 3., y: -1., z: 0. });
+            }
+            ref other => panic!("Expected a single frame, got {:?}", other),
+        }
+
+        let a = frames[1].at_time(0.05);
+        let b = frames[1].at_time(0.35);
+        assert_eq!((&*a.name, &*b.name), ("flame1", "flame2"));
+        assert_eq!(a.lerp(b, 0.5)[0], Vec3 { x: -1., y: -1., z: 4. });
+
+        assert!(Mdl::new(&file[..file.len() - 1]).is_err());
+
+        // A skin group without any skins in it
+        let mut empty = file[..84].to_vec();
+        le32(&mut empty, 1);
+        le32(&mut empty, 0);
+        empty.extend_from_slice(&file[88 + 32..]);
+        assert!(matches!(
+            Mdl::new(&empty[..]),
+            Err(Error::BadCount { lump: "skins", field: "count", count: 0, min: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn studio_pose() {
+        use studio::{SequenceGroupFile, Studio};
+
+        fn name(out: &mut Vec<u8>, name: &[u8], len: usize) {
+            let start = out.len();
+            out.extend_from_slice(name);
+            out.resize(start + len, 0);
+        }
+
+        fn patch(out: &mut [u8], at: usize, v: i32) {
+            out[at..at + 4].copy_from_slice(&v.to_le_bytes());
+        }
+
+        // Point the header field at `at` to the end of the file
+        fn here(out: &mut [u8], at: usize) {
+            let len = out.len() as i32;
+            patch(out, at, len);
+        }
+
+        let mut file = b"IDST".to_vec();
+        le32(&mut file, 10);
+        file.resize(244, 0);
+
+        // A root bone that moves along X and turns around Z, and a child 10 units in front of it
+        patch(&mut file, 140, 2);
+        here(&mut file, 144);
+        for &(parent, x) in &[(-1, 0.), (0, 10.)] {
+            name(&mut file, b"bone", 32);
+            le32(&mut file, parent);
+            le32(&mut file, 0);
+            for _ in 0..6 {
+                le32(&mut file, -1);
+            }
+            f32s(&mut file, &[x, 0., 0., 0., 0., 0.]);
+            f32s(&mut file, &[1., 1., 1., 0.01, 0.01, 0.01]);
+        }
+
+        let anims = file.len() + 2 * 176 + 2 * 104;
+        patch(&mut file, 164, 2);
+        here(&mut file, 168);
+        for &(label, group, anim_index) in &[(&b"walk"[..], 0, anims as i32), (b"idle", 1, 76)] {
+            let start = file.len();
+            name(&mut file, label, 32);
+            f32s(&mut file, &[10.]);
+            file.resize(start + 56, 0);
+            le32(&mut file, 2);
+            file.resize(start + 120, 0);
+            le32(&mut file, 1);
+            le32(&mut file, anim_index);
+            file.resize(start + 156, 0);
+            le32(&mut file, group);
+            file.resize(start + 176, 0);
+        }
+
+        patch(&mut file, 172, 2);
+        here(&mut file, 176);
+        for &group in &[&b""[..], b"models/test01.mdl"] {
+            name(&mut file, b"default", 32);
+            name(&mut file, group, 64);
+            file.resize(file.len() + 8, 0);
+        }
+
+        assert_eq!(file.len(), anims);
+        for &offsets in &[[24, 0, 0, 0, 0, 30], [0; 6]] {
+            for &offset in &offsets {
+                le16(&mut file, offset);
+            }
+        }
+        for &(a, b) in &[(0, 8), (0, 157)] {
+            file.extend_from_slice(&[2, 2]);
+            le16(&mut file, a);
+            le16(&mut file, b);
+        }
+
+        patch(&mut file, 204, 1);
+        here(&mut file, 208);
+        name(&mut file, b"body", 64);
+        le32(&mut file, 1);
+        le32(&mut file, 1);
+        let models = file.len() as i32 + 4;
+        le32(&mut file, models);
+
+        // One model with one mesh, a strip of two triangles
+        let model = file.len();
+        let mesh = model + 112;
+        let commands = mesh + 20;
+        let vertices = commands + 2 + 4 * 8 + 2;
+        name(&mut file, b"model", 64);
+        le32(&mut file, 0);
+        f32s(&mut file, &[16.]);
+        le32(&mut file, 1);
+        le32(&mut file, mesh as i32);
+        for &v in &[4, vertices + 48, vertices, 4, vertices + 52, vertices, 0, 0] {
+            le32(&mut file, v as i32);
+        }
+        for &v in &[2, commands as i32, 0, 4, 0] {
+            le32(&mut file, v);
+        }
+        le16(&mut file, 4);
+        for i in 0..4 {
+            for &v in &[i, i, i * 2, 0] {
+                le16(&mut file, v);
+            }
+        }
+        le16(&mut file, 0);
+        for i in 0..12 {
+            f32s(&mut file, &[i as f32]);
+        }
+        file.extend_from_slice(&[0, 0, 1, 1, 0, 0, 0, 0]);
+
+        patch(&mut file, 180, 1);
+        here(&mut file, 184);
+        name(&mut file, b"skin.bmp", 64);
+        let pixels = file.len() as i32 + 16;
+        for &v in &[0x40, 2, 2, pixels] {
+            le32(&mut file, v);
+        }
+        file.extend_from_slice(&[0, 1, 2, 255]);
+        file.extend((0..256).flat_map(|i| if i == 1 { vec![255, 0, 0] } else { vec![0; 3] }));
+
+        patch(&mut file, 192, 1);
+        patch(&mut file, 196, 1);
+        here(&mut file, 200);
+        le16(&mut file, 0);
+
+        let studio = Studio::new(&file[..]).unwrap();
+
+        assert_eq!(studio.bones()[1].parent, Some(0));
+        assert_eq!(studio.sequences()[1].seq_group, 1);
+        assert_eq!(studio.sequence_groups()[1].name, "models/test01.mdl");
+        assert_eq!(studio.skin_families(), vec![vec![0]]);
+
+        let texture = &studio.textures()[0];
+        assert!(texture.is_masked());
+        assert_eq!(&texture.rgba()[4..], &[255, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 0]);
+
+        let parts = studio.body_parts();
+        let model = parts[0].model_for(0).unwrap();
+        let triangles = model.meshes[0].triangles();
+        assert_eq!(
+            triangles
+                .iter()
+                .map(|t| [t[0].vertex, t[1].vertex, t[2].vertex])
+                .collect::<Vec<_>>(),
+            vec![[0, 1, 2], [2, 1, 3]]
+        );
+
+        let close = |a: Vec3<f32>, b: Vec3<f32>| {
+            (a.x - b.x).abs() < 0.05 && (a.y - b.y).abs() < 0.05 && (a.z - b.z).abs() < 0.05
+        };
+
+        let start = studio.pose(0, 0., None).unwrap();
+        assert!(close(start[1].origin, Vec3 { x: 10., y: 0., z: 0. }));
+
+        let end = studio.pose(0, 1., None).unwrap();
+        assert!(close(end[1].origin, Vec3 { x: 8., y: 10., z: 0. }));
+        assert!(close(model.posed_vertices(&end)[2], Vec3 { x: 1., y: 16., z: 8. }));
+
+        let middle = studio.pose(0, 0.5, None).unwrap();
+        assert!(close(middle[0].origin, Vec3 { x: 4., y: 0., z: 0. }));
+
+        assert!(studio.pose(1, 0., None).is_err());
+
+        let mut group = b"IDSQ".to_vec();
+        le32(&mut group, 10);
+        group.resize(76 + 24, 0);
+        let group = SequenceGroupFile::new(group).unwrap();
+        let idle = studio.pose(1, 0., Some(&group)).unwrap();
+        assert!(close(idle[1].origin, Vec3 { x: 10., y: 0., z: 0. }));
+
+        assert!(Studio::new(&file[..file.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn sprite_frames() {
+        use spr::{Frame, Orientation, RenderMode, Sprite, Version};
+
+        fn frame(out: &mut Vec<u8>, pixels: &[u8]) {
+            le32(out, -1);
+            le32(out, 1);
+            le32(out, 2);
+            le32(out, 1);
+            out.extend_from_slice(pixels);
+        }
+
+        // A GoldSrc alpha-tested sprite with one frame and then a two-frame group
+        let mut file = b"IDSP".to_vec();
+        for &v in &[2, 2, 3] {
+            le32(&mut file, v);
+        }
+        file.extend_from_slice(&8f32.to_bits().to_le_bytes());
+        for &v in &[2, 1, 2, 0, 0] {
+            le32(&mut file, v);
+        }
+        file.extend_from_slice(&[0, 1]);
+        file.extend((0..256).flat_map(|i| if i == 1 { vec![0, 255, 0] } else { vec![0; 3] }));
+
+        le32(&mut file, 0);
+        frame(&mut file, &[1, 255]);
+        le32(&mut file, 1);
+        le32(&mut file, 2);
+        file.extend_from_slice(&0.1f32.to_bits().to_le_bytes());
+        file.extend_from_slice(&0.2f32.to_bits().to_le_bytes());
+        frame(&mut file, &[1, 1]);
+        frame(&mut file, &[255, 1]);
+
+        let sprite = Sprite::new(&file[..]).unwrap();
+        assert_eq!(sprite.version(), Version::GoldSrc);
+        assert_eq!(sprite.orientation(), Orientation::Parallel);
+        assert_eq!(sprite.render_mode(), Some(RenderMode::AlphaTest));
+        assert_eq!(sprite.bounding_radius(), 8.);
+
+        let frames = sprite.frames();
+        match frames[0] {
+            Frame::Single(ref frame) => {
+                assert_eq!(frame.origin, (-1, 1));
+                assert_eq!(
+                    sprite.rgba(frame, None).unwrap(),
+                    vec![0, 255, 0, 255, 0, 0, 0, 0]
+                );
+            }
+            ref other => panic!("Expected a single frame, got {:?}", other),
+        }
+        assert_eq!(frames[1].at_time(0.15).pixels, &[255, 1]);
+        assert_eq!(frames[1].at_time(0.25).pixels, &[1, 1]);
+
+        assert!(Sprite::new(&file[..file.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn palette_colormap() {
+        use palette::{self, Colormap, Palette};
+
+        let quake = Palette::quake();
+        assert_eq!(quake.rgb(0), [0, 0, 0]);
+        assert_eq!(quake.rgb(254), [255, 255, 255]);
+        assert_eq!(Palette::new(quake.as_bytes()).unwrap(), quake);
+        assert!(Palette::new(&quake.as_bytes()[1..]).is_err());
+
+        assert!(!palette::is_fullbright(223));
+        assert!(palette::is_fullbright(224));
+
+        let colormap = Colormap::generate(&quake);
+        assert_eq!(colormap.lookup(15, 63), 0);
+        assert_eq!(colormap.lookup(250, 63), 250);
+        assert_eq!(colormap.shade(250, 0), 250);
+        assert_eq!(colormap.shade(15, 0), 0);
+
+        // Brighter light picks a brighter shade
+        let dim = quake.rgb(colormap.shade(8, 64));
+        let bright = quake.rgb(colormap.shade(8, 192));
+        assert!(dim[0] < bright[0]);
+
+        assert_eq!(palette::light_row(0), 63);
+        assert_eq!(palette::light_row(255 * 264), 0);
+
+        let mut lump = vec![0; 64 * 256 + 1];
+        lump[256 + 7] = 3;
+        assert_eq!(Colormap::new(&lump).unwrap().lookup(7, 1), 3);
+        assert!(Colormap::new(&lump[..100]).is_err());
+    }
+
+    #[test]
+    fn quake_dm1_render() {
+        use bsp::mapversions::Quake1;
+        use render::{Camera, Renderer};
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+
+        for (_, face) in bsp.faces() {
+            let size = face.extents().size;
+
+            for (_, map) in face.lightmaps() {
+                assert_eq!(map.len() as u32, size[0] * size[1]);
+            }
+        }
+
+        let entities = bsp.entities();
+        let start = entities
+            .iter()
+            .find(|e| e.class_name() == Some("info_player_start"))
+            .unwrap();
+        let camera = Camera::new(
+            start.origin().unwrap() + Vec3 { x: 0., y: 0., z: 22. },
+            start.angles().unwrap(),
+            64,
+            48,
+        );
+
+        let image = Renderer::new(&bsp).render(&camera);
+        assert_eq!(image.pixels.len(), 64 * 48 * 4);
+
+        // Stretching a texture out past what the engine would light leaves its faces unlit
+        // rather than overflowing the sample count
+        let mut stretched = DM1.to_vec();
+        let texinfo = u32::from_le_bytes([DM1[52], DM1[53], DM1[54], DM1[55]]) as usize;
+        for at in (texinfo..texinfo + 12).step_by(4) {
+            stretched[at..at + 4].copy_from_slice(&1e30f32.to_le_bytes());
+        }
+        let stretched: Bsp<Quake1> = Bsp::new(&stretched).unwrap();
+        let oversized = stretched
+            .faces()
+            .iter()
+            .filter(|(_, face)| !face.extents().is_valid())
+            .inspect(|(_, face)| assert!(face.lightmaps().is_empty()))
+            .count();
+        assert!(oversized > 0);
+        Renderer::new(&stretched).render(&camera);
+
+        // Standing inside the map, every pixel should be covered by something
+        assert!(image.pixels.chunks(4).all(|p| p[3] == 255));
+        assert!(image.pixels.chunks(4).any(|p| p != &image.pixels[..4]));
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+
+    #[test]
+    fn quake_dm1_overview() {
+        use bsp::mapversions::Quake1;
+        use overview::{Overview, Shading};
+        use render::Renderer;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let bounds = bsp.map_model().bounds();
+        let renderer = Renderer::new(&bsp);
+
+        let mut overview = Overview::new(bounds.aa.z, bounds.bb.z, 128, 128);
+        let (image, transform) = renderer.render_overview(&overview);
+
+        let start = bsp.entities()
+            .into_iter()
+            .find(|e| e.class_name() == Some("info_player_start"))
+            .unwrap()
+            .origin()
+            .unwrap();
+        let (x, y) = transform.world_to_pixel(start);
+        assert!((0. ..128.).contains(&x) && (0. ..128.).contains(&y));
+        assert_eq!(image.pixel(x as u32, y as u32)[3], 255);
+
+        let (wx, wy) = transform.pixel_to_world(x, y);
+        assert!((wx - start.x).abs() < 0.01 && (wy - start.y).abs() < 0.01);
+
+        // A slice above the whole map has nothing in it
+        overview.z_min = bounds.bb.z + 1.;
+        overview.z_max = bounds.bb.z + 100.;
+        overview.shading = Shading::Flat;
+        let (empty, empty_transform) = renderer.render_overview(&overview);
+        assert!(empty.pixels.chunks(4).all(|p| p[3] == 0));
+
+        // Every slice is drawn with the same transform, so the floors line up
+        assert_eq!(empty_transform, transform);
+    }
+
+    #[test]
+    fn quake_dm1_lightmap_atlas() {
+        use atlas::LightmapAtlas;
+        use bsp::mapversions::Quake1;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let atlas = LightmapAtlas::new(&bsp, 256, 1).unwrap();
+
+        assert_eq!(atlas.faces.len(), bsp.faces().len());
+        assert!(!atlas.pages.is_empty());
+
+        let mut rects = Vec::new();
+
+        for ((_, face), lightmaps) in bsp.faces().into_iter().zip(&atlas.faces) {
+            let source = face.lightmaps();
+            assert_eq!(lightmaps.len(), source.len());
+
+            for (lightmap, (style, data)) in lightmaps.iter().zip(source) {
+                let rect = lightmap.rect;
+                assert_eq!(lightmap.style, style);
+                assert_eq!(lightmap.uvs.len(), face.vertices().len());
+                assert!(lightmap.uvs.iter().all(|&(u, v)| {
+                    (0. ..=1.).contains(&u) && (0. ..=1.).contains(&v)
+                }));
+
+                let page = &atlas.pages[rect.page];
+                assert_eq!(page.pixel(rect.x, rect.y)[0], data[0]);
+                // The padding repeats the edge
+                assert_eq!(page.pixel(rect.x - 1, rect.y - 1)[0], data[0]);
+
+                rects.push(rect);
+            }
+        }
+
+        // Padded rectangles never overlap
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                assert!(
+                    a.page != b.page || a.x + a.width + 2 <= b.x ||
+                        b.x + b.width + 2 <= a.x ||
+                        a.y + a.height + 2 <= b.y ||
+                        b.y + b.height + 2 <= a.y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quake_dm1_navmesh() {
+        use bsp::mapversions::Quake1;
+        use nav::{LinkKind, NavConfig, NavMesh};
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let config = NavConfig::default();
+        let mesh = NavMesh::new(&bsp, &config).unwrap();
+
+        assert!(!mesh.polygons.is_empty());
+
+        // Floors the compiler cut up are merged back together, and every face is used once
+        let mut faces = mesh.polygons
+            .iter()
+            .flat_map(|polygon| polygon.faces.iter().cloned())
+            .collect::<Vec<_>>();
+        let total = faces.len();
+        faces.sort_unstable();
+        faces.dedup();
+        assert_eq!(faces.len(), total);
+        assert!(mesh.polygons.len() < total);
+
+        for polygon in &mesh.polygons {
+            assert!(polygon.normal.z >= config.min_normal_z);
+
+            // Merged polygons are still convex, so every vertex is inside all of the edges. Faces
+            // can be a little off convex from the precision of their vertices, so look just inside.
+            assert!(polygon.vertices.iter().all(|&v| {
+                let inside = v + (polygon.centre - v) * 0.01;
+
+                polygon.contains_xy(inside.x, inside.y)
+            }));
+
+            for link in &polygon.links {
+                assert!(link.to < mesh.polygons.len());
+
+                if link.kind != LinkKind::Jump {
+                    assert!(mesh.polygons[link.to].links.iter().any(|back| back.kind == link.kind));
+                }
+            }
+        }
+
+        let spawns = bsp.entities()
+            .into_iter()
+            .filter(|e| {
+                e.class_name()
+                    .is_some_and(|c| c.starts_with("info_player_"))
+            })
+            .filter_map(|e| e.origin())
+            .collect::<Vec<_>>();
+        assert_eq!(spawns.len(), 6);
+
+        // From one deathmatch spawn down to another, some way below
+        let (start, end) = (spawns[5], spawns[4]);
+        let path = mesh.find_path(start, end, &config).unwrap();
+        assert_eq!(path.points.first(), Some(&start));
+        assert_eq!(path.points.last(), Some(&end));
+        assert_eq!(path.polygons[0], mesh.polygon_at(start).unwrap());
+        assert_eq!(path.polygons.last(), mesh.polygon_at(end).as_ref());
+
+        let kinds = path.polygons
+            .windows(2)
+            .map(|pair| {
+                mesh.polygons[pair[0]]
+                    .links
+                    .iter()
+                    .find(|l| l.to == pair[1])
+                    .unwrap()
+                    .kind
+            })
+            .collect::<Vec<_>>();
+        assert!(kinds.iter().any(|&k| k != LinkKind::Walk));
+    }
+
+    #[test]
+    fn quake_dm1_waypoints() {
+        use bsp::mapversions::Quake1;
+        use nav::NavConfig;
+        use waypoint::WaypointGraph;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let config = NavConfig::default();
+        let graph = WaypointGraph::generate(&bsp, &config, 64.).unwrap();
+
+        // Spacings the grid can't be laid out with are refused
+        for &spacing in &[0., -64., f32::NAN, f32::INFINITY] {
+            assert!(WaypointGraph::generate(&bsp, &config, spacing).is_err());
+        }
+
+        assert!(graph.waypoints.len() > 100);
+        assert!(graph.waypoints.iter().any(|w| !w.links.is_empty()));
+
+        for waypoint in &graph.waypoints {
+            for link in &waypoint.links {
+                let other = graph.waypoints[link.to].origin;
+                assert!((other.z - waypoint.origin.z) <= config.jump_height);
+            }
+        }
+
+        let start = bsp.entities()
+            .into_iter()
+            .find(|e| e.class_name() == Some("info_player_start"))
+            .unwrap()
+            .origin()
+            .unwrap();
+        let nearest = graph.waypoints[graph.nearest(start).unwrap()].origin - start;
+        assert!(nearest.dot(&nearest).sqrt() < 64.);
+
+        let bytes = graph.to_bytes();
+        assert_eq!(WaypointGraph::read(&bytes).unwrap(), graph);
+        assert!(WaypointGraph::read(&bytes[..bytes.len() - 1]).is_err());
+
+        // A graph saved for another map is regenerated
+        let mut stale = graph.clone();
+        stale.map_key ^= 1;
+        stale.waypoints.truncate(1);
+        let reloaded =
+            WaypointGraph::load_or_generate(Some(&stale.to_bytes()), &bsp, &config, 64.).unwrap();
+        assert_eq!(reloaded, graph);
+    }
+
+    #[test]
+    fn map_checksum() {
+        use bsp::checksum::{crc32, md4};
+        use bsp::mapversions::{Goldsrc, Quake1};
+        use compile::{compile, CompileConfig};
+        use map::MapFile;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        // RFC 1320's test suite, as the digests' little-endian words
+        assert_eq!(md4(b""), [0xe0cf_d631, 0x31e9_6ad1, 0xd759_3cb7, 0xc089_c0e0]);
+        assert_eq!(md4(b"abc"), [0x7a01_48a4, 0x52d8_21af, 0xe80a_c15f, 0x9d72_a67a]);
+        // Two blocks long
+        assert_eq!(
+            md4(&b"1234567890".repeat(8)),
+            [0xdc4d_3be3, 0x19f2_389c, 0x167b_3e9c, 0x3605_cc4f]
+        );
+        assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+
+        // The values an independent MD4 gives for the lumps QuakeWorld reads
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let checksum = bsp.checksum();
+        assert_eq!(checksum, 0xe30f_6937);
+        assert_eq!(bsp.checksum2(), 0xe1ba_1c0e);
+
+        // Servers change the entities freely, so they aren't covered
+        let entities = bsp.entities().len();
+        let mut edited = DM1.to_vec();
+        let at = edited
+            .windows(15)
+            .position(|w| w == b"info_player_sta")
+            .unwrap();
+        edited[at] = b'I';
+        let edited: Bsp<Quake1> = Bsp::new(&edited[..]).unwrap();
+        assert_eq!(edited.entities().len(), entities);
+        assert_eq!(edited.checksum(), checksum);
+
+        // GoldSrc's `CRC_MapFile` runs one CRC over every lump after the entities, as they're
+        // laid out in the header
+        let map = MapFile::parse(&test_map(&[])).unwrap();
+        let goldsrc = compile::<Goldsrc>(&map, &CompileConfig::default()).unwrap().bsp;
+        let data = goldsrc.as_bytes();
+        let le32 = |at: usize| {
+            u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+        };
+        let crc = (1..15).fold(!0, |crc, i| {
+            let (offset, len) = (le32(4 + i * 8), le32(8 + i * 8));
+
+            crc32(crc, &data[offset..offset + len])
+        });
+        assert_eq!(goldsrc.checksum(), !crc);
+        assert_ne!(goldsrc.checksum(), goldsrc.checksum2());
+    }
+
+    #[test]
+    fn quake_dm1_raycast() {
+        use bsp::mapversions::Quake1;
+        use bsp::Vec3;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let start = bsp.entities()
+            .into_iter()
+            .find(|e| e.class_name() == Some("info_player_start"))
+            .unwrap()
+            .origin()
+            .unwrap();
+        let down = start - Vec3 { x: 0., y: 0., z: 4096. };
+
+        // Spawn points float a little above the floor, the hull 0 trace agrees on where it is
+        let hit = bsp.raycast(start, down).unwrap();
+        let trace = bsp.map_model().hull(0).unwrap().trace(&start, &down);
+        assert!((hit.point.z - trace.end_pos.z).abs() < 0.1);
+        assert!((hit.distance - (start.z - hit.point.z)).abs() < 0.01);
+        assert_eq!(
+            bsp.point_contents(hit.point - Vec3 { x: 0., y: 0., z: 1. }),
+            LeafType::Solid
+        );
+        assert!(hit.normal.z > 0.99);
+        assert!(hit.texture.is_some());
+
+        let face = bsp.faces().get(hit.face.unwrap()).unwrap();
+        assert!(face.plane().normal.z > 0.99);
+        assert!((face.plane().distance_to(&hit.point)).abs() < 0.1);
+
+        assert!(!bsp.can_see(start, down));
+        assert!(bsp.can_see(start, hit.point + Vec3 { x: 0., y: 0., z: 1. }));
+        assert!(bsp.raycast(start, start + Vec3 { x: 0., y: 0., z: 4. }).is_none());
+
+        // Starting inside a wall hits straight away
+        let buried = bsp.raycast(down, start).unwrap();
+        assert_eq!(buried.distance, 0.);
+        assert_eq!(buried.face, None);
+        assert_eq!(buried.normal, Vec3 { x: 0., y: 0., z: -1. });
+        assert_eq!(bsp.raycast(down, down).unwrap().normal, Vec3 { x: 0., y: 0., z: 0. });
+    }
+
+    #[test]
+    fn quake_dm1_portals() {
+        use bsp::mapversions::Quake1;
+        use bsp::Vec3;
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let portals = bsp.portals();
+        let mut connected = vec![false; portals.num_leaves + 1];
+
+        assert!(portals.num_leaves < bsp.leaves().len());
+        assert!(!portals.portals.is_empty());
+
+        for portal in &portals.portals {
+            let (front, back) = portal.leaves;
+
+            assert!(portal.winding.len() >= 3);
+            assert!(front != back);
+            assert!(front <= portals.num_leaves && back <= portals.num_leaves);
+
+            for point in &portal.winding {
+                assert!(portal.plane.distance_to(point).abs() < 0.1);
+            }
+
+            connected[front] = true;
+            connected[back] = true;
+
+            // Just in front of the middle of the portal is the front leaf and just behind it the
+            // back one, looking close by since leaves can be a fraction of a unit thick there
+            let centre = portal
+                .winding
+                .iter()
+                .fold(Vec3 { x: 0., y: 0., z: 0. }, |sum, &p| sum + p) *
+                (1. / portal.winding.len() as f32);
+            let bounds = |leaf: Option<Leaf<_>>| leaf.map(|leaf| leaf.bounds());
+
+            assert_eq!(
+                bounds(bsp.leaf_at(centre + portal.plane.normal * 0.01)),
+                bounds(bsp.leaf(front))
+            );
+            assert_eq!(
+                bounds(bsp.leaf_at(centre - portal.plane.normal * 0.01)),
+                bounds(bsp.leaf(back))
+            );
+        }
+
+        // The only leaves without neighbours are boxes sealed off from the rest of the map, that
+        // a ray from the middle can't leave in any direction
+        let lonely = (1..=portals.num_leaves)
+            .filter(|&i| !connected[i])
+            .collect::<Vec<_>>();
+        assert_eq!(lonely.len(), 9);
+
+        for leaf in lonely {
+            let bounds = bsp.leaf(leaf).unwrap().bounds();
+            let (min, max) = (bounds.aa, bounds.bb);
+            let centre = Vec3 {
+                x: (min.x + max.x) as f32 / 2.,
+                y: (min.y + max.y) as f32 / 2.,
+                z: (min.z + max.z) as f32 / 2.,
+            };
+            assert_eq!(bsp.leaf_at(centre).map(|leaf| leaf.bounds()), Some(bounds.clone()));
+
+            for axis in 0..6 {
+                let mut direction = [0.; 3];
+                direction[axis % 3] = if axis < 3 { 8192. } else { -8192. };
+                let end = centre + Vec3 { x: direction[0], y: direction[1], z: direction[2] };
+                let hit = bsp.raycast(centre, end).unwrap().point;
+
+                assert!(hit.x >= min.x as f32 - 1. && hit.x <= max.x as f32 + 1.);
+                assert!(hit.y >= min.y as f32 - 1. && hit.y <= max.y as f32 + 1.);
+                assert!(hit.z >= min.z as f32 - 1. && hit.z <= max.z as f32 + 1.);
+            }
+        }
+
+        let prt = String::from_utf8(portals.to_bytes()).unwrap();
+        let mut lines = prt.lines();
+        assert_eq!(lines.next(), Some("PRT1"));
+        assert_eq!(lines.next(), Some(&*portals.num_leaves.to_string()));
+
+        let count = lines.next().unwrap().parse::<usize>().unwrap();
+        assert!(count > 0 && count <= portals.portals.len());
+
+        for line in lines.by_ref().take(count) {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let points = fields[0].parse::<usize>().unwrap();
+
+            assert!(fields[1].parse::<usize>().unwrap() < portals.num_leaves);
+            assert!(fields[2].parse::<usize>().unwrap() < portals.num_leaves);
+            assert_eq!(line.matches('(').count(), points);
+        }
+
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn portal_file() {
+        use bsp::mapversions::Quake1;
+        use compile::{compile, CompileConfig};
+        use map::MapFile;
+
+        // Two rooms either side of a wall with a doorway through it, which the compiler makes
+        // into a leaf of its own
+        let mut map = String::from("{\n\"classname\" \"worldspawn\"\n");
+        for wall in &[
+            box_brush([-144, -80, -16], [144, 80, 0], "floor"),
+            box_brush([-144, -80, 128], [144, 80, 144], "ceiling"),
+            box_brush([-144, -80, 0], [-128, 80, 128], "wall"),
+            box_brush([128, -80, 0], [144, 80, 128], "wall"),
+            box_brush([-128, -80, 0], [128, -64, 128], "wall"),
+            box_brush([-128, 64, 0], [128, 80, 128], "wall"),
+            box_brush([-8, -64, 0], [8, -16, 128], "wall"),
+            box_brush([-8, 16, 0], [8, 64, 128], "wall"),
+        ] {
+            map += wall;
+        }
+        map += "}\n{\n\"classname\" \"info_player_start\"\n\"origin\" \"64 0 24\"\n}\n";
+
+        let map = MapFile::parse(&map).unwrap();
+        let bsp = compile::<Quake1>(&map, &CompileConfig::default()).unwrap().bsp;
+        let leaf = |i: usize| bsp.leaf(i).unwrap().bounds();
+        assert_eq!((leaf(1).aa.x, leaf(2).aa.x, leaf(3).bb.x), (8, -8, -8));
+
+        // One portal on each side of the doorway, between the leaves numbered from 0 without the
+        // solid leaf. The plane through each line's first three points faces its first leaf, the
+        // east room for the first and the doorway for the second, as `vis` expects.
+        assert_eq!(
+            String::from_utf8(bsp.portals().to_bytes()).unwrap(),
+            "PRT1\n\
+             3\n\
+             2\n\
+             4 0 1 (8 16 128 ) (8 16 0 ) (8 -16 0 ) (8 -16 128 ) \n\
+             4 1 2 (-8 16 128 ) (-8 16 0 ) (-8 -16 0 ) (-8 -16 128 ) \n"
+        );
+    }
+
+    #[test]
+    fn quake_dm1_vis() {
+        use bsp::mapversions::Quake1;
+        use vis::{compress_row, Visibility, VisMode};
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        assert_eq!(compress_row(&[0xff, 0, 0, 0, 1, 0]), [0xff, 0, 3, 1, 0, 1]);
+        assert_eq!(compress_row(&[0; 300]), [0, 255, 0, 45]);
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+
+        // Every leaf can see itself in the map's own data
+        for i in 1..100 {
+            let leaf = bsp.leaf(i).unwrap();
+            assert!(leaf.visible_leaves().any(|other| other.bounds() == leaf.bounds()));
+        }
+
+        let vis = Visibility::new(&bsp, VisMode::Fast);
+        let n = vis.num_leaves;
+        let visible = (1..=n)
+            .map(|i| (1..=n).filter(|&j| vis.can_see(i, j)).count())
+            .collect::<Vec<_>>();
+
+        assert!((1..=n).all(|i| vis.can_see(i, i)));
+        assert!(!vis.can_see(0, 1));
+        assert!(visible.iter().sum::<usize>() < n * n / 2);
+
+        let vised = vis.apply(&bsp).unwrap();
+
+        // Only the vislist and the leaves pointing into it change
+        assert_eq!(vised.checksum2(), bsp.checksum2());
+        assert!(!vised.vislist().is_empty());
+
+        for i in 1..=n {
+            assert_eq!(vised.leaf(i).unwrap().visible_leaves().count(), visible[i - 1]);
+        }
+    }
+
+    /// death.bsp's own vis rows, uncompressed with one bit per leaf from leaf 1, decoded straight
+    /// from the file.
+    fn dm1_vis_rows() -> Vec<Vec<u8>> {
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        fn int(offset: usize) -> usize {
+            let bytes = [DM1[offset], DM1[offset + 1], DM1[offset + 2], DM1[offset + 3]];
+            i32::from_le_bytes(bytes) as usize
+        }
+
+        // The vislist, leaves and models are lumps 4, 10 and 14, leaves are 28 bytes with the row
+        // offset at 4, and the world model (64 bytes) gives how many leaves each row covers at 52
+        let lump = |i: usize| int(4 + i * 8);
+        let (vislist, leaves) = (lump(4), lump(10));
+        let num_leaves = int(lump(14) + 52);
+
+        (1..=num_leaves)
+            .map(|i| {
+                let mut compressed = DM1[vislist + int(leaves + i * 28 + 4)..].iter();
+                let mut row = Vec::new();
+
+                while row.len() * 8 < num_leaves {
+                    match *compressed.next().unwrap() {
+                        0 => {
+                            let run = *compressed.next().unwrap() as usize;
+                            row.resize(row.len() + run, 0);
+                        }
+                        byte => row.push(byte),
+                    }
+                }
+
+                row.truncate(num_leaves.div_ceil(8));
+                if !num_leaves.is_multiple_of(8) {
+                    *row.last_mut().unwrap() &= (1 << (num_leaves % 8)) - 1;
+                }
+
+                row
+            })
+            .collect()
+    }
+
+    fn le16(out: &mut Vec<u8>, v: i16) {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn le32(out: &mut Vec<u8>, v: i32) {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32s(out: &mut Vec<u8>, values: &[f32]) {
+        for v in values {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    /// An axis-aligned box brush in the standard Quake format.
+    fn box_brush(min: [i32; 3], max: [i32; 3], texture: &str) -> String {
+        let ([x1, y1, z1], [x2, y2, z2]) = (min, max);
+        let faces = [
+            [[x1, y1, z1], [x1, y2, z1], [x1, y1, z2]],
+            [[x2, y1, z1], [x2, y1, z2], [x2, y2, z1]],
+            [[x1, y1, z1], [x1, y1, z2], [x2, y1, z1]],
+            [[x1, y2, z1], [x2, y2, z1], [x1, y2, z2]],
+            [[x1, y1, z1], [x2, y1, z1], [x1, y2, z1]],
+            [[x1, y1, z2], [x1, y2, z2], [x2, y1, z2]],
+        ];
+        let mut out = String::from("{\n");
+
+        for points in &faces {
+            for p in points {
+                out += &format!("( {} {} {} ) ", p[0], p[1], p[2]);
+            }
+            out += &format!("{} 0 0 0 1 1\n", texture);
+        }
+
+        out + "}\n"
+    }
+
+    /// A sealed room with a pool, a pillar in Valve 220 format and a door, leaving out the walls
+    /// in `skip`.
+    fn test_map(skip: &[usize]) -> String {
+        let walls = [
+            box_brush([-144, -144, -16], [144, 144, 0], "floor"),
+            box_brush([-144, -144, 128], [144, 144, 144], "ceiling"),
+            box_brush([-144, -144, 0], [-128, 144, 128], "wall"),
+            box_brush([128, -144, 0], [144, 144, 128], "wall"),
+            box_brush([-128, -144, 0], [128, -128, 128], "wall"),
+            box_brush([-128, 128, 0], [128, 144, 128], "wall"),
+            box_brush([-128, -128, 0], [0, 0, 32], "*water"),
+        ];
+        let mut map = String::from("// Test map\n{\n\"classname\" \"worldspawn\"\n");
+
+        for (i, wall) in walls.iter().enumerate() {
+            if !skip.contains(&i) {
+                map += wall;
+            }
+        }
+
+        map += "{\n";
+        for (points, axes) in &[
+            ("( -16 32 0 ) ( -16 64 0 ) ( -16 32 128 )", "[ 0 1 0 0 ] [ 0 0 -1 0 ]"),
+            ("( 16 32 0 ) ( 16 32 128 ) ( 16 64 0 )", "[ 0 1 0 0 ] [ 0 0 -1 0 ]"),
+            ("( -16 32 0 ) ( -16 32 128 ) ( 16 32 0 )", "[ 1 0 0 0 ] [ 0 0 -1 0 ]"),
+            ("( -16 64 0 ) ( 16 64 0 ) ( -16 64 128 )", "[ 1 0 0 0 ] [ 0 0 -1 0 ]"),
+            ("( -16 32 0 ) ( 16 32 0 ) ( -16 64 0 )", "[ 1 0 0 8 ] [ 0 -1 0 8 ]"),
+            ("( -16 32 128 ) ( -16 64 128 ) ( 16 32 128 )", "[ 1 0 0 8 ] [ 0 -1 0 8 ]"),
+        ] {
+            map += &format!("{} pillar {} 0 0.5 0.5\n", points, axes);
+        }
+        map += "}\n}\n";
+
+        map += "{\n\"classname\" \"info_player_start\"\n\"origin\" \"64 -64 24\"\n}\n";
+        map += "{\n\"classname\" \"light\"\n\"origin\" \"0 0 100\"\n\"light\" \"300\"\n}\n";
+        map += "{\n\"classname\" \"func_door\"\n\"angle\" \"90\"\n";
+        map += &box_brush([64, 64, 0], [96, 96, 64], "door");
+        map += "}\n";
+
+        map
+    }
+
+    #[test]
+    fn quake_dm1_full_vis() {
+        use bsp::mapversions::Quake1;
+        use vis::{Visibility, VisMode};
+
+        static DM1: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/death.bsp"));
+
+        let bsp: Bsp<Quake1> = Bsp::new(DM1).unwrap();
+        let fast = Visibility::new(&bsp, VisMode::Fast);
+        let full = Visibility::new(&bsp, VisMode::Full);
+        let n = fast.num_leaves;
+
+        assert_eq!(full.num_leaves, n);
+
+        // Full vis only takes away from what the fast pass lets through, never adds to it
+        for i in 1..=n {
+            assert!(full.can_see(i, i));
+
+            for (fast, full) in fast.row(i).unwrap().iter().zip(full.row(i).unwrap()) {
+                assert_eq!(full & !fast, 0, "leaf {}", i);
+            }
+        }
+
+        let count = |vis: &Visibility| {
+            (1..=n).map(|i| (1..=n).filter(|&j| vis.can_see(i, j)).count()).sum::<usize>()
+        };
+
+        assert!(count(&full) < count(&fast));
+
+        // Nor does it hide anything id's vis found visible, and sight goes both ways
+        for (i, shipped) in (1..).zip(dm1_vis_rows()) {
+            for (shipped, full) in shipped.iter().zip(full.row(i).unwrap()) {
+                assert_eq!(shipped & !full, 0, "leaf {}", i);
+            }
+
+            for j in 1..i {
+                assert_eq!(full.can_see(i, j), full.can_see(j, i), "leaves {} and {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn compile_map() {
+        use bsp::mapversions::{Goldsrc, Quake1};
+        use compile::{compile, CompileConfig};
+        use map::{MapFile, Projection};
+        use vis::{Visibility, VisMode};
+
+        let v = |x, y, z| Vec3 { x, y, z };
+
+        let map = MapFile::parse(&test_map(&[])).unwrap();
+
+        assert_eq!(map.entities.len(), 4);
+        assert_eq!(map.world().unwrap().brushes.len(), 8);
+        assert!(matches!(
+            map.world().unwrap().brushes[7].faces[0].projection,
+            Projection::Valve { scale: [0.5, 0.5], .. }
+        ));
+        assert!(matches!(
+            MapFile::parse("{\n\"classname\" \"worldspawn\"\n{\n( 0 0 ) ( 1 1 1 )"),
+            Err(Error::Syntax { line: 4, .. })
+        ));
+        // Only ASCII whitespace separates tokens, so anything else is an error rather than a hang
+        assert!(matches!(
+            MapFile::parse("{\n\"classname\" \"worldspawn\"\u{a0}\n}\n"),
+            Err(Error::Syntax { .. })
+        ));
+
+        let compiled = compile::<Quake1>(&map, &CompileConfig::default()).unwrap();
+        let bsp = &compiled.bsp;
+
+        assert_eq!(compiled.leak, None);
+        assert!(compiled.missing_textures.contains(&"pillar".to_owned()));
+        assert_eq!(bsp.models().len(), 2);
+        assert_eq!(bsp.entities()[3].model_index(), Some(1));
+
+        assert_eq!(bsp.point_contents(v(64., -64., 24.)), LeafType::Ordinary);
+        assert_eq!(bsp.point_contents(v(-64., -64., 16.)), LeafType::Water);
+        assert_eq!(bsp.point_contents(v(0., 48., 64.)), LeafType::Solid);
+        assert_eq!(bsp.point_contents(v(-136., 0., 64.)), LeafType::Solid);
+        // Everything outside the room is filled in
+        assert_eq!(bsp.point_contents(v(1000., 0., 64.)), LeafType::Solid);
+
+        let hit = bsp.raycast(v(64., -64., 24.), v(64., -64., -100.)).unwrap();
+        assert_eq!(hit.texture.as_deref(), Some("floor"));
+        assert_eq!(hit.point.z, 0.);
+
+        let hit = bsp.raycast(v(64., 48., 64.), v(-100., 48., 64.)).unwrap();
+        assert_eq!(hit.texture.as_deref(), Some("pillar"));
+        assert_eq!(hit.point.x, 16.);
+
+        let door = bsp.model(1).unwrap();
+        assert!(door.faces().count() >= 6);
+        assert!(!door.can_see(&v(80., 80., -10.), &v(80., 80., 100.)));
+
+        let vised = Visibility::new(bsp, VisMode::Full).apply(bsp).unwrap();
+        let light = vised.leaf_at(v(0., 0., 100.)).unwrap();
+        assert!(vised
+            .leaf_at(v(64., -64., 24.))
+            .unwrap()
+            .visible_leaves()
+            .any(|leaf| leaf.bounds() == light.bounds()));
+
+        // The player's hull keeps its centre 16 units away from the walls, 24 above the floor and
+        // 32 below the ceiling
+        let player = bsp.map_model().hull(1).unwrap();
+        assert_eq!(player.point_contents(&v(120., -64., 40.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(-120., -64., 40.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(64., 120., 40.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(64., -120., 40.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(64., -64., 24.)), LeafType::Ordinary);
+        assert_eq!(player.point_contents(&v(64., -64., 20.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(64., -64., 95.)), LeafType::Ordinary);
+        assert_eq!(player.point_contents(&v(64., -64., 100.)), LeafType::Solid);
+        assert_eq!(player.point_contents(&v(1000., 0., 64.)), LeafType::Solid);
+        let trace = player.trace(&v(64., -64., 40.), &v(200., -64., 40.));
+        assert!((trace.end_pos.x - 112.).abs() < 0.1);
+        let trace = player.trace(&v(64., -64., 40.), &v(64., -64., -100.));
+        assert!((trace.end_pos.z - 24.).abs() < 0.1);
+        let trace = player.trace(&v(64., -64., 40.), &v(64., -64., 200.));
+        assert!((trace.end_pos.z - 96.).abs() < 0.1);
+
+        // Half-Life's crouching hull fits under things that its standing hull doesn't
+        let goldsrc = compile::<Goldsrc>(&map, &CompileConfig::default()).unwrap();
+        let world = goldsrc.bsp.map_model();
+        assert_eq!(world.hull(1).unwrap().point_contents(&v(64., -64., 100.)), LeafType::Solid);
+        assert_eq!(world.hull(3).unwrap().point_contents(&v(64., -64., 100.)), LeafType::Ordinary);
+
+        // Without the east wall the player can walk out of the map
+        let leaky = MapFile::parse(&test_map(&[3])).unwrap();
+        let compiled = compile::<Quake1>(&leaky, &CompileConfig::default()).unwrap();
+        let leak = compiled.leak.unwrap();
+
+        assert_eq!(leak.entity, 1);
+        assert_eq!(leak.points[0], v(64., -64., 24.));
+        assert!(leak.points.last().unwrap().x > 144.);
+        assert_eq!(
+            String::from_utf8(leak.to_bytes()).unwrap().lines().count(),
+            leak.points.len()
+        );
+
+        // A map without any faces still needs the vertex that the reserved edge points at
+        let empty = MapFile::parse("{\n\"classname\" \"worldspawn\"\n}\n").unwrap();
+        let compiled = compile::<Quake1>(&empty, &CompileConfig::default()).unwrap();
+        assert_eq!(compiled.bsp.models().len(), 1);
+        assert_eq!(compiled.bsp.faces().len(), 0);
+        assert_eq!(compiled.bsp.vertices().len(), 1);
+    }
+
+    #[test]
+    fn light_map() {
+        use bsp::mapversions::{Goldsrc, Quake1};
+        use compile::{compile, CompileConfig};
+        use light::Lighting;
+        use map::MapFile;
+
+        /// The samples of each of the floor's lightmaps at `(x, y)`
+        fn floor_light<V>(bsp: &Bsp<V>, x: f32, y: f32) -> Vec<(u8, Vec<u8>)>
+        where
+            V: MapVersion<Lump = Quake1Lump>,
+        {
+            let start = Vec3 { x, y, z: 16. };
+            let hit = bsp.raycast(start, Vec3 { z: -16., ..start }).unwrap();
+            let face = bsp.faces().get(hit.face.unwrap()).unwrap();
+            let extents = face.extents();
+            let texinfo = face.texinfo();
+            let coord = |(axis, offset): (Vec3<f32>, f32), i: usize| {
+                let texel = hit.point.dot(&axis) + offset - extents.texture_min[i] as f32;
+
+                (texel / 16.).round() as usize
+            };
+            let sample = coord(texinfo.t(), 1) * extents.size[0] as usize + coord(texinfo.s(), 0);
+            let channels = bsp.lightmap_channels();
+
+            face.lightmaps()
+                .into_iter()
+                .map(|(style, map)| (style, map[sample * channels..][..channels].to_vec()))
+                .collect()
+        }
+
+        let map = MapFile::parse(&test_map(&[])).unwrap();
+        let bsp = compile::<Quake1>(&map, &CompileConfig::default()).unwrap().bsp;
+        let lit = Lighting::new(&bsp).apply(&bsp).unwrap();
+
+        // The pillar stands between the light and the floor to the north
+        let open = floor_light(&lit, 32., -100.);
+        let shadowed = floor_light(&lit, 32., 100.);
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].0, 0);
+        assert!(open[0].1[0] > 20);
+        assert_eq!(shadowed[0].1[0], 0);
+
+        // A red light, and a flickering one that falls off with the square of the distance
+        let source = test_map(&[]).replace(
+            "\"light\" \"300\"\n",
+            "\"light\" \"300\"\n\"_color\" \"255 0 0\"\n",
+        ) + "{\n\"classname\" \"light\"\n\"origin\" \"64 -64 64\"\n\"style\" \"5\"\n" +
+            "\"delay\" \"2\"\n}\n";
+        let map = MapFile::parse(&source).unwrap();
+        let bsp = compile::<Goldsrc>(&map, &CompileConfig::default()).unwrap().bsp;
+        let lit = Lighting::new(&bsp).apply(&bsp).unwrap();
+
+        let samples = floor_light(&lit, 32., -100.);
+        assert_eq!(samples.iter().map(|&(style, _)| style).collect::<Vec<_>>(), [0, 5]);
+        assert!(samples[0].1[0] > 20);
+        assert_eq!(&samples[0].1[1..], [0, 0]);
+        assert!(samples[1].1.iter().all(|&c| c > 0));
+        assert!(lit.faces().into_iter().all(|(_, face)| {
+            face.texinfo().is_special() || face.styles()[0] == 0 && face.lightmap_offset().is_some()
+        }));
+
+        // Faces left bigger than the engine would light get no lightmap
+        let map = MapFile::parse(&test_map(&[]).replace("0 0.5 0.5", "0 0.05 0.05")).unwrap();
+        let config = CompileConfig { subdivide: 4096., ..CompileConfig::default() };
+        let bsp = compile::<Quake1>(&map, &config).unwrap().bsp;
+        let lit = Lighting::new(&bsp).apply(&bsp).unwrap();
+        let oversized = lit
+            .faces()
+            .into_iter()
+            .filter(|(_, face)| !face.extents().is_valid())
+            .inspect(|(_, face)| assert!(face.lightmap_offset().is_none()))
+            .count();
+        assert!(oversized > 0);
+
+        // Lighting only fits the map it was made for
+        let map = MapFile::parse(&test_map(&[6])).unwrap();
+        let other = compile::<Goldsrc>(&map, &CompileConfig::default()).unwrap().bsp;
+
+        match Lighting::new(&bsp).apply(&other) {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("Expected a face count mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decompile_map() {
+        use bsp::mapversions::Quake1;
+        use compile::{compile, CompileConfig};
+        use decompile::decompile;
+        use map::{MapFile, Projection};
+
+        let v = |x, y, z| Vec3 { x, y, z };
+
+        let map = MapFile::parse(&test_map(&[])).unwrap();
+        let original = compile::<Quake1>(&map, &CompileConfig::default()).unwrap().bsp;
+        let source = decompile(&original);
+
+        assert_eq!(source.entities.len(), 4);
+        assert_eq!(source.entities[3].entity.class_name(), Some("func_door"));
+        assert_eq!(source.entities[3].entity.get("model"), None);
+        assert!(!source.entities[3].brushes.is_empty());
+
+        // What's written out reads back the same, and compiles into the same map again
+        let text = String::from_utf8(source.to_bytes()).unwrap();
+        assert_eq!(MapFile::parse(&text).unwrap(), source);
+
+        // Hidden sides are written in the 220 format worldspawn says the map is in too
+        assert_eq!(source.entities[0].entity.get("mapversion"), Some("220"));
+        assert!(source
+            .entities
+            .iter()
+            .flat_map(|entity| &entity.brushes)
+            .flat_map(|brush| &brush.faces)
+            .all(|face| matches!(face.projection, Projection::Valve { .. })));
+
+        let compiled = compile::<Quake1>(&source, &CompileConfig::default()).unwrap();
+        let bsp = &compiled.bsp;
+        assert_eq!(compiled.leak, None);
+        assert_eq!(bsp.entities()[3].model_index(), Some(1));
+
+        for &point in &[
+            v(64., -64., 24.),
+            v(-64., -64., 16.),
+            v(0., 48., 64.),
+            v(-136., 0., 64.),
+            v(1000., 0., 64.),
+            v(80., 80., 32.),
+        ] {
+            assert_eq!(bsp.point_contents(point), original.point_contents(point));
+        }
+
+        // Textures keep their alignment, down to the pillar's half scale
+        for &(start, end) in &[
+            (v(64., -64., 24.), v(64., -64., -100.)),
+            (v(64., 48., 64.), v(-100., 48., 64.)),
+        ] {
+            let (a, b) = (original.raycast(start, end).unwrap(), bsp.raycast(start, end).unwrap());
+            let (a, b) = (
+                original.faces().get(a.face.unwrap()).unwrap().texinfo(),
+                bsp.faces().get(b.face.unwrap()).unwrap().texinfo(),
+            );
+
+            assert_eq!(b.texture().unwrap().name(), a.texture().unwrap().name());
+            assert_eq!((b.s(), b.t()), (a.s(), a.t()));
+        }
+    }
+
+    #[test]
+    fn decompile_quake2_map() {
+        use decompile::decompile_quake2;
+        use map::{MapFile, Projection};
+
+        let mut lumps = vec![Vec::new(); 19];
+        lumps[0] = b"{ \"classname\" \"worldspawn\" }\n\
+            { \"classname\" \"func_door\" \"model\" \"*1\" \"origin\" \"0 0 100\" }\n\0"
+            .to_vec();
+
+        // A floor with a bevel along one of its edges, like the compiler adds for collision,
+        // and a door built around the origin
+        let bevel = 0.5f32.sqrt();
+        let brushes: [&[([f32; 3], f32)]; 2] = [
+            &[
+                ([0., 0., 1.], 0.),
+                ([0., 0., -1.], 16.),
+                ([1., 0., 0.], 64.),
+                ([-1., 0., 0.], 64.),
+                ([0., 1., 0.], 64.),
+                ([0., -1., 0.], 64.),
+                ([bevel, bevel, 0.], 128. * bevel),
+            ],
+            &[
+                ([0., 0., 1.], 8.),
+                ([0., 0., -1.], 8.),
+                ([1., 0., 0.], 8.),
+                ([-1., 0., 0.], 8.),
+                ([0., 1., 0.], 8.),
+                ([0., -1., 0.], 8.),
+            ],
+        ];
+
+        for sides in &brushes {
+            let first = lumps[15].len() as i32 / 4;
+            le32(&mut lumps[14], first);
+            le32(&mut lumps[14], sides.len() as i32);
+            le32(&mut lumps[14], 1);
+
+            for (i, &(normal, distance)) in sides.iter().enumerate() {
+                let plane = lumps[1].len() / 20;
+
+                f32s(&mut lumps[1], &normal);
+                f32s(&mut lumps[1], &[distance]);
+                le32(&mut lumps[1], 0);
+                // Leave the floor's underside without a texture
+                let texinfo = if i == 1 { -1i16 } else { 0 };
+                lumps[15].extend_from_slice(&(plane as u16).to_le_bytes());
+                lumps[15].extend_from_slice(&texinfo.to_le_bytes());
+            }
+        }
+
+        f32s(&mut lumps[5], &[1., 0., 0., 8., 0., -1., 0., 0.]);
+        le32(&mut lumps[5], 0);
+        le32(&mut lumps[5], 0);
+        let mut name = b"e1u1/floor1_1".to_vec();
+        name.resize(32, 0);
+        lumps[5].extend_from_slice(&name);
+        le32(&mut lumps[5], -1);
+
+        // The world's node has the floor in both of its leaves, the door is just a leaf
+        le32(&mut lumps[4], 0);
+        le32(&mut lumps[4], -1);
+        le32(&mut lumps[4], -2);
+        lumps[4].extend_from_slice(&[0; 16]);
+
+        for (first, brush) in [(0u16, 0u16), (1, 0), (2, 1)] {
+            le32(&mut lumps[8], 1);
+            lumps[8].extend_from_slice(&[0; 16]);
+            lumps[8].extend_from_slice(&[0; 4]);
+            lumps[8].extend_from_slice(&first.to_le_bytes());
+            lumps[8].extend_from_slice(&1u16.to_le_bytes());
+            lumps[10].extend_from_slice(&brush.to_le_bytes());
+        }
+
+        for head in [0, -3] {
+            f32s(&mut lumps[13], &[0.; 9]);
+            le32(&mut lumps[13], head);
+            le32(&mut lumps[13], 0);
+            le32(&mut lumps[13], 0);
+        }
+
+        let mut file = b"IBSP".to_vec();
+        le32(&mut file, 38);
+        let mut offset = 8 + 19 * 8;
+        for lump in &lumps {
+            le32(&mut file, offset as i32);
+            le32(&mut file, lump.len() as i32);
+            offset += lump.len();
+        }
+        for lump in &lumps {
+            file.extend_from_slice(lump);
+        }
+
+        let source = decompile_quake2(&file).unwrap();
+        let text = String::from_utf8(source.to_bytes()).unwrap();
+        assert_eq!(MapFile::parse(&text).unwrap(), source);
+
+        assert_eq!(source.entities.len(), 2);
+        assert_eq!(source.entities[0].entity.get("mapversion"), Some("220"));
+        assert_eq!(source.entities[1].entity.get("model"), None);
+        assert_eq!(source.entities[1].entity.get("origin"), None);
+
+        // The floor comes back once, without its bevel, and the hidden side takes its texture
+        let floor = &source.entities[0].brushes;
+        assert_eq!(floor.len(), 1);
+        assert_eq!(floor[0].faces.len(), 6);
+        assert!(floor[0].faces.iter().all(|face| face.texture == "e1u1/floor1_1"));
+
+        let hidden = floor[0]
+            .faces
+            .iter()
+            .find(|face| face.points.iter().all(|p| p.z == -16.))
+            .unwrap();
+        match hidden.projection {
+            Projection::Valve { axes, .. } => {
+                assert_eq!(axes[0], (Vec3 { x: 1., y: 0., z: 0. }, 0.));
+                assert_eq!(axes[1], (Vec3 { x: 0., y: -1., z: 0. }, 0.));
+            }
+            ref other => panic!("Expected a Valve projection, got {:?}", other),
+        }
+
+        // The door moves back around its origin, which gets a brush for the compiler
+        let door = &source.entities[1].brushes;
+        assert_eq!(door.len(), 2);
+        assert!(door[0].faces.iter().all(|face| face.texture == "e1u1/floor1_1"));
+        assert!(door[0]
+            .faces
+            .iter()
+            .flat_map(|face| &face.points)
+            .all(|p| (92. ..=108.).contains(&p.z)));
+        assert!(door[1].faces.iter().all(|face| face.texture == "origin"));
+
+        match decompile_quake2(&file[..100]) {
+            Err(Error::Truncated { lump: "header", .. }) => {}
+            other => panic!("Expected truncated header, got {:?}", other),
+        }
+
+        file[4] = 29;
+        match decompile_quake2(&file) {
+            Err(Error::VersionMismatch(29)) => {}
+            other => panic!("Expected version mismatch, got {:?}", other),
+        }
+
+        // A texinfo whose `s` axis has no length is laid like a hidden side rather than scaled
+        // by infinity
+        file[4] = 38;
+        let texinfo = 8 + 19 * 8 + lumps[..5].iter().map(|lump| lump.len()).sum::<usize>();
+        file[texinfo..texinfo + 12].copy_from_slice(&[0; 12]);
+
+        let source = decompile_quake2(&file).unwrap();
+        let faces = source
+            .entities
+            .iter()
+            .flat_map(|entity| &entity.brushes)
+            .flat_map(|brush| &brush.faces);
+
+        for face in faces {
+            match face.projection {
+                Projection::Valve { scale, .. } => assert_eq!(scale, [1., 1.]),
+                ref other => panic!("Expected a Valve projection, got {:?}", other),
+            }
+        }
+    }
 }
@@ -0,0 +1,389 @@
+//! Baking the lightmaps of a compiled map from its light entities, the job of `light`
+//!
+//! Like Quake's `light`, only direct light is worked out: every lightmap sample is lit by each
+//! light that can reach it through hull 0, less the further away the light is and the more
+//! glancing the angle it comes in at. Lights with a `style` go in lightmaps of their own for the
+//! engine to animate, and GoldSrc maps get coloured lightmaps from each light's `_color`.
+
+use std::mem;
+
+use bsp::hull::Hull;
+use bsp::portals::{cross, dot, Point};
+use bsp::quake1::{Face, LeafType};
+use bsp::{Bsp, BspWriter, Entity, Error, MapVersion, Quake1Lump, Vec3};
+use parallel::parallel;
+use sys::bsp::Face as RawFace;
+
+/// The most lightmaps, and so styles, a face can have
+const MAX_STYLES: usize = 4;
+/// Texels between lightmap samples
+const SAMPLE_SPACING: i32 = 16;
+/// How far off the surface samples are taken, so that the surface doesn't shadow itself
+const SURFACE_OFFSET: f64 = 1.;
+/// How much of a light's brightness depends on the angle it hits a surface at
+const ANGLE_SCALE: f32 = 0.5;
+/// `light` halves everything before storing it, so that a light of 300 is only a little brighter
+/// than normal (128) right next to it
+const RANGE_SCALE: f32 = 0.5;
+/// The brightness of a light without a `light` key
+const DEFAULT_LIGHT: f32 = 300.;
+
+/// How a light gets dimmer with distance, from its `delay` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Falloff {
+    /// Loses one point of brightness per unit, the default
+    Linear,
+    /// Falls off with 1/distance, at full brightness 128 units away
+    Inverse,
+    /// Falls off with 1/distance², at full brightness 128 units away
+    InverseSquare,
+    /// The same brightness at any distance
+    None,
+}
+
+impl Falloff {
+    pub fn from_delay(delay: i32) -> Option<Self> {
+        match delay {
+            0 => Some(Falloff::Linear),
+            1 => Some(Falloff::Inverse),
+            2 => Some(Falloff::InverseSquare),
+            3 => Some(Falloff::None),
+            _ => None,
+        }
+    }
+}
+
+/// A point light, as read from a `light*` entity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub origin: Vec3<f32>,
+    /// Negative for lights that take light away
+    pub intensity: f32,
+    /// From 0 to 1 in each channel
+    pub color: Vec3<f32>,
+    pub style: u8,
+    pub falloff: Falloff,
+    /// Scales distances, so higher values make the light reach less far
+    pub wait: f32,
+}
+
+impl Light {
+    /// The light an entity gives off, or `None` if it isn't a light. `light_environment`
+    /// (sunlight) isn't supported, since it would need rays out to the sky rather than to a
+    /// point.
+    pub fn from_entity(entity: &Entity) -> Option<Self> {
+        let class = entity.class_name()?;
+
+        if !class.starts_with("light") || class == "light_environment" {
+            return None;
+        }
+
+        let origin = entity.origin()?;
+        let number = |key| entity.get(key).and_then(|v| v.trim().parse::<f32>().ok());
+        let white = Vec3 { x: 1., y: 1., z: 1. };
+        let mut intensity = number("light").unwrap_or(DEFAULT_LIGHT);
+        let mut color = entity.vector("_color").unwrap_or(white);
+
+        // GoldSrc lights have their colour and brightness together, as "r g b brightness"
+        if entity.get("light").is_none() {
+            let parts = entity
+                .get("_light")
+                .into_iter()
+                .flat_map(|v| v.split_whitespace())
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_default();
+
+            if let [r, g, b, brightness] = parts[..] {
+                color = Vec3 { x: r, y: g, z: b };
+                intensity = brightness;
+            }
+        }
+
+        // Colours can be given from 0 to 1 or from 0 to 255
+        if color.x > 1. || color.y > 1. || color.z > 1. {
+            color = color * (1. / 255.);
+        }
+
+        Some(Light {
+            origin,
+            intensity,
+            color,
+            style: number("style")
+                .filter(|&style| (0. ..255.).contains(&style))
+                .map_or(0, |style| style as u8),
+            falloff: number("delay")
+                .and_then(|delay| Falloff::from_delay(delay as i32))
+                .unwrap_or(Falloff::Linear),
+            wait: number("wait").filter(|&wait| wait > 0.).unwrap_or(1.),
+        })
+    }
+
+    /// How bright the light is `distance` away, before shadows and the angle it comes in at.
+    pub fn brightness(&self, distance: f32) -> f32 {
+        let magnitude = self.intensity.abs();
+        let distance = (distance * self.wait).max(1.);
+        let value = match self.falloff {
+            Falloff::Linear => magnitude - distance,
+            Falloff::Inverse => magnitude * 128. / distance,
+            Falloff::InverseSquare => magnitude * 128. * 128. / (distance * distance),
+            Falloff::None => magnitude,
+        };
+
+        value.max(0.) * self.intensity.signum()
+    }
+}
+
+fn widen(v: &Vec3<f32>) -> Point {
+    Vec3 {
+        x: v.x as f64,
+        y: v.y as f64,
+        z: v.z as f64,
+    }
+}
+
+fn narrow(v: &Point) -> Vec3<f32> {
+    Vec3 {
+        x: v.x as f32,
+        y: v.y as f32,
+        z: v.z as f32,
+    }
+}
+
+/// Where each of the face's lightmap samples is in the world, just off the surface. Samples that
+/// end up inside a wall, past the edge of the face, are pulled in towards its middle. `None` for
+/// faces the engine wouldn't light.
+fn sample_points<V>(face: &Face<V>, world: &Hull<V>) -> Option<Vec<Vec3<f32>>>
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let texinfo = face.texinfo();
+    let plane = face.plane();
+    let extents = face.extents();
+
+    // The engine refuses faces this big, and there would be far too many samples to place
+    if !extents.is_valid() {
+        return None;
+    }
+
+    let ((s, s_offset), (t, t_offset)) = (texinfo.s(), texinfo.t());
+    let (s, t, normal) = (widen(&s), widen(&t), widen(&plane.normal));
+    let det = dot(&s, &cross(&t, &normal));
+
+    // The texture is projected edge-on, so the samples can't be placed
+    if det.abs() < 1e-6 {
+        return None;
+    }
+
+    let vertices = face.vertices();
+    let zero = Vec3 { x: 0., y: 0., z: 0. };
+    let centre = vertices.iter().fold(zero, |sum, v| sum + widen(v)) *
+        (1. / vertices.len().max(1) as f64) +
+        normal * SURFACE_OFFSET;
+    let mut points = Vec::with_capacity(extents.samples());
+
+    for y in 0..extents.size[1] as i32 {
+        for x in 0..extents.size[0] as i32 {
+            let u = (extents.texture_min[0] + x * SAMPLE_SPACING) as f64 - s_offset as f64;
+            let v = (extents.texture_min[1] + y * SAMPLE_SPACING) as f64 - t_offset as f64;
+            let on_plane = (cross(&t, &normal) * u +
+                cross(&normal, &s) * v +
+                cross(&s, &t) * plane.distance as f64) *
+                (1. / det);
+            let mut point = on_plane + normal * SURFACE_OFFSET;
+
+            for _ in 0..4 {
+                if world.point_contents(&narrow(&point)) != LeafType::Solid {
+                    break;
+                }
+
+                point = point + (centre - point) * 0.5;
+            }
+
+            points.push(narrow(&point));
+        }
+    }
+
+    Some(points)
+}
+
+/// The styles of the face's lightmaps and the lightmaps one after another, or `None` for faces
+/// that don't have any.
+fn light_face<V>(
+    face: &Face<V>,
+    world: &Hull<V>,
+    lights: &[Light],
+    minlight: f32,
+    channels: usize,
+) -> Option<(Vec<u8>, Vec<u8>)>
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    if face.texinfo().is_special() {
+        return None;
+    }
+
+    let points = sample_points(face, world)?;
+    let normal = face.plane().normal;
+    let black = Vec3 { x: 0., y: 0., z: 0. };
+    // Style 0 is always there, so that unlit faces are dark rather than fullbright
+    let mut maps = vec![(0, vec![black; points.len()])];
+
+    for light in lights {
+        let mut slot = maps.iter().position(|&(style, _)| style == light.style);
+
+        for (i, point) in points.iter().enumerate() {
+            let incoming = light.origin - *point;
+            let distance = incoming.dot(&incoming).sqrt();
+            let angle = incoming.dot(&normal) / distance.max(f32::EPSILON);
+
+            if angle <= 0. {
+                continue;
+            }
+
+            let value = light.brightness(distance) * (1. - ANGLE_SCALE + ANGLE_SCALE * angle);
+
+            if value == 0. || world.trace(&light.origin, point).hit() {
+                continue;
+            }
+
+            let slot = match slot {
+                Some(slot) => slot,
+                None if maps.len() < MAX_STYLES => {
+                    maps.push((light.style, vec![black; points.len()]));
+                    slot = Some(maps.len() - 1);
+                    maps.len() - 1
+                }
+                // The face is lit by too many styles already
+                None => break,
+            };
+
+            maps[slot].1[i] = maps[slot].1[i] + light.color * value;
+        }
+    }
+
+    let mut styles = Vec::with_capacity(maps.len());
+    let mut data = Vec::with_capacity(maps.len() * points.len() * channels);
+
+    for (style, samples) in maps {
+        styles.push(style);
+
+        for sample in samples {
+            let floor = if style == 0 { minlight } else { 0. };
+            let byte = |v: f32| (v.max(floor) * RANGE_SCALE).clamp(0., 255.) as u8;
+
+            if channels == 3 {
+                data.extend_from_slice(&[byte(sample.x), byte(sample.y), byte(sample.z)]);
+            } else {
+                // Greyscale lightmaps leave out the colour
+                data.push(byte(sample.x.max(sample.y).max(sample.z)));
+            }
+        }
+    }
+
+    Some((styles, data))
+}
+
+/// The lightmaps of every face of a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lighting {
+    /// Bytes per sample: 1 for Quake's greyscale lighting, 3 for GoldSrc's RGB
+    pub channels: usize,
+    faces: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl Lighting {
+    /// Light every face of `bsp` with the light entities in it. The world's `light` key sets the
+    /// least light that any surface gets.
+    pub fn new<V>(bsp: &Bsp<V>) -> Self
+    where
+        V: MapVersion<Lump = Quake1Lump> + Sync,
+    {
+        let entities = bsp.entities();
+        let lights = entities
+            .iter()
+            .filter_map(Light::from_entity)
+            .collect::<Vec<_>>();
+        let minlight = entities
+            .first()
+            .filter(|world| world.class_name() == Some("worldspawn"))
+            .and_then(|world| world.get("light").or_else(|| world.get("_minlight")))
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(0.);
+
+        Lighting::from_lights(bsp, &lights, minlight)
+    }
+
+    pub fn from_lights<V>(bsp: &Bsp<V>, lights: &[Light], minlight: f32) -> Self
+    where
+        V: MapVersion<Lump = Quake1Lump> + Sync,
+    {
+        let channels = bsp.lightmap_channels();
+        let world = bsp.map_model().hull(0);
+        let faces = bsp.faces();
+        let order = (0..faces.len()).collect::<Vec<_>>();
+
+        let faces = parallel(&order, |i| {
+            let face = faces.get(i)?;
+
+            light_face(&face, world.as_ref()?, lights, minlight, channels)
+        });
+
+        Lighting { channels, faces }
+    }
+
+    /// The styles of face `face`'s lightmaps and the lightmaps one after another, each
+    /// `Face::extents().size` samples of `channels` bytes.
+    pub fn face(&self, face: usize) -> Option<(&[u8], &[u8])> {
+        self.faces
+            .get(face)
+            .and_then(|lit| lit.as_ref())
+            .map(|(styles, data)| (&styles[..], &data[..]))
+    }
+
+    /// Replace the lightmaps lump and point each face at its lightmaps and styles. Fails if the
+    /// map doesn't have as many faces as the one that was lit, since the faces left over would
+    /// point into the old lump.
+    pub fn write_lumps(&self, writer: &mut BspWriter) -> Result<(), Error> {
+        let face_len = mem::size_of::<RawFace>();
+        let styles_field = mem::offset_of!(RawFace, typelight);
+        let offset_field = mem::offset_of!(RawFace, lightmap);
+        let mut lightmaps = Vec::new();
+
+        if writer.faces.len() / face_len != self.faces.len() {
+            return Err(Error::Unsupported("lighting from a map with a different number of faces"));
+        }
+
+        for (face, lit) in writer.faces.chunks_mut(face_len).zip(&self.faces) {
+            let mut styles = [255; MAX_STYLES];
+            let offset = match *lit {
+                Some((ref lit_styles, ref data)) => {
+                    let offset = lightmaps.len() as i32;
+
+                    styles[..lit_styles.len()].copy_from_slice(lit_styles);
+                    lightmaps.extend_from_slice(data);
+                    offset
+                }
+                None => -1,
+            };
+
+            face[styles_field..styles_field + MAX_STYLES].copy_from_slice(&styles);
+            face[offset_field..offset_field + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        writer.lightmaps = lightmaps;
+
+        Ok(())
+    }
+
+    /// A copy of `bsp` with this lighting in it.
+    pub fn apply<V>(&self, bsp: &Bsp<V>) -> Result<Bsp<'static, V>, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump> + 'static,
+    {
+        let mut writer = BspWriter::from_bsp(bsp);
+
+        self.write_lumps(&mut writer)?;
+        writer.build()
+    }
+}
@@ -0,0 +1,354 @@
+//! The `.map` source files that level editors save and compilers turn into BSPs
+//!
+//! A map is a list of entities, each with its key/value pairs and any number of convex brushes.
+//! A brush is a list of faces, each giving three points on its plane, a texture and how the
+//! texture is laid on the face. Both the original Quake layout (offset, rotation and scale on
+//! world-aligned axes) and Valve's 220 format (explicit texture axes) are read and written.
+
+use std::borrow::Cow;
+use std::io::Write;
+
+use bsp::{Entity, Error, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// The texture is projected along whichever world axis is closest to the face normal, then
+    /// rotated, scaled and shifted.
+    Quake {
+        offset: [f64; 2],
+        rotation: f64,
+        scale: [f64; 2],
+    },
+    /// The texture's horizontal and vertical axes with their offsets, as written by Valve Hammer
+    /// and TrenchBroom in 220 format. `rotation` is only kept for editors, it's already applied
+    /// to the axes.
+    Valve {
+        axes: [(Vec3<f64>, f64); 2],
+        rotation: f64,
+        scale: [f64; 2],
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapFace {
+    /// Three points on the face's plane, clockwise when looking at the face from outside the
+    /// brush
+    pub points: [Vec3<f64>; 3],
+    pub texture: String,
+    pub projection: Projection,
+}
+
+/// A convex volume bounded by the planes of its faces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapBrush {
+    pub faces: Vec<MapFace>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntity {
+    pub entity: Entity<'static>,
+    pub brushes: Vec<MapBrush>,
+}
+
+/// A parsed `.map` file. The first entity is the `worldspawn`, holding the world's brushes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapFile {
+    pub entities: Vec<MapEntity>,
+}
+
+struct Tokenizer<'a> {
+    data: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_space(&mut self) {
+        let bytes = self.data.as_bytes();
+
+        loop {
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+                if bytes[self.pos] == b'\n' {
+                    self.line += 1;
+                }
+                self.pos += 1;
+            }
+
+            if self.data[self.pos..].starts_with("//") {
+                while self.pos < bytes.len() && bytes[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<&'a str> {
+        let (pos, line) = (self.pos, self.line);
+        let token = self.next();
+
+        self.pos = pos;
+        self.line = line;
+        token
+    }
+
+    /// The next token: a quoted string without its quotes, or a run of anything but ASCII
+    /// whitespace, the same whitespace that `skip_space` skips so that tokens are never empty.
+    /// Braces and parentheses have to be separated by whitespace like every editor writes them,
+    /// since GoldSrc texture names can start with `{`.
+    fn next(&mut self) -> Option<&'a str> {
+        self.skip_space();
+
+        let data = self.data;
+        let rest = &data[self.pos..];
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let len = quoted.find('"').unwrap_or(quoted.len());
+
+            self.line += quoted[..len].matches('\n').count();
+            self.pos += len + 2;
+            self.pos = self.pos.min(data.len());
+
+            return Some(&quoted[..len]);
+        }
+
+        let len = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn error(&self, expected: &'static str) -> Error {
+        Error::Syntax {
+            line: self.line,
+            expected,
+        }
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), Error> {
+        match self.next() {
+            Some(found) if found == token => Ok(()),
+            _ => Err(self.error(token)),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        self.next()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| self.error("a number"))
+    }
+
+    fn vector(&mut self) -> Result<Vec3<f64>, Error> {
+        Ok(Vec3 {
+            x: self.number()?,
+            y: self.number()?,
+            z: self.number()?,
+        })
+    }
+
+    fn point(&mut self) -> Result<Vec3<f64>, Error> {
+        self.expect("(")?;
+        let point = self.vector()?;
+        self.expect(")")?;
+
+        Ok(point)
+    }
+
+    fn face(&mut self) -> Result<MapFace, Error> {
+        let points = [self.point()?, self.point()?, self.point()?];
+        let texture = self.next().ok_or_else(|| self.error("a texture name"))?.to_owned();
+
+        let projection = if self.peek() == Some("[") {
+            let mut axes = [(Vec3 { x: 0., y: 0., z: 0. }, 0.); 2];
+
+            for axis in &mut axes {
+                self.expect("[")?;
+                *axis = (self.vector()?, self.number()?);
+                self.expect("]")?;
+            }
+
+            Projection::Valve {
+                axes,
+                rotation: self.number()?,
+                scale: [self.number()?, self.number()?],
+            }
+        } else {
+            Projection::Quake {
+                offset: [self.number()?, self.number()?],
+                rotation: self.number()?,
+                scale: [self.number()?, self.number()?],
+            }
+        };
+
+        // Quake 2 style content and surface flags, which neither engine uses
+        while self.peek().is_some_and(|token| token.parse::<f64>().is_ok()) {
+            self.next();
+        }
+
+        Ok(MapFace {
+            points,
+            texture,
+            projection,
+        })
+    }
+
+    fn brush(&mut self) -> Result<MapBrush, Error> {
+        let mut faces = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some("(") => faces.push(self.face()?),
+                Some("}") => {
+                    self.next();
+                    return Ok(MapBrush { faces });
+                }
+                Some("brushDef") | Some("brushDef3") | Some("patchDef2") => {
+                    return Err(Error::Unsupported("Quake 3 brush and patch definitions"))
+                }
+                _ => return Err(self.error("a face or `}`")),
+            }
+        }
+    }
+
+    fn entity(&mut self) -> Result<MapEntity, Error> {
+        let mut entity = Entity::default();
+        let mut brushes = Vec::new();
+
+        loop {
+            match self.next() {
+                Some("}") => return Ok(MapEntity { entity, brushes }),
+                Some("{") => brushes.push(self.brush()?),
+                Some(key) => {
+                    let value = self.next().ok_or_else(|| self.error("a value"))?;
+
+                    entity
+                        .properties
+                        .push((Cow::Owned(key.to_owned()), Cow::Owned(value.to_owned())));
+                }
+                None => return Err(self.error("`}`")),
+            }
+        }
+    }
+}
+
+impl MapFile {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut tokens = Tokenizer {
+            data: text,
+            pos: 0,
+            line: 1,
+        };
+        let mut entities = Vec::new();
+
+        while let Some(token) = tokens.next() {
+            if token != "{" {
+                return Err(tokens.error("`{`"));
+            }
+
+            entities.push(tokens.entity()?);
+        }
+
+        Ok(MapFile { entities })
+    }
+
+    /// The `worldspawn`, which should always be the first entity.
+    pub fn world(&self) -> Option<&MapEntity> {
+        self.entities.first()
+    }
+
+    /// Write the map out the way editors save it, each face in the format of its projection.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        for (i, map_entity) in self.entities.iter().enumerate() {
+            writeln!(out, "// entity {}", i)?;
+            writeln!(out, "{{")?;
+
+            for (key, value) in &map_entity.entity.properties {
+                writeln!(out, "\"{}\" \"{}\"", key, value)?;
+            }
+
+            for (j, brush) in map_entity.brushes.iter().enumerate() {
+                writeln!(out, "// brush {}", j)?;
+                writeln!(out, "{{")?;
+
+                for face in &brush.faces {
+                    for p in &face.points {
+                        write!(out, "( {} {} {} ) ", number(p.x), number(p.y), number(p.z))?;
+                    }
+
+                    write!(out, "{}", face.texture)?;
+
+                    match face.projection {
+                        Projection::Quake {
+                            offset,
+                            rotation,
+                            scale,
+                        } => writeln!(
+                            out,
+                            " {} {} {} {} {}",
+                            number(offset[0]),
+                            number(offset[1]),
+                            number(rotation),
+                            number(scale[0]),
+                            number(scale[1])
+                        )?,
+                        Projection::Valve {
+                            axes,
+                            rotation,
+                            scale,
+                        } => {
+                            for (axis, offset) in &axes {
+                                write!(
+                                    out,
+                                    " [ {} {} {} {} ]",
+                                    number(axis.x),
+                                    number(axis.y),
+                                    number(axis.z),
+                                    number(*offset)
+                                )?;
+                            }
+
+                            writeln!(
+                                out,
+                                " {} {} {}",
+                                number(rotation),
+                                number(scale[0]),
+                                number(scale[1])
+                            )?;
+                        }
+                    }
+                }
+
+                writeln!(out, "}}")?;
+            }
+
+            writeln!(out, "}}")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+}
+
+/// A number as short as it can be written without losing more than editors keep.
+fn number(v: f64) -> String {
+    let text = format!("{:.6}", v);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+
+    if text == "-0" {
+        "0".to_owned()
+    } else {
+        text.to_owned()
+    }
+}
@@ -0,0 +1,500 @@
+//! Quake 1 alias models (`progs/*.mdl`), used for monsters, items and weapons
+//!
+//! The skins and frames sections are variable-length, so their offsets are found once when the
+//! model is loaded and everything else is read lazily out of the buffer, like `Bsp`.
+
+use std::borrow::Cow;
+use std::mem;
+
+use ioendian::{IntoNativeEndian, Little};
+
+use bsp::{Error, Vec3};
+use group::group_item;
+use sys::{slice_at, value_at};
+use sys::mdl as raw;
+
+pub use sys::mdl::TriVertex;
+
+const MAGIC: &[u8; 4] = b"IDPO";
+const VERSION: i32 = 6;
+
+#[derive(Debug, Clone)]
+enum Section {
+    Single(usize),
+    Group {
+        count: usize,
+        times: usize,
+        start: usize,
+    },
+}
+
+pub struct Mdl<'a> {
+    data: Cow<'a, [u8]>,
+    skins: Vec<Section>,
+    tex_coords: usize,
+    triangles: usize,
+    frames: Vec<Section>,
+}
+
+impl<'a> ::std::fmt::Debug for Mdl<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(
+            f,
+            "Mdl {{ {} skins, {} frames, {} vertices }}",
+            self.skins.len(),
+            self.frames.len(),
+            self.num_vertices()
+        )
+    }
+}
+
+/// Walks through the variable-length sections, checking that each one fits in the file.
+struct Cursor<'b> {
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Cursor<'b> {
+    fn skip(&mut self, lump: &'static str, len: usize) -> Result<usize, Error> {
+        let start = self.pos;
+        let end = start.saturating_add(len);
+
+        if end > self.data.len() {
+            return Err(Error::Truncated {
+                lump,
+                expected: end,
+                actual: self.data.len(),
+            });
+        }
+
+        self.pos = end;
+
+        Ok(start)
+    }
+
+    fn count(&mut self, lump: &'static str) -> Result<usize, Error> {
+        let at = self.skip(lump, 4)?;
+        let value: &Little<i32> = unsafe { value_at(self.data, at).unwrap() };
+        let value = value.native();
+
+        if value < 0 {
+            Err(Error::BadCount {
+                lump,
+                record: 0,
+                field: "count",
+                count: value as _,
+                min: 0,
+            })
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    /// Item `record`, on its own or as a group. `group_extra` is the length of anything between
+    /// the group's count and its timings.
+    fn section(
+        &mut self,
+        lump: &'static str,
+        record: usize,
+        item_len: usize,
+        group_extra: usize,
+    ) -> Result<Section, Error> {
+        if self.count(lump)? == 0 {
+            return Ok(Section::Single(self.skip(lump, item_len)?));
+        }
+
+        let count = self.count(lump)?;
+
+        if count < 1 {
+            return Err(Error::BadCount {
+                lump,
+                record,
+                field: "count",
+                count: count as _,
+                min: 1,
+            });
+        }
+
+        self.skip(lump, group_extra)?;
+
+        let times = self.skip(lump, count.saturating_mul(4))?;
+        let start = self.skip(lump, count.saturating_mul(item_len))?;
+
+        Ok(Section::Group {
+            count,
+            times,
+            start,
+        })
+    }
+}
+
+impl<'a> Mdl<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        let data = buffer.into();
+
+        let header: &raw::Header = unsafe { value_at(&data, 0) }.ok_or_else(|| {
+            Error::Truncated {
+                lump: "header",
+                expected: mem::size_of::<raw::Header>(),
+                actual: data.len(),
+            }
+        })?;
+
+        if &header.magic != MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: MAGIC,
+                found: header.magic,
+            });
+        }
+
+        if header.version.native() != VERSION {
+            return Err(Error::VersionMismatch(header.version.native() as _));
+        }
+
+        let counts = [
+            ("skins", header.num_skins.native()),
+            ("skin_width", header.skin_width.native()),
+            ("skin_height", header.skin_height.native()),
+            ("vertices", header.num_verts.native()),
+            ("triangles", header.num_tris.native()),
+            ("frames", header.num_frames.native()),
+        ];
+
+        for &(field, count) in &counts {
+            if count < 0 {
+                return Err(Error::BadCount {
+                    lump: "header",
+                    record: 0,
+                    field,
+                    count: count as _,
+                    min: 0,
+                });
+            }
+        }
+
+        let skin_len = header.skin_width.native() as usize * header.skin_height.native() as usize;
+        let num_verts = header.num_verts.native() as usize;
+        let num_tris = header.num_tris.native() as usize;
+        let frame_len = mem::size_of::<raw::SimpleFrame>() +
+            num_verts * mem::size_of::<raw::TriVertex>();
+
+        let mut cursor = Cursor {
+            data: &data,
+            pos: mem::size_of::<raw::Header>(),
+        };
+
+        let skins = (0..header.num_skins.native())
+            .map(|i| cursor.section("skins", i as usize, skin_len, 0))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tex_coords = cursor.skip(
+            "tex_coords",
+            num_verts * mem::size_of::<raw::TexCoord>(),
+        )?;
+        let triangles = cursor.skip("triangles", num_tris * mem::size_of::<raw::Triangle>())?;
+        let frames = (0..header.num_frames.native())
+            // Frame groups have a bounding box between the count and the timings
+            .map(|i| {
+                let extra = 2 * mem::size_of::<raw::TriVertex>();
+
+                cursor.section("frames", i as usize, frame_len, extra)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (i, triangle) in unsafe { slice_at::<raw::Triangle>(&data, triangles, num_tris) }
+            .unwrap()
+            .iter()
+            .enumerate()
+        {
+            for vertex in &triangle.vertices {
+                let vertex = vertex.native();
+
+                if vertex < 0 || vertex as usize >= num_verts {
+                    return Err(Error::BadIndex {
+                        lump: "triangles",
+                        record: i,
+                        field: "vertices",
+                        index: vertex as _,
+                        len: num_verts,
+                    });
+                }
+            }
+        }
+
+        Ok(Mdl {
+            data,
+            skins,
+            tex_coords,
+            triangles,
+            frames,
+        })
+    }
+
+    fn header(&self) -> &raw::Header {
+        unsafe { value_at(&self.data, 0).unwrap() }
+    }
+
+    pub fn scale(&self) -> Vec3<f32> {
+        self.header().scale.native()
+    }
+
+    pub fn translate(&self) -> Vec3<f32> {
+        self.header().translate.native()
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        self.header().bounding_radius.native()
+    }
+
+    pub fn eye_position(&self) -> Vec3<f32> {
+        self.header().eye_position.native()
+    }
+
+    pub fn skin_width(&self) -> u32 {
+        self.header().skin_width.native() as _
+    }
+
+    pub fn skin_height(&self) -> u32 {
+        self.header().skin_height.native() as _
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.header().num_verts.native() as _
+    }
+
+    /// Whether animations of different entities using this model run in sync (0) or are
+    /// randomly offset (1).
+    pub fn sync_type(&self) -> i32 {
+        self.header().sync_type.native()
+    }
+
+    /// Effect flags such as rocket trails and rotation (`EF_ROCKET`, `EF_ROTATE`, ...)
+    pub fn flags(&self) -> i32 {
+        self.header().flags.native()
+    }
+
+    fn times(&self, offset: usize, count: usize) -> Vec<f32> {
+        let times: &[Little<f32>] = unsafe { slice_at(&self.data, offset, count).unwrap() };
+
+        times.iter().map(|t| t.native()).collect()
+    }
+
+    /// Every skin, each one `skin_width` by `skin_height` palette indices.
+    pub fn skins(&self) -> Vec<Skin<'_>> {
+        let len = self.skin_width() as usize * self.skin_height() as usize;
+
+        self.skins
+            .iter()
+            .map(|section| match *section {
+                Section::Single(offset) => Skin::Single(&self.data[offset..offset + len]),
+                Section::Group {
+                    count,
+                    times,
+                    start,
+                } => {
+                    Skin::Group {
+                        times: self.times(times, count),
+                        images: (0..count)
+                            .map(|i| &self.data[start + i * len..start + (i + 1) * len])
+                            .collect(),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn tex_coords(&self) -> Vec<TexCoord> {
+        let raw: &[raw::TexCoord] =
+            unsafe { slice_at(&self.data, self.tex_coords, self.num_vertices()).unwrap() };
+
+        raw.iter()
+            .map(|tc| {
+                TexCoord {
+                    on_seam: tc.on_seam.native() != 0,
+                    s: tc.s.native(),
+                    t: tc.t.native(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn triangles(&self) -> Vec<Triangle> {
+        let count = self.header().num_tris.native() as usize;
+        let raw: &[raw::Triangle] =
+            unsafe { slice_at(&self.data, self.triangles, count).unwrap() };
+
+        raw.iter()
+            .map(|tri| {
+                Triangle {
+                    faces_front: tri.faces_front.native() != 0,
+                    vertices: [
+                        tri.vertices[0].native() as usize,
+                        tri.vertices[1].native() as usize,
+                        tri.vertices[2].native() as usize,
+                    ],
+                }
+            })
+            .collect()
+    }
+
+    /// The texture coordinates of one corner of a triangle, in the range 0 to 1. Vertices on the
+    /// seam between the front and back halves of the skin are shifted across to the back half for
+    /// triangles that face backwards.
+    pub fn triangle_uv(&self, triangle: &Triangle, corner: usize) -> (f32, f32) {
+        let tc: &raw::TexCoord = unsafe {
+            value_at(
+                &self.data,
+                self.tex_coords + triangle.vertices[corner] * mem::size_of::<raw::TexCoord>(),
+            ).unwrap()
+        };
+        let mut s = tc.s.native() as f32;
+
+        if tc.on_seam.native() != 0 && !triangle.faces_front {
+            s += self.skin_width() as f32 / 2.;
+        }
+
+        (
+            (s + 0.5) / self.skin_width() as f32,
+            (tc.t.native() as f32 + 0.5) / self.skin_height() as f32,
+        )
+    }
+
+    fn simple_frame(&self, offset: usize) -> SimpleFrame<'_> {
+        let header: &raw::SimpleFrame = unsafe { value_at(&self.data, offset).unwrap() };
+        let vertices = unsafe {
+            slice_at(
+                &self.data,
+                offset + mem::size_of::<raw::SimpleFrame>(),
+                self.num_vertices(),
+            ).unwrap()
+        };
+        let len = header.name.iter().position(|&c| c == 0).unwrap_or(16);
+
+        SimpleFrame {
+            name: String::from_utf8_lossy(&header.name[..len]),
+            min: header.min,
+            max: header.max,
+            vertices,
+            scale: self.scale(),
+            translate: self.translate(),
+        }
+    }
+
+    pub fn frames(&self) -> Vec<Frame<'_>> {
+        let frame_len = mem::size_of::<raw::SimpleFrame>() +
+            self.num_vertices() * mem::size_of::<raw::TriVertex>();
+
+        self.frames
+            .iter()
+            .map(|section| match *section {
+                Section::Single(offset) => Frame::Single(self.simple_frame(offset)),
+                Section::Group {
+                    count,
+                    times,
+                    start,
+                } => {
+                    Frame::Group {
+                        times: self.times(times, count),
+                        frames: (0..count)
+                            .map(|i| self.simple_frame(start + i * frame_len))
+                            .collect(),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Skin<'a> {
+    Single(&'a [u8]),
+    /// An animated skin. `times` are when each image ends, in seconds since the start of the
+    /// loop.
+    Group {
+        times: Vec<f32>,
+        images: Vec<&'a [u8]>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TexCoord {
+    pub on_seam: bool,
+    pub s: i32,
+    pub t: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Triangle {
+    pub faces_front: bool,
+    pub vertices: [usize; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct SimpleFrame<'a> {
+    pub name: Cow<'a, str>,
+    pub min: TriVertex,
+    pub max: TriVertex,
+    pub vertices: &'a [TriVertex],
+    scale: Vec3<f32>,
+    translate: Vec3<f32>,
+}
+
+impl<'a> SimpleFrame<'a> {
+    fn decompress(&self, vertex: &TriVertex) -> Vec3<f32> {
+        Vec3 {
+            x: vertex.position[0] as f32 * self.scale.x + self.translate.x,
+            y: vertex.position[1] as f32 * self.scale.y + self.translate.y,
+            z: vertex.position[2] as f32 * self.scale.z + self.translate.z,
+        }
+    }
+
+    /// The model-space position of vertex `index`.
+    pub fn position(&self, index: usize) -> Vec3<f32> {
+        self.decompress(&self.vertices[index])
+    }
+
+    pub fn positions(&self) -> Vec<Vec3<f32>> {
+        self.vertices.iter().map(|v| self.decompress(v)).collect()
+    }
+
+    pub fn bounds(&self) -> (Vec3<f32>, Vec3<f32>) {
+        (self.decompress(&self.min), self.decompress(&self.max))
+    }
+
+    /// The vertex positions `fraction` of the way from this frame to `other`, as the engine does
+    /// for smooth animation.
+    pub fn lerp(&self, other: &SimpleFrame, fraction: f32) -> Vec<Vec3<f32>> {
+        self.vertices
+            .iter()
+            .zip(other.vertices)
+            .map(|(a, b)| {
+                let a = self.decompress(a);
+                let b = other.decompress(b);
+
+                a + (b - a) * fraction
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Frame<'a> {
+    Single(SimpleFrame<'a>),
+    /// A self-contained animation, such as a flame. `times` are when each frame ends.
+    Group {
+        times: Vec<f32>,
+        frames: Vec<SimpleFrame<'a>>,
+    },
+}
+
+impl<'a> Frame<'a> {
+    /// The frame to show `time` seconds into the animation, looping groups as the engine does.
+    pub fn at_time(&self, time: f32) -> &SimpleFrame<'a> {
+        match *self {
+            Frame::Single(ref frame) => frame,
+            Frame::Group {
+                ref times,
+                ref frames,
+            } => group_item(times, frames, time),
+        }
+    }
+}
@@ -0,0 +1,689 @@
+//! Navigation meshes for bots, built from the floors the player can stand on
+//!
+//! Every upward-facing world face that isn't too steep becomes a polygon, as long as the player's
+//! clipping hull (hull 1) has room to stand on it. Polygons are linked where their edges meet,
+//! where a step joins them, and where the player can jump or drop from one to the other, and
+//! `find_path` runs A* over those links. The compiler cuts floors up along every plane in the
+//! map, so neighbouring floor faces on the same plane are merged back together wherever the
+//! result is still convex, as `qbsp` merges faces.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use bsp::hull::Hull;
+use bsp::quake1::LeafType;
+use bsp::raycast::cross;
+use bsp::{Bsp, Error, MapVersion, Vec3};
+use sys::bsp::Quake1Lump;
+
+/// How close two edges have to be, horizontally, to count as touching.
+const EDGE_EPSILON: f32 = 0.5;
+/// How much two touching edges have to overlap to be walked across.
+const MIN_OVERLAP: f32 = 1.;
+/// How close two vertices have to be to be the same one, and how far a vertex can be off the
+/// line through its neighbours to still be on it.
+const VERTEX_EPSILON: f32 = 0.01;
+
+/// What the player is able to do, by default the Quake player's limits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavConfig {
+    /// The least Z a floor's normal can have. The engine treats anything below 0.7 as a wall.
+    pub min_normal_z: f32,
+    /// The highest ledge that can be walked up
+    pub step_height: f32,
+    /// The highest ledge that can be jumped onto
+    pub jump_height: f32,
+    /// The widest gap that can be jumped across
+    pub jump_distance: f32,
+    /// The furthest the player can fall without taking damage
+    pub max_drop: f32,
+    /// What taking a jump or drop link costs on top of its length
+    pub jump_cost: f32,
+}
+
+impl Default for NavConfig {
+    fn default() -> Self {
+        NavConfig {
+            min_normal_z: 0.7,
+            step_height: 18.,
+            jump_height: 44.,
+            jump_distance: 128.,
+            max_drop: 256.,
+            jump_cost: 64.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// The polygons share an edge
+    Walk,
+    /// The polygons meet at a ledge no higher than `step_height`
+    Step,
+    /// A jump up or across a gap, or a drop off a ledge. These only go one way.
+    Jump,
+}
+
+/// A way from one polygon to another. Walk and step links always come in pairs, one on each
+/// polygon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavLink {
+    pub to: usize,
+    pub kind: LinkKind,
+    /// Where the link leaves this polygon, on the floor
+    pub start: Vec3<f32>,
+    /// Where it arrives on the other, on the floor
+    pub end: Vec3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPolygon {
+    /// The faces this polygon was merged from
+    pub faces: Vec<usize>,
+    pub vertices: Vec<Vec3<f32>>,
+    pub normal: Vec3<f32>,
+    pub distance: f32,
+    pub centre: Vec3<f32>,
+    pub links: Vec<NavLink>,
+}
+
+impl NavPolygon {
+    /// The height of the floor at `x`, `y`.
+    pub fn height_at(&self, x: f32, y: f32) -> f32 {
+        (self.distance - self.normal.x * x - self.normal.y * y) / self.normal.z
+    }
+
+    /// Whether `x`, `y` is inside the polygon when seen from above.
+    pub fn contains_xy(&self, x: f32, y: f32) -> bool {
+        let (mut front, mut back) = (false, false);
+
+        for (i, a) in self.vertices.iter().enumerate() {
+            let b = &self.vertices[(i + 1) % self.vertices.len()];
+            let cross = (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+
+            front |= cross > 0.01;
+            back |= cross < -0.01;
+        }
+
+        !(front && back)
+    }
+
+    /// `point` moved up to `distance` towards the centre, onto the floor.
+    fn inset(&self, point: Vec3<f32>, distance: f32) -> Vec3<f32> {
+        let (dx, dy) = (self.centre.x - point.x, self.centre.y - point.y);
+        let t = (distance / (dx * dx + dy * dy).sqrt().max(distance)).min(1.);
+        let (x, y) = (point.x + dx * t, point.y + dy * t);
+
+        Vec3 {
+            x,
+            y,
+            z: self.height_at(x, y),
+        }
+    }
+}
+
+/// A route found by `NavMesh::find_path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPath {
+    /// Every polygon passed through, from the start's to the end's
+    pub polygons: Vec<usize>,
+    /// The start, where each link is crossed and the end, all on the floor except for the
+    /// start and end which are as given
+    pub points: Vec<Vec3<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavMesh {
+    pub polygons: Vec<NavPolygon>,
+}
+
+fn length(v: Vec3<f32>) -> f32 {
+    v.dot(&v).sqrt()
+}
+
+pub(crate) fn up(z: f32) -> Vec3<f32> {
+    Vec3 { x: 0., y: 0., z }
+}
+
+/// The closest points of two segments, looking only at X and Y, and the distance between them.
+fn closest_xy(
+    a: (Vec3<f32>, Vec3<f32>),
+    b: (Vec3<f32>, Vec3<f32>),
+) -> (Vec3<f32>, Vec3<f32>, f32) {
+    let project = |p: Vec3<f32>, (s, e): (Vec3<f32>, Vec3<f32>)| {
+        let d = (e.x - s.x, e.y - s.y);
+        let len = d.0 * d.0 + d.1 * d.1;
+        let t = if len > 0. {
+            (((p.x - s.x) * d.0 + (p.y - s.y) * d.1) / len).clamp(0., 1.)
+        } else {
+            0.
+        };
+
+        s + (e - s) * t
+    };
+    let distance = |p: Vec3<f32>, q: Vec3<f32>| ((p.x - q.x).powi(2) + (p.y - q.y).powi(2)).sqrt();
+
+    let mut best = (a.0, project(a.0, b));
+
+    for &(p, q) in &[
+        (a.1, project(a.1, b)),
+        (project(b.0, a), b.0),
+        (project(b.1, a), b.1),
+    ] {
+        if distance(p, q) < distance(best.0, best.1) {
+            best = (p, q);
+        }
+    }
+
+    // Crossing segments touch where they cross, which the endpoints alone don't find
+    let denominator = (a.1.x - a.0.x) * (b.1.y - b.0.y) - (a.1.y - a.0.y) * (b.1.x - b.0.x);
+
+    if denominator.abs() > 1e-6 {
+        let t = ((b.0.x - a.0.x) * (b.1.y - b.0.y) - (b.0.y - a.0.y) * (b.1.x - b.0.x)) /
+            denominator;
+        let u = ((b.0.x - a.0.x) * (a.1.y - a.0.y) - (b.0.y - a.0.y) * (a.1.x - a.0.x)) /
+            denominator;
+
+        if (0. ..=1.).contains(&t) && (0. ..=1.).contains(&u) {
+            best = (a.0 + (a.1 - a.0) * t, b.0 + (b.1 - b.0) * u);
+        }
+    }
+
+    let d = distance(best.0, best.1);
+
+    (best.0, best.1, d)
+}
+
+/// Where two edges lie along the same line when seen from above, the middle of the part they
+/// share, on each edge.
+fn shared_xy(
+    a: (Vec3<f32>, Vec3<f32>),
+    b: (Vec3<f32>, Vec3<f32>),
+) -> Option<(Vec3<f32>, Vec3<f32>)> {
+    let d = (a.1.x - a.0.x, a.1.y - a.0.y);
+    let len = (d.0 * d.0 + d.1 * d.1).sqrt();
+
+    if len < MIN_OVERLAP {
+        return None;
+    }
+
+    let dir = (d.0 / len, d.1 / len);
+    let along = |p: Vec3<f32>| (p.x - a.0.x) * dir.0 + (p.y - a.0.y) * dir.1;
+    let across = |p: Vec3<f32>| ((p.x - a.0.x) * dir.1 - (p.y - a.0.y) * dir.0).abs();
+
+    if across(b.0) > EDGE_EPSILON || across(b.1) > EDGE_EPSILON {
+        return None;
+    }
+
+    let (b0, b1) = (along(b.0), along(b.1));
+    let start = b0.min(b1).max(0.);
+    let end = b0.max(b1).min(len);
+
+    if end - start < MIN_OVERLAP {
+        return None;
+    }
+
+    let middle = (start + end) / 2.;
+    let on_a = a.0 + (a.1 - a.0) * (middle / len);
+    let on_b = if (b1 - b0).abs() > 0. {
+        b.0 + (b.1 - b.0) * ((middle - b0) / (b1 - b0))
+    } else {
+        b.0
+    };
+
+    Some((on_a, on_b))
+}
+
+/// `a` and `b` joined along an edge they share into one convex polygon, if they share one and
+/// the result is convex. Both have to be on the same plane, facing along `normal`.
+fn merge(a: &[Vec3<f32>], b: &[Vec3<f32>], normal: &Vec3<f32>) -> Option<Vec<Vec3<f32>>> {
+    let same = |p: Vec3<f32>, q: Vec3<f32>| length(p - q) < VERTEX_EPSILON;
+    let (i, j) = (0..a.len())
+        .flat_map(|i| (0..b.len()).map(move |j| (i, j)))
+        .find(|&(i, j)| {
+            same(a[i], b[(j + 1) % b.len()]) && same(a[(i + 1) % a.len()], b[j])
+        })?;
+
+    // All of `a` from the far end of the shared edge round to its near end, then the rest of `b`
+    let joined = (1..=a.len())
+        .map(|k| a[(i + k) % a.len()])
+        .chain((2..b.len()).map(|k| b[(j + k) % b.len()]))
+        .collect::<Vec<_>>();
+    let mut merged = Vec::with_capacity(joined.len());
+    let mut winding = 0.;
+
+    for (k, &v) in joined.iter().enumerate() {
+        let previous = joined[(k + joined.len() - 1) % joined.len()];
+        let next = joined[(k + 1) % joined.len()];
+        let (into, out) = (v - previous, next - v);
+        let turn = cross(into, out).dot(normal);
+
+        // Vertices in the middle of a straight edge are dropped
+        if turn.abs() <= VERTEX_EPSILON * length(into).max(length(out)) {
+            continue;
+        }
+
+        if turn * winding < 0. {
+            return None;
+        }
+
+        winding = turn;
+        merged.push(v);
+    }
+
+    Some(merged)
+}
+
+/// Merge the polygons that are on the same plane and share an edge, as long as they stay convex.
+fn merge_coplanar(polygons: &mut Vec<NavPolygon>) {
+    let mut i = 0;
+
+    while i < polygons.len() {
+        let mut j = i + 1;
+
+        while j < polygons.len() {
+            let (a, b) = (&polygons[i], &polygons[j]);
+            let coplanar = a.normal.dot(&b.normal) > 1. - VERTEX_EPSILON &&
+                (a.distance - b.distance).abs() < VERTEX_EPSILON;
+
+            match merge(&a.vertices, &b.vertices, &a.normal).filter(|_| coplanar) {
+                Some(vertices) => {
+                    let b = polygons.remove(j);
+                    let a = &mut polygons[i];
+
+                    a.centre = vertices.iter().fold(up(0.), |sum, &v| sum + v) *
+                        (1. / vertices.len() as f32);
+                    a.vertices = vertices;
+                    a.faces.extend(b.faces);
+
+                    // What's grown might now reach polygons that were passed over already
+                    j = i + 1;
+                }
+                None => j += 1,
+            }
+        }
+
+        i += 1;
+    }
+}
+
+fn edges(vertices: &[Vec3<f32>]) -> impl Iterator<Item = (Vec3<f32>, Vec3<f32>)> + '_ {
+    (0..vertices.len()).map(move |i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+}
+
+pub(crate) fn is_open(contents: LeafType) -> bool {
+    matches!(contents, LeafType::Ordinary | LeafType::Water)
+}
+
+/// The cheapest way found to a polygon so far.
+#[derive(Clone, Copy)]
+struct Visit {
+    cost: f32,
+    /// Where the polygon was entered
+    position: Vec3<f32>,
+    /// The polygon it was entered from and the index of the link taken
+    link: Option<(usize, usize)>,
+}
+
+struct Open {
+    cost: f32,
+    estimate: f32,
+    polygon: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    // Reversed, so that `BinaryHeap` pops the cheapest first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// The world's walkable floors, along with the player's hull and how far the player's origin is
+/// above the floor they stand on.
+pub(crate) struct Floors<'a, V: 'a> {
+    pub hull: Hull<'a, V>,
+    pub stand: Vec3<f32>,
+    pub polygons: Vec<NavPolygon>,
+}
+
+/// Find the world faces the player can stand on, as unlinked polygons. Fails if the map has no
+/// clipping hulls.
+pub(crate) fn floors<'a, V>(bsp: &'a Bsp<'a, V>, config: &NavConfig) -> Result<Floors<'a, V>, Error>
+where
+    V: MapVersion<Lump = Quake1Lump>,
+{
+    let world = bsp.map_model();
+    let hull = world
+        .hull(1)
+        .ok_or(Error::Unsupported("navigation without a player clipping hull"))?;
+    let stand = up(-hull.bounds().aa.z + 1.);
+
+    let mut polygons = Vec::new();
+
+    for (i, face) in world.faces().enumerate() {
+        let plane = face.plane();
+
+        if plane.normal.z < config.min_normal_z || face.texinfo().is_special() {
+            continue;
+        }
+
+        let vertices = face.vertices();
+
+        if vertices.len() < 3 {
+            continue;
+        }
+
+        let centre = vertices
+            .iter()
+            .fold(up(0.), |sum, &v| sum + v) *
+            (1. / vertices.len() as f32);
+
+        // A floor is worth keeping if the player fits anywhere on it, not just in the middle,
+        // since the compiler cuts floors into strips along walls
+        let fits = ::std::iter::once(centre)
+            .chain(vertices.iter().map(|&v| v + (centre - v) * 0.25))
+            .any(|point| is_open(hull.point_contents(&(point + stand))));
+
+        if fits {
+            polygons.push(NavPolygon {
+                faces: vec![world.face_id() + i],
+                vertices,
+                normal: plane.normal,
+                distance: plane.distance,
+                centre,
+                links: Vec::new(),
+            });
+        }
+    }
+
+    merge_coplanar(&mut polygons);
+
+    Ok(Floors {
+        hull,
+        stand,
+        polygons,
+    })
+}
+
+impl NavMesh {
+    /// Build a navigation mesh from the world model. Fails if the map has no clipping hulls.
+    pub fn new<V>(bsp: &Bsp<V>, config: &NavConfig) -> Result<Self, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        let Floors {
+            hull,
+            stand,
+            polygons,
+        } = floors(bsp, config)?;
+        let mut mesh = NavMesh { polygons };
+
+        mesh.link(&hull, stand, config);
+
+        Ok(mesh)
+    }
+
+    fn link<V>(&mut self, hull: &Hull<V>, stand: Vec3<f32>, config: &NavConfig)
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        let bounds = self.polygons
+            .iter()
+            .map(|polygon| {
+                polygon.vertices.iter().fold(
+                    (polygon.vertices[0], polygon.vertices[0]),
+                    |(min, max), v| {
+                        (
+                            Vec3 {
+                                x: min.x.min(v.x),
+                                y: min.y.min(v.y),
+                                z: min.z.min(v.z),
+                            },
+                            Vec3 {
+                                x: max.x.max(v.x),
+                                y: max.y.max(v.y),
+                                z: max.z.max(v.z),
+                            },
+                        )
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let radius = hull.bounds().bb.x.max(hull.bounds().bb.y) + 1.;
+        let reach = config.jump_distance.max(EDGE_EPSILON);
+        let reach_z = config.max_drop.max(config.jump_height);
+
+        // The player can get from `start` to `end` by rising to the higher of the two, moving
+        // across and then falling
+        let clear = |start: Vec3<f32>, end: Vec3<f32>| {
+            let (start, end) = (start + stand, end + stand);
+            let top = start.z.max(end.z);
+            let waypoints = [
+                start,
+                Vec3 { z: top, ..start },
+                Vec3 { z: top, ..end },
+                end,
+            ];
+
+            waypoints
+                .windows(2)
+                .all(|pair| !hull.trace(&pair[0], &pair[1]).hit())
+        };
+
+        for a in 0..self.polygons.len() {
+            for b in a + 1..self.polygons.len() {
+                let ((a_min, a_max), (b_min, b_max)) = (bounds[a], bounds[b]);
+
+                if a_min.x - reach > b_max.x || b_min.x - reach > a_max.x ||
+                    a_min.y - reach > b_max.y || b_min.y - reach > a_max.y ||
+                    a_min.z - reach_z > b_max.z || b_min.z - reach_z > a_max.z
+                {
+                    continue;
+                }
+
+                let mut touching = None;
+                let mut ledge = None;
+                let mut nearest: Option<(Vec3<f32>, Vec3<f32>, f32)> = None;
+
+                for edge_a in edges(&self.polygons[a].vertices) {
+                    for edge_b in edges(&self.polygons[b].vertices) {
+                        if let Some((on_a, on_b)) = shared_xy(edge_a, edge_b) {
+                            if (on_b.z - on_a.z).abs() <= config.step_height {
+                                touching = Some((on_a, on_b));
+                                break;
+                            }
+
+                            // A ledge straight above the other floor. Its middle is a better
+                            // place to jump or drop than the corners `closest_xy` finds.
+                            ledge = Some((on_a, on_b, 0.));
+                        }
+
+                        let closest = closest_xy(edge_a, edge_b);
+
+                        if nearest.map_or(true, |n| closest.2 < n.2) {
+                            nearest = Some(closest);
+                        }
+                    }
+
+                    if touching.is_some() {
+                        break;
+                    }
+                }
+
+                if let Some((on_a, on_b)) = touching {
+                    let kind = if (on_b.z - on_a.z).abs() <= EDGE_EPSILON {
+                        LinkKind::Walk
+                    } else {
+                        LinkKind::Step
+                    };
+
+                    self.polygons[a].links.push(NavLink {
+                        to: b,
+                        kind,
+                        start: on_a,
+                        end: on_b,
+                    });
+                    self.polygons[b].links.push(NavLink {
+                        to: a,
+                        kind,
+                        start: on_b,
+                        end: on_a,
+                    });
+
+                    continue;
+                }
+
+                let (on_a, on_b, gap) = match ledge.or(nearest) {
+                    Some(nearest) if nearest.2 <= config.jump_distance => nearest,
+                    _ => continue,
+                };
+
+                for &(from, to, start, end) in &[(a, b, on_a, on_b), (b, a, on_b, on_a)] {
+                    let rise = end.z - start.z;
+
+                    // Edges meeting at a corner at the same height aren't a jump
+                    if gap <= EDGE_EPSILON && rise.abs() <= config.step_height {
+                        continue;
+                    }
+
+                    if rise > config.jump_height || -rise > config.max_drop {
+                        continue;
+                    }
+
+                    // Standing right on the edge would put half the player in the wall below a
+                    // ledge, so take off and land a player's width in
+                    let start = self.polygons[from].inset(start, radius);
+                    let end = self.polygons[to].inset(end, radius);
+
+                    if clear(start, end) {
+                        self.polygons[from].links.push(NavLink {
+                            to,
+                            kind: LinkKind::Jump,
+                            start,
+                            end,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// The polygon under `point`: the highest floor below it, or at most a unit above it so
+    /// that points on the floor itself are found.
+    pub fn polygon_at(&self, point: Vec3<f32>) -> Option<usize> {
+        self.polygons
+            .iter()
+            .enumerate()
+            .filter(|(_, polygon)| polygon.contains_xy(point.x, point.y))
+            .map(|(i, polygon)| (i, polygon.height_at(point.x, point.y)))
+            .filter(|&(_, z)| z <= point.z + 1.)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+
+    /// The shortest route over the mesh from `start` to `end`, or `None` if either isn't above
+    /// a polygon or there's no way between them. Jumps cost `jump_cost` more than their length.
+    pub fn find_path(
+        &self,
+        start: Vec3<f32>,
+        end: Vec3<f32>,
+        config: &NavConfig,
+    ) -> Option<NavPath> {
+        let first = self.polygon_at(start)?;
+        let last = self.polygon_at(end)?;
+
+        let mut best: Vec<Option<Visit>> = vec![None; self.polygons.len()];
+        let mut open = BinaryHeap::new();
+
+        best[first] = Some(Visit {
+            cost: 0.,
+            position: start,
+            link: None,
+        });
+        open.push(Open {
+            cost: 0.,
+            estimate: length(end - start),
+            polygon: first,
+        });
+
+        while let Some(Open { cost, polygon, .. }) = open.pop() {
+            let position = match best[polygon] {
+                Some(ref visit) if visit.cost >= cost => visit.position,
+                _ => continue,
+            };
+
+            if polygon == last {
+                break;
+            }
+
+            for (i, link) in self.polygons[polygon].links.iter().enumerate() {
+                let mut step = length(link.start - position) + length(link.end - link.start);
+
+                if link.kind == LinkKind::Jump {
+                    step += config.jump_cost;
+                }
+
+                let cost = cost + step;
+
+                if best[link.to].as_ref().map_or(true, |visit| cost < visit.cost) {
+                    best[link.to] = Some(Visit {
+                        cost,
+                        position: link.end,
+                        link: Some((polygon, i)),
+                    });
+                    open.push(Open {
+                        cost,
+                        estimate: cost + length(end - link.end),
+                        polygon: link.to,
+                    });
+                }
+            }
+        }
+
+        best[last]?;
+
+        let mut polygons = vec![last];
+        let mut points = vec![end];
+        let mut current = last;
+
+        while let Some(Visit {
+            link: Some((previous, link)),
+            ..
+        }) = best[current]
+        {
+            let link = &self.polygons[previous].links[link];
+
+            points.push(link.end);
+
+            if link.start != link.end {
+                points.push(link.start);
+            }
+
+            polygons.push(previous);
+            current = previous;
+        }
+
+        points.push(start);
+        polygons.reverse();
+        points.reverse();
+
+        Some(NavPath { polygons, points })
+    }
+}
@@ -0,0 +1,197 @@
+//! Top-down overview images, like the radar maps GoldSrc games show on the scoreboard
+//!
+//! The map is drawn orthographically from above, one horizontal slice at a time, so that
+//! multi-storey maps can get one image per floor.
+
+use bsp::{MapVersion, Vec3};
+use render::{self, Image, Renderer};
+use sys::bsp::Quake1Lump;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading {
+    /// Grey, darker the steeper the floor
+    Flat,
+    /// The lit texture, as `Renderer::render` would draw it
+    Textured,
+}
+
+/// What to draw. Only geometry between `z_min` and `z_max` is included, and it's scaled to fit
+/// in `width` by `height` pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overview {
+    pub z_min: f32,
+    pub z_max: f32,
+    pub width: u32,
+    pub height: u32,
+    pub shading: Shading,
+    /// Tint floors from blue at `z_min` through green to red at `z_max`
+    pub height_colors: bool,
+}
+
+impl Overview {
+    pub fn new(z_min: f32, z_max: f32, width: u32, height: u32) -> Self {
+        Overview {
+            z_min,
+            z_max,
+            width,
+            height,
+            shading: Shading::Textured,
+            height_colors: true,
+        }
+    }
+}
+
+/// Maps world X and Y to image pixels. World X points right and world Y points up the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverviewTransform {
+    /// The world position of the image's top-left corner
+    pub origin: (f32, f32),
+    /// Pixels per world unit
+    pub scale: f32,
+}
+
+impl OverviewTransform {
+    pub fn world_to_pixel(&self, point: Vec3<f32>) -> (f32, f32) {
+        (
+            (point.x - self.origin.0) * self.scale,
+            (self.origin.1 - point.y) * self.scale,
+        )
+    }
+
+    pub fn pixel_to_world(&self, x: f32, y: f32) -> (f32, f32) {
+        (self.origin.0 + x / self.scale, self.origin.1 - y / self.scale)
+    }
+}
+
+/// Blue, green, red as `fraction` goes from 0 to 1.
+fn height_color(fraction: f32) -> [f32; 3] {
+    let f = fraction.clamp(0., 1.) * 2.;
+
+    if f < 1. {
+        [0., f * 255., (1. - f) * 255.]
+    } else {
+        [(f - 1.) * 255., (2. - f) * 255., 0.]
+    }
+}
+
+/// Clip a polygon to the side of the horizontal plane at `z` given by `keep_above`.
+fn clip_z(polygon: &[Vec3<f32>], z: f32, keep_above: bool) -> Vec<Vec3<f32>> {
+    let inside = |v: &Vec3<f32>| (v.z >= z) == keep_above;
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+
+        if inside(a) {
+            out.push(*a);
+        }
+
+        if inside(a) != inside(b) {
+            out.push(*a + (*b - *a) * ((z - a.z) / (b.z - a.z)));
+        }
+    }
+
+    out
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump> + 'a> Renderer<'a, V> {
+    /// Render the upward-facing faces within the overview's slice, returning the image and the
+    /// transform from world to pixel coordinates. The transform only depends on the world's
+    /// bounds and the image size, so it's shared by every slice. Pixels with nothing in them are
+    /// transparent.
+    pub fn render_overview(&self, overview: &Overview) -> (Image, OverviewTransform) {
+        let floors = self.surfaces()
+            .into_iter()
+            .filter(|surface| {
+                surface.normal.z > 0. &&
+                    !surface
+                        .texture
+                        .is_some_and(|t| t.name.to_lowercase().starts_with("sky"))
+            })
+            .filter_map(|surface| {
+                let clipped = clip_z(&surface.vertices, overview.z_min, true);
+                let clipped = clip_z(&clipped, overview.z_max, false);
+
+                if clipped.len() < 3 {
+                    None
+                } else {
+                    Some((surface, clipped))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Fit the world's bounds in the image, centred, keeping the aspect ratio. Every slice of
+        // a map gets the same transform, so the floors of a multi-storey map line up.
+        let bounds = self.bsp.map_model().bounds();
+        let (min, max) = ((bounds.aa.x, bounds.aa.y), (bounds.bb.x, bounds.bb.y));
+        let scale = (overview.width as f32 / (max.0 - min.0).max(1.))
+            .min(overview.height as f32 / (max.1 - min.1).max(1.));
+        let centre = ((min.0 + max.0) / 2., (min.1 + max.1) / 2.);
+        let transform = OverviewTransform {
+            origin: (
+                centre.0 - overview.width as f32 / 2. / scale,
+                centre.1 + overview.height as f32 / 2. / scale,
+            ),
+            scale,
+        };
+
+        let mut image = Image::new(overview.width, overview.height);
+        let mut depth = vec![f32::MIN; overview.width as usize * overview.height as usize];
+        let range = (overview.z_max - overview.z_min).max(1.);
+
+        for (surface, polygon) in floors {
+            let projected = polygon
+                .iter()
+                .map(|&v| {
+                    let (x, y) = transform.world_to_pixel(v);
+
+                    (
+                        x,
+                        y,
+                        [
+                            v.z,
+                            v.dot(&surface.s.0) + surface.s.1,
+                            v.dot(&surface.t.0) + surface.t.1,
+                        ],
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            for i in 2..projected.len() {
+                let triangle = [projected[0], projected[i - 1], projected[i]];
+
+                render::rasterize(&triangle, overview.width, overview.height, |x, y, attrs| {
+                    let index = y as usize * overview.width as usize + x as usize;
+
+                    if attrs[0] <= depth[index] {
+                        return;
+                    }
+
+                    let base = match overview.shading {
+                        Shading::Flat => {
+                            let grey = 64. + 191. * surface.normal.z;
+
+                            Some([grey as u8; 3])
+                        }
+                        Shading::Textured => self.shade(&surface, attrs[1], attrs[2]),
+                    };
+
+                    if let Some(mut rgb) = base {
+                        if overview.height_colors {
+                            let tint = height_color((attrs[0] - overview.z_min) / range);
+
+                            for c in 0..3 {
+                                rgb[c] = ((rgb[c] as f32 + tint[c]) / 2.) as u8;
+                            }
+                        }
+
+                        depth[index] = attrs[0];
+                        image.set_pixel(x, y, [rgb[0], rgb[1], rgb[2], 255]);
+                    }
+                });
+            }
+        }
+
+        (image, transform)
+    }
+}
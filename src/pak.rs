@@ -0,0 +1,350 @@
+//! Quake PAK archives and a layered virtual filesystem over them
+//!
+//! Files inside a PAK are borrowed straight out of the (usually memory-mapped) archive, so a map
+//! can be loaded with `Bsp::new(pak.get("maps/e1m1.bsp").unwrap())` without copying it.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+
+use ioendian::IntoNativeEndian;
+
+use bsp::Error;
+use storage::Storage;
+use sys::{slice_at, value_at};
+use sys::pak as raw;
+
+const MAGIC: &[u8; 4] = b"PACK";
+const NAME_LEN: usize = 56;
+
+pub struct Pak<'a> {
+    data: Storage<'a>,
+}
+
+impl<'a> ::std::fmt::Debug for Pak<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "Pak {{ {} files }}", self.len())
+    }
+}
+
+impl<'a> Pak<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        Self::from_storage(Storage::Buffer(buffer.into()))
+    }
+
+    /// Memory-map a PAK file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Pak<'static>, Error> {
+        Pak::from_storage(Storage::open(path)?)
+    }
+
+    fn from_storage(data: Storage<'a>) -> Result<Self, Error> {
+        let header: &raw::Header = match unsafe { value_at(&data, 0) } {
+            Some(header) => header,
+            None => {
+                return Err(Error::Truncated {
+                    lump: "header",
+                    expected: mem::size_of::<raw::Header>(),
+                    actual: data.len(),
+                })
+            }
+        };
+
+        if &header.magic != MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: MAGIC,
+                found: header.magic,
+            });
+        }
+
+        let dir_offset = header.dir_offset.native();
+        let dir_len = header.dir_len.native();
+
+        if dir_offset < 0 || dir_len < 0 || dir_offset as i64 + dir_len as i64 > data.len() as i64 {
+            return Err(Error::LumpOutOfBounds {
+                lump: "directory",
+                offset: dir_offset,
+                len: dir_len,
+                file_len: data.len(),
+            });
+        }
+
+        if (dir_len as usize) % mem::size_of::<raw::DirEntry>() != 0 {
+            return Err(Error::Truncated {
+                lump: "directory",
+                expected: (dir_len as usize / mem::size_of::<raw::DirEntry>() + 1) *
+                    mem::size_of::<raw::DirEntry>(),
+                actual: dir_len as usize,
+            });
+        }
+
+        let out = Pak { data };
+
+        for (i, entry) in out.directory().iter().enumerate() {
+            let offset = entry.offset.native();
+            let len = entry.len.native();
+
+            if offset < 0 || len < 0 || offset as i64 + len as i64 > out.data.len() as i64 {
+                return Err(Error::BadIndex {
+                    lump: "directory",
+                    record: i,
+                    field: "offset",
+                    index: offset as _,
+                    len: out.data.len(),
+                });
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn directory(&self) -> &[raw::DirEntry] {
+        let header: &raw::Header = unsafe { value_at(&self.data, 0).unwrap() };
+        let count = header.dir_len.native() as usize / mem::size_of::<raw::DirEntry>();
+
+        unsafe { slice_at(&self.data, header.dir_offset.native() as usize, count).unwrap() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.directory().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directory().is_empty()
+    }
+
+    pub fn files(&self) -> Files<'_> {
+        Files {
+            pak: self,
+            inner: self.directory().iter(),
+        }
+    }
+
+    /// The contents of the file called `name`. If the archive contains the same name more than
+    /// once then the last one wins, like it does between archives.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files()
+            .filter(|file| file.name() == name)
+            .last()
+            .map(|file| file.data())
+    }
+}
+
+pub struct Files<'a> {
+    pak: &'a Pak<'a>,
+    inner: ::std::slice::Iter<'a, raw::DirEntry>,
+}
+
+impl<'a> Iterator for Files<'a> {
+    type Item = PakFile<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pak = self.pak;
+
+        self.inner.next().map(|entry| PakFile { entry, pak })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Files<'a> {}
+
+pub struct PakFile<'a> {
+    entry: &'a raw::DirEntry,
+    pak: &'a Pak<'a>,
+}
+
+impl<'a> PakFile<'a> {
+    pub fn name(&self) -> Cow<'a, str> {
+        let name = &self.entry.name;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        String::from_utf8_lossy(&name[..len])
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        let start = self.entry.offset.native() as usize;
+        let end = start + self.entry.len.native() as usize;
+
+        &self.pak.data[start..end]
+    }
+}
+
+enum Layer {
+    Pak(Pak<'static>),
+    Dir(PathBuf),
+}
+
+/// Whether `name` stays inside the directory it's looked up in. Names come from maps and other
+/// files, so without this `..`, roots and drive prefixes could reach anywhere on disk.
+fn is_contained(name: &str) -> bool {
+    Path::new(name)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// A stack of PAKs and directories searched from the most recently added down, so later
+/// layers override earlier ones. `add_game_dir` sets up the usual Quake layout.
+#[derive(Default)]
+pub struct FileSystem {
+    layers: Vec<Layer>,
+}
+
+impl ::std::fmt::Debug for FileSystem {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "FileSystem {{ {} layers }}", self.layers.len())
+    }
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_pak(&mut self, pak: Pak<'static>) {
+        self.layers.push(Layer::Pak(pak));
+    }
+
+    pub fn add_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.layers.push(Layer::Dir(dir.into()));
+    }
+
+    /// Add `pak0.pak`, `pak1.pak` and so on from `dir` until one is missing, then the directory
+    /// itself so that loose files override anything packed.
+    pub fn add_game_dir<P: Into<PathBuf>>(&mut self, dir: P) -> Result<(), Error> {
+        let dir = dir.into();
+
+        for i in 0.. {
+            let path = dir.join(format!("pak{}.pak", i));
+
+            if !path.is_file() {
+                break;
+            }
+
+            self.add_pak(Pak::open(path)?);
+        }
+
+        self.add_dir(dir);
+
+        Ok(())
+    }
+
+    /// Read `name` from the topmost layer that has it. Files inside PAKs are borrowed, loose
+    /// files have to be read into memory. Names that would leave the layers' directories, with
+    /// `..` or an absolute path, are rejected.
+    pub fn read(&self, name: &str) -> Result<Cow<'_, [u8]>, Error> {
+        if !is_contained(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, name.to_owned()).into());
+        }
+
+        for layer in self.layers.iter().rev() {
+            match *layer {
+                Layer::Pak(ref pak) => {
+                    if let Some(data) = pak.get(name) {
+                        return Ok(Cow::Borrowed(data));
+                    }
+                }
+                Layer::Dir(ref dir) => {
+                    match fs::read(dir.join(name)) {
+                        Ok(data) => return Ok(Cow::Owned(data)),
+                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, name.to_owned()).into())
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        is_contained(name) && self.layers.iter().any(|layer| match *layer {
+            Layer::Pak(ref pak) => pak.get(name).is_some(),
+            Layer::Dir(ref dir) => dir.join(name).is_file(),
+        })
+    }
+}
+
+/// Builds a new PAK archive. Files are written in the order they were added.
+#[derive(Debug, Default, Clone)]
+pub struct PakWriter {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl PakWriter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a file. Names are stored in a fixed-size field and must be at most 55 bytes long, and
+    /// offsets in signed 32-bit ones, so the whole archive has to stay under 2GiB. Names have to
+    /// be relative paths that stay inside the archive, as the reader only finds those.
+    pub fn add<S: Into<String>, D: Into<Vec<u8>>>(&mut self, name: S, data: D) -> Result<(), Error> {
+        let (name, data) = (name.into(), data.into());
+
+        if name.len() >= NAME_LEN {
+            return Err(Error::Unsupported("PAK file names longer than 55 bytes"));
+        }
+
+        // A NUL would end the name early once it's in its fixed-size field
+        if name.contains('\0') || !is_contained(&name) {
+            return Err(Error::Unsupported("PAK file names outside of the archive"));
+        }
+
+        let end = self.files.iter().map(|(_, data)| data.len()).sum::<usize>() +
+            data.len() +
+            mem::size_of::<raw::Header>() +
+            (self.files.len() + 1) * mem::size_of::<raw::DirEntry>();
+
+        if end > i32::MAX as usize {
+            return Err(Error::Unsupported("PAK files of 2GiB or more"));
+        }
+
+        self.files.push((name, data));
+
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        // `add` keeps everything in range of the header's and directory's `i32`s
+        fn le32(v: usize) -> [u8; 4] {
+            (v as u32).to_le_bytes()
+        }
+
+        let header_len = mem::size_of::<raw::Header>();
+        let data_len: usize = self.files.iter().map(|(_, data)| data.len()).sum();
+        let dir_len = self.files.len() * mem::size_of::<raw::DirEntry>();
+
+        out.write_all(MAGIC)?;
+        out.write_all(&le32(header_len + data_len))?;
+        out.write_all(&le32(dir_len))?;
+
+        for (_, data) in &self.files {
+            out.write_all(data)?;
+        }
+
+        let mut offset = header_len;
+
+        for (name, data) in &self.files {
+            let mut raw_name = [0; NAME_LEN];
+            raw_name[..name.len()].copy_from_slice(name.as_bytes());
+
+            out.write_all(&raw_name)?;
+            out.write_all(&le32(offset))?;
+            out.write_all(&le32(data.len()))?;
+
+            offset += data.len();
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+}
@@ -0,0 +1,55 @@
+//! Sharing work out between threads, for the compilers' passes over every portal or face.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Work can recurse deeply, vis's flows go once per leaf they pass through in large maps
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Run `work` on every item of `order`, which must be a permutation of the indices of the result,
+/// spreading the items over as many threads as there are cores.
+pub(crate) fn parallel<T, F>(order: &[usize], work: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut results = (0..order.len()).map(|_| None).collect::<Vec<_>>();
+
+    thread::scope(|scope| {
+        let (next, work) = (&next, &work);
+        let handles = (0..threads)
+            .map(|_| {
+                thread::Builder::new()
+                    .stack_size(STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        let mut done = Vec::new();
+
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+
+                            match order.get(i) {
+                                Some(&item) => done.push((item, work(item))),
+                                None => break done,
+                            }
+                        }
+                    })
+                    .expect("Couldn't start a worker thread")
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            for (item, result) in handle.join().expect("A worker thread panicked") {
+                results[item] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.expect("Every item is worked on"))
+        .collect()
+}
@@ -0,0 +1,432 @@
+//! A software renderer for map previews, drawing textured and lightmapped faces into an RGBA
+//! image with a z-buffer. It needs no GPU, so thumbnails can be made on headless machines.
+
+use std::io::{self, Write};
+
+use bsp::hull::Transform;
+use bsp::quake1::{Face, LightmapExtents};
+use bsp::{Bsp, Error, MapVersion, Vec3};
+use palette::{self, Colormap, Palette};
+use sys::bsp::Quake1Lump;
+use wad::MipTex;
+
+/// How close to the camera geometry is clipped.
+const NEAR: f32 = 1.;
+/// A lightmap sample at this level leaves the texture at its normal brightness.
+const NORMAL_LIGHT: f32 = 128.;
+
+/// An RGBA image, row by row from the top left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn write_png<W: Write>(&self, out: W) -> Result<(), Error> {
+        fn to_io(e: ::png::EncodingError) -> Error {
+            io::Error::other(e).into()
+        }
+
+        let mut encoder = ::png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(::png::ColorType::Rgba);
+        encoder.set_depth(::png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(to_io)?;
+        writer.write_image_data(&self.pixels).map_err(to_io)?;
+        writer.finish().map_err(to_io)
+    }
+}
+
+/// Where to render from. `angles` are pitch, yaw and roll in degrees like an entity's, and `fov`
+/// is the horizontal field of view in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    pub origin: Vec3<f32>,
+    pub angles: Vec3<f32>,
+    pub fov: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Camera {
+    pub fn new(origin: Vec3<f32>, angles: Vec3<f32>, width: u32, height: u32) -> Self {
+        Camera {
+            origin,
+            angles,
+            fov: 90.,
+            width,
+            height,
+        }
+    }
+}
+
+/// A face prepared for drawing: its texture and its lightmaps summed into a single grid.
+pub(crate) struct Surface<'b> {
+    pub(crate) vertices: Vec<Vec3<f32>>,
+    pub(crate) normal: Vec3<f32>,
+    distance: f32,
+    pub(crate) s: (Vec3<f32>, f32),
+    pub(crate) t: (Vec3<f32>, f32),
+    pub(crate) texture: Option<&'b MipTex<'b>>,
+    masked: bool,
+    extents: LightmapExtents,
+    /// Summed light per sample, or `None` to draw at normal brightness
+    light: Option<Vec<[f32; 3]>>,
+}
+
+pub struct Renderer<'a, V: 'a> {
+    pub(crate) bsp: &'a Bsp<'a, V>,
+    textures: Vec<Option<MipTex<'a>>>,
+    palette: Palette,
+    colormap: Colormap,
+    /// Colour for textures that are missing or external
+    missing: [u8; 3],
+}
+
+impl<'a, V: MapVersion<Lump = Quake1Lump> + 'a> Renderer<'a, V> {
+    /// A renderer using the textures embedded in the map, the Quake palette and a colormap
+    /// generated from it.
+    pub fn new(bsp: &'a Bsp<'a, V>) -> Self {
+        let palette = Palette::quake();
+
+        Renderer {
+            bsp,
+            textures: bsp.textures()
+                .iter()
+                .map(|(_, texture)| texture.and_then(|t| t.miptex()))
+                .collect(),
+            colormap: Colormap::generate(&palette),
+            palette,
+            missing: [128, 128, 128],
+        }
+    }
+
+    /// Use these textures instead, one per miptex slot, such as the result of
+    /// `wad::Resolver::resolve`.
+    pub fn with_textures(mut self, textures: Vec<Option<MipTex<'a>>>) -> Self {
+        self.textures = textures;
+        self
+    }
+
+    /// Use a different palette and colormap for textures that don't have their own palette.
+    pub fn with_palette(mut self, palette: Palette, colormap: Colormap) -> Self {
+        self.palette = palette;
+        self.colormap = colormap;
+        self
+    }
+
+    fn surface(&self, face: &Face<'a, V>, offset: Vec3<f32>) -> Surface<'_> {
+        let plane = face.plane();
+        let texinfo = face.texinfo();
+        let (s_axis, s_offset) = texinfo.s();
+        let (t_axis, t_offset) = texinfo.t();
+        let texture = self.textures
+            .get(texinfo.texture_id() as usize)
+            .and_then(|t| t.as_ref());
+        let extents = face.extents();
+        let maps = face.lightmaps();
+        let channels = self.bsp.lightmap_channels();
+
+        let light = if texinfo.is_special() || maps.is_empty() || !extents.is_valid() {
+            None
+        } else {
+            let mut light = vec![[0.; 3]; extents.samples()];
+
+            for (_, map) in maps {
+                for (sample, value) in light.iter_mut().zip(map.chunks(channels)) {
+                    for c in 0..3 {
+                        sample[c] += value[c.min(channels - 1)] as f32;
+                    }
+                }
+            }
+
+            Some(light)
+        };
+
+        Surface {
+            vertices: face.vertices().into_iter().map(|v| v + offset).collect(),
+            normal: plane.normal,
+            distance: plane.distance + plane.normal.dot(&offset),
+            // Moving the face moves where its texture lands, so shift the offsets back
+            s: (s_axis, s_offset - s_axis.dot(&offset)),
+            t: (t_axis, t_offset - t_axis.dot(&offset)),
+            masked: texture.map(|t| t.is_masked()).unwrap_or(false),
+            texture,
+            extents,
+            light,
+        }
+    }
+
+    /// The faces to draw: the world's, then those of every brush entity that isn't a trigger,
+    /// moved to the entity's origin.
+    pub(crate) fn surfaces(&self) -> Vec<Surface<'_>> {
+        let mut models = vec![(0, Vec3 { x: 0., y: 0., z: 0. })];
+
+        for entity in self.bsp.entities() {
+            if entity.class_name().is_some_and(|c| c.starts_with("trigger_")) {
+                continue;
+            }
+
+            if let Some(index) = entity.model_index().filter(|&i| i > 0) {
+                models.push((index, entity.origin().unwrap_or(Vec3 { x: 0., y: 0., z: 0. })));
+            }
+        }
+
+        models
+            .into_iter()
+            .filter_map(|(index, offset)| Some((self.bsp.model(index)?, offset)))
+            .flat_map(|(model, offset)| {
+                model
+                    .faces()
+                    .map(|face| self.surface(&face, offset))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The lit colour of a surface at texture coordinates `s`, `t`, or `None` if it's a
+    /// transparent texel.
+    pub(crate) fn shade(&self, surface: &Surface, s: f32, t: f32) -> Option<[u8; 3]> {
+        let light = surface
+            .light
+            .as_ref()
+            .map(|light| sample_light(light, &surface.extents, s, t))
+            .unwrap_or([NORMAL_LIGHT; 3]);
+
+        let texture = match surface.texture {
+            Some(texture) => texture,
+            None => {
+                return Some([
+                    scale(self.missing[0], light[0]),
+                    scale(self.missing[1], light[1]),
+                    scale(self.missing[2], light[2]),
+                ])
+            }
+        };
+
+        let x = (s.floor() as i64).rem_euclid(texture.width.max(1) as i64) as usize;
+        let y = (t.floor() as i64).rem_euclid(texture.height.max(1) as i64) as usize;
+        let index = *texture.mips[0].get(y * texture.width as usize + x)?;
+
+        if surface.masked && index == 255 {
+            return None;
+        }
+
+        Some(match texture.palette {
+            Some(own) => {
+                let i = index as usize * 3;
+                let rgb = own.get(i..i + 3)?;
+
+                [
+                    scale(rgb[0], light[0]),
+                    scale(rgb[1], light[1]),
+                    scale(rgb[2], light[2]),
+                ]
+            }
+            None => {
+                // Quake textures go through the colormap like the software renderer, which
+                // also keeps fullbright colours unlit
+                let block_light = (light[0] * palette::NORMAL_STYLE_SCALE as f32) as u32;
+                let row = palette::light_row(block_light);
+
+                self.palette.rgb(self.colormap.lookup(index, row))
+            }
+        })
+    }
+
+    pub fn render(&self, camera: &Camera) -> Image {
+        let mut image = Image::new(camera.width, camera.height);
+        let mut depth = vec![0f32; camera.width as usize * camera.height as usize];
+
+        let (forward, right, up) = Transform::angle_vectors(&camera.angles);
+        let half_width = camera.width as f32 / 2.;
+        let half_height = camera.height as f32 / 2.;
+        let focal = half_width / (camera.fov.to_radians() / 2.).tan();
+
+        for surface in self.surfaces() {
+            // Skip faces pointing away from the camera
+            if surface.normal.dot(&camera.origin) - surface.distance <= 0. {
+                continue;
+            }
+
+            let view = surface
+                .vertices
+                .iter()
+                .map(|&v| {
+                    let d = v - camera.origin;
+
+                    (
+                        Vec3 {
+                            x: d.dot(&right),
+                            y: d.dot(&up),
+                            z: d.dot(&forward),
+                        },
+                        v.dot(&surface.s.0) + surface.s.1,
+                        v.dot(&surface.t.0) + surface.t.1,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let clipped = clip_near(&view);
+
+            if clipped.len() < 3 {
+                continue;
+            }
+
+            // Screen position, then 1/z, s/z and t/z which interpolate linearly on screen
+            let projected = clipped
+                .iter()
+                .map(|&(v, s, t)| {
+                    let w = 1. / v.z;
+
+                    (
+                        half_width + v.x * w * focal,
+                        half_height - v.y * w * focal,
+                        [w, s * w, t * w],
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            for i in 2..projected.len() {
+                let triangle = [projected[0], projected[i - 1], projected[i]];
+
+                rasterize(&triangle, camera.width, camera.height, |x, y, attrs| {
+                    let index = y as usize * camera.width as usize + x as usize;
+
+                    if attrs[0] <= depth[index] {
+                        return;
+                    }
+
+                    let z = 1. / attrs[0];
+
+                    if let Some(rgb) = self.shade(&surface, attrs[1] * z, attrs[2] * z) {
+                        depth[index] = attrs[0];
+                        image.set_pixel(x, y, [rgb[0], rgb[1], rgb[2], 255]);
+                    }
+                });
+            }
+        }
+
+        image
+    }
+}
+
+fn scale(value: u8, light: f32) -> u8 {
+    (value as f32 * light / NORMAL_LIGHT).clamp(0., 255.) as u8
+}
+
+/// Bilinearly filtered light at texture coordinates `s`, `t`.
+fn sample_light(light: &[[f32; 3]], extents: &LightmapExtents, s: f32, t: f32) -> [f32; 3] {
+    let width = extents.size[0] as usize;
+    let height = extents.size[1] as usize;
+    let ls = ((s - extents.texture_min[0] as f32) / 16.).clamp(0., (width - 1) as f32);
+    let lt = ((t - extents.texture_min[1] as f32) / 16.).clamp(0., (height - 1) as f32);
+    let (x, y) = (ls as usize, lt as usize);
+    let (x1, y1) = ((x + 1).min(width - 1), (y + 1).min(height - 1));
+    let (fx, fy) = (ls - x as f32, lt - y as f32);
+
+    let mut out = [0.; 3];
+
+    for (c, out) in out.iter_mut().enumerate() {
+        let top = light[y * width + x][c] * (1. - fx) + light[y * width + x1][c] * fx;
+        let bottom = light[y1 * width + x][c] * (1. - fx) + light[y1 * width + x1][c] * fx;
+
+        *out = top * (1. - fy) + bottom * fy;
+    }
+
+    out
+}
+
+/// Clip a view-space polygon (with its texture coordinates) to the near plane.
+fn clip_near(polygon: &[(Vec3<f32>, f32, f32)]) -> Vec<(Vec3<f32>, f32, f32)> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+
+        if a.0.z >= NEAR {
+            out.push(a);
+        }
+
+        if (a.0.z >= NEAR) != (b.0.z >= NEAR) {
+            let f = (NEAR - a.0.z) / (b.0.z - a.0.z);
+
+            out.push((
+                a.0 + (b.0 - a.0) * f,
+                a.1 + (b.1 - a.1) * f,
+                a.2 + (b.2 - a.2) * f,
+            ));
+        }
+    }
+
+    out
+}
+
+/// Call `plot` for every pixel whose centre is inside the triangle, with the vertex attributes
+/// interpolated linearly in screen space.
+pub(crate) fn rasterize<F>(triangle: &[(f32, f32, [f32; 3]); 3], width: u32, height: u32, mut plot: F)
+where
+    F: FnMut(u32, u32, [f32; 3]),
+{
+    let [(x0, y0, a0), (x1, y1, a1), (x2, y2, a2)] = *triangle;
+    let area = (x1 - x0) * (y2 - y0) - (x2 - x0) * (y1 - y0);
+
+    if area.abs() < 1e-6 {
+        return;
+    }
+
+    let min_x = x0.min(x1).min(x2).floor().max(0.) as u32;
+    let max_x = (x0.max(x1).max(x2).ceil().max(0.) as u32).min(width);
+    let min_y = y0.min(y1).min(y2).floor().max(0.) as u32;
+    let max_y = (y0.max(y1).max(y2).ceil().max(0.) as u32).min(height);
+
+    for y in min_y..max_y {
+        let py = y as f32 + 0.5;
+
+        for x in min_x..max_x {
+            let px = x as f32 + 0.5;
+            let w0 = ((x1 - px) * (y2 - py) - (x2 - px) * (y1 - py)) / area;
+            let w1 = ((x2 - px) * (y0 - py) - (x0 - px) * (y2 - py)) / area;
+            let w2 = 1. - w0 - w1;
+
+            if w0 < 0. || w1 < 0. || w2 < 0. {
+                continue;
+            }
+
+            let mut attrs = [0.; 3];
+
+            for (i, attr) in attrs.iter_mut().enumerate() {
+                *attr = a0[i] * w0 + a1[i] * w1 + a2[i] * w2;
+            }
+
+            plot(x, y, attrs);
+        }
+    }
+}
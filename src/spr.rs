@@ -0,0 +1,372 @@
+//! Sprites (`.spr`), the camera-facing images used for torches, explosions and glows
+//!
+//! Quake sprites (version 1) use the game palette with index 255 transparent. GoldSrc sprites
+//! (version 2) carry their own palette and a render mode saying how to blend them.
+
+use std::borrow::Cow;
+use std::mem;
+
+use ioendian::{IntoNativeEndian, Little};
+
+use bsp::Error;
+use group::group_item;
+use sys::value_at;
+use sys::spr as raw;
+use wad;
+
+const MAGIC: &[u8; 4] = b"IDSP";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Quake,
+    GoldSrc,
+}
+
+/// How the sprite is turned to face the viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// Faces the view plane, but stays upright
+    ParallelUpright,
+    /// Faces the viewer's position, but stays upright
+    FacingUpright,
+    /// Faces the view plane
+    Parallel,
+    /// Uses the entity's angles
+    Oriented,
+    /// Faces the view plane, rotated by the entity's roll
+    ParallelOriented,
+    Other(i32),
+}
+
+impl From<i32> for Orientation {
+    fn from(other: i32) -> Self {
+        match other {
+            0 => Orientation::ParallelUpright,
+            1 => Orientation::FacingUpright,
+            2 => Orientation::Parallel,
+            3 => Orientation::Oriented,
+            4 => Orientation::ParallelOriented,
+            other => Orientation::Other(other),
+        }
+    }
+}
+
+/// How a GoldSrc sprite's colours are turned into RGBA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Normal,
+    Additive,
+    /// Every pixel is the last palette colour, with the index as its alpha
+    IndexAlpha,
+    /// Index 255 is transparent
+    AlphaTest,
+    Other(i32),
+}
+
+impl From<i32> for RenderMode {
+    fn from(other: i32) -> Self {
+        match other {
+            0 => RenderMode::Normal,
+            1 => RenderMode::Additive,
+            2 => RenderMode::IndexAlpha,
+            3 => RenderMode::AlphaTest,
+            other => RenderMode::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Section {
+    Single(usize),
+    Group { times: Vec<f32>, frames: Vec<usize> },
+}
+
+pub struct Sprite<'a> {
+    data: Cow<'a, [u8]>,
+    version: Version,
+    render_mode: Option<RenderMode>,
+    header: usize,
+    palette: Option<(usize, usize)>,
+    frames: Vec<Section>,
+}
+
+impl<'a> ::std::fmt::Debug for Sprite<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "Sprite {{ {:?}, {} frames }}", self.version, self.frames.len())
+    }
+}
+
+fn truncated(lump: &'static str, expected: usize, actual: usize) -> Error {
+    Error::Truncated {
+        lump,
+        expected,
+        actual,
+    }
+}
+
+fn read_i32(data: &[u8], at: usize, lump: &'static str) -> Result<i32, Error> {
+    let value: &Little<i32> =
+        unsafe { value_at(data, at) }.ok_or_else(|| truncated(lump, at + 4, data.len()))?;
+
+    Ok(value.native())
+}
+
+/// Check the frame at `at` fits in the file, returning the offset just past it.
+fn skip_frame(data: &[u8], at: usize) -> Result<usize, Error> {
+    let frame: &raw::Frame = unsafe { value_at(data, at) }
+        .ok_or_else(|| truncated("frames", at + mem::size_of::<raw::Frame>(), data.len()))?;
+    let width = frame.width.native().max(0) as usize;
+    let height = frame.height.native().max(0) as usize;
+    let end = at + mem::size_of::<raw::Frame>() + width * height;
+
+    if end > data.len() {
+        Err(truncated("frames", end, data.len()))
+    } else {
+        Ok(end)
+    }
+}
+
+impl<'a> Sprite<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        let data = buffer.into();
+
+        let ident: &raw::Ident = unsafe { value_at(&data, 0) }
+            .ok_or_else(|| truncated("header", mem::size_of::<raw::Ident>(), data.len()))?;
+
+        if &ident.magic != MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: MAGIC,
+                found: ident.magic,
+            });
+        }
+
+        let mut pos = mem::size_of::<raw::Ident>();
+
+        let (version, render_mode) = match ident.version.native() {
+            1 => (Version::Quake, None),
+            2 => {
+                pos += 4;
+                (Version::GoldSrc, Some(read_i32(&data, pos - 4, "header")?.into()))
+            }
+            other => return Err(Error::VersionMismatch(other as _)),
+        };
+
+        let header = pos;
+        let num_frames = {
+            let header: &raw::Header = unsafe { value_at(&data, header) }.ok_or_else(|| {
+                truncated("header", header + mem::size_of::<raw::Header>(), data.len())
+            })?;
+
+            header.num_frames.native().max(0)
+        };
+
+        pos += mem::size_of::<raw::Header>();
+
+        let palette = if version == Version::GoldSrc {
+            let count: &Little<i16> = unsafe { value_at(&data, pos) }
+                .ok_or_else(|| truncated("palette", pos + 2, data.len()))?;
+            let len = count.native().max(0) as usize * 3;
+
+            pos += 2;
+
+            if pos + len > data.len() {
+                return Err(truncated("palette", pos + len, data.len()));
+            }
+
+            pos += len;
+
+            Some((pos - len, len))
+        } else {
+            None
+        };
+
+        let mut frames = Vec::new();
+
+        for _ in 0..num_frames {
+            let kind = read_i32(&data, pos, "frames")?;
+
+            pos += 4;
+
+            if kind == 0 {
+                frames.push(Section::Single(pos));
+                pos = skip_frame(&data, pos)?;
+            } else {
+                let count = read_i32(&data, pos, "frames")?;
+
+                if count < 1 {
+                    return Err(Error::BadCount {
+                        lump: "frames",
+                        record: frames.len(),
+                        field: "count",
+                        count: count as _,
+                        min: 1,
+                    });
+                }
+
+                let count = count as usize;
+
+                pos += 4;
+
+                let times = (0..count)
+                    .map(|i| {
+                        let time: &Little<f32> = unsafe { value_at(&data, pos + i * 4) }
+                            .ok_or_else(|| truncated("frames", pos + i * 4 + 4, data.len()))?;
+
+                        Ok(time.native())
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+
+                pos += count * 4;
+
+                let mut group = Vec::with_capacity(count);
+
+                for _ in 0..count {
+                    group.push(pos);
+                    pos = skip_frame(&data, pos)?;
+                }
+
+                frames.push(Section::Group {
+                    times,
+                    frames: group,
+                });
+            }
+        }
+
+        Ok(Sprite {
+            data,
+            version,
+            render_mode,
+            header,
+            palette,
+            frames,
+        })
+    }
+
+    fn header(&self) -> &raw::Header {
+        unsafe { value_at(&self.data, self.header).unwrap() }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        let ident: &raw::Ident = unsafe { value_at(&self.data, 0).unwrap() };
+
+        ident.orientation.native().into()
+    }
+
+    /// Only GoldSrc sprites have a render mode.
+    pub fn render_mode(&self) -> Option<RenderMode> {
+        self.render_mode
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        self.header().bounding_radius.native()
+    }
+
+    pub fn max_width(&self) -> u32 {
+        self.header().max_width.native().max(0) as _
+    }
+
+    pub fn max_height(&self) -> u32 {
+        self.header().max_height.native().max(0) as _
+    }
+
+    pub fn sync_type(&self) -> i32 {
+        self.header().sync_type.native()
+    }
+
+    /// The sprite's own palette, only present in GoldSrc sprites.
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.palette
+            .map(|(start, len)| &self.data[start..start + len])
+    }
+
+    fn frame(&self, at: usize) -> SpriteFrame<'_> {
+        let frame: &raw::Frame = unsafe { value_at(&self.data, at).unwrap() };
+        let width = frame.width.native().max(0) as u32;
+        let height = frame.height.native().max(0) as u32;
+        let start = at + mem::size_of::<raw::Frame>();
+
+        SpriteFrame {
+            origin: (frame.origin[0].native(), frame.origin[1].native()),
+            width,
+            height,
+            pixels: &self.data[start..start + width as usize * height as usize],
+        }
+    }
+
+    pub fn frames(&self) -> Vec<Frame<'_>> {
+        self.frames
+            .iter()
+            .map(|section| match *section {
+                Section::Single(at) => Frame::Single(self.frame(at)),
+                Section::Group {
+                    ref times,
+                    ref frames,
+                } => {
+                    Frame::Group {
+                        times: times.clone(),
+                        frames: frames.iter().map(|&at| self.frame(at)).collect(),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Decode a frame to RGBA, using the sprite's own palette if it has one and `palette`
+    /// otherwise.
+    pub fn rgba(&self, frame: &SpriteFrame, palette: Option<&[u8]>) -> Option<Vec<u8>> {
+        let palette = self.palette().or(palette)?;
+
+        Some(match self.render_mode {
+            Some(RenderMode::IndexAlpha) => {
+                let color = palette.get(255 * 3..256 * 3)?;
+
+                frame
+                    .pixels
+                    .iter()
+                    .flat_map(|&alpha| vec![color[0], color[1], color[2], alpha])
+                    .collect()
+            }
+            Some(RenderMode::Normal) | Some(RenderMode::Additive) => {
+                wad::indexed_to_rgba(frame.pixels, palette, None)
+            }
+            _ => wad::indexed_to_rgba(frame.pixels, palette, Some(255)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpriteFrame<'a> {
+    /// The position of the image's top-left corner relative to the entity, with Y pointing up
+    pub origin: (i32, i32),
+    pub width: u32,
+    pub height: u32,
+    pub pixels: &'a [u8],
+}
+
+#[derive(Debug, Clone)]
+pub enum Frame<'a> {
+    Single(SpriteFrame<'a>),
+    /// An animation that runs by itself. `times` are when each frame ends, in seconds since the
+    /// start of the loop.
+    Group {
+        times: Vec<f32>,
+        frames: Vec<SpriteFrame<'a>>,
+    },
+}
+
+impl<'a> Frame<'a> {
+    /// The frame to show `time` seconds in, looping groups like models' frame groups.
+    pub fn at_time(&self, time: f32) -> &SpriteFrame<'a> {
+        match *self {
+            Frame::Single(ref frame) => frame,
+            Frame::Group {
+                ref times,
+                ref frames,
+            } => group_item(times, frames, time),
+        }
+    }
+}
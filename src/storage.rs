@@ -0,0 +1,39 @@
+//! Backing storage for the archive formats, which can either be handed a buffer or map a file
+//! themselves.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap::Mmap;
+
+pub enum Storage<'a> {
+    Buffer(Cow<'a, [u8]>),
+    Mapped(Mmap),
+}
+
+impl Storage<'static> {
+    /// Memory-map `path`. Empty files can't be mapped, so they get an empty buffer instead.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        if file.metadata()?.len() == 0 {
+            return Ok(Storage::Buffer(Cow::Borrowed(&[])));
+        }
+
+        Ok(Storage::Mapped(unsafe { Mmap::map(&file)? }))
+    }
+}
+
+impl<'a> Deref for Storage<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Storage::Buffer(ref buffer) => buffer,
+            Storage::Mapped(ref map) => map,
+        }
+    }
+}
@@ -0,0 +1,1055 @@
+//! GoldSrc studio models (`models/*.mdl`), the skeletal models Half-Life uses for NPCs, weapons
+//! and props
+//!
+//! Every offset in the file is checked when the model is loaded, so the accessors below can't
+//! fail. Sequences stored in an external group file (`scientist01.mdl` and so on) need that file
+//! loaded with `SequenceGroupFile::new` and passed to `Studio::pose`. Models whose textures live
+//! in a separate `*t.mdl` have no textures of their own; load that file as another `Studio` and use
+//! its `textures`.
+
+use std::borrow::Cow;
+use std::f32::consts::PI;
+use std::io;
+use std::mem;
+
+use ioendian::{IntoNativeEndian, Little};
+
+use bsp::{Error, Vec3};
+use sys::{slice_at, value_at};
+use sys::bsp::Scalar3;
+use sys::studio as raw;
+use wad;
+
+const MAGIC: &[u8; 4] = b"IDST";
+const SEQ_GROUP_MAGIC: &[u8; 4] = b"IDSQ";
+const VERSION: i32 = 10;
+
+/// Texture flag for `{`-style masked textures, where palette index 255 is transparent.
+pub const TEXTURE_MASKED: i32 = 0x40;
+
+fn c_str(bytes: &[u8]) -> Cow<'_, str> {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len])
+}
+
+/// Check that `count` records of type `T` at `offset` fit in the file.
+fn check_array<T>(data: &[u8], lump: &'static str, offset: i32, count: i32) -> Result<(), Error> {
+    let fits = offset >= 0 && count >= 0 &&
+        unsafe { slice_at::<T>(data, offset as usize, count as usize) }.is_some();
+
+    if fits {
+        Ok(())
+    } else {
+        Err(Error::LumpOutOfBounds {
+            lump,
+            offset,
+            len: count.saturating_mul(mem::size_of::<T>() as i32),
+            file_len: data.len(),
+        })
+    }
+}
+
+fn check_index(
+    lump: &'static str,
+    record: usize,
+    field: &'static str,
+    index: i32,
+    len: usize,
+) -> Result<(), Error> {
+    if index < 0 || index as usize >= len {
+        Err(Error::BadIndex {
+            lump,
+            record,
+            field,
+            index: index as _,
+            len,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+pub struct Studio<'a> {
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> ::std::fmt::Debug for Studio<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(
+            f,
+            "Studio {{ {:?}, {} bones, {} sequences }}",
+            self.name(),
+            self.header().bones.count.native(),
+            self.header().sequences.count.native()
+        )
+    }
+}
+
+impl<'a> Studio<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        let out = Studio { data: buffer.into() };
+
+        let header: &raw::Header = unsafe { value_at(&out.data, 0) }.ok_or_else(|| {
+            Error::Truncated {
+                lump: "header",
+                expected: mem::size_of::<raw::Header>(),
+                actual: out.data.len(),
+            }
+        })?;
+
+        if &header.magic != MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: MAGIC,
+                found: header.magic,
+            });
+        }
+
+        if header.version.native() != VERSION {
+            return Err(Error::VersionMismatch(header.version.native() as _));
+        }
+
+        out.validate()?;
+
+        Ok(out)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let data = &self.data[..];
+        let header = self.header();
+
+        macro_rules! check {
+            ($lump:expr, $ty:ty, $array:expr) => {
+                check_array::<$ty>(data, $lump, $array.offset.native(), $array.count.native())?
+            };
+        }
+
+        check!("bones", raw::Bone, header.bones);
+        check!("bone_controllers", raw::BoneController, header.bone_controllers);
+        check!("hitboxes", raw::HitBox, header.hitboxes);
+        check!("sequences", raw::SeqDesc, header.sequences);
+        check!("seq_groups", raw::SeqGroup, header.seq_groups);
+        check!("textures", raw::Texture, header.textures);
+        check!("body_parts", raw::BodyPart, header.body_parts);
+        check_array::<Little<i16>>(
+            data,
+            "skins",
+            header.skins.native(),
+            header
+                .num_skin_refs
+                .native()
+                .saturating_mul(header.num_skin_families.native()),
+        )?;
+
+        let num_bones = self.raw_bones().len();
+
+        for (i, bone) in self.raw_bones().iter().enumerate() {
+            // Parents always come first, which is what lets `pose` work in a single pass
+            let parent = bone.parent.native();
+
+            if parent != -1 {
+                check_index("bones", i, "parent", parent, i)?;
+            }
+        }
+
+        for (i, controller) in self.raw_bone_controllers().iter().enumerate() {
+            check_index("bone_controllers", i, "bone", controller.bone.native(), num_bones)?;
+        }
+
+        for (i, hitbox) in self.raw_hitboxes().iter().enumerate() {
+            check_index("hitboxes", i, "bone", hitbox.bone.native(), num_bones)?;
+        }
+
+        for (i, seq) in self.raw_sequences().iter().enumerate() {
+            check!("events", raw::Event, seq.events);
+            check_index(
+                "sequences",
+                i,
+                "seq_group",
+                seq.seq_group.native(),
+                self.raw_seq_groups().len(),
+            )?;
+
+            for &(field, count) in &[
+                ("num_frames", seq.num_frames.native()),
+                ("num_blends", seq.num_blends.native()),
+            ] {
+                if count < 1 {
+                    return Err(Error::BadCount {
+                        lump: "sequences",
+                        record: i,
+                        field,
+                        count: count as _,
+                        min: 1,
+                    });
+                }
+            }
+        }
+
+        for texture in self.raw_textures() {
+            if texture.index.native() != 0 {
+                check_array::<u8>(
+                    data,
+                    "texture_data",
+                    texture.index.native(),
+                    texture
+                        .width
+                        .native()
+                        .saturating_mul(texture.height.native())
+                        .saturating_add(256 * 3),
+                )?;
+            }
+        }
+
+        for part in self.raw_body_parts() {
+            check_array::<raw::Model>(
+                data,
+                "models",
+                part.model_index.native(),
+                part.num_models.native(),
+            )?;
+
+            for model in self.raw_models(part) {
+                let num_verts = model.num_verts.native();
+                let num_norms = model.num_norms.native();
+
+                check!("meshes", raw::Mesh, model.meshes);
+                check_array::<Scalar3>(data, "vertices", model.vert_index.native(), num_verts)?;
+                check_array::<u8>(data, "vertices", model.vert_info_index.native(), num_verts)?;
+                check_array::<Scalar3>(data, "normals", model.norm_index.native(), num_norms)?;
+                check_array::<u8>(data, "normals", model.norm_info_index.native(), num_norms)?;
+
+                let bones = self.bytes(model.vert_info_index.native(), num_verts).iter().chain(
+                    self.bytes(model.norm_info_index.native(), num_norms),
+                );
+
+                for (i, &bone) in bones.enumerate() {
+                    check_index("vertices", i, "bone", bone as _, num_bones)?;
+                }
+
+                for mesh in self.raw_meshes(model) {
+                    self.tri_commands(mesh, num_verts as usize, num_norms as usize)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn header(&self) -> &raw::Header {
+        unsafe { value_at(&self.data, 0).unwrap() }
+    }
+
+    fn array<T>(&self, array: &raw::Array) -> &[T] {
+        unsafe {
+            slice_at(
+                &self.data,
+                array.offset.native() as usize,
+                array.count.native() as usize,
+            ).unwrap()
+        }
+    }
+
+    fn bytes(&self, offset: i32, count: i32) -> &[u8] {
+        &self.data[offset as usize..offset as usize + count as usize]
+    }
+
+    fn vectors(&self, offset: i32, count: i32) -> Vec<Vec3<f32>> {
+        let raw: &[Scalar3] =
+            unsafe { slice_at(&self.data, offset as usize, count as usize).unwrap() };
+
+        raw.iter().map(|v| v.native()).collect()
+    }
+
+    fn raw_bones(&self) -> &[raw::Bone] {
+        self.array(&self.header().bones)
+    }
+
+    fn raw_bone_controllers(&self) -> &[raw::BoneController] {
+        self.array(&self.header().bone_controllers)
+    }
+
+    fn raw_hitboxes(&self) -> &[raw::HitBox] {
+        self.array(&self.header().hitboxes)
+    }
+
+    fn raw_sequences(&self) -> &[raw::SeqDesc] {
+        self.array(&self.header().sequences)
+    }
+
+    fn raw_seq_groups(&self) -> &[raw::SeqGroup] {
+        self.array(&self.header().seq_groups)
+    }
+
+    fn raw_textures(&self) -> &[raw::Texture] {
+        self.array(&self.header().textures)
+    }
+
+    fn raw_body_parts(&self) -> &[raw::BodyPart] {
+        self.array(&self.header().body_parts)
+    }
+
+    fn raw_models(&self, part: &raw::BodyPart) -> &[raw::Model] {
+        unsafe {
+            slice_at(
+                &self.data,
+                part.model_index.native() as usize,
+                part.num_models.native() as usize,
+            ).unwrap()
+        }
+    }
+
+    fn raw_meshes(&self, model: &raw::Model) -> &[raw::Mesh] {
+        self.array(&model.meshes)
+    }
+
+    /// Walk a mesh's triangle commands, checking them against the model's vertex and normal
+    /// counts.
+    fn tri_commands(
+        &self,
+        mesh: &raw::Mesh,
+        num_verts: usize,
+        num_norms: usize,
+    ) -> Result<Vec<TriCommand>, Error> {
+        let mut out = Vec::new();
+        let mut pos = mesh.tri_index.native().max(0) as usize;
+
+        loop {
+            let count: &Little<i16> = unsafe { value_at(&self.data, pos) }.ok_or_else(|| {
+                Error::Truncated {
+                    lump: "tri_commands",
+                    expected: pos + 2,
+                    actual: self.data.len(),
+                }
+            })?;
+            let count = count.native();
+
+            pos += 2;
+
+            if count == 0 {
+                return Ok(out);
+            }
+
+            let len = (count as i32).unsigned_abs() as usize;
+            let vertices: &[raw::TriCommandVertex] = unsafe { slice_at(&self.data, pos, len) }
+                .ok_or_else(|| {
+                    Error::Truncated {
+                        lump: "tri_commands",
+                        expected: pos + len * mem::size_of::<raw::TriCommandVertex>(),
+                        actual: self.data.len(),
+                    }
+                })?;
+
+            pos += len * mem::size_of::<raw::TriCommandVertex>();
+
+            let vertices = vertices
+                .iter()
+                .map(|v| {
+                    check_index("tri_commands", pos, "vertex", v.vertex.native() as _, num_verts)?;
+                    check_index("tri_commands", pos, "normal", v.normal.native() as _, num_norms)?;
+
+                    Ok(MeshVertex {
+                        vertex: v.vertex.native() as usize,
+                        normal: v.normal.native() as usize,
+                        s: v.s.native(),
+                        t: v.t.native(),
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            out.push(TriCommand {
+                fan: count < 0,
+                vertices,
+            });
+        }
+    }
+
+    pub fn name(&self) -> Cow<'_, str> {
+        c_str(&self.header().name)
+    }
+
+    pub fn eye_position(&self) -> Vec3<f32> {
+        self.header().eye_position.native()
+    }
+
+    /// The bounding box used for movement.
+    pub fn bounds(&self) -> (Vec3<f32>, Vec3<f32>) {
+        (self.header().min.native(), self.header().max.native())
+    }
+
+    /// The bounding box used for visibility culling.
+    pub fn clipping_bounds(&self) -> (Vec3<f32>, Vec3<f32>) {
+        (self.header().bb_min.native(), self.header().bb_max.native())
+    }
+
+    pub fn flags(&self) -> i32 {
+        self.header().flags.native()
+    }
+
+    pub fn bones(&self) -> Vec<Bone<'_>> {
+        self.raw_bones()
+            .iter()
+            .map(|bone| {
+                let mut controllers = [None; 6];
+                let mut value = [0.; 6];
+                let mut scale = [0.; 6];
+
+                for i in 0..6 {
+                    let controller = bone.controllers[i].native();
+
+                    controllers[i] = if controller < 0 { None } else { Some(controller as usize) };
+                    value[i] = bone.value[i].native();
+                    scale[i] = bone.scale[i].native();
+                }
+
+                Bone {
+                    name: c_str(&bone.name),
+                    parent: if bone.parent.native() < 0 {
+                        None
+                    } else {
+                        Some(bone.parent.native() as usize)
+                    },
+                    flags: bone.flags.native(),
+                    controllers,
+                    value,
+                    scale,
+                }
+            })
+            .collect()
+    }
+
+    pub fn bone_controllers(&self) -> Vec<BoneController> {
+        self.raw_bone_controllers()
+            .iter()
+            .map(|c| {
+                BoneController {
+                    bone: c.bone.native() as usize,
+                    kind: c.kind.native(),
+                    start: c.start.native(),
+                    end: c.end.native(),
+                    rest: c.rest.native(),
+                    index: c.index.native(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn hitboxes(&self) -> Vec<HitBox> {
+        self.raw_hitboxes()
+            .iter()
+            .map(|h| {
+                HitBox {
+                    bone: h.bone.native() as usize,
+                    group: h.group.native(),
+                    min: h.min.native(),
+                    max: h.max.native(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn sequences(&self) -> Vec<Sequence<'_>> {
+        self.raw_sequences()
+            .iter()
+            .map(|seq| {
+                let events: &[raw::Event] = self.array(&seq.events);
+
+                Sequence {
+                    label: c_str(&seq.label),
+                    fps: seq.fps.native(),
+                    flags: seq.flags.native(),
+                    activity: seq.activity.native(),
+                    activity_weight: seq.activity_weight.native(),
+                    num_frames: seq.num_frames.native() as usize,
+                    motion_type: seq.motion_type.native(),
+                    motion_bone: seq.motion_bone.native(),
+                    linear_movement: seq.linear_movement.native(),
+                    min: seq.min.native(),
+                    max: seq.max.native(),
+                    num_blends: seq.num_blends.native() as usize,
+                    seq_group: seq.seq_group.native() as usize,
+                    events: events
+                        .iter()
+                        .map(|e| {
+                            Event {
+                                frame: e.frame.native(),
+                                event: e.event.native(),
+                                kind: e.kind.native(),
+                                options: c_str(&e.options),
+                            }
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Group 0 is this file, the rest are the external files that `SequenceGroup::name` points
+    /// to, relative to the game directory.
+    pub fn sequence_groups(&self) -> Vec<SequenceGroup<'_>> {
+        self.raw_seq_groups()
+            .iter()
+            .map(|group| {
+                SequenceGroup {
+                    label: c_str(&group.label),
+                    name: c_str(&group.name),
+                }
+            })
+            .collect()
+    }
+
+    pub fn textures(&self) -> Vec<Texture<'_>> {
+        self.raw_textures()
+            .iter()
+            .map(|texture| {
+                let width = texture.width.native() as u32;
+                let height = texture.height.native() as u32;
+                let start = texture.index.native() as usize;
+                let len = width as usize * height as usize;
+                let (pixels, palette) = if start == 0 {
+                    (&[][..], &[][..])
+                } else {
+                    (
+                        &self.data[start..start + len],
+                        &self.data[start + len..start + len + 256 * 3],
+                    )
+                };
+
+                Texture {
+                    name: c_str(&texture.name),
+                    flags: texture.flags.native(),
+                    width,
+                    height,
+                    pixels,
+                    palette,
+                }
+            })
+            .collect()
+    }
+
+    /// For each skin family, the texture index to use for each skin reference. Meshes refer to
+    /// textures through these, so that a model can switch between sets of textures.
+    pub fn skin_families(&self) -> Vec<Vec<usize>> {
+        let header = self.header();
+        let refs = header.num_skin_refs.native() as usize;
+        let families = header.num_skin_families.native() as usize;
+        let table: &[Little<i16>] =
+            unsafe { slice_at(&self.data, header.skins.native() as usize, refs * families) }
+                .unwrap();
+
+        table
+            .chunks(refs.max(1))
+            .take(families)
+            .map(|family| family.iter().map(|i| i.native().max(0) as usize).collect())
+            .collect()
+    }
+
+    pub fn body_parts(&self) -> Vec<BodyPart<'_>> {
+        self.raw_body_parts()
+            .iter()
+            .map(|part| {
+                BodyPart {
+                    name: c_str(&part.name),
+                    base: part.base.native().max(1) as usize,
+                    models: self.raw_models(part)
+                        .iter()
+                        .map(|model| self.model(model))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn model<'b>(&'b self, model: &'b raw::Model) -> Model<'b> {
+        let num_verts = model.num_verts.native();
+        let num_norms = model.num_norms.native();
+
+        Model {
+            name: c_str(&model.name),
+            bounding_radius: model.bounding_radius.native(),
+            vertices: self.vectors(model.vert_index.native(), num_verts),
+            vertex_bones: self.bytes(model.vert_info_index.native(), num_verts),
+            normals: self.vectors(model.norm_index.native(), num_norms),
+            normal_bones: self.bytes(model.norm_info_index.native(), num_norms),
+            meshes: self.raw_meshes(model)
+                .iter()
+                .map(|mesh| {
+                    Mesh {
+                        skin_ref: mesh.skin_ref.native().max(0) as usize,
+                        commands: self.tri_commands(mesh, num_verts as usize, num_norms as usize)
+                            .expect("Checked when loading"),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The model-space transform of every bone at `frame` (which may be fractional) of
+    /// `sequence`, using the first blend and with bone controllers at rest. If the sequence is
+    /// in an external group, `group` must be that group's file.
+    pub fn pose(
+        &self,
+        sequence: usize,
+        frame: f32,
+        group: Option<&SequenceGroupFile>,
+    ) -> Result<Vec<BoneTransform>, Error> {
+        let seq = self.raw_sequences().get(sequence).ok_or_else(|| {
+            Error::BadIndex {
+                lump: "sequences",
+                record: 0,
+                field: "sequence",
+                index: sequence as _,
+                len: self.raw_sequences().len(),
+            }
+        })?;
+
+        let data: &[u8] = match seq.seq_group.native() {
+            0 => &self.data,
+            i => {
+                match group {
+                    Some(group) => &group.data,
+                    None => {
+                        let name = c_str(&self.raw_seq_groups()[i as usize].name).into_owned();
+
+                        return Err(io::Error::new(io::ErrorKind::NotFound, name).into());
+                    }
+                }
+            }
+        };
+
+        let last = (seq.num_frames.native() - 1) as f32;
+        let frame = frame.max(0.).min(last);
+        let index = frame.floor() as usize;
+        let fraction = frame - index as f32;
+        let bones = self.raw_bones();
+
+        let mut out: Vec<BoneTransform> = Vec::with_capacity(bones.len());
+
+        for (i, bone) in bones.iter().enumerate() {
+            let anim_at = seq.anim_index.native().max(0) as usize + i * mem::size_of::<raw::Anim>();
+            let anim: &raw::Anim = unsafe { value_at(data, anim_at) }.ok_or_else(|| {
+                Error::Truncated {
+                    lump: "animations",
+                    expected: anim_at + mem::size_of::<raw::Anim>(),
+                    actual: data.len(),
+                }
+            })?;
+
+            let mut channels = [(0., 0.); 6];
+
+            for (c, channel) in channels.iter_mut().enumerate() {
+                let (a, b) = match anim.offsets[c].native() {
+                    0 => (0., 0.),
+                    offset => {
+                        anim_value(data, anim_at + offset as usize, index).ok_or_else(|| {
+                            Error::Truncated {
+                                lump: "animations",
+                                expected: anim_at + offset as usize,
+                                actual: data.len(),
+                            }
+                        })?
+                    }
+                };
+                let value = bone.value[c].native();
+                let scale = bone.scale[c].native();
+
+                *channel = (value + a * scale, value + b * scale);
+            }
+
+            let position = Vec3 {
+                x: channels[0].0 * (1. - fraction) + channels[0].1 * fraction,
+                y: channels[1].0 * (1. - fraction) + channels[1].1 * fraction,
+                z: channels[2].0 * (1. - fraction) + channels[2].1 * fraction,
+            };
+            let q1 = angle_quaternion([channels[3].0, channels[4].0, channels[5].0]);
+            let q2 = angle_quaternion([channels[3].1, channels[4].1, channels[5].1]);
+
+            let local = BoneTransform {
+                rotation: quaternion_matrix(quaternion_slerp(q1, q2, fraction)),
+                origin: position,
+            };
+
+            out.push(match bone.parent.native() {
+                -1 => local,
+                parent => out[parent as usize].concat(&local),
+            });
+        }
+
+        Ok(out)
+    }
+}
+
+/// Decode the RLE-compressed values of one channel at `frame` and the frame after it. Each run
+/// starts with a `valid` and a `total` byte, followed by `valid` values. Frames past `valid` but
+/// within `total` repeat the last value.
+fn anim_value(data: &[u8], mut at: usize, frame: usize) -> Option<(f32, f32)> {
+    let value = |at: usize, i: usize| -> Option<f32> {
+        let value: &Little<i16> = unsafe { value_at(data, at + i * 2) }?;
+
+        Some(value.native() as f32)
+    };
+
+    let mut k = frame;
+
+    let (valid, total) = loop {
+        let valid = *data.get(at)? as usize;
+        let total = *data.get(at + 1)? as usize;
+
+        if total == 0 {
+            return None;
+        }
+
+        if total > k {
+            break (valid, total);
+        }
+
+        k -= total;
+        at += (valid + 1) * 2;
+    };
+
+    // The next frame's value may be the first one of the next run, which isn't there after the
+    // last frame, so fall back to holding the current value.
+    Some(if valid > k {
+        let first = value(at, k + 1)?;
+        let second = if valid > k + 1 {
+            value(at, k + 2)?
+        } else if total > k + 1 {
+            first
+        } else {
+            value(at, valid + 2).unwrap_or(first)
+        };
+
+        (first, second)
+    } else {
+        let first = value(at, valid)?;
+        let second = if total > k + 1 {
+            first
+        } else {
+            value(at, valid + 2).unwrap_or(first)
+        };
+
+        (first, second)
+    })
+}
+
+/// Euler angles in radians, in the order the format stores them (around X, Y, Z).
+fn angle_quaternion(angles: [f32; 3]) -> [f32; 4] {
+    let (sy, cy) = (angles[2] * 0.5).sin_cos();
+    let (sp, cp) = (angles[1] * 0.5).sin_cos();
+    let (sr, cr) = (angles[0] * 0.5).sin_cos();
+
+    [
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+        cr * cp * cy + sr * sp * sy,
+    ]
+}
+
+fn quaternion_slerp(p: [f32; 4], mut q: [f32; 4], t: f32) -> [f32; 4] {
+    // Go the short way round
+    let a: f32 = (0..4).map(|i| (p[i] - q[i]) * (p[i] - q[i])).sum();
+    let b: f32 = (0..4).map(|i| (p[i] + q[i]) * (p[i] + q[i])).sum();
+
+    if a > b {
+        q = [-q[0], -q[1], -q[2], -q[3]];
+    }
+
+    let cosom: f32 = (0..4).map(|i| p[i] * q[i]).sum();
+    let mut out = [0.; 4];
+
+    if 1. + cosom > 1e-6 {
+        let (sclp, sclq) = if 1. - cosom > 1e-6 {
+            let omega = cosom.acos();
+            let sinom = omega.sin();
+
+            (((1. - t) * omega).sin() / sinom, (t * omega).sin() / sinom)
+        } else {
+            (1. - t, t)
+        };
+
+        for i in 0..4 {
+            out[i] = sclp * p[i] + sclq * q[i];
+        }
+    } else {
+        let perpendicular = [-p[1], p[0], -p[3], p[2]];
+        let sclp = ((1. - t) * 0.5 * PI).sin();
+        let sclq = (t * 0.5 * PI).sin();
+
+        for i in 0..3 {
+            out[i] = sclp * p[i] + sclq * perpendicular[i];
+        }
+
+        out[3] = perpendicular[3];
+    }
+
+    out
+}
+
+fn quaternion_matrix(q: [f32; 4]) -> [[f32; 3]; 3] {
+    let [x, y, z, w] = q;
+
+    [
+        [
+            1. - 2. * y * y - 2. * z * z,
+            2. * x * y - 2. * w * z,
+            2. * x * z + 2. * w * y,
+        ],
+        [
+            2. * x * y + 2. * w * z,
+            1. - 2. * x * x - 2. * z * z,
+            2. * y * z - 2. * w * x,
+        ],
+        [
+            2. * x * z - 2. * w * y,
+            2. * y * z + 2. * w * x,
+            1. - 2. * x * x - 2. * y * y,
+        ],
+    ]
+}
+
+/// A loaded external sequence group, `<model>01.mdl` and so on.
+pub struct SequenceGroupFile<'a> {
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> ::std::fmt::Debug for SequenceGroupFile<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "SequenceGroupFile {{ {} bytes }}", self.data.len())
+    }
+}
+
+impl<'a> SequenceGroupFile<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        let data = buffer.into();
+
+        let header: &raw::SeqGroupHeader = unsafe { value_at(&data, 0) }.ok_or_else(|| {
+            Error::Truncated {
+                lump: "header",
+                expected: mem::size_of::<raw::SeqGroupHeader>(),
+                actual: data.len(),
+            }
+        })?;
+
+        if &header.magic != SEQ_GROUP_MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: SEQ_GROUP_MAGIC,
+                found: header.magic,
+            });
+        }
+
+        if header.version.native() != VERSION {
+            return Err(Error::VersionMismatch(header.version.native() as _));
+        }
+
+        Ok(SequenceGroupFile { data })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bone<'a> {
+    pub name: Cow<'a, str>,
+    pub parent: Option<usize>,
+    pub flags: i32,
+    /// The bone controller driving each of X, Y, Z, XR, YR and ZR
+    pub controllers: [Option<usize>; 6],
+    /// The base value of each channel, added to the animation's value times `scale`
+    pub value: [f32; 6],
+    pub scale: [f32; 6],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneController {
+    pub bone: usize,
+    /// Which channel it moves (`STUDIO_X`, `STUDIO_XR`, ...), possibly with `STUDIO_RLOOP`
+    pub kind: i32,
+    pub start: f32,
+    pub end: f32,
+    pub rest: i32,
+    /// The controller slot entities set, or 4 for the mouth
+    pub index: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitBox {
+    pub bone: usize,
+    pub group: i32,
+    pub min: Vec3<f32>,
+    pub max: Vec3<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sequence<'a> {
+    pub label: Cow<'a, str>,
+    pub fps: f32,
+    pub flags: i32,
+    pub activity: i32,
+    pub activity_weight: i32,
+    pub num_frames: usize,
+    pub motion_type: i32,
+    pub motion_bone: i32,
+    pub linear_movement: Vec3<f32>,
+    pub min: Vec3<f32>,
+    pub max: Vec3<f32>,
+    pub num_blends: usize,
+    /// Index into `Studio::sequence_groups` of the file holding the animation
+    pub seq_group: usize,
+    pub events: Vec<Event<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event<'a> {
+    pub frame: i32,
+    pub event: i32,
+    pub kind: i32,
+    pub options: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SequenceGroup<'a> {
+    pub label: Cow<'a, str>,
+    pub name: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture<'a> {
+    pub name: Cow<'a, str>,
+    pub flags: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Indexed pixels, empty if the textures are in a separate file
+    pub pixels: &'a [u8],
+    pub palette: &'a [u8],
+}
+
+impl<'a> Texture<'a> {
+    pub fn is_masked(&self) -> bool {
+        self.flags & TEXTURE_MASKED != 0
+    }
+
+    pub fn rgba(&self) -> Vec<u8> {
+        wad::indexed_to_rgba(
+            self.pixels,
+            self.palette,
+            if self.is_masked() { Some(255) } else { None },
+        )
+    }
+}
+
+/// A group of interchangeable submodels, such as heads or weapons.
+#[derive(Debug, Clone)]
+pub struct BodyPart<'a> {
+    pub name: Cow<'a, str>,
+    pub base: usize,
+    pub models: Vec<Model<'a>>,
+}
+
+impl<'a> BodyPart<'a> {
+    /// The submodel selected by an entity's `body` value.
+    pub fn model_for(&self, body: usize) -> Option<&Model<'a>> {
+        self.models.get((body / self.base) % self.models.len().max(1))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Model<'a> {
+    pub name: Cow<'a, str>,
+    pub bounding_radius: f32,
+    /// Vertex positions relative to the bone in `vertex_bones`
+    pub vertices: Vec<Vec3<f32>>,
+    pub vertex_bones: &'a [u8],
+    pub normals: Vec<Vec3<f32>>,
+    pub normal_bones: &'a [u8],
+    pub meshes: Vec<Mesh>,
+}
+
+impl<'a> Model<'a> {
+    /// Every vertex moved into model space by the bone it's attached to.
+    pub fn posed_vertices(&self, pose: &[BoneTransform]) -> Vec<Vec3<f32>> {
+        self.vertices
+            .iter()
+            .zip(self.vertex_bones)
+            .map(|(&v, &bone)| pose[bone as usize].apply(v))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    /// Index into a skin family, see `Studio::skin_families`
+    pub skin_ref: usize,
+    pub commands: Vec<TriCommand>,
+}
+
+impl Mesh {
+    /// The strips and fans split into individual triangles, all with the same winding.
+    pub fn triangles(&self) -> Vec<[MeshVertex; 3]> {
+        let mut out = Vec::new();
+
+        for command in &self.commands {
+            let v = &command.vertices;
+
+            for i in 2..v.len() {
+                out.push(if command.fan {
+                    [v[0], v[i - 1], v[i]]
+                } else if i % 2 == 1 {
+                    [v[i - 1], v[i - 2], v[i]]
+                } else {
+                    [v[i - 2], v[i - 1], v[i]]
+                });
+            }
+        }
+
+        out
+    }
+}
+
+/// A triangle strip or fan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriCommand {
+    pub fan: bool,
+    pub vertices: Vec<MeshVertex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshVertex {
+    pub vertex: usize,
+    pub normal: usize,
+    /// Texture coordinates in pixels
+    pub s: i16,
+    pub t: i16,
+}
+
+/// A bone's rotation and position in model space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneTransform {
+    pub rotation: [[f32; 3]; 3],
+    pub origin: Vec3<f32>,
+}
+
+impl BoneTransform {
+    pub fn apply(&self, point: Vec3<f32>) -> Vec3<f32> {
+        let r = &self.rotation;
+
+        Vec3 {
+            x: r[0][0] * point.x + r[0][1] * point.y + r[0][2] * point.z + self.origin.x,
+            y: r[1][0] * point.x + r[1][1] * point.y + r[1][2] * point.z + self.origin.y,
+            z: r[2][0] * point.x + r[2][1] * point.y + r[2][2] * point.z + self.origin.z,
+        }
+    }
+
+    fn concat(&self, child: &BoneTransform) -> BoneTransform {
+        let mut rotation = [[0.; 3]; 3];
+
+        for (i, row) in rotation.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..3).map(|k| self.rotation[i][k] * child.rotation[k][j]).sum();
+            }
+        }
+
+        BoneTransform {
+            rotation,
+            origin: self.apply(child.origin),
+        }
+    }
+}
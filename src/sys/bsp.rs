@@ -6,6 +6,7 @@
 
 use ioendian::{Little, IntoNativeEndian};
 use std::marker::PhantomData;
+use std::ops::{Add, Sub, Mul, Neg};
 
 type LU8 = Little<u8>;
 type LU16 = Little<u16>;
@@ -76,14 +77,14 @@ pub struct Quake1Lump {
     pub vertices: Entry<Scalar3>,
     pub vislist: Entry<u8>,
     pub nodes: Entry<Node>,
-    pub texinfo: Entry,
+    pub texinfo: Entry<Surface>,
     pub faces: Entry<Face>,
     pub lightmaps: Entry,
-    pub clipnodes: Entry,
+    pub clipnodes: Entry<ClipNode>,
     pub leaves: Entry<Leaf>,
     pub lfaces: Entry<LU16>,
     pub edges: Entry<Edge>,
-    pub ledges: Entry<LI16>,
+    pub ledges: Entry<LI32>,
     pub models: Entry<Model>,
 }
 
@@ -94,25 +95,25 @@ pub struct Quake2Lump {
     pub planes: Entry<Plane>,
     pub vertices: Entry<Scalar3>,
     pub vislist: Entry<u8>,
-    pub nodes: Entry<Node>,
-    pub texinfo: Entry,
+    pub nodes: Entry<Quake2Node>,
+    pub texinfo: Entry<Quake2Surface>,
     pub faces: Entry<Face>,
     pub lightmaps: Entry,
-    pub leaves: Entry<Leaf>,
+    pub leaves: Entry<Quake2Leaf>,
     pub lface: Entry<LU16>,
-    pub lbrush: Entry,
+    pub lbrush: Entry<LU16>,
     pub edges: Entry<Edge>,
-    pub ledges: Entry<LI16>,
-    pub models: Entry<Model>,
-    pub brushes: Entry,
-    pub brush_sides: Entry,
+    pub ledges: Entry<LI32>,
+    pub models: Entry<Quake2Model>,
+    pub brushes: Entry<Brush>,
+    pub brush_sides: Entry<BrushSide>,
     pub pop: Entry,
     pub areas: Entry,
     pub area_portals: Entry,
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 // TODO: Use nalgebra
 pub struct Vec3<T> {
     pub x: T,
@@ -130,11 +131,79 @@ impl<T: IntoNativeEndian> Vec3<T> {
     }
 }
 
+impl<T: IntoNativeEndian> IntoNativeEndian for Vec3<T> {
+    type Out = Vec3<T::Out>;
+
+    fn native(self) -> Self::Out {
+        Vec3::native(self)
+    }
+}
+
+impl<T: Add<Output = T>> Add for Vec3<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Vec3 {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Vec3<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Vec3 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl<T: Neg<Output = T>> Neg for Vec3<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl<T: Mul<Output = T> + Copy> Mul<T> for Vec3<T> {
+    type Output = Self;
+
+    fn mul(self, scale: T) -> Self {
+        Vec3 {
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+}
+
+impl Vec3<f32> {
+    pub fn dot(&self, other: &Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+}
+
+impl From<Scalar3> for Vec3<f32> {
+    fn from(other: Scalar3) -> Self {
+        other.native()
+    }
+}
+
 pub type Scalar3 = Vec3<Scalar>;
 pub type Short3 = Vec3<LI16>;
 
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BoundingBox<T> {
     pub aa: T,
     pub bb: T,
@@ -188,8 +257,8 @@ pub struct Surface {
 pub struct Face {
     pub plane_id: LU16,
     pub side: LU16,
-    pub ledge_id: LU32,
-    pub ledge_len: LU32,
+    pub ledge_id: LI32,
+    pub ledge_len: LU16,
     pub texinfo_id: LU16,
     pub typelight: LU8,
     pub baselight: LU8,
@@ -224,6 +293,14 @@ pub struct Node {
     pub face_len: LU16,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct ClipNode {
+    pub plane_id: LI32,
+    pub front_id: LI16,
+    pub back_id: LI16,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub struct Sounds {
@@ -251,3 +328,65 @@ pub struct Plane {
     pub dist: Scalar,
     pub plane_type: LI32,
 }
+
+// Quake 2 gives most of these a few more fields, and keeps the map's brushes for collision
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Quake2Model {
+    pub bound: BBoxV3,
+    pub origin: Scalar3,
+    pub head_id: LI32,
+    pub face_id: LI32,
+    pub face_len: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Quake2Surface {
+    pub s: TextureCoord,
+    pub t: TextureCoord,
+    pub flags: LI32,
+    pub value: LI32,
+    pub texture: [LU8; 32],
+    pub next: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Quake2Node {
+    pub plane_id: LI32,
+    pub front_id: LI32,
+    pub back_id: LI32,
+    pub bounds: BBoxShort,
+    pub face_id: LU16,
+    pub face_len: LU16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Quake2Leaf {
+    pub contents: LI32,
+    pub cluster: LI16,
+    pub area: LI16,
+    pub bounds: BBoxShort,
+    pub face_index_id: LU16,
+    pub face_index_len: LU16,
+    pub brush_index_id: LU16,
+    pub brush_index_len: LU16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Brush {
+    pub side_id: LI32,
+    pub side_len: LI32,
+    pub contents: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct BrushSide {
+    pub plane_id: LU16,
+    pub texinfo_id: LI16,
+}
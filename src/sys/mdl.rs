@@ -0,0 +1,62 @@
+//! Quake 1 alias model (`.mdl`, `IDPO` version 6) layout
+//!
+//! Skins and frames are variable-length, so unlike the BSP format only the fixed-size pieces
+//! can be described here and the rest has to be walked in order.
+
+use ioendian::Little;
+
+use sys::bsp::{Scalar, Scalar3};
+
+type LI32 = Little<i32>;
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub version: LI32,
+    pub scale: Scalar3,
+    pub translate: Scalar3,
+    pub bounding_radius: Scalar,
+    pub eye_position: Scalar3,
+    pub num_skins: LI32,
+    pub skin_width: LI32,
+    pub skin_height: LI32,
+    pub num_verts: LI32,
+    pub num_tris: LI32,
+    pub num_frames: LI32,
+    pub sync_type: LI32,
+    pub flags: LI32,
+    pub size: Scalar,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct TexCoord {
+    pub on_seam: LI32,
+    pub s: LI32,
+    pub t: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub faces_front: LI32,
+    pub vertices: [LI32; 3],
+}
+
+/// A compressed vertex position, scaled by the header's `scale` and offset by its `translate`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TriVertex {
+    pub position: [u8; 3],
+    pub normal_index: u8,
+}
+
+/// Followed by one `TriVertex` per vertex.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SimpleFrame {
+    pub min: TriVertex,
+    pub max: TriVertex,
+    pub name: [u8; 16],
+}
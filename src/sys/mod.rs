@@ -1,2 +1,40 @@
 #[cfg(target_endian = "little")]
 pub mod bsp;
+#[cfg(target_endian = "little")]
+pub mod mdl;
+#[cfg(target_endian = "little")]
+pub mod pak;
+#[cfg(target_endian = "little")]
+pub mod spr;
+#[cfg(target_endian = "little")]
+pub mod studio;
+#[cfg(target_endian = "little")]
+pub mod wad;
+
+/// Reinterpret the bytes at `offset` as one of the `#[repr(C)]` structures from this module,
+/// returning `None` if the buffer is too short.
+///
+/// # Safety
+///
+/// `T` must be made entirely of byte arrays (like `ioendian::Little`) so that it has no alignment
+/// requirement and every bit pattern is valid.
+pub unsafe fn value_at<T>(data: &[u8], offset: usize) -> Option<&T> {
+    slice_at(data, offset, 1).map(|slice| &slice[0])
+}
+
+/// Like `value_at`, but for `count` consecutive structures.
+///
+/// # Safety
+///
+/// See `value_at`.
+pub unsafe fn slice_at<T>(data: &[u8], offset: usize, count: usize) -> Option<&[T]> {
+    use std::{mem, slice};
+
+    let len = count.checked_mul(mem::size_of::<T>())?;
+
+    if offset.checked_add(len)? > data.len() {
+        return None;
+    }
+
+    Some(slice::from_raw_parts(data.as_ptr().add(offset) as *const T, count))
+}
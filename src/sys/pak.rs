@@ -0,0 +1,21 @@
+//! Quake PAK archive layout
+
+use ioendian::Little;
+
+type LI32 = Little<i32>;
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub dir_offset: LI32,
+    pub dir_len: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: [u8; 56],
+    pub offset: LI32,
+    pub len: LI32,
+}
@@ -0,0 +1,39 @@
+//! Sprite (`.spr`, `IDSP`) layout, version 1 for Quake and version 2 for GoldSrc
+//!
+//! Version 2 adds a render mode after the sprite type, and a palette (an `i16` colour count and
+//! then RGB triples) between the header and the frames.
+
+use ioendian::Little;
+
+use sys::bsp::Scalar;
+
+type LI32 = Little<i32>;
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Ident {
+    pub magic: [u8; 4],
+    pub version: LI32,
+    pub orientation: LI32,
+}
+
+/// The rest of the header after `Ident` and, for version 2, the render mode.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub bounding_radius: Scalar,
+    pub max_width: LI32,
+    pub max_height: LI32,
+    pub num_frames: LI32,
+    pub beam_length: Scalar,
+    pub sync_type: LI32,
+}
+
+/// Followed by `width * height` palette indices.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub origin: [LI32; 2],
+    pub width: LI32,
+    pub height: LI32,
+}
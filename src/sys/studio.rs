@@ -0,0 +1,210 @@
+//! GoldSrc studio model (`.mdl`, `IDST` version 10) layout
+//!
+//! Every section is found through an offset in the header or in its parent record, relative to
+//! the start of the file. Sequences in external groups (`*01.mdl`, ...) point into that file
+//! instead, which starts with a `SeqGroupHeader`.
+
+use ioendian::Little;
+
+use sys::bsp::{Scalar, Scalar3};
+
+type LI16 = Little<i16>;
+type LU16 = Little<u16>;
+type LI32 = Little<i32>;
+
+/// An offset and a count, in the order the format stores them.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Array {
+    pub count: LI32,
+    pub offset: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub version: LI32,
+    pub name: [u8; 64],
+    pub length: LI32,
+    pub eye_position: Scalar3,
+    pub min: Scalar3,
+    pub max: Scalar3,
+    pub bb_min: Scalar3,
+    pub bb_max: Scalar3,
+    pub flags: LI32,
+    pub bones: Array,
+    pub bone_controllers: Array,
+    pub hitboxes: Array,
+    pub sequences: Array,
+    pub seq_groups: Array,
+    pub textures: Array,
+    pub texture_data: LI32,
+    pub num_skin_refs: LI32,
+    pub num_skin_families: LI32,
+    pub skins: LI32,
+    pub body_parts: Array,
+    pub attachments: Array,
+    pub sound_table: LI32,
+    pub sound_index: LI32,
+    pub sound_groups: LI32,
+    pub sound_group_index: LI32,
+    pub transitions: Array,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SeqGroupHeader {
+    pub magic: [u8; 4],
+    pub version: LI32,
+    pub name: [u8; 64],
+    pub length: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Bone {
+    pub name: [u8; 32],
+    pub parent: LI32,
+    pub flags: LI32,
+    /// Controller driving each of X, Y, Z, XR, YR, ZR, or -1
+    pub controllers: [LI32; 6],
+    pub value: [Scalar; 6],
+    pub scale: [Scalar; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct BoneController {
+    pub bone: LI32,
+    pub kind: LI32,
+    pub start: Scalar,
+    pub end: Scalar,
+    pub rest: LI32,
+    pub index: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct HitBox {
+    pub bone: LI32,
+    pub group: LI32,
+    pub min: Scalar3,
+    pub max: Scalar3,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SeqGroup {
+    pub label: [u8; 32],
+    pub name: [u8; 64],
+    pub cache: LI32,
+    pub data: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SeqDesc {
+    pub label: [u8; 32],
+    pub fps: Scalar,
+    pub flags: LI32,
+    pub activity: LI32,
+    pub activity_weight: LI32,
+    pub events: Array,
+    pub num_frames: LI32,
+    pub pivots: Array,
+    pub motion_type: LI32,
+    pub motion_bone: LI32,
+    pub linear_movement: Scalar3,
+    pub automove_pos: LI32,
+    pub automove_angle: LI32,
+    pub min: Scalar3,
+    pub max: Scalar3,
+    pub num_blends: LI32,
+    /// Offset of `num_blends * num_bones` `Anim`s, in the sequence group's file
+    pub anim_index: LI32,
+    pub blend_type: [LI32; 2],
+    pub blend_start: [Scalar; 2],
+    pub blend_end: [Scalar; 2],
+    pub blend_parent: LI32,
+    pub seq_group: LI32,
+    pub entry_node: LI32,
+    pub exit_node: LI32,
+    pub node_flags: LI32,
+    pub next_seq: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub frame: LI32,
+    pub event: LI32,
+    pub kind: LI32,
+    pub options: [u8; 64],
+}
+
+/// Offsets from this structure to the RLE-compressed values of X, Y, Z, XR, YR and ZR, or 0 if
+/// that channel is constant.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Anim {
+    pub offsets: [LU16; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct BodyPart {
+    pub name: [u8; 64],
+    pub num_models: LI32,
+    pub base: LI32,
+    pub model_index: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub name: [u8; 64],
+    pub kind: LI32,
+    pub bounding_radius: Scalar,
+    pub meshes: Array,
+    pub num_verts: LI32,
+    /// One bone index byte per vertex
+    pub vert_info_index: LI32,
+    pub vert_index: LI32,
+    pub num_norms: LI32,
+    pub norm_info_index: LI32,
+    pub norm_index: LI32,
+    pub groups: Array,
+}
+
+/// `tri_index` points at a list of triangle commands: an `i16` count (negative for a fan,
+/// positive for a strip, zero to end) followed by that many `TriCommandVertex`es.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub num_tris: LI32,
+    pub tri_index: LI32,
+    pub skin_ref: LI32,
+    pub num_norms: LI32,
+    pub norm_index: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct TriCommandVertex {
+    pub vertex: LI16,
+    pub normal: LI16,
+    pub s: LI16,
+    pub t: LI16,
+}
+
+/// `index` points at `width * height` palette indices followed by a 256-colour RGB palette.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub name: [u8; 64],
+    pub flags: LI32,
+    pub width: LI32,
+    pub height: LI32,
+    pub index: LI32,
+}
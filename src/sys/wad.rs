@@ -0,0 +1,64 @@
+//! WAD2 (Quake) and WAD3 (GoldSrc) texture archive layout
+//!
+//! Both versions share the same header and directory, they only differ in which lump types they
+//! use and in WAD3 storing a palette after each image.
+
+use ioendian::Little;
+
+type LI16 = Little<i16>;
+type LI32 = Little<i32>;
+type LU32 = Little<u32>;
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub count: LI32,
+    pub dir_offset: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub offset: LI32,
+    pub disk_size: LI32,
+    pub size: LI32,
+    pub kind: u8,
+    pub compression: u8,
+    pub pad: [u8; 2],
+    pub name: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct MipTexture {
+    pub name: [u8; 16],
+    pub width: LU32,
+    pub height: LU32,
+    pub offsets: [LU32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Pic {
+    pub width: LI32,
+    pub height: LI32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct CharInfo {
+    pub offset: LI16,
+    pub width: LI16,
+}
+
+/// WAD3 font header, followed by a `width` by `height` image with the glyphs arranged in rows.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Font {
+    pub width: LI32,
+    pub height: LI32,
+    pub row_count: LI32,
+    pub row_height: LI32,
+    pub chars: [CharInfo; 256],
+}
@@ -0,0 +1,525 @@
+//! Working out which leaves can see each other (the potentially visible set), from the portals
+//! between them
+//!
+//! This follows Quake's `vis`. Each portal first floods through every portal at least partly in
+//! front of it, giving a rough set of leaves it might see, and the fast mode stops there. The full
+//! mode then flows through each chain of portals, narrowing the view down with the planes that
+//! separate the portals it came through, until nothing new can be seen. Portals are shared out
+//! between threads, and each one's flow is cut short by the results of those already done.
+//! Either way, a leaf ends up seeing every leaf that can see it.
+
+use std::mem;
+use std::sync::OnceLock;
+
+use bsp::portals::{self, Point, Portals, Split};
+use bsp::{Bsp, BspWriter, Error, MapVersion, Quake1Lump, Vec3};
+use parallel::parallel;
+use sys::bsp::Leaf as RawLeaf;
+
+/// How far a point can be from a plane and still count as lying on it
+const ON_EPSILON: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisMode {
+    /// Only flood through the portals in front of each other, like `vis -fast`. Quick, but lets a
+    /// lot through that can't really be seen.
+    Fast,
+    /// Clip the view through every chain of portals.
+    Full,
+}
+
+/// One bit per leaf, counting from 0 at leaf 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(len: usize) -> Self {
+        Bits(vec![0; len.div_ceil(64)])
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn and(&self, other: &Bits) -> Bits {
+        Bits(self.0.iter().zip(&other.0).map(|(a, b)| a & b).collect())
+    }
+
+    fn or(&mut self, other: &Bits) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+
+    /// Whether any bits set in both this and `other` are missing from `seen`
+    fn and_any_outside(&self, other: &Bits, seen: &Bits) -> bool {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .zip(&seen.0)
+            .any(|((a, b), c)| a & b & !c != 0)
+    }
+
+    fn to_row(&self, len: usize) -> Vec<u8> {
+        let mut row = self.0
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        row.truncate(len.div_ceil(8));
+        row
+    }
+}
+
+/// A portal seen from one of its leaves.
+struct VisPortal {
+    winding: Vec<Point>,
+    /// Facing into `leaf`
+    plane: Split,
+    /// The leaf on the other side, counting from 0 at leaf 1
+    leaf: usize,
+}
+
+/// One step along a chain of portals.
+struct Stack<'s> {
+    /// What's left of the first portal that can see through the chain so far
+    source: &'s [Point],
+    /// What's left of the last portal in the chain, `None` for the first step
+    pass: Option<&'s [Point]>,
+    /// The plane of the last portal, facing into the leaf being flowed into
+    plane: Split,
+    might: Bits,
+}
+
+struct Flow<'a> {
+    num_leaves: usize,
+    portals: &'a [VisPortal],
+    /// The portals leading out of each leaf
+    leaves: &'a [Vec<usize>],
+    might: &'a [Bits],
+    done: &'a [OnceLock<Bits>],
+}
+
+fn same_normal(a: &Point, b: &Point) -> bool {
+    (a.x - b.x).abs() < 0.001 && (a.y - b.y).abs() < 0.001 && (a.z - b.z).abs() < 0.001
+}
+
+/// The part of `winding` in front of `plane`, going by vis's own idea of lying on a plane
+fn clip(winding: &[Point], plane: &Split) -> Option<Vec<Point>> {
+    let front = portals::divide_within(winding, plane, ON_EPSILON).0;
+
+    if front.is_empty() {
+        None
+    } else {
+        Some(front)
+    }
+}
+
+/// The planes between `source` and `pass` that are the edges of the view through `pass` from
+/// anywhere on `source`, facing the way the view goes. With `flip` they face back instead, to
+/// narrow `source` down to the part that can see through `pass`.
+fn separators(source: &[Point], pass: &[Point], flip: bool) -> Vec<Split> {
+    let mut planes = Vec::new();
+
+    for i in 0..source.len() {
+        let l = (i + 1) % source.len();
+        let edge = source[l] - source[i];
+
+        // Find the planes through this edge and a corner of `pass` that have all of `pass` in
+        // front and all of `source` behind
+        for (j, &corner) in pass.iter().enumerate() {
+            let normal = portals::cross(&edge, &(corner - source[i]));
+            let length = portals::dot(&normal, &normal).sqrt();
+
+            if length < ON_EPSILON {
+                continue;
+            }
+
+            let normal = normal * (1. / length);
+            let mut plane = Split {
+                normal,
+                distance: portals::dot(&corner, &normal),
+            };
+
+            let behind = source
+                .iter()
+                .enumerate()
+                .filter(|&(k, _)| k != i && k != l)
+                .map(|(_, p)| plane.distance_to(p))
+                .find(|d| d.abs() > ON_EPSILON);
+
+            plane = match behind {
+                // Lies in the same plane as `source`
+                None => continue,
+                Some(d) if d > 0. => plane.flip(),
+                Some(_) => plane,
+            };
+
+            let mut ahead = 0;
+
+            let separates = pass.iter().enumerate().filter(|&(k, _)| k != j).all(|(_, p)| {
+                let d = plane.distance_to(p);
+
+                if d > ON_EPSILON {
+                    ahead += 1;
+                }
+
+                d >= -ON_EPSILON
+            });
+
+            if separates && ahead > 0 {
+                planes.push(if flip { plane.flip() } else { plane });
+            }
+        }
+    }
+
+    planes
+}
+
+fn clip_all(mut winding: Vec<Point>, planes: &[Split]) -> Option<Vec<Point>> {
+    for plane in planes {
+        winding = clip(&winding, plane)?;
+    }
+
+    Some(winding)
+}
+
+impl<'a> Flow<'a> {
+    /// The leaves `base` might see: those reachable through portals in front of it that it's in
+    /// turn behind.
+    fn might_see(&self, base: usize) -> Bits {
+        let p = &self.portals[base];
+        let sees = self
+            .portals
+            .iter()
+            .enumerate()
+            .map(|(i, other)| {
+                i != base &&
+                    other
+                        .winding
+                        .iter()
+                        .any(|v| p.plane.distance_to(v) > ON_EPSILON) &&
+                    p.winding.iter().any(|v| other.plane.distance_to(v) < -ON_EPSILON)
+            })
+            .collect::<Vec<_>>();
+
+        let mut might = Bits::new(self.num_leaves);
+        let mut stack = vec![p.leaf];
+
+        while let Some(leaf) = stack.pop() {
+            if might.get(leaf) {
+                continue;
+            }
+
+            might.set(leaf);
+            stack.extend(
+                self.leaves[leaf]
+                    .iter()
+                    .filter(|&&i| sees[i])
+                    .map(|&i| self.portals[i].leaf),
+            );
+        }
+
+        might
+    }
+
+    fn portal_flow(&self, base: usize) -> Bits {
+        let p = &self.portals[base];
+        let mut vis = Bits::new(self.num_leaves);
+        let head = Stack {
+            source: &p.winding,
+            pass: None,
+            plane: p.plane,
+            might: self.might[base].clone(),
+        };
+
+        self.leaf_flow(base, p.leaf, &head, &mut vis);
+
+        vis
+    }
+
+    fn leaf_flow(&self, base: usize, leaf: usize, prev: &Stack, vis: &mut Bits) {
+        vis.set(leaf);
+
+        let base_plane = self.portals[base].plane;
+
+        for &i in &self.leaves[leaf] {
+            let p = &self.portals[i];
+
+            if !prev.might.get(p.leaf) {
+                continue;
+            }
+
+            // A portal that's done can see much less than it might have
+            let test = self.done[i].get().unwrap_or(&self.might[i]);
+
+            if !prev.might.and_any_outside(test, vis) {
+                continue;
+            }
+
+            let might = prev.might.and(test);
+
+            let back = p.plane.flip();
+
+            // Can't go back out through a portal on the same plane
+            if same_normal(&prev.plane.normal, &back.normal) {
+                continue;
+            }
+
+            let target = match clip(&p.winding, &base_plane) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            // Only the part of the source behind this portal can see through it
+            let source = match clip(prev.source, &back) {
+                Some(source) => source,
+                None => continue,
+            };
+
+            let pass = match prev.pass {
+                Some(pass) => pass,
+                None => {
+                    // The second leaf can only be blocked by portals on the same plane
+                    let next = Stack {
+                        source: &source,
+                        pass: Some(&target),
+                        plane: p.plane,
+                        might,
+                    };
+
+                    self.leaf_flow(base, p.leaf, &next, vis);
+                    continue;
+                }
+            };
+
+            if let Some((source, target)) = narrow(source, pass, target, &prev.plane) {
+                let next = Stack {
+                    source: &source,
+                    pass: Some(&target),
+                    plane: p.plane,
+                    might,
+                };
+
+                self.leaf_flow(base, p.leaf, &next, vis);
+            }
+        }
+    }
+}
+
+/// Narrow `target` down to what can be seen through `pass` from `source`, and `source` down to
+/// what can see through both, or `None` if nothing can.
+fn narrow(
+    source: Vec<Point>,
+    pass: &[Point],
+    target: Vec<Point>,
+    pass_plane: &Split,
+) -> Option<(Vec<Point>, Vec<Point>)> {
+    let target = clip(&target, pass_plane)?;
+    let target = clip_all(target, &separators(&source, pass, false))?;
+    let target = clip_all(target, &separators(pass, &source, true))?;
+    let source = clip_all(source, &separators(&target, pass, false))?;
+    let source = clip_all(source, &separators(pass, &target, true))?;
+
+    Some((source, target))
+}
+
+/// Run-length encode a row the way Quake stores it: each run of zero bytes becomes a zero
+/// followed by its length, up to 255.
+pub fn compress_row(row: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < row.len() {
+        out.push(row[i]);
+
+        if row[i] != 0 {
+            i += 1;
+            continue;
+        }
+
+        let run = row[i..]
+            .iter()
+            .take(255)
+            .take_while(|&&b| b == 0)
+            .count();
+
+        out.push(run as u8);
+        i += run;
+    }
+
+    out
+}
+
+/// Which leaves of the world can see which others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Visibility {
+    /// The number of leaves in the world, not counting leaf 0
+    pub num_leaves: usize,
+    /// Uncompressed, one per leaf starting from leaf 1
+    rows: Vec<Vec<u8>>,
+}
+
+impl Visibility {
+    /// Work out the visibility of the world from its portals.
+    pub fn new<V>(bsp: &Bsp<V>, mode: VisMode) -> Self
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        Visibility::from_portals(&bsp.portals(), mode)
+    }
+
+    pub fn from_portals(portals: &Portals, mode: VisMode) -> Self {
+        let num_leaves = portals.num_leaves;
+        let widen = |v: &Vec3<f32>| Vec3 {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+        };
+
+        let mut vis_portals = Vec::new();
+        let mut leaves = vec![Vec::new(); num_leaves];
+
+        for portal in portals.visible() {
+            let winding = portal.winding.iter().map(widen).collect::<Vec<_>>();
+            let plane = Split {
+                normal: widen(&portal.plane.normal),
+                distance: portal.plane.distance as f64,
+            };
+            let (front, back) = (portal.leaves.0 - 1, portal.leaves.1 - 1);
+
+            // The plane faces the front leaf, so the front leaf's way out faces the other way
+            leaves[front].push(vis_portals.len());
+            vis_portals.push(VisPortal {
+                winding: winding.clone(),
+                plane: plane.flip(),
+                leaf: back,
+            });
+
+            leaves[back].push(vis_portals.len());
+            vis_portals.push(VisPortal {
+                winding,
+                plane,
+                leaf: front,
+            });
+        }
+
+        let done = (0..vis_portals.len())
+            .map(|_| OnceLock::new())
+            .collect::<Vec<_>>();
+        let mut flow = Flow {
+            num_leaves,
+            portals: &vis_portals,
+            leaves: &leaves,
+            might: &[],
+            done: &done,
+        };
+
+        let order = (0..vis_portals.len()).collect::<Vec<_>>();
+        let might = parallel(&order, |i| flow.might_see(i));
+
+        flow.might = &might;
+
+        let seen = match mode {
+            VisMode::Fast => might.clone(),
+            VisMode::Full => {
+                // The portals that might see the least are quickest to finish, and help cut
+                // the others short once they are
+                let mut order = order;
+                order.sort_by_key(|&i| might[i].0.iter().map(|w| w.count_ones()).sum::<u32>());
+
+                parallel(&order, |i| {
+                    let seen = flow.portal_flow(i);
+                    let _ = done[i].set(seen.clone());
+
+                    seen
+                })
+            }
+        };
+
+        let mut rows = leaves
+            .iter()
+            .enumerate()
+            .map(|(leaf, leaf_portals)| {
+                let mut row = Bits::new(num_leaves);
+
+                for &i in leaf_portals {
+                    row.or(&seen[i]);
+                }
+
+                row.set(leaf);
+                row
+            })
+            .collect::<Vec<_>>();
+
+        // Each flow can only rule out what's truly hidden from its own side, and some sight lines
+        // are only found from the other end, so a leaf sees every leaf that sees it
+        for from in 0..num_leaves {
+            for to in 0..from {
+                if rows[from].get(to) || rows[to].get(from) {
+                    rows[from].set(to);
+                    rows[to].set(from);
+                }
+            }
+        }
+
+        let rows = rows.iter().map(|row| row.to_row(num_leaves)).collect();
+
+        Visibility { num_leaves, rows }
+    }
+
+    /// Whether anything in leaf `from` can see into leaf `to`, as indices into `Bsp::leaves`.
+    pub fn can_see(&self, from: usize, to: usize) -> bool {
+        if from == 0 || to == 0 || from > self.num_leaves || to > self.num_leaves {
+            return false;
+        }
+
+        self.rows[from - 1][(to - 1) / 8] & (1 << ((to - 1) % 8)) != 0
+    }
+
+    /// The uncompressed row of leaf `leaf`, one bit per leaf starting from leaf 1.
+    pub fn row(&self, leaf: usize) -> Option<&[u8]> {
+        leaf.checked_sub(1)
+            .and_then(|i| self.rows.get(i))
+            .map(|row| &row[..])
+    }
+
+    /// Replace the vislist lump and point each world leaf at its compressed row. Leaf 0 and the
+    /// leaves of brush entities don't get one.
+    pub fn write_lumps(&self, writer: &mut BspWriter) {
+        let leaf_len = mem::size_of::<RawLeaf>();
+        let field = mem::offset_of!(RawLeaf, vis_index);
+        let mut vislist = Vec::new();
+
+        for (i, leaf) in writer.leaves.chunks_mut(leaf_len).enumerate() {
+            let offset = match i.checked_sub(1).and_then(|i| self.rows.get(i)) {
+                Some(row) => {
+                    let offset = vislist.len() as i32;
+
+                    vislist.extend(compress_row(row));
+                    offset
+                }
+                None => -1,
+            };
+
+            leaf[field..field + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        writer.vislist = vislist;
+    }
+
+    /// A copy of `bsp` with this visibility in it.
+    pub fn apply<V>(&self, bsp: &Bsp<V>) -> Result<Bsp<'static, V>, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump> + 'static,
+    {
+        let mut writer = BspWriter::from_bsp(bsp);
+
+        self.write_lumps(&mut writer);
+        writer.build()
+    }
+}
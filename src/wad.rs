@@ -0,0 +1,578 @@
+//! WAD2 (Quake) and WAD3 (GoldSrc) texture archives
+//!
+//! Like `Bsp`, this reads lazily straight out of the buffer (or the memory-mapped file) rather
+//! than copying anything out.
+
+use std::borrow::Cow;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use ioendian::IntoNativeEndian;
+
+use bsp::{Bsp, Error, MapVersion};
+use storage::Storage;
+use sys::{self, slice_at, value_at};
+use sys::wad as raw;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Wad2,
+    Wad3,
+}
+
+/// What a lump contains. The type bytes mean different things in WAD2 and WAD3, so this is
+/// decoded with the archive's version in mind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumpKind {
+    Palette,
+    /// WAD2's `TYP_QTEX`, an early texture format that Quake itself never reads
+    QTex,
+    Pic,
+    Sound,
+    MipTex,
+    Font,
+    Other(u8),
+}
+
+impl LumpKind {
+    fn new(version: Version, kind: u8) -> Self {
+        match (version, kind) {
+            (Version::Wad2, 0x40) => LumpKind::Palette,
+            (Version::Wad2, 0x41) => LumpKind::QTex,
+            (Version::Wad2, 0x43) => LumpKind::Sound,
+            (Version::Wad2, 0x44) => LumpKind::MipTex,
+            (Version::Wad3, 0x43) => LumpKind::MipTex,
+            (Version::Wad3, 0x46) => LumpKind::Font,
+            (_, 0x42) => LumpKind::Pic,
+            (_, other) => LumpKind::Other(other),
+        }
+    }
+}
+
+pub struct Wad<'a> {
+    data: Storage<'a>,
+    version: Version,
+}
+
+impl<'a> ::std::fmt::Debug for Wad<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "Wad {{ {:?}, {} lumps }}", self.version, self.len())
+    }
+}
+
+impl<'a> Wad<'a> {
+    pub fn new<T: Into<Cow<'a, [u8]>>>(buffer: T) -> Result<Self, Error> {
+        Self::from_storage(Storage::Buffer(buffer.into()))
+    }
+
+    /// Memory-map a WAD file. Nothing stops another process from modifying the file while it's
+    /// mapped, which is undefined behaviour, so making sure that doesn't happen is the caller's
+    /// responsibility.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Wad<'static>, Error> {
+        Wad::from_storage(Storage::open(path)?)
+    }
+
+    fn from_storage(data: Storage<'a>) -> Result<Self, Error> {
+        let header: &raw::Header = match unsafe { value_at(&data, 0) } {
+            Some(header) => header,
+            None => {
+                return Err(Error::Truncated {
+                    lump: "header",
+                    expected: mem::size_of::<raw::Header>(),
+                    actual: data.len(),
+                })
+            }
+        };
+
+        let version = match &header.magic {
+            b"WAD2" => Version::Wad2,
+            b"WAD3" => Version::Wad3,
+            found => {
+                return Err(Error::InvalidMagic {
+                    expected: b"WAD2 or WAD3",
+                    found: *found,
+                })
+            }
+        };
+
+        let count = header.count.native();
+        let dir_offset = header.dir_offset.native();
+        let dir_len = count as i64 * mem::size_of::<raw::DirEntry>() as i64;
+
+        if count < 0 || dir_offset < 0 || dir_offset as i64 + dir_len > data.len() as i64 {
+            return Err(Error::LumpOutOfBounds {
+                lump: "directory",
+                offset: dir_offset,
+                len: dir_len as i32,
+                file_len: data.len(),
+            });
+        }
+
+        let out = Wad { data, version };
+
+        for (i, entry) in out.directory().iter().enumerate() {
+            let offset = entry.offset.native();
+            let len = entry.disk_size.native();
+
+            if offset < 0 || len < 0 || offset as i64 + len as i64 > out.data.len() as i64 {
+                return Err(Error::BadIndex {
+                    lump: "directory",
+                    record: i,
+                    field: "offset",
+                    index: offset as _,
+                    len: out.data.len(),
+                });
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    fn directory(&self) -> &[raw::DirEntry] {
+        let header: &raw::Header = unsafe { value_at(&self.data, 0).unwrap() };
+
+        unsafe {
+            slice_at(
+                &self.data,
+                header.dir_offset.native() as usize,
+                header.count.native() as usize,
+            ).unwrap()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.directory().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directory().is_empty()
+    }
+
+    pub fn lumps(&self) -> Lumps<'_> {
+        Lumps {
+            wad: self,
+            inner: self.directory().iter(),
+        }
+    }
+
+    /// Find a lump by name. Names are compared case-insensitively, as the engines do.
+    pub fn lump(&self, name: &str) -> Option<WadLump<'_>> {
+        self.lumps()
+            .find(|lump| lump.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Find a texture by name, skipping any non-texture lump that happens to share it.
+    pub fn texture(&self, name: &str) -> Option<MipTex<'_>> {
+        self.lumps()
+            .filter(|lump| lump.kind() == LumpKind::MipTex)
+            .find(|lump| lump.name().eq_ignore_ascii_case(name))
+            .and_then(|lump| lump.miptex().ok())
+    }
+}
+
+pub struct Lumps<'a> {
+    wad: &'a Wad<'a>,
+    inner: ::std::slice::Iter<'a, raw::DirEntry>,
+}
+
+impl<'a> Iterator for Lumps<'a> {
+    type Item = WadLump<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let wad = self.wad;
+
+        self.inner.next().map(|entry| WadLump { entry, wad })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Lumps<'a> {}
+
+pub struct WadLump<'a> {
+    entry: &'a raw::DirEntry,
+    wad: &'a Wad<'a>,
+}
+
+fn name_from_bytes(name: &[u8]) -> Cow<'_, str> {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+    String::from_utf8_lossy(&name[..len])
+}
+
+impl<'a> WadLump<'a> {
+    pub fn name(&self) -> Cow<'a, str> {
+        name_from_bytes(&self.entry.name)
+    }
+
+    pub fn kind(&self) -> LumpKind {
+        LumpKind::new(self.wad.version, self.entry.kind)
+    }
+
+    /// Whether the lump is compressed. No shipped WAD uses compression and neither engine can
+    /// read it, so the typed accessors return `Error::Unsupported` for these.
+    pub fn is_compressed(&self) -> bool {
+        self.entry.compression != 0
+    }
+
+    /// The lump's bytes as they're stored in the file.
+    pub fn data(&self) -> &'a [u8] {
+        let start = self.entry.offset.native() as usize;
+        let end = start + self.entry.disk_size.native() as usize;
+
+        &self.wad.data[start..end]
+    }
+
+    fn uncompressed(&self) -> Result<&'a [u8], Error> {
+        if self.is_compressed() {
+            Err(Error::Unsupported("compressed WAD lumps"))
+        } else {
+            Ok(self.data())
+        }
+    }
+
+    pub fn miptex(&self) -> Result<MipTex<'a>, Error> {
+        MipTex::parse(self.uncompressed()?, self.wad.version == Version::Wad3)
+    }
+
+    pub fn pic(&self) -> Result<Pic<'a>, Error> {
+        Pic::parse(self.uncompressed()?, self.wad.version == Version::Wad3)
+    }
+
+    pub fn font(&self) -> Result<Font<'a>, Error> {
+        Font::parse(self.uncompressed()?)
+    }
+}
+
+fn truncated(lump: &'static str, expected: usize, actual: usize) -> Error {
+    Error::Truncated {
+        lump,
+        expected,
+        actual,
+    }
+}
+
+/// The `len` bytes at `offset`, where both come from the file and so can be anything. A range
+/// that doesn't fit in `usize` is as truncated as one that runs off the end of the data.
+fn bytes_at<'a>(
+    data: &'a [u8],
+    offset: usize,
+    len: Option<usize>,
+    lump: &'static str,
+) -> Result<&'a [u8], Error> {
+    let end = len.and_then(|len| offset.checked_add(len));
+
+    end.and_then(|end| data.get(offset..end))
+        .ok_or_else(|| truncated(lump, end.unwrap_or(usize::MAX), data.len()))
+}
+
+/// Read the `u16` count and RGB triples that WAD3 images (and GoldSrc's embedded textures) store
+/// after their pixels.
+fn trailing_palette<'a>(
+    data: &'a [u8],
+    offset: usize,
+    lump: &'static str,
+) -> Result<&'a [u8], Error> {
+    let count = bytes_at(data, offset, Some(2), lump)?;
+    let count = count[0] as usize | (count[1] as usize) << 8;
+
+    bytes_at(data, offset + 2, Some(count * 3), lump)
+}
+
+/// Expand 8-bit indexed pixels to RGBA. If `transparent` is set then that index becomes fully
+/// transparent, which is how GoldSrc treats index 255 in `{`-prefixed textures. Indices past the
+/// end of a short palette come out black.
+pub fn indexed_to_rgba(pixels: &[u8], palette: &[u8], transparent: Option<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * 4);
+
+    for &index in pixels {
+        if Some(index) == transparent {
+            out.extend_from_slice(&[0, 0, 0, 0]);
+        } else {
+            let i = index as usize * 3;
+
+            match palette.get(i..i + 3) {
+                Some(rgb) => out.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]),
+                None => out.extend_from_slice(&[0, 0, 0, 255]),
+            }
+        }
+    }
+
+    out
+}
+
+/// A mipmapped wall texture, from either a WAD or a map's miptex lump.
+#[derive(Debug, Clone)]
+pub struct MipTex<'a> {
+    pub name: Cow<'a, str>,
+    pub width: u32,
+    pub height: u32,
+    /// Indexed pixels of each mip level, each half the size of the previous one
+    pub mips: [&'a [u8]; 4],
+    /// The texture's own palette, only present in WAD3 and GoldSrc maps
+    pub palette: Option<&'a [u8]>,
+}
+
+impl<'a> MipTex<'a> {
+    /// Parse a miptex structure from the start of `data`. `paletted` says whether a palette
+    /// follows the last mip level.
+    pub fn parse(data: &'a [u8], paletted: bool) -> Result<Self, Error> {
+        let header: &raw::MipTexture = unsafe { value_at(data, 0) }.ok_or_else(|| {
+            truncated("miptex", mem::size_of::<raw::MipTexture>(), data.len())
+        })?;
+
+        let width = header.width.native();
+        let height = header.height.native();
+        let mut mips: [&[u8]; 4] = [&[]; 4];
+        let mut end = 0;
+
+        for (level, mip) in mips.iter_mut().enumerate() {
+            let offset = header.offsets[level].native() as usize;
+            let len = (width as usize >> level).checked_mul(height as usize >> level);
+
+            *mip = bytes_at(data, offset, len, "miptex")?;
+            end = offset + mip.len();
+        }
+
+        let palette = if paletted {
+            Some(trailing_palette(data, end, "miptex")?)
+        } else {
+            None
+        };
+
+        Ok(MipTex {
+            name: name_from_bytes(&header.name),
+            width,
+            height,
+            mips,
+            palette,
+        })
+    }
+
+    /// Whether index 255 should be treated as transparent, for GoldSrc's `{` textures.
+    pub fn is_masked(&self) -> bool {
+        self.name.starts_with('{')
+    }
+
+    /// Decode a mip level to RGBA, using the texture's own palette if it has one and
+    /// `palette` otherwise.
+    pub fn rgba(&self, level: usize, palette: Option<&[u8]>) -> Option<Vec<u8>> {
+        let palette = self.palette.or(palette)?;
+        let transparent = if self.is_masked() { Some(255) } else { None };
+
+        Some(indexed_to_rgba(self.mips.get(level)?, palette, transparent))
+    }
+}
+
+/// A flat 2D image, used for HUD and menu graphics.
+#[derive(Debug, Clone)]
+pub struct Pic<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: &'a [u8],
+    pub palette: Option<&'a [u8]>,
+}
+
+impl<'a> Pic<'a> {
+    pub fn parse(data: &'a [u8], paletted: bool) -> Result<Self, Error> {
+        let header: &raw::Pic = unsafe { value_at(data, 0) }
+            .ok_or_else(|| truncated("qpic", mem::size_of::<raw::Pic>(), data.len()))?;
+
+        let width = header.width.native().max(0) as usize;
+        let height = header.height.native().max(0) as usize;
+        let start = mem::size_of::<raw::Pic>();
+        let pixels = bytes_at(data, start, width.checked_mul(height), "qpic")?;
+        let end = start + pixels.len();
+        let palette = if paletted {
+            Some(trailing_palette(data, end, "qpic")?)
+        } else {
+            None
+        };
+
+        Ok(Pic {
+            width: width as _,
+            height: height as _,
+            pixels,
+            palette,
+        })
+    }
+
+    pub fn rgba(&self, palette: Option<&[u8]>) -> Option<Vec<u8>> {
+        Some(indexed_to_rgba(self.pixels, self.palette.or(palette)?, None))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyph {
+    /// Byte offset of the glyph's top-left corner into `Font::pixels`
+    pub offset: usize,
+    pub width: usize,
+}
+
+/// A WAD3 bitmap font. All 256 glyphs are packed into rows of a single image.
+#[derive(Debug, Clone)]
+pub struct Font<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub row_count: u32,
+    pub row_height: u32,
+    pub glyphs: Vec<Glyph>,
+    pub pixels: &'a [u8],
+    pub palette: &'a [u8],
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let header: &raw::Font = unsafe { value_at(data, 0) }
+            .ok_or_else(|| truncated("font", mem::size_of::<raw::Font>(), data.len()))?;
+
+        let width = header.width.native().max(0) as usize;
+        let height = header.height.native().max(0) as usize;
+        let start = mem::size_of::<raw::Font>();
+        let pixels = bytes_at(data, start, width.checked_mul(height), "font")?;
+        let end = start + pixels.len();
+
+        Ok(Font {
+            width: width as _,
+            height: height as _,
+            row_count: header.row_count.native().max(0) as _,
+            row_height: header.row_height.native().max(0) as _,
+            glyphs: header
+                .chars
+                .iter()
+                .map(|c| {
+                    Glyph {
+                        offset: c.offset.native() as u16 as usize,
+                        width: c.width.native().max(0) as usize,
+                    }
+                })
+                .collect(),
+            pixels,
+            palette: trailing_palette(data, end, "font")?,
+        })
+    }
+
+    /// The RGBA pixels of one character, `glyph.width` by `row_height` in size.
+    pub fn glyph_rgba(&self, c: u8) -> Vec<u8> {
+        let glyph = self.glyphs[c as usize];
+        let mut out = Vec::with_capacity(glyph.width * self.row_height as usize * 4);
+
+        for row in 0..self.row_height as usize {
+            let start = glyph.offset + row * self.width as usize;
+            let end = (start + glyph.width).min(self.pixels.len());
+            let line = self.pixels.get(start..end).unwrap_or(&[]);
+
+            // Index 255 is the background in every shipped font
+            out.extend(indexed_to_rgba(line, self.palette, Some(255)));
+        }
+
+        out
+    }
+}
+
+/// Finds the textures that a map leaves to external WADs. The WADs to load come from the
+/// worldspawn's `wad` key, which lists absolute paths from the mapper's machine, so only the
+/// file names are used and looked up in each search path directory in turn.
+#[derive(Debug, Default)]
+pub struct Resolver {
+    search_path: Vec<PathBuf>,
+    wads: Vec<Wad<'static>>,
+    missing: Vec<String>,
+}
+
+impl Resolver {
+    pub fn new<I>(search_path: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        Resolver {
+            search_path: search_path.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a WAD explicitly. WADs are searched in the order they were added.
+    pub fn add_wad(&mut self, wad: Wad<'static>) {
+        self.wads.push(wad);
+    }
+
+    /// WADs that `load_for` couldn't find in any search path.
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    fn find(&self, file_name: &str) -> Option<PathBuf> {
+        for dir in &self.search_path {
+            let exact = dir.join(file_name);
+
+            if exact.is_file() {
+                return Some(exact);
+            }
+
+            // Maps are usually made on Windows, so the case in the `wad` key is unreliable
+            if let Ok(entries) = fs::read_dir(dir) {
+                for entry in entries.flatten() {
+                    if entry.file_name().to_string_lossy().eq_ignore_ascii_case(file_name) {
+                        return Some(entry.path());
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Load every WAD named in the map's worldspawn. WADs that can't be found are recorded in
+    /// `missing` rather than treated as an error, since maps often list WADs they don't use.
+    pub fn load_for<V>(&mut self, bsp: &Bsp<V>) -> Result<(), Error>
+    where
+        V: MapVersion<Lump = sys::bsp::Quake1Lump>,
+    {
+        let entities = bsp.entities();
+        let wads = match entities.first().and_then(|world| world.get("wad")) {
+            Some(wads) => wads.to_owned(),
+            None => return Ok(()),
+        };
+
+        for path in wads.split(';').filter(|p| !p.trim().is_empty()) {
+            let file_name = path.trim().rsplit(['\\', '/']).next().unwrap_or(path);
+
+            match self.find(file_name) {
+                Some(found) => self.wads.push(Wad::open(found)?),
+                None => self.missing.push(file_name.to_owned()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every texture of the map's miptex lump, in order. Textures embedded in the map are used
+    /// as they are, external ones are taken from the first loaded WAD that has them and are
+    /// `None` if none do.
+    pub fn resolve<'b, V>(&'b self, bsp: &'b Bsp<'b, V>) -> Vec<Option<MipTex<'b>>>
+    where
+        V: MapVersion<Lump = sys::bsp::Quake1Lump>,
+    {
+        bsp.textures()
+            .iter()
+            .map(|(_, texture)| {
+                let texture = texture?;
+
+                if !texture.is_external() {
+                    return texture.miptex();
+                }
+
+                let name = texture.name();
+                self.wads.iter().filter_map(|wad| wad.texture(&name)).next()
+            })
+            .collect()
+    }
+}
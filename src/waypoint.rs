@@ -0,0 +1,360 @@
+//! Waypoint graphs for bots, a lighter alternative to a navigation mesh
+//!
+//! Waypoints are player origins on a grid over the walkable floors, linked to their neighbours
+//! when the player's hull can get between them. Generating a graph takes a while, so it can be
+//! saved alongside the map and is only thrown away once the map changes.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use ioendian::{IntoNativeEndian, Little};
+
+use bsp::{Bsp, Error, MapVersion, Vec3};
+use nav::{self, LinkKind, NavConfig};
+use sys::bsp::Quake1Lump;
+use sys::value_at;
+
+const MAGIC: &[u8; 4] = b"WAYP";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaypointLink {
+    pub to: usize,
+    pub kind: LinkKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waypoint {
+    /// Where the player's origin is when standing here
+    pub origin: Vec3<f32>,
+    pub links: Vec<WaypointLink>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaypointGraph {
+    /// The `Bsp::checksum` of the map the graph was made for
+    pub map_key: u32,
+    pub waypoints: Vec<Waypoint>,
+}
+
+fn truncated(expected: usize, actual: usize) -> Error {
+    Error::Truncated {
+        lump: "waypoints",
+        expected,
+        actual,
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, Error> {
+    let value: &Little<u32> =
+        unsafe { value_at(data, at) }.ok_or_else(|| truncated(at + 4, data.len()))?;
+
+    Ok(value.native())
+}
+
+fn read_f32(data: &[u8], at: usize) -> Result<f32, Error> {
+    let value: &Little<f32> =
+        unsafe { value_at(data, at) }.ok_or_else(|| truncated(at + 4, data.len()))?;
+
+    Ok(value.native())
+}
+
+impl WaypointGraph {
+    /// Put a waypoint every `spacing` units over the walkable floors and link each one to those
+    /// around it. Fails if the map has no clipping hulls, or if `spacing` isn't a positive,
+    /// finite number.
+    pub fn generate<V>(bsp: &Bsp<V>, config: &NavConfig, spacing: f32) -> Result<Self, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        WaypointGraph::generate_for(bsp, config, spacing, bsp.checksum())
+    }
+
+    /// `generate`, with the map's checksum already worked out by the caller.
+    fn generate_for<V>(
+        bsp: &Bsp<V>,
+        config: &NavConfig,
+        spacing: f32,
+        map_key: u32,
+    ) -> Result<Self, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        if !(spacing.is_finite() && spacing > 0.) {
+            return Err(Error::Unsupported("waypoint spacing that isn't a positive, finite number"));
+        }
+
+        let floors = nav::floors(bsp, config)?;
+        let hull = &floors.hull;
+        let mut waypoints = Vec::new();
+        let mut seen = HashSet::new();
+
+        for polygon in &floors.polygons {
+            let (mut min, mut max) = ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN));
+
+            for v in &polygon.vertices {
+                min = (min.0.min(v.x), min.1.min(v.y));
+                max = (max.0.max(v.x), max.1.max(v.y));
+            }
+
+            // The grid is offset by half a cell, since brushes are usually aligned to it and a
+            // player standing right on the edge of one is usually inside a wall
+            let cells = |min: f32, max: f32| {
+                ((min / spacing - 0.5).ceil() as i32)..((max / spacing - 0.5).floor() as i32 + 1)
+            };
+
+            for gx in cells(min.0, max.0) {
+                for gy in cells(min.1, max.1) {
+                    let (x, y) = ((gx as f32 + 0.5) * spacing, (gy as f32 + 0.5) * spacing);
+
+                    if !polygon.contains_xy(x, y) {
+                        continue;
+                    }
+
+                    let origin = Vec3 {
+                        x,
+                        y,
+                        z: polygon.height_at(x, y),
+                    } + floors.stand;
+
+                    // Neighbouring floors share their edges, so points on them are found twice
+                    if !seen.insert((gx, gy, origin.z.round() as i32)) ||
+                        !nav::is_open(hull.point_contents(&origin))
+                    {
+                        continue;
+                    }
+
+                    waypoints.push(Waypoint {
+                        origin,
+                        links: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let mut buckets = HashMap::<(i32, i32), Vec<usize>>::new();
+
+        for (i, waypoint) in waypoints.iter().enumerate() {
+            let cell = (
+                (waypoint.origin.x / spacing).floor() as i32,
+                (waypoint.origin.y / spacing).floor() as i32,
+            );
+
+            buckets.entry(cell).or_default().push(i);
+        }
+
+        // Far enough to reach the diagonal neighbours, but not the ones beyond them
+        let reach = spacing * 1.5;
+
+        // How the player gets from `a` to `b`, if they can
+        let connect = |a: Vec3<f32>, b: Vec3<f32>| {
+            let rise = b.z - a.z;
+
+            if rise > config.jump_height || -rise > config.max_drop {
+                return None;
+            }
+
+            // Walking needs floor all the way, so look for it below the midpoint
+            let middle = (a + b) * 0.5;
+            let middle = Vec3 {
+                z: a.z.max(b.z),
+                ..middle
+            };
+            let walking = rise.abs() <= config.step_height &&
+                hull.trace(&middle, &(middle - nav::up(config.step_height * 2. + 1.)))
+                    .hit();
+
+            // Walking steps up over anything in the way, jumping and falling only get as high
+            // as the higher end
+            let top = a.z.max(b.z) + if walking { config.step_height } else { 0. };
+            let waypoints = [a, Vec3 { z: top, ..a }, Vec3 { z: top, ..b }, b];
+
+            if waypoints
+                .windows(2)
+                .any(|pair| hull.trace(&pair[0], &pair[1]).hit())
+            {
+                return None;
+            }
+
+            Some(if !walking {
+                LinkKind::Jump
+            } else if rise.abs() <= 1. {
+                LinkKind::Walk
+            } else {
+                LinkKind::Step
+            })
+        };
+
+        for i in 0..waypoints.len() {
+            let origin = waypoints[i].origin;
+            let cell = (
+                (origin.x / spacing).floor() as i32,
+                (origin.y / spacing).floor() as i32,
+            );
+
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let neighbours = match buckets.get(&(cell.0 + dx, cell.1 + dy)) {
+                        Some(neighbours) => neighbours,
+                        None => continue,
+                    };
+
+                    for &j in neighbours {
+                        let other = waypoints[j].origin;
+                        let distance =
+                            ((other.x - origin.x).powi(2) + (other.y - origin.y).powi(2)).sqrt();
+
+                        if j == i || distance > reach {
+                            continue;
+                        }
+
+                        if let Some(kind) = connect(origin, other) {
+                            waypoints[i].links.push(WaypointLink { to: j, kind });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(WaypointGraph { map_key, waypoints })
+    }
+
+    /// Load a graph saved with `write`.
+    pub fn read(data: &[u8]) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        magic.copy_from_slice(data.get(..4).ok_or_else(|| truncated(4, data.len()))?);
+
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic {
+                expected: MAGIC,
+                found: magic,
+            });
+        }
+
+        let version = read_u32(data, 4)?;
+
+        if version != VERSION {
+            return Err(Error::VersionMismatch(version));
+        }
+
+        let map_key = read_u32(data, 8)?;
+        let count = read_u32(data, 12)? as usize;
+        let mut pos = 16;
+        let mut waypoints = Vec::new();
+
+        for record in 0..count {
+            let origin = Vec3 {
+                x: read_f32(data, pos)?,
+                y: read_f32(data, pos + 4)?,
+                z: read_f32(data, pos + 8)?,
+            };
+            let num_links = read_u32(data, pos + 12)? as usize;
+
+            pos += 16;
+
+            let mut links = Vec::new();
+
+            for _ in 0..num_links {
+                let to = read_u32(data, pos)? as usize;
+                let kind = *data.get(pos + 4).ok_or_else(|| truncated(pos + 5, data.len()))?;
+
+                if to >= count {
+                    return Err(Error::BadIndex {
+                        lump: "waypoints",
+                        record,
+                        field: "to",
+                        index: to as _,
+                        len: count,
+                    });
+                }
+
+                let kind = match kind {
+                    0 => LinkKind::Walk,
+                    1 => LinkKind::Step,
+                    2 => LinkKind::Jump,
+                    other => {
+                        return Err(Error::BadIndex {
+                            lump: "waypoints",
+                            record,
+                            field: "kind",
+                            index: other as _,
+                            len: 3,
+                        })
+                    }
+                };
+
+                links.push(WaypointLink { to, kind });
+                pos += 5;
+            }
+
+            waypoints.push(Waypoint { origin, links });
+        }
+
+        Ok(WaypointGraph { map_key, waypoints })
+    }
+
+    /// Load the graph in `cached` if it was made for this map, or generate a new one if it wasn't
+    /// or there's no saved graph at all.
+    pub fn load_or_generate<V>(
+        cached: Option<&[u8]>,
+        bsp: &Bsp<V>,
+        config: &NavConfig,
+        spacing: f32,
+    ) -> Result<Self, Error>
+    where
+        V: MapVersion<Lump = Quake1Lump>,
+    {
+        let map_key = bsp.checksum();
+
+        match cached.map(WaypointGraph::read) {
+            Some(Ok(graph)) if graph.map_key == map_key => Ok(graph),
+            _ => WaypointGraph::generate_for(bsp, config, spacing, map_key),
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&self.map_key.to_le_bytes())?;
+        out.write_all(&(self.waypoints.len() as u32).to_le_bytes())?;
+
+        for waypoint in &self.waypoints {
+            for &v in &[waypoint.origin.x, waypoint.origin.y, waypoint.origin.z] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+
+            out.write_all(&(waypoint.links.len() as u32).to_le_bytes())?;
+
+            for link in &waypoint.links {
+                out.write_all(&(link.to as u32).to_le_bytes())?;
+                out.write_all(&[match link.kind {
+                    LinkKind::Walk => 0,
+                    LinkKind::Step => 1,
+                    LinkKind::Jump => 2,
+                }])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).expect("Writing to a Vec can't fail");
+        out
+    }
+
+    /// The waypoint closest to `point`.
+    pub fn nearest(&self, point: Vec3<f32>) -> Option<usize> {
+        self.waypoints
+            .iter()
+            .enumerate()
+            .map(|(i, waypoint)| {
+                let d = waypoint.origin - point;
+
+                (i, d.dot(&d))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+}